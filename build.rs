@@ -0,0 +1,20 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Makes `git describe` available as BLAISE_GIT_DESCRIBE when building from a checkout.
+fn main() {
+    let describe = Command::new("git")
+        .args(["describe", "--tags", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(describe) = describe {
+        println!("cargo:rustc-env=BLAISE_GIT_DESCRIBE={}", describe.trim());
+    }
+    for path in [".git/HEAD", ".git/index"] {
+        if Path::new(path).exists() {
+            println!("cargo:rerun-if-changed={}", path);
+        }
+    }
+}
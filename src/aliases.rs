@@ -0,0 +1,230 @@
+//! Probable duplicate labels (`blaise labels --suggest-merges`), eg., misspelled
+//! concept names: labels differing only in case, underscores, hyphens or spaces,
+//! or by a few edits, grouped under the most frequent of them.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+/// Why a label is taken as a variant of another, the closest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Similarity {
+    /// The same but for case, underscores, hyphens or spaces.
+    Variant,
+    /// The given number of character edits apart (after the above).
+    Edits(usize),
+}
+
+/// A label probably meaning the canonical one of its group.
+#[derive(Debug, PartialEq)]
+pub struct Alias {
+    pub label: String,
+    pub count: usize,
+    /// To the canonical label, or else to the closest label of the group.
+    pub similarity: Similarity,
+}
+
+/// Labels probably meaning the same.
+#[derive(Debug, PartialEq)]
+pub struct MergeGroup {
+    /// The most frequent label of the group.
+    pub canonical: String,
+    pub count: usize,
+    pub aliases: Vec<Alias>,
+}
+
+/// The label compared: lowercase, with underscores, hyphens and runs of
+/// whitespace as single spaces.
+fn key(label: &str) -> String {
+    let spaced = label.to_lowercase().replace(['_', '-'], " ");
+    spaced.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Levenshtein distance, in characters.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + (ca != *cb) as usize;
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// How two labels are similar, if they are: the same key, or at most
+/// `max_edits` edits apart, and these at most a quarter of the shorter key
+/// (so that short names like `cat` and `bat` are not taken as the same).
+fn similarity(a: &str, b: &str, max_edits: usize) -> Option<Similarity> {
+    if a == b {
+        return Some(Similarity::Variant);
+    }
+    let shorter = a.chars().count().min(b.chars().count());
+    let edits = edit_distance(a, b);
+    (edits <= max_edits && edits * 4 <= shorter).then_some(Similarity::Edits(edits))
+}
+
+/// The groups of similar labels, by decreasing total count.
+pub fn suggest_merges(counts: &BTreeMap<String, usize>, max_edits: usize) -> Vec<MergeGroup> {
+    let labels: Vec<(&String, usize, String)> =
+        counts.iter().map(|(label, count)| (label, *count, key(label))).collect();
+    // union-find of the similar labels, with the similarity to the label joined:
+    let mut parent: Vec<usize> = (0..labels.len()).collect();
+    let mut found: Vec<Option<Similarity>> = vec![None; labels.len()];
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for i in 0..labels.len() {
+        for j in i + 1..labels.len() {
+            let Some(similarity) = similarity(&labels[i].2, &labels[j].2, max_edits) else {
+                continue;
+            };
+            let (ri, rj) = (root(&mut parent, i), root(&mut parent, j));
+            if ri != rj {
+                parent[rj] = ri;
+            }
+            for k in [i, j] {
+                found[k] = Some(found[k].map_or(similarity, |s| s.min(similarity)));
+            }
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for i in 0..labels.len() {
+        let r = root(&mut parent, i);
+        groups.entry(r).or_default().push(i);
+    }
+    let mut merges: Vec<MergeGroup> = groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .map(|mut members| {
+            // the most frequent first, then by name:
+            members.sort_by(|&a, &b| labels[b].1.cmp(&labels[a].1).then(labels[a].0.cmp(labels[b].0)));
+            let canonical = members[0];
+            let aliases = members[1..]
+                .iter()
+                .map(|&i| Alias {
+                    label: labels[i].0.clone(),
+                    count: labels[i].1,
+                    // else similar through another label of the group:
+                    similarity: similarity(&labels[canonical].2, &labels[i].2, max_edits)
+                        .or(found[i])
+                        .unwrap(),
+                })
+                .collect();
+            MergeGroup {
+                canonical: labels[canonical].0.clone(),
+                count: labels[canonical].1,
+                aliases,
+            }
+        })
+        .collect();
+    let total = |group: &MergeGroup| group.count + group.aliases.iter().map(|a| a.count).sum::<usize>();
+    merges.sort_by(|a, b| total(b).cmp(&total(a)).then(a.canonical.cmp(&b.canonical)));
+    merges
+}
+
+pub fn show(groups: &[MergeGroup]) {
+    if groups.is_empty() {
+        println!("no probable duplicate labels");
+        return;
+    }
+    println!("probable duplicate labels ({} groups):", groups.len());
+    for group in groups {
+        println!("  {:?} ({})", group.canonical, group.count);
+        for alias in &group.aliases {
+            let how = match alias.similarity {
+                Similarity::Variant => "case/separators".to_string(),
+                Similarity::Edits(1) => "1 edit".to_string(),
+                Similarity::Edits(n) => format!("{} edits", n),
+            };
+            println!("    {:?} ({}, {})", alias.label, alias.count, how);
+        }
+    }
+}
+
+/// Writes the aliases with their canonical labels, as `label,canonical` CSV
+/// lines, to be reviewed before use.
+pub fn write_label_map(path: &Path, groups: &[MergeGroup]) -> io::Result<usize> {
+    let mut writer = csv::Writer::from_path(path)?;
+    writer.write_record(["label", "canonical"])?;
+    let mut count = 0;
+    for group in groups {
+        for alias in &group.aliases {
+            writer.write_record([&alias.label, &group.canonical])?;
+            count += 1;
+        }
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        assert_eq!(edit_distance("sebastes", "sebastes"), 0);
+        assert_eq!(edit_distance("sebastes", "sebastess"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(key(" Sebastes_mentella-X "), "sebastes mentella x");
+    }
+
+    #[test]
+    fn merges() {
+        let counts: BTreeMap<String, usize> = [
+            ("Sebastes", 120),
+            ("sebastes", 4),
+            ("Sebastess", 1),
+            ("Aegina_citrea", 30),
+            ("Aegina citrea", 50),
+            ("cat", 5),
+            ("bat", 5),
+            ("Merluccius", 10),
+        ]
+        .into_iter()
+        .map(|(label, count)| (label.to_string(), count))
+        .collect();
+        let groups = suggest_merges(&counts, 2);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].canonical, "Sebastes");
+        assert_eq!(
+            groups[0].aliases,
+            [
+                Alias {
+                    label: "sebastes".to_string(),
+                    count: 4,
+                    similarity: Similarity::Variant,
+                },
+                Alias {
+                    label: "Sebastess".to_string(),
+                    count: 1,
+                    similarity: Similarity::Edits(1),
+                },
+            ]
+        );
+        assert_eq!(groups[1].canonical, "Aegina citrea");
+        assert_eq!(groups[1].aliases[0].label, "Aegina_citrea");
+        assert!(suggest_merges(&counts, 0).iter().all(|g| g
+            .aliases
+            .iter()
+            .all(|a| a.similarity == Similarity::Variant)));
+
+        let path = std::env::temp_dir().join(format!("blaise-label-map-{}.csv", std::process::id()));
+        assert_eq!(write_label_map(&path, &groups).unwrap(), 3);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "label,canonical\nsebastes,Sebastes\nSebastess,Sebastes\nAegina_citrea,Aegina citrea\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
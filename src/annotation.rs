@@ -1,57 +1,354 @@
+use crate::geometry::{PixelRect, Point, Rect};
+#[cfg(feature = "pipeline")]
+use crate::{checksum, download};
+use imagesize::ImageSize;
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use unicode_normalization::UnicodeNormalization;
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct Annotation {
     pub folder: String,
     pub filename: String,
     pub objects: Option<Vec<Object>>,
+    /// Frame number, for annotations derived from video sequences.
+    pub frame: Option<u32>,
+    /// Image dimensions, if given by the annotation format.
+    #[serde(skip)]
+    pub image_size: Option<ImageSize>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct Object {
     pub name: String,
     pub bndbox: Bndbox,
+    /// Track (instance) id, for objects followed across video frames.
+    pub track_id: Option<u32>,
+    /// Id of the object in the source, if given (eg., the COCO annotation id).
+    #[serde(skip)]
+    pub id: Option<String>,
+    /// Instance segmentation mask, if given.
+    #[serde(skip)]
+    pub mask: Option<Mask>,
+    /// Additional attributes (eg., occluded, life-stage), with values as strings.
+    #[serde(default)]
+    pub attributes: BTreeMap<String, String>,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
-pub struct Bndbox {
-    pub xmin: u32,
-    pub ymin: u32,
-    pub xmax: u32,
-    pub ymax: u32,
+/// Attributes for the object flags in Pascal VOC.
+pub const TRUNCATED: &str = "truncated";
+pub const OCCLUDED: &str = "occluded";
+pub const DIFFICULT: &str = "difficult";
+
+/// Attribute for the Pascal VOC object pose (view angle).
+pub const POSE: &str = "pose";
+
+#[cfg(feature = "pipeline")]
+impl Annotation {
+    /// File name of the image, for naming the crops: the last path segment
+    /// if the image is referenced by URL.
+    pub fn image_name(&self) -> &str {
+        if download::is_url(&self.filename) {
+            download::url_filename(&self.filename)
+        } else {
+            &self.filename
+        }
+    }
+
+    /// Ids of the objects, for naming their crops. With `ObjectIds::Stable`, the
+    /// ids shared by several objects (eg., duplicated boxes) get a suffix.
+    pub fn object_ids(&self, scheme: ObjectIds) -> Vec<String> {
+        let objects = self.objects.iter().flatten();
+        if scheme == ObjectIds::Index {
+            return (0..objects.count()).map(|i| i.to_string()).collect();
+        }
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        objects
+            .map(|object| {
+                let id = object.stable_id();
+                let count = counts.entry(id.clone()).or_insert(0);
+                *count += 1;
+                match *count {
+                    1 => id,
+                    n => format!("{}-{}", id, n),
+                }
+            })
+            .collect()
+    }
+}
+
+/// How the crops of the objects are numbered in their filenames.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectIds {
+    /// By the id of the object in the source if given (eg., COCO), else by its
+    /// label and box coordinates, so not changing with the objects filtered out
+    Stable,
+    /// By the position of the object among those loaded
+    Index,
+}
+
+#[cfg(feature = "pipeline")]
+impl Object {
+    /// The id given in the source, made safe as a filename, else a hash of the
+    /// label and box coordinates.
+    fn stable_id(&self) -> String {
+        match &self.id {
+            Some(id) => id
+                .chars()
+                .map(|c| match c.is_ascii_alphanumeric() || c == '-' {
+                    true => c,
+                    false => '_',
+                })
+                .collect(),
+            None => {
+                let Bndbox {
+                    xmin,
+                    ymin,
+                    xmax,
+                    ymax,
+                } = self.bndbox;
+                let key = format!("{}:{},{},{},{}", self.name, xmin, ymin, xmax, ymax);
+                checksum::to_hex(&checksum::sha256(key.as_bytes())[..4])
+            }
+        }
+    }
+}
+
+impl Object {
+    /// Whether the given boolean attribute is set.
+    pub fn flag(&self, key: &str) -> bool {
+        self.attributes.get(key).is_some_and(|v| v == "true")
+    }
+
+    /// The pose, "Unspecified" if not given.
+    pub fn pose(&self) -> &str {
+        self.attributes
+            .get(POSE)
+            .map_or("Unspecified", |p| p.as_str())
+    }
+
+    /// Whether the object has all the given attribute values.
+    pub fn has_attributes(&self, attributes: &[(String, String)]) -> bool {
+        attributes
+            .iter()
+            .all(|(key, value)| self.attributes.get(key) == Some(value))
+    }
+}
+
+/// Parses a `key=value` attribute filter.
+pub fn parse_attribute(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected key=value".to_string()),
+    }
+}
+
+/// Object bounding box, in (possibly fractional) pixel coordinates as given
+/// by the annotations. It is only quantized to pixels at crop time.
+pub type Bndbox = Rect;
+
+/// Binary instance mask, stored only within its (tight) bounding box.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mask {
+    bounds: PixelRect,
+    /// Row-major, for the pixels in the bounds.
+    bits: Vec<bool>,
 }
 
-impl Bndbox {
-    pub fn width(&self) -> u32 {
-        self.xmax - self.xmin
+impl Mask {
+    /// Creates the mask for the image pixels for which `is_set` is true,
+    /// which gets (x, y) for all image pixels.
+    pub fn from_fn(width: u32, height: u32, is_set: impl Fn(u32, u32) -> bool) -> Mask {
+        let region = PixelRect {
+            xmin: 0,
+            ymin: 0,
+            xmax: width,
+            ymax: height,
+        };
+        Mask::from_fn_in(&region, is_set)
+    }
+
+    /// As `from_fn` but only evaluating `is_set` for the pixels in the region.
+    fn from_fn_in(region: &PixelRect, is_set: impl Fn(u32, u32) -> bool) -> Mask {
+        let width = region.width();
+        let mut bits_full = Vec::with_capacity((width * region.height()) as usize);
+        let (mut xmin, mut ymin, mut xmax, mut ymax) = (u32::MAX, u32::MAX, 0, 0);
+        for y in region.ymin..region.ymax {
+            for x in region.xmin..region.xmax {
+                let set = is_set(x, y);
+                if set {
+                    xmin = xmin.min(x);
+                    ymin = ymin.min(y);
+                    xmax = xmax.max(x + 1);
+                    ymax = ymax.max(y + 1);
+                }
+                bits_full.push(set);
+            }
+        }
+        if xmin > xmax {
+            return Mask {
+                bounds: PixelRect {
+                    xmin: 0,
+                    ymin: 0,
+                    xmax: 0,
+                    ymax: 0,
+                },
+                bits: Vec::new(),
+            };
+        }
+        let bounds = PixelRect {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        };
+        let bits = (ymin..ymax)
+            .flat_map(|y| (xmin..xmax).map(move |x| (x, y)))
+            .map(|(x, y)| bits_full[((y - region.ymin) * width + (x - region.xmin)) as usize])
+            .collect();
+        Mask { bounds, bits }
     }
 
-    pub fn height(&self) -> u32 {
-        self.ymax - self.ymin
+    /// Creates the mask for the pixels whose centers are inside the polygon,
+    /// given by its vertices in pixel coordinates (even-odd rule).
+    pub fn from_polygon(vertices: &[Point]) -> Mask {
+        let Some(bounds) = Rect::bounding(vertices) else {
+            return Mask::from_fn(0, 0, |_, _| false);
+        };
+        let region = PixelRect {
+            xmin: bounds.xmin.floor() as u32,
+            ymin: bounds.ymin.floor() as u32,
+            xmax: bounds.xmax.ceil() as u32,
+            ymax: bounds.ymax.ceil() as u32,
+        };
+        Mask::from_fn_in(&region, |x, y| {
+            Point::new(x as f64 + 0.5, y as f64 + 0.5).in_polygon(vertices)
+        })
+    }
+
+    /// Tight bounding box of the set pixels (empty if none).
+    pub fn bounds(&self) -> PixelRect {
+        self.bounds
+    }
+
+    /// Whether the image pixel at (x, y) is set.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        let b = &self.bounds;
+        b.contains(x, y) && self.bits[((y - b.ymin) * b.width() + (x - b.xmin)) as usize]
+    }
+
+    /// Number of set pixels.
+    pub fn area(&self) -> usize {
+        self.bits.iter().filter(|&&set| set).count()
+    }
+
+    /// Largest distance between the corners of the set pixels (maximum Feret
+    /// diameter), eg., the length of a fish.
+    pub fn max_feret_diameter(&self) -> f64 {
+        let b = &self.bounds;
+        let mut corners = Vec::new();
+        for (y, row) in (b.ymin..b.ymax).zip(self.bits.chunks(b.width().max(1) as usize)) {
+            let (Some(first), Some(last)) = (
+                row.iter().position(|&set| set),
+                row.iter().rposition(|&set| set),
+            ) else {
+                continue;
+            };
+            let (x0, x1) = (
+                (b.xmin as usize + first) as i64,
+                (b.xmin as usize + last + 1) as i64,
+            );
+            let y = y as i64;
+            corners.extend([(x0, y), (x0, y + 1), (x1, y), (x1, y + 1)]);
+        }
+        let hull = convex_hull(corners);
+        let mut max = 0i64;
+        for (i, (ax, ay)) in hull.iter().enumerate() {
+            for (bx, by) in &hull[i + 1..] {
+                max = max.max((ax - bx).pow(2) + (ay - by).pow(2));
+            }
+        }
+        (max as f64).sqrt()
+    }
+}
+
+/// Vertices of the convex hull of the points (monotone chain).
+fn convex_hull(mut points: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    points.sort_unstable();
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let half = |points: &mut dyn Iterator<Item = &(i64, i64)>| {
+        let mut chain: Vec<(i64, i64)> = Vec::new();
+        for &(x, y) in points {
+            while let [.., (ox, oy), (ax, ay)] = chain[..] {
+                if (ax - ox) * (y - oy) - (ay - oy) * (x - ox) > 0 {
+                    break;
+                }
+                chain.pop();
+            }
+            chain.push((x, y));
+        }
+        // the last point is the first of the other half:
+        chain.pop();
+        chain
+    };
+    let mut hull = half(&mut points.iter());
+    hull.extend(half(&mut points.iter().rev()));
+    hull
+}
+
+/// Size category of a bounding box according to its pixel area, as in the
+/// COCO evaluation: small below 32², medium below 96², large otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum SizeBucket {
+    Small,
+    Medium,
+    Large,
+}
+
+impl SizeBucket {
+    pub const ALL: [SizeBucket; 3] = [SizeBucket::Small, SizeBucket::Medium, SizeBucket::Large];
+
+    pub fn of(bndbox: &Bndbox) -> SizeBucket {
+        SizeBucket::for_area(bndbox.area())
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.width() == 0 || self.height() == 0
+    pub fn for_area(area: f64) -> SizeBucket {
+        if area < 32. * 32. {
+            SizeBucket::Small
+        } else if area < 96. * 96. {
+            SizeBucket::Medium
+        } else {
+            SizeBucket::Large
+        }
     }
 
-    pub fn aspect_ratio(&self) -> f64 {
-        let max = self.width().max(self.height());
-        let min = self.width().min(self.height());
-        max as f64 / min as f64
+    pub fn name(&self) -> &'static str {
+        match self {
+            SizeBucket::Small => "small",
+            SizeBucket::Medium => "medium",
+            SizeBucket::Large => "large",
+        }
     }
 }
 
 impl Annotation {
     /// Returns a copy with only the objects satisfying to the given labels, if any.
     /// Returns None if no objects are left after filtering.
-    pub fn with_filtered_objects(self, labels: &Option<Vec<String>>) -> Option<Annotation> {
+    pub fn with_filtered_objects(
+        self,
+        labels: &Option<Vec<String>>,
+        case_insensitive: bool,
+    ) -> Option<Annotation> {
         match self.objects {
             Some(objects) => {
                 let filtered: Vec<Object> = objects
                     .into_iter()
                     .filter(|object| {
                         if let Some(labels) = labels {
-                            labels.contains(&object.name)
+                            is_label_in(&object.name, labels, case_insensitive)
                         } else {
                             true
                         }
@@ -71,12 +368,143 @@ impl Annotation {
     }
 }
 
+/// Applies a label frequency floor: objects of labels with fewer than `min_count`
+/// objects overall are renamed to `merge_into`, if given, or otherwise dropped
+/// (along with annotations left without objects).
+/// Returns the resulting annotations and the rare labels with their counts.
+pub fn apply_label_floor(
+    annotations: Vec<Annotation>,
+    min_count: usize,
+    merge_into: Option<&str>,
+) -> (Vec<Annotation>, Vec<(String, usize)>) {
+    let mut counts: std::collections::HashMap<String, usize> = Default::default();
+    for object in annotations.iter().flat_map(|a| a.objects.iter().flatten()) {
+        *counts.entry(object.name.clone()).or_insert(0) += 1;
+    }
+    let mut rare: Vec<(String, usize)> = counts
+        .into_iter()
+        .filter(|(_, count)| *count < min_count)
+        .collect();
+    if rare.is_empty() {
+        return (annotations, rare);
+    }
+    rare.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    let is_rare = |name: &String| rare.iter().any(|(label, _)| label == name);
+
+    let annotations = annotations
+        .into_iter()
+        .filter_map(|annotation| {
+            let objects: Vec<Object> = annotation
+                .objects
+                .into_iter()
+                .flatten()
+                .filter_map(|object| match (is_rare(&object.name), merge_into) {
+                    (false, _) => Some(object),
+                    (true, Some(other)) => Some(Object {
+                        name: other.to_string(),
+                        ..object
+                    }),
+                    (true, None) => None,
+                })
+                .collect();
+            (!objects.is_empty()).then_some(Annotation {
+                objects: Some(objects),
+                ..annotation
+            })
+        })
+        .collect();
+    (annotations, rare)
+}
+
+/// Whether the label is one of the given labels, ignoring the case if so indicated.
+pub fn is_label_in(label: &str, labels: &[String], case_insensitive: bool) -> bool {
+    match case_insensitive {
+        true => {
+            let label = label.to_lowercase();
+            labels.iter().any(|l| l.to_lowercase() == label)
+        }
+        false => labels.iter().any(|l| l == label),
+    }
+}
+
+/// How labels are normalized when the annotations are read.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelNormalize {
+    /// As given
+    None,
+    /// Trimmed, with runs of whitespace as a single space, in Unicode NFC (the
+    /// letters given with combining marks composed)
+    Basic,
+    /// As `basic`, and lowercase
+    FoldCase,
+}
+
+/// The label, normalized.
+pub fn normalize_label(label: &str, how: LabelNormalize) -> String {
+    if how == LabelNormalize::None {
+        return label.to_string();
+    }
+    let words: Vec<&str> = label.split_whitespace().collect();
+    let label: String = words.join(" ").nfc().collect();
+    match how {
+        LabelNormalize::FoldCase => label.to_lowercase(),
+        _ => label,
+    }
+}
+
+/// How labels are made safe as directory names.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelSanitize {
+    /// ASCII letters, digits, `-`, `_` and `.` kept, any other run of characters as `_`
+    Slug,
+    /// Hexadecimal of the UTF-8 bytes
+    Hex,
+    /// As is
+    None,
+}
+
+/// The directory name for the label.
+pub fn sanitize_label(label: &str, how: LabelSanitize) -> String {
+    match how {
+        LabelSanitize::None => label.to_string(),
+        LabelSanitize::Hex => label.bytes().map(|b| format!("{:02x}", b)).collect(),
+        LabelSanitize::Slug => {
+            let mut slug = String::new();
+            for c in label.chars() {
+                if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                    slug.push(c);
+                } else if !slug.ends_with('_') {
+                    slug.push('_');
+                }
+            }
+            // not hidden, nor `.` or `..`:
+            let slug = slug.trim_start_matches('.');
+            if slug.is_empty() {
+                "_".to_string()
+            } else {
+                slug.to_string()
+            }
+        }
+    }
+}
+
 #[derive(Debug, serde::Serialize)]
 pub struct BndboxItem {
     pub img_filename: String,
-    pub width: u32,
-    pub height: u32,
+    pub width: f64,
+    pub height: f64,
     pub aspect_ratio: f64,
+    /// Longer side of the box, with the pixel size.
+    pub length_mm: Option<f64>,
+    pub area_mm2: Option<f64>,
+    /// Maximum Feret diameter of the mask, if any, with the pixel size.
+    pub mask_length_mm: Option<f64>,
+    pub mask_area_mm2: Option<f64>,
+}
+
+/// To the µm (or µm²).
+fn round_um(mm: f64) -> f64 {
+    (mm * 1e6).round() / 1e6
 }
 
 pub struct BndboxItemReporter {
@@ -98,13 +526,23 @@ impl BndboxItemReporter {
             })
     }
 
-    pub fn add_item(&mut self, img_filename: String, object: &Object) {
+    /// Adds the object, with its length and area estimates if the pixel size
+    /// (mm per pixel) of the image is given.
+    pub fn add_item(&mut self, img_filename: String, object: &Object, pixel_size: Option<f64>) {
         if let Some(items) = &mut self.items {
+            let bndbox = &object.bndbox;
+            let mask = object.mask.as_ref();
+            let mm = |pixels: f64| pixel_size.map(|size| round_um(pixels * size));
+            let mm2 = |pixels: f64| pixel_size.map(|size| round_um(pixels * size * size));
             let item = BndboxItem {
                 img_filename,
-                width: object.bndbox.width(),
-                height: object.bndbox.height(),
-                aspect_ratio: object.bndbox.aspect_ratio(),
+                width: bndbox.width(),
+                height: bndbox.height(),
+                aspect_ratio: bndbox.aspect_ratio(),
+                length_mm: mm(bndbox.width().max(bndbox.height())),
+                area_mm2: mm2(bndbox.area()),
+                mask_length_mm: mask.and_then(|m| mm(m.max_feret_diameter())),
+                mask_area_mm2: mask.and_then(|m| mm2(m.area() as f64)),
             };
             items.push(item);
         }
@@ -124,3 +562,207 @@ impl BndboxItemReporter {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bndbox(width: f64, height: f64) -> Bndbox {
+        Bndbox {
+            xmin: 10.,
+            ymin: 20.,
+            xmax: 10. + width,
+            ymax: 20. + height,
+        }
+    }
+
+    #[test]
+    fn size_buckets() {
+        assert_eq!(SizeBucket::of(&bndbox(31., 32.)), SizeBucket::Small);
+        assert_eq!(SizeBucket::of(&bndbox(32., 32.)), SizeBucket::Medium);
+        assert_eq!(SizeBucket::of(&bndbox(95., 96.)), SizeBucket::Medium);
+        assert_eq!(SizeBucket::of(&bndbox(96., 96.)), SizeBucket::Large);
+        assert_eq!(SizeBucket::of(&bndbox(256., 256.)), SizeBucket::Large);
+    }
+
+    #[test]
+    fn label_floor() {
+        let object = |name: &str| Object {
+            name: name.to_string(),
+            bndbox: bndbox(10., 10.),
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        };
+        let annotation = |names: &[&str]| Annotation {
+            folder: "f".to_string(),
+            filename: "x.png".to_string(),
+            objects: Some(names.iter().map(|n| object(n)).collect()),
+            frame: None,
+            image_size: None,
+        };
+        let names = |annotations: &[Annotation]| -> Vec<Vec<String>> {
+            annotations
+                .iter()
+                .map(|a| a.objects.iter().flatten().map(|o| o.name.clone()).collect())
+                .collect()
+        };
+        let annotations = || {
+            vec![
+                annotation(&["A", "B"]),
+                annotation(&["A", "C"]),
+                annotation(&["C"]),
+            ]
+        };
+
+        let (skipped, rare) = apply_label_floor(annotations(), 2, None);
+        assert_eq!(rare, vec![("B".to_string(), 1)]);
+        assert_eq!(names(&skipped), vec![vec!["A"], vec!["A", "C"], vec!["C"]]);
+
+        let (merged, rare) = apply_label_floor(annotations(), 3, Some("OTHER"));
+        assert_eq!(rare.len(), 3);
+        assert_eq!(
+            names(&merged),
+            vec![
+                vec!["OTHER", "OTHER"],
+                vec!["OTHER", "OTHER"],
+                vec!["OTHER"]
+            ]
+        );
+
+        let (_, rare) = apply_label_floor(vec![annotation(&["B"])], 4, None);
+        assert_eq!(rare, vec![("B".to_string(), 1)]);
+    }
+
+    #[test]
+    fn polygon_mask() {
+        // right triangle with the right angle at (10, 10):
+        let mask = Mask::from_polygon(&[
+            Point::new(10., 10.),
+            Point::new(20., 10.),
+            Point::new(10., 20.),
+        ]);
+        assert_eq!(
+            mask.bounds(),
+            PixelRect {
+                xmin: 10,
+                ymin: 10,
+                xmax: 19,
+                ymax: 19,
+            }
+        );
+        assert!(mask.contains(10, 10) && mask.contains(18, 10) && mask.contains(10, 18));
+        assert!(!mask.contains(18, 18) && !mask.contains(9, 10));
+        assert!(
+            Mask::from_polygon(&[Point::new(1., 1.), Point::new(5., 5.)])
+                .bounds()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn mask_measurements() {
+        let mask = Mask::from_fn(40, 40, |x, y| (10..13).contains(&x) && (5..9).contains(&y));
+        assert_eq!(mask.area(), 12);
+        assert_eq!(mask.max_feret_diameter(), 5.);
+        // L shape, from (0, 0) to (7, 1) and (0, 5):
+        let mask = Mask::from_fn(10, 10, |x, y| (y == 0 && x < 7) || (x == 0 && y < 5));
+        assert_eq!(mask.area(), 11);
+        assert_eq!(mask.max_feret_diameter(), (49f64 + 25.).sqrt());
+        assert_eq!(Mask::from_fn(3, 3, |_, _| false).max_feret_diameter(), 0.);
+    }
+
+    #[cfg(feature = "pipeline")]
+    #[test]
+    fn object_ids() {
+        let object = |id: Option<&str>, xmin: f64| Object {
+            name: "A".to_string(),
+            bndbox: Bndbox {
+                xmin,
+                ymin: 20.,
+                xmax: 30.,
+                ymax: 40.,
+            },
+            track_id: None,
+            id: id.map(str::to_string),
+            mask: None,
+            attributes: Default::default(),
+        };
+        let annotation = |objects| Annotation {
+            folder: "f".to_string(),
+            filename: "x.png".to_string(),
+            objects: Some(objects),
+            frame: None,
+            image_size: None,
+        };
+        let all = annotation(vec![
+            object(None, 10.),
+            object(None, 12.),
+            object(Some("17"), 10.),
+            object(Some("a/b"), 10.),
+            object(None, 10.),
+        ]);
+        let ids = all.object_ids(ObjectIds::Stable);
+        assert_eq!(ids[0].len(), 8);
+        assert_ne!(ids[0], ids[1]);
+        assert_eq!(ids[2..4], ["17", "a_b"]);
+        assert_eq!(ids[4], format!("{}-2", ids[0]));
+        // the same with objects filtered out:
+        let filtered = annotation(vec![object(None, 12.)]);
+        assert_eq!(filtered.object_ids(ObjectIds::Stable), [ids[1].clone()]);
+        assert_eq!(all.object_ids(ObjectIds::Index), ["0", "1", "2", "3", "4"]);
+        // the same box with another label:
+        let mut other = object(None, 10.);
+        other.name = "B".to_string();
+        assert_ne!(
+            annotation(vec![other]).object_ids(ObjectIds::Stable),
+            [ids[0].clone()]
+        );
+    }
+
+    #[test]
+    fn label_normalize() {
+        let basic = |label| normalize_label(label, LabelNormalize::Basic);
+        assert_eq!(basic(" Sebastes  \tmentella "), "Sebastes mentella");
+        assert_eq!(basic("Sebastes\u{a0}"), "Sebastes");
+        assert_eq!(basic("Cine\u{301}ma"), "Cinéma");
+        assert_eq!(basic("Cin\u{e9}ma"), "Cinéma");
+        assert_eq!(basic("\u{301}x"), "\u{301}x");
+        assert_eq!(basic("S\u{30c}ipka\u{308}"), "Šipkä");
+        assert_eq!(basic("Nguye\u{302}\u{303}n"), "Nguy\u{1ec5}n");
+        assert_eq!(basic("\u{212b}ngstr\u{f6}m"), "\u{c5}ngstr\u{f6}m");
+        // no composed letter:
+        assert_eq!(basic("x\u{301}"), "x\u{301}");
+        assert_eq!(
+            normalize_label("Sebastes ", LabelNormalize::FoldCase),
+            "sebastes"
+        );
+        assert_eq!(
+            normalize_label("Sebastes ", LabelNormalize::None),
+            "Sebastes "
+        );
+    }
+
+    #[test]
+    fn label_in() {
+        let labels = ["Sebastes".to_string(), "Kelp".to_string()];
+        assert!(is_label_in("Kelp", &labels, false));
+        assert!(!is_label_in("kelp", &labels, false));
+        assert!(is_label_in("kelp", &labels, true));
+        assert!(is_label_in("SEBASTES", &labels, true));
+        assert!(!is_label_in("Aegina", &labels, true));
+    }
+
+    #[test]
+    fn label_sanitize() {
+        let slug = |label| sanitize_label(label, LabelSanitize::Slug);
+        assert_eq!(slug("Aegina citrea"), "Aegina_citrea");
+        assert_eq!(slug("fish/other 'small'"), "fish_other_small_");
+        assert_eq!(slug("Ça va-1.2"), "_a_va-1.2");
+        assert_eq!(slug(".."), "_");
+        assert_eq!(slug("../x"), "_x");
+        assert_eq!(sanitize_label("a/b", LabelSanitize::Hex), "612f62");
+        assert_eq!(sanitize_label("a/b", LabelSanitize::None), "a/b");
+    }
+}
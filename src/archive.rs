@@ -0,0 +1,455 @@
+//! Archive sinks: the crops in a tar or zip file, or as WebDataset shards
+//! (tar files where each crop is a sample with its image and a `.json` with
+//! the label, source image and box). Entries are named by the crop paths
+//! relative to the output directory. Zip entries are stored uncompressed, as
+//! the crops are compressed images already.
+
+use crate::sink::{Crop, CropSink};
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Writes the value to the numeric field: octal, zero-padded and NUL-terminated,
+/// or if too large for that (eg., sizes from 8 GiB), in the GNU base-256 form
+/// (big-endian, with the high bit of the first byte set).
+fn put_number(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    if value < 1 << (3 * digits) {
+        let s = format!("{:0width$o}\0", value, width = digits);
+        field.copy_from_slice(s.as_bytes());
+    } else {
+        field.fill(0);
+        let end = field.len();
+        field[end - 8..].copy_from_slice(&value.to_be_bytes());
+        field[0] |= 0x80;
+    }
+}
+
+/// A ustar header for a regular file.
+fn tar_header(name: &str, size: u64, mtime: u64) -> io::Result<[u8; 512]> {
+    // names over 100 bytes are split into prefix and name at a slash:
+    let (prefix, name) = if name.len() <= 100 {
+        ("", name)
+    } else {
+        name.char_indices()
+            .filter(|(i, c)| *c == '/' && *i <= 155 && name.len() - i - 1 <= 100)
+            .map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .next()
+            .ok_or_else(|| invalid(format!("name too long for tar: {}", name)))?
+    };
+    let mut header = [0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    put_number(&mut header[100..108], 0o644);
+    put_number(&mut header[108..116], 0);
+    put_number(&mut header[116..124], 0);
+    put_number(&mut header[124..136], size);
+    put_number(&mut header[136..148], mtime);
+    header[148..156].fill(b' ');
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
+
+struct TarWriter {
+    out: BufWriter<File>,
+    mtime: u64,
+}
+
+impl TarWriter {
+    fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            out: BufWriter::new(File::create(path)?),
+            mtime: now_secs(),
+        })
+    }
+
+    fn append(&mut self, name: &str, bytes: &[u8]) -> io::Result<()> {
+        self.out
+            .write_all(&tar_header(name, bytes.len() as u64, self.mtime)?)?;
+        self.out.write_all(bytes)?;
+        let padding = (512 - bytes.len() % 512) % 512;
+        self.out.write_all(&[0u8; 512][..padding])
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.write_all(&[0u8; 1024])?;
+        self.out.flush()
+    }
+}
+
+/// The crops in a tar file.
+pub struct TarSink {
+    path: PathBuf,
+    tar: Mutex<(TarWriter, usize)>,
+}
+
+impl TarSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            tar: Mutex::new((TarWriter::create(path)?, 0)),
+        })
+    }
+}
+
+impl CropSink for TarSink {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        let mut tar = self.tar.lock().unwrap();
+        tar.0.append(&crop.name.to_string_lossy(), crop.bytes)?;
+        tar.1 += 1;
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut tar = self.tar.lock().unwrap();
+        tar.0.finish()?;
+        println!("Wrote {} crops to {:?}", tar.1, self.path);
+        Ok(())
+    }
+}
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+static CRC32_TABLE: [u32; 256] = crc32_table();
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |c, &b| {
+        CRC32_TABLE[((c ^ b as u32) & 0xFF) as usize] ^ (c >> 8)
+    })
+}
+
+/// MS-DOS time and date (UTC) of the given unix time, as used in zip files.
+fn dos_time(secs: u64) -> (u16, u16) {
+    // civil date from days since the epoch (Howard Hinnant's algorithm):
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    let secs_of_day = secs % 86400;
+    let time =
+        ((secs_of_day / 3600) << 11) | ((secs_of_day % 3600 / 60) << 5) | (secs_of_day % 60 / 2);
+    let date = ((year - 1980).clamp(0, 127) << 9) | (month << 5) | day;
+    (time as u16, date as u16)
+}
+
+struct ZipState {
+    out: BufWriter<File>,
+    offset: u64,
+    /// Central directory entries written so far.
+    central: Vec<u8>,
+    entries: usize,
+}
+
+/// The crops in a zip file, stored.
+pub struct ZipSink {
+    path: PathBuf,
+    time: (u16, u16),
+    state: Mutex<ZipState>,
+}
+
+impl ZipSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            path: path.to_path_buf(),
+            time: dos_time(now_secs()),
+            state: Mutex::new(ZipState {
+                out: BufWriter::new(File::create(path)?),
+                offset: 0,
+                central: Vec::new(),
+                entries: 0,
+            }),
+        })
+    }
+}
+
+impl CropSink for ZipSink {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        let name = crop.name.to_string_lossy();
+        let mut state = self.state.lock().unwrap();
+        let end = state.offset + 30 + name.len() as u64 + crop.bytes.len() as u64;
+        if end > u32::MAX as u64 || state.entries == u16::MAX as usize {
+            return Err(invalid(format!("{:?} is full (no zip64)", self.path)));
+        }
+        let (time, date) = self.time;
+        let crc = crc32(crop.bytes);
+        let size = crop.bytes.len() as u32;
+        // fields common to the local header and central directory entry:
+        let mut fields = Vec::with_capacity(26);
+        fields.extend_from_slice(&20u16.to_le_bytes()); // version needed
+        fields.extend_from_slice(&0x0800u16.to_le_bytes()); // UTF-8 names
+        fields.extend_from_slice(&0u16.to_le_bytes()); // stored
+        fields.extend_from_slice(&time.to_le_bytes());
+        fields.extend_from_slice(&date.to_le_bytes());
+        fields.extend_from_slice(&crc.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&size.to_le_bytes());
+        fields.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        fields.extend_from_slice(&0u16.to_le_bytes()); // extra length
+
+        let offset = state.offset as u32;
+        state.out.write_all(&0x0403_4b50u32.to_le_bytes())?;
+        state.out.write_all(&fields)?;
+        state.out.write_all(name.as_bytes())?;
+        state.out.write_all(crop.bytes)?;
+        state.offset = end;
+
+        let central = &mut state.central;
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&0x0314u16.to_le_bytes()); // made by unix, version 20
+        central.extend_from_slice(&fields);
+        central.extend_from_slice(&[0u8; 6]); // comment length, disk, internal attributes
+        central.extend_from_slice(&(0o100644u32 << 16).to_le_bytes()); // file mode
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        state.entries += 1;
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let central = std::mem::take(&mut state.central);
+        let entries = (state.entries as u16).to_le_bytes();
+        let offset = state.offset as u32;
+        state.out.write_all(&central)?;
+        state.out.write_all(&0x0605_4b50u32.to_le_bytes())?;
+        state.out.write_all(&[0u8; 4])?; // disk numbers
+        state.out.write_all(&entries)?;
+        state.out.write_all(&entries)?;
+        state.out.write_all(&(central.len() as u32).to_le_bytes())?;
+        state.out.write_all(&offset.to_le_bytes())?;
+        state.out.write_all(&[0u8; 2])?; // comment length
+        state.out.flush()?;
+        println!("Wrote {} crops to {:?}", state.entries, self.path);
+        Ok(())
+    }
+}
+
+struct ShardState {
+    tar: Option<TarWriter>,
+    shard: usize,
+    in_shard: usize,
+    total: usize,
+}
+
+/// The crops as samples in WebDataset tar shards.
+pub struct WebDatasetSink {
+    dir: PathBuf,
+    shard_size: usize,
+    state: Mutex<ShardState>,
+}
+
+impl WebDatasetSink {
+    pub fn create(dir: &Path, shard_size: usize) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            shard_size: shard_size.max(1),
+            state: Mutex::new(ShardState {
+                tar: None,
+                shard: 0,
+                in_shard: 0,
+                total: 0,
+            }),
+        })
+    }
+}
+
+/// Sample key for the crop: its path without extension, with no dots,
+/// as WebDataset takes the extension from the first dot of the file name.
+fn sample_key(name: &Path) -> String {
+    name.with_extension("").to_string_lossy().replace('.', "_")
+}
+
+impl CropSink for WebDatasetSink {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        let key = sample_key(crop.name);
+        let extension = crop
+            .name
+            .extension()
+            .map_or("png".into(), |e| e.to_string_lossy());
+        let b = crop.bndbox;
+        let metadata = json!({
+            "label": crop.label,
+            "source": crop.source,
+            "bbox": [b.xmin, b.ymin, b.xmax, b.ymax],
+        });
+
+        let mut state = self.state.lock().unwrap();
+        if state.tar.is_none() || state.in_shard == self.shard_size {
+            if let Some(mut tar) = state.tar.take() {
+                tar.finish()?;
+                state.shard += 1;
+            }
+            let path = self.dir.join(format!("crops-{:06}.tar", state.shard));
+            state.tar = Some(TarWriter::create(&path)?);
+            state.in_shard = 0;
+        }
+        let tar = state.tar.as_mut().unwrap();
+        tar.append(&format!("{}.{}", key, extension), crop.bytes)?;
+        tar.append(&format!("{}.json", key), metadata.to_string().as_bytes())?;
+        state.in_shard += 1;
+        state.total += 1;
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(mut tar) = state.tar.take() {
+            tar.finish()?;
+            println!(
+                "Wrote {} WebDataset samples in {} shards under {:?}",
+                state.total,
+                state.shard + 1,
+                self.dir
+            );
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::PixelRect;
+    use crate::manifest::CropKind;
+    use image::DynamicImage;
+    use std::process::Command;
+
+    fn add_crops(sink: &dyn CropSink, names: &[&str]) {
+        let image = DynamicImage::new_rgb8(1, 1);
+        let bndbox = PixelRect {
+            xmin: 1,
+            ymin: 2,
+            xmax: 3,
+            ymax: 4,
+        };
+        for name in names {
+            let path = Path::new("out").join(name);
+            sink.add(&Crop {
+                kind: CropKind::Object,
+                image: &image,
+                bytes: name.as_bytes(),
+                path: &path,
+                name: Path::new(name),
+                label: "Aegina",
+                source: "imgs/a.png",
+                bndbox: &bndbox,
+            })
+            .unwrap();
+        }
+        sink.finish().unwrap();
+    }
+
+    fn list(program: &str, args: &[&str], path: &Path) -> String {
+        let output = Command::new(program).args(args).arg(path).output().unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        String::from_utf8(output.stdout).unwrap()
+    }
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        // 2023-06-15 12:34:56
+        assert_eq!(
+            dos_time(1_686_832_496),
+            ((12 << 11) | (34 << 5) | 28, (43 << 9) | (6 << 5) | 15)
+        );
+    }
+
+    #[test]
+    fn tar_numbers() {
+        let mut field = [0u8; 12];
+        put_number(&mut field, 0o644);
+        assert_eq!(&field, b"00000000644\0");
+        put_number(&mut field, (8 << 30) - 1);
+        assert_eq!(&field, b"77777777777\0");
+        put_number(&mut field, 8 << 30);
+        assert_eq!(field, [0x80, 0, 0, 0, 0, 0, 0, 0x02, 0, 0, 0, 0]);
+        put_number(&mut field, u64::MAX);
+        assert_eq!(field, [0x80, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]);
+
+        let header = tar_header("big.png", 10 << 30, 0).unwrap();
+        assert_eq!(header[124..136], [0x80, 0, 0, 0, 0, 0, 0, 0x02, 0x80, 0, 0, 0]);
+    }
+
+    #[test]
+    fn tar_and_zip() {
+        let dir = std::env::temp_dir().join(format!("blaise-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let long = format!("{}/b_0.png", "x".repeat(120));
+        let names = ["Aegina/a_0.png", long.as_str()];
+
+        let tar = dir.join("crops.tar");
+        add_crops(&TarSink::create(&tar).unwrap(), &names);
+        assert_eq!(
+            list("tar", &["-tf"], &tar),
+            format!("{}\n{}\n", names[0], names[1])
+        );
+        let contents = list("tar", &["-xOf"], &tar);
+        assert_eq!(contents, format!("{}{}", names[0], names[1]));
+
+        let zip = dir.join("crops.zip");
+        add_crops(&ZipSink::create(&zip).unwrap(), &names);
+        assert_eq!(
+            list("zipinfo", &["-1"], &zip),
+            format!("{}\n{}\n", names[0], names[1])
+        );
+        list("unzip", &["-tq"], &zip);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn webdataset() {
+        let dir = std::env::temp_dir().join(format!("blaise-wds-{}", std::process::id()));
+        let names = ["Aegina/a.1_0.png", "Aegina/a.1_1.png", "Kelp/b_0.png"];
+        add_crops(&WebDatasetSink::create(&dir, 2).unwrap(), &names);
+        assert_eq!(
+            list("tar", &["-tf"], &dir.join("crops-000000.tar")),
+            "Aegina/a_1_0.png\nAegina/a_1_0.json\nAegina/a_1_1.png\nAegina/a_1_1.json\n"
+        );
+        let json = list("tar", &["-xOf"], &dir.join("crops-000001.tar"));
+        assert!(json.ends_with(r#"{"bbox":[1,2,3,4],"label":"Aegina","source":"imgs/a.png"}"#));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! Batch mode (`blaise batch <job-file>`): several independent crop jobs, eg.,
+//! label-specific crop sets from the same corpus, run at once in the process,
+//! sharing the processing threads and the decoded images, so each image is
+//! decoded once for all the jobs reading it.
+//!
+//! The job file is JSON, with the command line arguments of each job as with
+//! the C interface:
+//!
+//! ```json
+//! {"jobs": [
+//!   {"name": "fish", "args": ["-p", "corpus", "-L", "fish", "-o", "out/fish"]},
+//!   {"name": "squid", "args": ["-p", "corpus", "-L", "squid", "-o", "out/squid"]}
+//! ]}
+//! ```
+//!
+//! Each job gets its share of the threads unless given `-j`, and writes its
+//! own output directory with its `run.json`. The reports of all the jobs
+//! (as with `blaise_run`) can be written to a file.
+
+use crate::ffi;
+use image::{DynamicImage, ImageResult};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+#[derive(Deserialize)]
+struct JobFile {
+    jobs: Vec<JobSpec>,
+}
+
+#[derive(Deserialize)]
+struct JobSpec {
+    name: Option<String>,
+    args: Vec<String>,
+}
+
+/// What a job of a batch shares with the others.
+pub struct Batch<'a> {
+    pub jobs: usize,
+    pub images: &'a ImageCache,
+    /// Whether the job told the images it reads (see `will_read`).
+    settled: AtomicBool,
+}
+
+impl<'a> Batch<'a> {
+    fn new(jobs: usize, images: &'a ImageCache) -> Self {
+        Self {
+            jobs,
+            images,
+            settled: AtomicBool::new(false),
+        }
+    }
+
+    /// Registers the images the job reads through the cache, once per read,
+    /// for them to be kept until read by all the jobs.
+    pub fn will_read(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        if !self.settled.swap(true, Ordering::SeqCst) {
+            self.images.register(paths);
+        }
+    }
+}
+
+impl Drop for Batch<'_> {
+    /// A job ending (or failing) before registering its images reads none.
+    fn drop(&mut self) {
+        self.will_read([]);
+    }
+}
+
+/// Runs the jobs in the given file, returning the highest exit code of the
+/// jobs.
+pub fn run(job_file: &Path, cache_mb: u64, report: Option<&Path>) -> Result<i32, String> {
+    let contents = fs::read_to_string(job_file)
+        .map_err(|e| format!("cannot read job file {:?}: {}", job_file, e))?;
+    let job_file: JobFile = serde_json::from_str(&contents)
+        .map_err(|e| format!("invalid job file {:?}: {}", job_file, e))?;
+    let jobs = job_file.jobs;
+    let images = ImageCache::new(jobs.len(), cache_mb * 1024 * 1024);
+    println!("running {} jobs", jobs.len());
+    let reports = run_jobs(jobs, &images);
+
+    for report in &reports {
+        println!(
+            "job {}: exit code {}, {} crops",
+            report["name"].as_str().unwrap_or_default(),
+            report["exit_code"],
+            report["run"]["crops"]
+        );
+    }
+    let (decoded, shared) = images.stats();
+    println!("images decoded: {} ({} reused by other jobs)", decoded, shared);
+    if let Some(path) = report {
+        let json = serde_json::to_string_pretty(&reports).unwrap();
+        if let Err(e) = fs::write(path, json + "\n") {
+            eprintln!("ERROR: cannot write report {:?}: {}", path, e);
+        }
+    }
+    Ok(reports
+        .iter()
+        .filter_map(|report| report["exit_code"].as_i64())
+        .max()
+        .unwrap_or(0) as i32)
+}
+
+fn run_jobs(jobs: Vec<JobSpec>, images: &ImageCache) -> Vec<Value> {
+    let count = jobs.len();
+    thread::scope(|scope| {
+        let handles: Vec<_> = jobs
+            .into_iter()
+            .enumerate()
+            .map(|(i, job)| {
+                let name = job.name.unwrap_or_else(|| (i + 1).to_string());
+                scope.spawn(move || {
+                    let batch = Batch::new(count, images);
+                    let mut report = ffi::run_args(job.args, Some(&batch));
+                    report["name"] = json!(name);
+                    report
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    })
+}
+
+/// Decoded images shared by the jobs, each kept until read as many times as
+/// the jobs registered, or evicted (least recently used first) to keep within
+/// the given bytes.
+pub struct ImageCache {
+    max_bytes: u64,
+    state: Mutex<CacheState>,
+    loaded: Condvar,
+}
+
+#[derive(Default)]
+struct CacheState {
+    slots: HashMap<PathBuf, Slot>,
+    /// The reads registered by the jobs and not done yet, by image.
+    readers: HashMap<PathBuf, usize>,
+    /// The jobs yet to register their reads, until which images are kept
+    /// even with no registered reader.
+    unsettled: usize,
+    bytes: u64,
+    tick: u64,
+    decoded: usize,
+    shared: usize,
+}
+
+impl CacheState {
+    /// Counts a read of the image, returning whether to keep it for others.
+    fn read(&mut self, path: &Path) -> bool {
+        let left = match self.readers.get_mut(path) {
+            Some(readers) => {
+                *readers -= 1;
+                *readers
+            }
+            None => 0,
+        };
+        if left == 0 {
+            self.readers.remove(path);
+        }
+        left > 0 || self.unsettled > 0
+    }
+}
+
+enum Slot {
+    /// Being decoded by a job, for the others to wait for.
+    Loading,
+    Ready {
+        image: Arc<DynamicImage>,
+        last_read: u64,
+    },
+}
+
+/// Clears the `Loading` slot of an image if decoding it panics, for the jobs
+/// waiting for it to decode it themselves.
+struct Loading<'c> {
+    cache: &'c ImageCache,
+    path: &'c Path,
+}
+
+impl Drop for Loading<'_> {
+    fn drop(&mut self) {
+        let mut state = self.cache.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.slots.remove(self.path);
+        self.cache.loaded.notify_all();
+    }
+}
+
+impl ImageCache {
+    pub fn new(jobs: usize, max_bytes: u64) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(CacheState {
+                unsettled: jobs,
+                ..Default::default()
+            }),
+            loaded: Condvar::new(),
+        }
+    }
+
+    /// Registers the reads of a job, once per image read.
+    fn register(&self, paths: impl IntoIterator<Item = PathBuf>) {
+        let mut state = self.state.lock().unwrap();
+        for path in paths {
+            *state.readers.entry(path).or_default() += 1;
+        }
+        state.unsettled = state.unsettled.saturating_sub(1);
+        if state.unsettled == 0 {
+            // release the images kept for jobs which do not read them
+            let unread: Vec<PathBuf> = state
+                .slots
+                .iter()
+                .filter(|(path, slot)| {
+                    matches!(slot, Slot::Ready { .. }) && !state.readers.contains_key(*path)
+                })
+                .map(|(path, _)| path.clone())
+                .collect();
+            for path in unread {
+                if let Some(Slot::Ready { image, .. }) = state.slots.remove(&path) {
+                    state.bytes -= size(&image);
+                }
+            }
+        }
+    }
+
+    /// The image at the given path, decoded with `load` unless cached, or
+    /// being decoded for another job.
+    pub fn get(
+        &self,
+        path: &Path,
+        load: impl FnOnce() -> ImageResult<DynamicImage>,
+    ) -> ImageResult<Arc<DynamicImage>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            state.tick += 1;
+            let tick = state.tick;
+            match state.slots.get_mut(path) {
+                Some(Slot::Loading) => state = self.loaded.wait(state).unwrap(),
+                Some(Slot::Ready { image, last_read }) => {
+                    let image = Arc::clone(image);
+                    *last_read = tick;
+                    if !state.read(path) {
+                        state.slots.remove(path);
+                        state.bytes -= size(&image);
+                    }
+                    state.shared += 1;
+                    return Ok(image);
+                }
+                None => break,
+            }
+        }
+        state.slots.insert(path.to_path_buf(), Slot::Loading);
+        drop(state);
+
+        let loading = Loading { cache: self, path };
+        let result = load().map(Arc::new);
+        std::mem::forget(loading);
+        let mut state = self.state.lock().unwrap();
+        state.slots.remove(path);
+        let keep = state.read(path);
+        if let Ok(image) = &result {
+            state.decoded += 1;
+            let size = size(image);
+            if keep && size <= self.max_bytes {
+                self.evict(&mut state, self.max_bytes - size);
+                state.bytes += size;
+                let last_read = state.tick;
+                state.slots.insert(
+                    path.to_path_buf(),
+                    Slot::Ready {
+                        image: Arc::clone(image),
+                        last_read,
+                    },
+                );
+            }
+        }
+        self.loaded.notify_all();
+        result
+    }
+
+    fn evict(&self, state: &mut CacheState, max_bytes: u64) {
+        while state.bytes > max_bytes {
+            let oldest = state
+                .slots
+                .iter()
+                .filter_map(|(path, slot)| match slot {
+                    Slot::Ready { last_read, .. } => Some((*last_read, path)),
+                    Slot::Loading => None,
+                })
+                .min()
+                .map(|(_, path)| path.clone());
+            match oldest.and_then(|path| state.slots.remove(&path)) {
+                Some(Slot::Ready { image, .. }) => state.bytes -= size(&image),
+                _ => break,
+            }
+        }
+    }
+
+    /// The number of images decoded, and of reads of images decoded for
+    /// another job.
+    pub fn stats(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.decoded, state.shared)
+    }
+}
+
+fn size(image: &DynamicImage) -> u64 {
+    image.as_bytes().len() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u32) -> ImageResult<DynamicImage> {
+        Ok(DynamicImage::new_rgb8(width, 1))
+    }
+
+    #[test]
+    fn cache() {
+        let cache = ImageCache::new(2, 30);
+        let a = Path::new("a.png");
+        let b = Path::new("b.png");
+        Batch::new(2, &cache).will_read([a.to_path_buf(), b.to_path_buf()]);
+        Batch::new(2, &cache).will_read([a.to_path_buf()]);
+        cache.get(a, || image(5)).unwrap();
+        // cached for the other job:
+        cache.get(a, || panic!("decoded again")).unwrap();
+        // read by both jobs, so released:
+        assert_eq!(cache.get(a, || image(5)).unwrap().width(), 5);
+        // read by one job only, not kept:
+        cache.get(b, || image(5)).unwrap();
+        assert_eq!(cache.state.lock().unwrap().bytes, 0);
+        assert_eq!(cache.stats(), (3, 1));
+
+        // over 30 bytes, a (read longest ago) is evicted:
+        let cache = ImageCache::new(2, 30);
+        let paths = ["a.png", "b.png", "c.png"].map(PathBuf::from);
+        Batch::new(2, &cache).will_read(paths.clone());
+        Batch::new(2, &cache).will_read(paths.clone());
+        for path in &paths {
+            cache.get(path, || image(5)).unwrap();
+        }
+        assert_eq!(cache.state.lock().unwrap().bytes, 30);
+        cache.get(b, || panic!("decoded again")).unwrap();
+        cache.get(a, || image(5)).unwrap();
+        assert_eq!(cache.stats(), (4, 1));
+    }
+
+    #[test]
+    fn cache_unsettled() {
+        // kept until the other job tells what it reads:
+        let cache = ImageCache::new(2, 30);
+        let a = Path::new("a.png");
+        let job = Batch::new(2, &cache);
+        Batch::new(2, &cache).will_read([a.to_path_buf()]);
+        cache.get(a, || image(5)).unwrap();
+        assert_eq!(cache.state.lock().unwrap().bytes, 15);
+        // ended without reading it:
+        drop(job);
+        assert_eq!(cache.state.lock().unwrap().bytes, 0);
+    }
+
+    #[test]
+    fn cache_load_panics() {
+        let cache = ImageCache::new(2, 30);
+        let a = Path::new("a.png");
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cache.get(a, || panic!("cannot decode"))
+        }));
+        assert!(result.is_err());
+        // not left loading, for the waiting jobs:
+        assert!(cache.state.lock().unwrap().slots.is_empty());
+        assert!(cache.get(a, || image(5)).is_ok());
+    }
+
+    #[test]
+    fn jobs() {
+        let out = std::env::temp_dir().join(format!("blaise-batch-{}", std::process::id()));
+        let jobs = (1..=2)
+            .map(|i| JobSpec {
+                name: None,
+                args: ["-p", "data", "--npb", "-o"]
+                    .into_iter()
+                    .map(String::from)
+                    .chain([out.join(i.to_string()).to_string_lossy().into_owned()])
+                    .collect(),
+            })
+            .collect();
+        let images = ImageCache::new(2, 1 << 30);
+        let reports = run_jobs(jobs, &images);
+        assert_eq!(reports.len(), 2);
+        for (i, report) in reports.iter().enumerate() {
+            assert_eq!(report["name"], (i + 1).to_string());
+            assert_eq!(report["exit_code"], 0);
+            assert_eq!(report["run"]["crops"], 1);
+        }
+        // the one image, decoded once:
+        assert_eq!(images.stats(), (1, 1));
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+}
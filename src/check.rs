@@ -0,0 +1,288 @@
+//! Checking of annotation files on their own, without the images: parsing with
+//! the same code as the command, with the counts by label and the boxes that
+//! would be skipped (or rejected) per `--bad-boxes`.
+//!
+//! This is for an in-browser checker, with the crate built for wasm32 without
+//! the `pipeline` feature. The page passes the files as a JSON request (see
+//! [Request]) in memory from [blaise_alloc] to [blaise_check], and gets the
+//! [Report] as JSON, released with [blaise_check_free].
+//!
+//! The format of each file is told by its extension and contents: `.xml`
+//! Pascal VOC, `.txt` YOLO (MOT if comma separated), `.json` COCO,
+//! Supervisely or VIA, and `.csv` VIA. YOLO boxes are in image fractions,
+//! the image sizes being unknown.
+
+use crate::annotation::Annotation;
+#[cfg(feature = "coco")]
+use crate::coco;
+#[cfg(feature = "mot")]
+use crate::mot;
+use crate::source::Strictness;
+#[cfg(feature = "supervisely")]
+use crate::supervisely;
+#[cfg(feature = "via")]
+use crate::via;
+use crate::{pascal, yolo};
+use imagesize::ImageSize;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::{c_char, CString};
+use std::path::Path;
+
+#[derive(Debug, Deserialize)]
+pub struct Request {
+    pub files: Vec<File>,
+    /// Class names, for the YOLO and MOT class ids.
+    #[serde(default)]
+    pub names: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct File {
+    pub name: String,
+    pub contents: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Report {
+    pub files: usize,
+    pub invalid: Vec<Invalid>,
+    pub images: usize,
+    pub objects: usize,
+    pub labels: BTreeMap<String, usize>,
+    pub bad_boxes: Vec<BadBox>,
+    /// YOLO objects with coordinates outside the image.
+    pub out_of_range: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Invalid {
+    pub file: String,
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BadBox {
+    pub file: String,
+    pub label: String,
+    pub defect: String,
+}
+
+pub fn check(request: &Request) -> Report {
+    let mut report = Report::default();
+    for file in &request.files {
+        report.files += 1;
+        match parse(file, &request.names, &mut report.out_of_range) {
+            Ok(annotations) => {
+                for annotation in annotations {
+                    report.add(&file.name, annotation);
+                }
+            }
+            Err(error) => report.invalid.push(Invalid {
+                file: file.name.clone(),
+                error,
+            }),
+        }
+    }
+    report
+}
+
+impl Report {
+    fn add(&mut self, file: &str, annotation: Annotation) {
+        self.images += 1;
+        for object in annotation.objects.into_iter().flatten() {
+            self.objects += 1;
+            if let Some(defect) = object.bndbox.defect() {
+                self.bad_boxes.push(BadBox {
+                    file: file.to_string(),
+                    label: object.name.clone(),
+                    defect: format!("{:?}", defect),
+                });
+            }
+            *self.labels.entry(object.name).or_insert(0) += 1;
+        }
+    }
+}
+
+fn class_name(names: &[String], index: usize, class_id: u32) -> String {
+    names
+        .get(index)
+        .cloned()
+        .unwrap_or_else(|| format!("class_{}", class_id))
+}
+
+fn parse(
+    file: &File,
+    names: &[String],
+    out_of_range: &mut usize,
+) -> Result<Vec<Annotation>, String> {
+    let src = file.contents.as_str();
+    let path = Path::new(&file.name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "xml" => pascal::parse_xml_with(src, |issue| Strictness::Normal.accepts(&issue))
+            .map(|pascal_voc| vec![pascal_voc.into()]),
+        #[cfg(feature = "mot")]
+        "txt" if src.contains(',') => {
+            // MOT class ids are 1-based:
+            let class_id_to_name = |id: u32| class_name(names, (id as usize).wrapping_sub(1), id);
+            let records = mot::parse_mot(class_id_to_name, src).map_err(|e| e.to_string())?;
+            Ok(mot::to_annotations(
+                "",
+                |frame| format!("{:06}", frame),
+                records,
+            ))
+        }
+        "txt" => {
+            let class_id_to_name = |id: u32| Some(class_name(names, id as usize, id));
+            let unit = ImageSize {
+                width: 1,
+                height: 1,
+            };
+            let mut yolo = yolo::parse_yolo("", &stem, &unit, class_id_to_name, src)
+                .map_err(|e| e.to_string())?;
+            *out_of_range += yolo
+                .apply_coord_policy(yolo::CoordPolicy::Clamp)
+                .map_err(|e| e.to_string())?;
+            Ok(vec![yolo.into()])
+        }
+        #[cfg(feature = "coco")]
+        "json" if coco::is_coco(src) => coco::parse_coco("", src).map_err(|e| e.to_string()),
+        #[cfg(feature = "supervisely")]
+        "json"
+            if serde_json::from_str::<serde_json::Value>(src)
+                .is_ok_and(|v| v.get("objects").is_some()) =>
+        {
+            supervisely::parse_ann("", &stem, src)
+                .map(|(annotation, _)| vec![annotation])
+                .map_err(|e| e.to_string())
+        }
+        #[cfg(feature = "via")]
+        "json" => via::parse_via_json("", src, None)
+            .map(|(annotations, _)| annotations)
+            .map_err(|e| e.to_string()),
+        #[cfg(feature = "via")]
+        "csv" => via::parse_via_csv("", src, None)
+            .map(|(annotations, _)| annotations)
+            .map_err(|e| e.to_string()),
+        _ => Err(format!("unsupported file: {}", file.name)),
+    }
+}
+
+/// Checks the request JSON, returning the report JSON.
+pub fn check_json(request: &str) -> String {
+    match serde_json::from_str::<Request>(request) {
+        Ok(request) => serde_json::to_string(&check(&request)).unwrap(),
+        Err(e) => serde_json::json!({"error": format!("invalid request: {}", e)}).to_string(),
+    }
+}
+
+/// Allocates a buffer of `len` bytes for the request.
+#[no_mangle]
+pub extern "C" fn blaise_alloc(len: usize) -> *mut u8 {
+    let mut buffer = Vec::<u8>::with_capacity(len);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr
+}
+
+/// Releases a buffer from [blaise_alloc].
+///
+/// # Safety
+///
+/// `ptr` and `len` must be those of a buffer from [blaise_alloc].
+#[no_mangle]
+pub unsafe extern "C" fn blaise_dealloc(ptr: *mut u8, len: usize) {
+    drop(Vec::from_raw_parts(ptr, 0, len));
+}
+
+/// Checks the request, the UTF-8 JSON of `len` bytes at `ptr`, returning the
+/// report JSON as a NUL-terminated string, to be released with [blaise_check_free].
+///
+/// # Safety
+///
+/// `ptr` must point to `len` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn blaise_check(ptr: *const u8, len: usize) -> *mut c_char {
+    let request = std::slice::from_raw_parts(ptr, len);
+    let report = check_json(&String::from_utf8_lossy(request));
+    // the report has no NULs, being JSON:
+    CString::new(report).unwrap().into_raw()
+}
+
+/// Releases a report returned by [blaise_check].
+///
+/// # Safety
+///
+/// `report` must come from [blaise_check] and not be released already.
+#[no_mangle]
+pub unsafe extern "C" fn blaise_check_free(report: *mut c_char) {
+    if !report.is_null() {
+        drop(CString::from_raw(report));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    #[test]
+    fn files() {
+        let voc = std::fs::read_to_string("data/annotations/IMG_TEST.xml").unwrap();
+        let request = json!({
+            "files": [
+                {"name": "IMG_TEST.xml", "contents": voc},
+                {"name": "a.txt", "contents": "0 0.5 0.5 0.2 0.2\n1 0.5 0.5 0 0.1\n1 1.0 0.5 0.1 0.1\n"},
+                {"name": "b.xml", "contents": "<annotation>"},
+                {"name": "c.png", "contents": ""},
+            ],
+            "names": ["Aegina"],
+        });
+        let report: Value = serde_json::from_str(&check_json(&request.to_string())).unwrap();
+        assert_eq!(report["files"], 4);
+        assert_eq!(report["images"], 2);
+        assert_eq!(report["objects"], 4);
+        assert_eq!(report["labels"]["Aegina"], 1);
+        assert_eq!(report["labels"]["class_1"], 2);
+        assert_eq!(report["out_of_range"], 1);
+        assert_eq!(
+            report["bad_boxes"],
+            json!([{"file": "a.txt", "label": "class_1", "defect": "ZeroArea"}])
+        );
+        let invalid: Vec<&str> = report["invalid"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|i| i["file"].as_str().unwrap())
+            .collect();
+        assert_eq!(invalid, ["b.xml", "c.png"]);
+
+        let report: Value = serde_json::from_str(&check_json("{}")).unwrap();
+        assert!(report["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid request"));
+    }
+
+    #[test]
+    fn through_memory() {
+        let request = br#"{"files": [{"name": "a.txt", "contents": "0 0.5 0.5 0.2 0.2"}]}"#;
+        unsafe {
+            let ptr = blaise_alloc(request.len());
+            std::ptr::copy_nonoverlapping(request.as_ptr(), ptr, request.len());
+            let report = blaise_check(ptr, request.len());
+            blaise_dealloc(ptr, request.len());
+            let report = std::ffi::CStr::from_ptr(report.cast_const());
+            assert!(report
+                .to_str()
+                .unwrap()
+                .contains(r#""labels":{"class_0":1}"#));
+            blaise_check_free(report.as_ptr().cast_mut());
+        }
+    }
+}
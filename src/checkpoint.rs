@@ -0,0 +1,146 @@
+//! Periodic checkpoint of the running counts (`checkpoint.json` in the output
+//! directory), so a crashed run still leaves a usable partial report.
+
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Name of the checkpoint file in the output directory.
+pub const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+#[derive(Default, Clone)]
+struct ThreadCounts {
+    processed: usize,
+    failed: usize,
+    by_label: HashMap<String, usize>,
+}
+
+struct State {
+    threads: Vec<ThreadCounts>,
+    since_written: usize,
+    written: Instant,
+}
+
+#[derive(Serialize)]
+struct Summary {
+    annotations: usize,
+    processed: usize,
+    failed: usize,
+    crops: usize,
+    crops_by_label: BTreeMap<String, usize>,
+}
+
+pub struct Checkpoint {
+    path: PathBuf,
+    annotations: usize,
+    every: Option<usize>,
+    interval: Option<Duration>,
+    state: Mutex<State>,
+}
+
+impl Checkpoint {
+    /// Checkpoint written every `every` annotations and/or `interval`.
+    pub fn new(
+        output_dir: &Path,
+        threads: usize,
+        annotations: usize,
+        every: Option<usize>,
+        interval: Option<Duration>,
+    ) -> Self {
+        Self {
+            path: output_dir.join(CHECKPOINT_FILE),
+            annotations,
+            every,
+            interval,
+            state: Mutex::new(State {
+                threads: vec![Default::default(); threads],
+                since_written: 0,
+                written: Instant::now(),
+            }),
+        }
+    }
+
+    /// Updates the counts of a processing thread after an annotation,
+    /// writing the checkpoint if due. Returns whether it was written.
+    pub fn update(
+        &self,
+        th: usize,
+        processed: usize,
+        failed: usize,
+        by_label: &HashMap<String, usize>,
+    ) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let counts = &mut state.threads[th];
+        counts.processed = processed;
+        counts.failed = failed;
+        counts.by_label.clone_from(by_label);
+        state.since_written += 1;
+        let due = self.every.is_some_and(|n| state.since_written >= n)
+            || self.interval.is_some_and(|t| state.written.elapsed() >= t);
+        if !due {
+            return false;
+        }
+        state.since_written = 0;
+        state.written = Instant::now();
+        if let Err(e) = self.write(&state) {
+            eprintln!("WARN: cannot write checkpoint {:?}: {}", self.path, e);
+        }
+        true
+    }
+
+    fn write(&self, state: &State) -> std::io::Result<()> {
+        let mut crops_by_label = BTreeMap::new();
+        for counts in &state.threads {
+            for (label, count) in &counts.by_label {
+                *crops_by_label.entry(label.clone()).or_insert(0) += count;
+            }
+        }
+        let summary = Summary {
+            annotations: self.annotations,
+            processed: state.threads.iter().map(|c| c.processed).sum(),
+            failed: state.threads.iter().map(|c| c.failed).sum(),
+            crops: crops_by_label.values().sum(),
+            crops_by_label,
+        };
+        std::fs::create_dir_all(self.path.parent().unwrap())?;
+        // through a temporary file so a crash never leaves a truncated checkpoint:
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_string_pretty(&summary)? + "\n")?;
+        std::fs::rename(&tmp_path, &self.path)
+    }
+
+    /// Removes the checkpoint once the run is complete.
+    pub fn remove(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn every() {
+        let dir = std::env::temp_dir().join(format!("blaise-checkpoint-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let checkpoint = Checkpoint::new(&dir, 2, 10, Some(3), None);
+        let by_label = HashMap::from([("Aegina".to_string(), 2)]);
+        assert!(!checkpoint.update(0, 1, 0, &by_label));
+        assert!(!checkpoint.update(1, 1, 1, &HashMap::new()));
+        assert!(checkpoint.update(0, 2, 0, &by_label));
+
+        let path = dir.join(CHECKPOINT_FILE);
+        let summary: Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(summary["processed"], 3);
+        assert_eq!(summary["failed"], 1);
+        assert_eq!(summary["crops_by_label"]["Aegina"], 2);
+
+        checkpoint.remove();
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
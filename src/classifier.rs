@@ -0,0 +1,264 @@
+//! Classification of crops to route doubtful ones for review.
+
+use image::DynamicImage;
+use std::path::Path;
+
+/// Name of the subdirectory (under the output directory) for crops to be reviewed.
+pub const REVIEW_DIR: &str = "_review";
+
+/// Classifier of crops.
+pub trait CropClassifier: Send + Sync {
+    /// Returns the predicted label and its confidence (in [0, 1]).
+    fn classify(&self, img: &DynamicImage) -> Result<(String, f32), String>;
+}
+
+/// Whether a crop with the given annotated label should be routed for review,
+/// that is, if the classifier disagrees with the label or has low confidence.
+pub fn needs_review(label: &str, predicted: &str, confidence: f32, threshold: f32) -> bool {
+    predicted != label || confidence < threshold
+}
+
+/// Loads the ONNX classification model at the given path, with `labels` the
+/// names of its classes, in the order of its scores.
+#[cfg(feature = "onnx")]
+pub fn load_onnx_model(path: &Path, labels: Vec<String>) -> Result<Box<dyn CropClassifier>, String> {
+    match onnx::OnnxClassifier::load(path, labels) {
+        Ok(classifier) => Ok(Box::new(classifier)),
+        Err(e) => Err(format!("cannot load {:?}: {}", path, e)),
+    }
+}
+
+#[cfg(not(feature = "onnx"))]
+pub fn load_onnx_model(path: &Path, _labels: Vec<String>) -> Result<Box<dyn CropClassifier>, String> {
+    Err(format!(
+        "cannot load {:?}: this build of blaise does not include the `onnx` feature",
+        path
+    ))
+}
+
+/// Classifier running an ONNX model with tract.
+///
+/// The model takes one image, `[1, 3, height, width]` (RGB, normalized with the
+/// ImageNet mean and standard deviation, as the torchvision models), and gives
+/// a score per class, probabilities or logits (then applying softmax).
+#[cfg(feature = "onnx")]
+mod onnx {
+    use super::CropClassifier;
+    use image::imageops::FilterType;
+    use image::DynamicImage;
+    use std::path::Path;
+    use tract_onnx::pb;
+    use tract_onnx::pb::tensor_shape_proto::dimension::Value;
+    use tract_onnx::prelude::*;
+
+    const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+    const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+    /// Input size when not fixed in the model.
+    const DEFAULT_SIZE: usize = 224;
+
+    pub struct OnnxClassifier {
+        model: TypedRunnableModel<TypedModel>,
+        width: usize,
+        height: usize,
+        labels: Vec<String>,
+    }
+
+    impl OnnxClassifier {
+        pub fn load(path: &Path, labels: Vec<String>) -> TractResult<OnnxClassifier> {
+            OnnxClassifier::new(&tract_onnx::onnx().proto_model_for_path(path)?, labels)
+        }
+
+        pub fn new(proto: &pb::ModelProto, labels: Vec<String>) -> TractResult<OnnxClassifier> {
+            let (height, width) = input_size(proto);
+            let model = tract_onnx::onnx()
+                .model_for_proto_model(proto)?
+                .with_input_fact(0, f32::fact([1, 3, height, width]).into())?
+                .into_optimized()?
+                .into_runnable()?;
+            Ok(OnnxClassifier {
+                model,
+                width,
+                height,
+                labels,
+            })
+        }
+
+        fn scores(&self, img: &DynamicImage) -> TractResult<Vec<f32>> {
+            let (w, h) = (self.width as u32, self.height as u32);
+            let rgb = img.resize_exact(w, h, FilterType::Triangle).into_rgb8();
+            let input = tract_ndarray::Array4::from_shape_fn(
+                (1, 3, self.height, self.width),
+                |(_, c, y, x)| {
+                    let v = rgb.get_pixel(x as u32, y as u32)[c] as f32 / 255.0;
+                    (v - MEAN[c]) / STD[c]
+                },
+            );
+            let outputs = self.model.run(tvec!(input.into_tensor().into()))?;
+            Ok(outputs[0].to_array_view::<f32>()?.iter().copied().collect())
+        }
+    }
+
+    impl CropClassifier for OnnxClassifier {
+        fn classify(&self, img: &DynamicImage) -> Result<(String, f32), String> {
+            let scores = self.scores(img).map_err(|e| e.to_string())?;
+            if scores.len() != self.labels.len() {
+                return Err(format!(
+                    "the model gives {} scores for {} labels",
+                    scores.len(),
+                    self.labels.len()
+                ));
+            }
+            let probabilities = probabilities(scores);
+            let (index, confidence) = probabilities
+                .iter()
+                .copied()
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .ok_or("the model gives no scores")?;
+            Ok((self.labels[index].clone(), confidence))
+        }
+    }
+
+    /// The (height, width) of the input image of the model.
+    fn input_size(proto: &pb::ModelProto) -> (usize, usize) {
+        let dims = proto
+            .graph
+            .as_ref()
+            .and_then(|graph| {
+                // (initializers may also be listed as inputs)
+                let initializers: Vec<&str> =
+                    graph.initializer.iter().map(|t| t.name.as_str()).collect();
+                graph
+                    .input
+                    .iter()
+                    .find(|input| !initializers.contains(&input.name.as_str()))
+            })
+            .and_then(|input| match &input.r#type.as_ref()?.value {
+                Some(pb::type_proto::Value::TensorType(tensor)) => tensor.shape.clone(),
+                _ => None,
+            })
+            .map(|shape| {
+                (shape.dim.iter())
+                    .map(|dim| match dim.value {
+                        Some(Value::DimValue(n)) if n > 0 => Some(n as usize),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        let size = |i: usize| dims.get(i).copied().flatten().unwrap_or(DEFAULT_SIZE);
+        (size(2), size(3))
+    }
+
+    /// The scores as probabilities: as given if they already are, else by softmax.
+    fn probabilities(scores: Vec<f32>) -> Vec<f32> {
+        let sum: f32 = scores.iter().sum();
+        if scores.iter().all(|s| (0.0..=1.0).contains(s)) && (sum - 1.0).abs() < 1e-3 {
+            return scores;
+        }
+        let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+        let sum: f32 = exps.iter().sum();
+        exps.into_iter().map(|e| e / sum).collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use image::{Rgb, RgbImage};
+
+        fn value_info(name: &str, dims: &[i64]) -> pb::ValueInfoProto {
+            pb::ValueInfoProto {
+                name: name.into(),
+                r#type: Some(pb::TypeProto {
+                    value: Some(pb::type_proto::Value::TensorType(pb::type_proto::Tensor {
+                        elem_type: pb::tensor_proto::DataType::Float as i32,
+                        shape: Some(pb::TensorShapeProto {
+                            dim: (dims.iter())
+                                .map(|&n| pb::tensor_shape_proto::Dimension {
+                                    value: Some(Value::DimValue(n)),
+                                    ..Default::default()
+                                })
+                                .collect(),
+                        }),
+                    })),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        }
+
+        fn node(op_type: &str, input: &str, output: &str) -> pb::NodeProto {
+            pb::NodeProto {
+                input: vec![input.into()],
+                output: vec![output.into()],
+                name: op_type.into(),
+                op_type: op_type.into(),
+                ..Default::default()
+            }
+        }
+
+        /// A "model" scoring each class by the mean of the corresponding channel.
+        fn mean_color_model() -> pb::ModelProto {
+            pb::ModelProto {
+                ir_version: 7,
+                opset_import: vec![pb::OperatorSetIdProto {
+                    domain: "".into(),
+                    version: 13,
+                }],
+                graph: Some(pb::GraphProto {
+                    node: vec![
+                        node("GlobalAveragePool", "input", "pooled"),
+                        node("Flatten", "pooled", "scores"),
+                    ],
+                    name: "mean_color".into(),
+                    input: vec![value_info("input", &[1, 3, 8, 8])],
+                    output: vec![value_info("scores", &[1, 3])],
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn classify() {
+            let labels = vec!["red".into(), "green".into(), "blue".into()];
+            let classifier = OnnxClassifier::new(&mean_color_model(), labels).unwrap();
+            assert_eq!((classifier.height, classifier.width), (8, 8));
+            let img = DynamicImage::ImageRgb8(RgbImage::from_pixel(20, 10, Rgb([255, 0, 0])));
+            let (label, confidence) = classifier.classify(&img).unwrap();
+            assert_eq!(label, "red");
+            assert!(confidence > 0.9, "{}", confidence);
+        }
+
+        #[test]
+        fn labels_mismatch() {
+            let labels = vec!["red".into(), "green".into()];
+            let classifier = OnnxClassifier::new(&mean_color_model(), labels).unwrap();
+            let img = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+            let e = classifier.classify(&img).unwrap_err();
+            assert!(e.contains("3 scores for 2 labels"), "{}", e);
+        }
+
+        #[test]
+        fn softmax() {
+            assert_eq!(probabilities(vec![0.25, 0.75]), vec![0.25, 0.75]);
+            let p = probabilities(vec![1.0, 1.0, 3.0]);
+            assert!((p.iter().sum::<f32>() - 1.0).abs() < 1e-6);
+            assert!(p[2] > p[0] && p[0] == p[1]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn review() {
+        assert!(!needs_review("FOO", "FOO", 0.9, 0.5));
+        assert!(needs_review("FOO", "FOO", 0.4, 0.5));
+        assert!(needs_review("FOO", "BAR", 0.9, 0.5));
+    }
+}
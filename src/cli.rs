@@ -0,0 +1,3804 @@
+use clap::Parser;
+use indicatif::{HumanDuration, MultiProgress, ProgressBar, ProgressStyle};
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use walkdir::WalkDir;
+
+use crate::annotation::{
+    Annotation, Bndbox, BndboxItemReporter, LabelNormalize, LabelSanitize, Object, ObjectIds, SizeBucket,
+};
+use crate::batch::{Batch, ImageCache};
+use crate::checkpoint::Checkpoint;
+use crate::checksum::{ChecksumAlgorithm, Verification};
+use crate::classifier::CropClassifier;
+#[cfg(feature = "coco")]
+use crate::coco;
+use crate::cooccurrence::Cooccurrence;
+use crate::dedup::HashKind;
+use crate::detect::{Detected, InputFormat};
+use crate::estimate::{self, Estimate};
+#[cfg(feature = "geotiff")]
+use crate::geo;
+use crate::geo::GeoTransform;
+use crate::geometry::{BoxDefect, BoxPolicy, Compat, PixelRect, Quantization, Rounding};
+use crate::icc::IccPolicy;
+use crate::image::{
+    convert_bit_depth, crop_image, load_frames, load_image, resize_image, save_image, AlphaPolicy,
+    BitDepth, FrameSelection,
+};
+use crate::imagefolder::ClassIndex;
+use crate::kb::{Concept, Kb, KbReport};
+use crate::manifest::{self, CropKind, Manifest, ManifestEntry, ManifestOrder, PriorCrop, PriorCrops};
+use crate::metadata::{Metadata, MetadataColumn};
+use crate::metrics::Metrics;
+use crate::monitor::Monitor;
+#[cfg(feature = "mot")]
+use crate::mot;
+use crate::overlap::Overlaps;
+use crate::montage::MontageOrder;
+use crate::patches::{PatchStrategy, Patching};
+use crate::windows::WindowLabel;
+use crate::quality::QualityFilter;
+use crate::remote::Remote;
+use crate::roi::Exclusion;
+use crate::scan::ScanProgress;
+use crate::sink::{Crop, SinkSpec, Sinks};
+use crate::source::{AnnotationSource, Strictness};
+use crate::scale::{self, PixelSize};
+use crate::scrub::Scrubber;
+use crate::split::{Split, SplitBy, SplitSpec};
+use crate::storage::{Storage, SyncPolicy};
+#[cfg(feature = "supervisely")]
+use crate::supervisely;
+use crate::timestamp::{TimePattern, TimeRange, Timestamp};
+#[cfg(feature = "underwater")]
+use crate::underwater;
+#[cfg(feature = "via")]
+use crate::via;
+use crate::{
+    aliases, annotation, batch, checksum, classifier, coverage, daemon, dedup, detect, download, ffi, metrics,
+    overlap, overlay, pascal, patches, proposals, quality, roi, rules, run, sink, source, split, yolo,
+};
+use ::image::ImageFormat;
+
+mod crop;
+mod outputs;
+mod validate;
+
+use crop::process_annotation;
+use outputs::Outputs;
+
+fn cli_styles() -> clap::builder::Styles {
+    use anstyle::{
+        AnsiColor::{self, *},
+        Color, Style,
+    };
+    fn style(color: AnsiColor) -> Style {
+        Style::new().bold().fg_color(Some(Color::Ansi(color)))
+    }
+    clap::builder::Styles::styled()
+        .usage(style(Yellow).underline())
+        .header(style(Yellow).underline())
+        .literal(style(Green))
+        .placeholder(style(Blue))
+}
+
+#[derive(clap::Parser, Debug)]
+#[clap(version, about = "Creates image crops for given annotations", long_about = None)]
+#[command(styles=cli_styles(), subcommand_negates_reqs = true)]
+// one of the annotation formats (or --input), and/or --proposals:
+#[command(group(clap::ArgGroup::new("annotations").multiple(false)))]
+#[command(group(clap::ArgGroup::new("source").multiple(true).required(true)))]
+#[group(skip)]
+struct Opts {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Directory with annotations in any supported format, detected automatically
+    #[clap(long, value_name = "dir", groups = ["annotations", "source"])]
+    input: Option<PathBuf>,
+
+    /// Use the given format with --input instead of the first one detected
+    #[clap(long, value_name = "format", requires = "input")]
+    input_format: Option<InputFormat>,
+
+    /// Base directory to scan for pascal voc annotations
+    #[clap(short, long, value_name = "dir", groups = ["annotations", "source"])]
+    pascal: Option<PathBuf>,
+
+    /// Use yolo annotations
+    #[clap(short, long, value_names = &["image-dir", "label-dir", "names-file"], number_of_values = 3, groups = ["annotations", "source"])]
+    yolo: Option<Vec<PathBuf>>,
+
+    /// Comma separated list of YOLO class ids (as in the label files) to crop
+    #[clap(long, value_name = "ids", use_value_delimiter = true)]
+    select_classes: Option<Vec<u32>>,
+
+    /// YOLO class remap file, with `<old_id> <new_id|name>` lines,
+    /// applied while parsing the label files
+    #[clap(long, value_name = "file")]
+    class_remap: Option<PathBuf>,
+
+    /// What to do with YOLO objects having coordinates outside the image;
+    /// with `error` the label file is counted as invalid
+    #[clap(long, value_name = "policy", default_value = "clamp")]
+    yolo_coord_policy: yolo::CoordPolicy,
+
+    /// Rounding of the box coordinates to pixels at crop time
+    #[clap(long, value_name = "mode", default_value = "round")]
+    round: Rounding,
+
+    /// Quantize the boxes as done by the given tool, for bit-exact parity
+    #[clap(long, value_name = "tool")]
+    compat: Option<Compat>,
+
+    /// What to do with inverted (max below min) and zero-area boxes
+    #[clap(long, value_name = "policy", default_value = "skip")]
+    bad_boxes: BoxPolicy,
+
+    /// What to do with images without a YOLO label file
+    #[clap(long, value_name = "policy", default_value = "empty")]
+    missing_labels: yolo::MissingLabels,
+
+    /// Take the annotation files with any issue (eg., an unknown image size, or
+    /// out-of-range YOLO coordinates) as invalid, and fail the run if any is
+    #[clap(long, conflicts_with = "lenient")]
+    strict: bool,
+
+    /// Recover from malformed annotation contents where possible (eg., skipping
+    /// a malformed YOLO line instead of the file), with warnings
+    #[clap(long)]
+    lenient: bool,
+
+    /// Use MOTChallenge-style tracking annotations
+    #[cfg(feature = "mot")]
+    #[clap(long, value_names = &["image-dir", "gt-file"], number_of_values = 2, groups = ["annotations", "source"])]
+    mot: Option<Vec<PathBuf>>,
+
+    /// Use COCO annotations (image file names relative to --image-dir, by default the file's directory)
+    #[cfg(feature = "coco")]
+    #[clap(long, value_name = "json-file", groups = ["annotations", "source"])]
+    coco: Option<PathBuf>,
+
+    /// Use a Supervisely project export (meta.json and <dataset>/ann/*.json with rectangle objects)
+    #[cfg(feature = "supervisely")]
+    #[clap(long, value_name = "project-dir", groups = ["annotations", "source"])]
+    supervisely: Option<PathBuf>,
+
+    /// Use a VGG Image Annotator (VIA) project or annotations file (.json or .csv) with rect regions
+    #[cfg(feature = "via")]
+    #[clap(long, value_name = "file", groups = ["annotations", "source"])]
+    via: Option<PathBuf>,
+
+    /// Region attribute with the label for the VIA regions (by default, the only attribute of each region)
+    #[cfg(feature = "via")]
+    #[clap(long, value_name = "key", requires = "via")]
+    via_label_key: Option<String>,
+
+    /// Class names for the MOT annotations (one per line, for class ids 1, 2, ...)
+    #[cfg(feature = "mot")]
+    #[clap(long, value_name = "names-file", requires = "mot")]
+    mot_names: Option<PathBuf>,
+
+    /// Image base directory
+    #[clap(short, long, value_name = "dir")]
+    image_dir: Option<PathBuf>,
+
+    /// Crop the region proposals (class-agnostic boxes) in the CSV file instead of the
+    /// annotated objects; they take the label of the annotated object they overlap most
+    #[clap(long, value_name = "csv-file", group = "source")]
+    proposals: Option<PathBuf>,
+
+    /// Label of the proposals, or with annotations, of those overlapping no annotated object
+    #[clap(long, value_name = "name", requires = "proposals")]
+    proposal_label: Option<String>,
+
+    /// Minimum IoU of a proposal with an annotated object to take its label
+    #[clap(long, value_name = "iou", default_value = "0.5")]
+    proposal_min_iou: f64,
+
+    /// Shift all boxes by the given offset in pixels (applied after any --scale-boxes)
+    #[clap(long, value_name = "dx,dy", value_parser = parse_offset, allow_hyphen_values = true)]
+    global_offset: Option<(i64, i64)>,
+
+    /// Scale all box coordinates by the given factor
+    #[clap(long, value_name = "factor")]
+    scale_boxes: Option<f64>,
+
+    /// Exclude the given image region (eg., a burned-in overlay): boxes mostly
+    /// inside it are skipped, and crop padding does not extend into it. Can be repeated
+    #[clap(long, value_name = "x,y,w,h", value_parser = roi::parse_region)]
+    exclude_region: Vec<PixelRect>,
+
+    /// Mask image whose dark pixels (below 128) are excluded as with --exclude-region
+    #[clap(long, value_name = "image")]
+    roi_mask: Option<PathBuf>,
+
+    /// Recompute the boxes from the instance masks (COCO RLE segmentations,
+    /// YOLO polygons), where given
+    #[clap(long)]
+    tight_bbox: bool,
+
+    /// Make the pixels outside the instance mask, where given, transparent in the object crops
+    #[clap(long)]
+    mask_crops: bool,
+
+    /// Skip the objects whose box aspect ratio (longer over shorter side)
+    /// exceeds the given value, eg., slivers from annotation mistakes
+    #[clap(long, alias = "max-ar", value_name = "r")]
+    max_aspect_ratio: Option<f64>,
+
+    /// Correct the color cast of the crops: gray-world white balance, or dark channel prior
+    #[cfg(feature = "underwater")]
+    #[clap(long, value_name = "method")]
+    underwater_correct: Option<underwater::ColorCorrection>,
+
+    /// Instead of the box extent, crop a window of this size centered on the
+    /// box (shifted inward at the image borders)
+    #[clap(long, value_name = "WxH", value_parser = parse_size)]
+    fixed_crop: Option<(u32, u32)>,
+
+    /// Expand boxes smaller than this size to at least this size, centered on
+    /// the box (shifted inward at the image borders)
+    #[clap(long, value_name = "WxH", value_parser = parse_size, conflicts_with = "fixed_crop")]
+    min_crop: Option<(u32, u32)>,
+
+    /// Resize the resulting crops (aspect ratio not necessarily preserved)
+    #[clap(
+        short,
+        long,
+        value_names = &["width", "height"],
+        number_of_values = 2,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    resize: Option<Vec<u32>>,
+
+    /// Pixel size of the images in mm, or a CSV file with it by image or folder
+    /// path (as for --metadata), for the size of the objects in the manifest
+    #[clap(long, value_name = "mm|csv-file")]
+    pixel_size: Option<String>,
+
+    /// Resize the crops to this pixel size in mm, for all the objects at the same
+    /// physical scale; the images without --pixel-size are skipped
+    #[clap(
+        long,
+        value_name = "mm-per-pixel",
+        requires = "pixel_size",
+        conflicts_with = "resize"
+    )]
+    normalize_scale: Option<f64>,
+
+    /// Comma separated list of labels to crop. Defaults to everything
+    #[clap(short = 'L', long, value_name = "labels", use_value_delimiter = true)]
+    select_labels: Option<Vec<String>>,
+
+    /// Match the --select-labels ignoring the case
+    #[clap(long, requires = "select_labels")]
+    case_insensitive_labels: bool,
+
+    /// Normalize the labels when read, so that eg. "Sebastes " and "Sebastes" are the same
+    #[clap(long, value_name = "how", value_enum, default_value = "none")]
+    normalize_labels: LabelNormalize,
+
+    /// Look up the labels in the MBARI knowledge base concept service at this URL
+    /// (eg., https://kb.shore.mbari.org/kb/v1), warning about the unknown concepts
+    #[clap(long, value_name = "url")]
+    kb_url: Option<String>,
+
+    /// Replace the labels that are alternate names of a concept in the knowledge
+    /// base by the concept name, before the --select-labels are matched
+    #[clap(long, requires = "kb_url")]
+    kb_correct: bool,
+
+    /// Only crop objects having the given attribute value (eg., occluded=false); can be repeated
+    #[clap(long = "attr", value_name = "key=value", value_parser = annotation::parse_attribute)]
+    attributes: Vec<(String, String)>,
+
+    /// Skip objects marked as difficult
+    #[clap(long)]
+    skip_difficult: bool,
+
+    /// Skip objects marked as truncated
+    #[clap(long)]
+    skip_truncated: bool,
+
+    /// Skip objects marked as occluded
+    #[clap(long)]
+    skip_occluded: bool,
+
+    /// Skip the objects of labels with fewer than the given number of boxes overall
+    #[clap(long, value_name = "N")]
+    min_label_count: Option<usize>,
+
+    /// Instead of skipping them, merge the labels below --min-label-count into the given label
+    #[clap(long, value_name = "label", requires = "min_label_count")]
+    merge_rare_into: Option<String>,
+
+    /// Also generate, for each image, a crop covering all the selected objects
+    #[clap(long)]
+    union_crop: bool,
+
+    /// Padding in pixels around the union crop
+    #[clap(long, value_name = "px", default_value_t = 0, requires = "union_crop")]
+    union_padding: u32,
+
+    /// Also generate crops covering pairs of nearby objects with the given labels
+    #[clap(long, value_name = "labelA,labelB", value_parser = parse_label_pair, requires = "max_distance")]
+    pair_crops: Option<(String, String)>,
+
+    /// Maximum distance in pixels between the boxes of a pair
+    #[clap(long, value_name = "px", requires = "pair_crops")]
+    max_distance: Option<f64>,
+
+    /// Also write, for each image, a montage of its object crops in a grid, with a JSON index
+    #[clap(long)]
+    montage: bool,
+
+    /// Size of the tiles of the montage, the crops being resized to fit
+    #[clap(long, value_name = "WxH", value_parser = parse_size, default_value = "128x128")]
+    montage_tile: (u32, u32),
+
+    /// Order of the crops in the montage
+    #[clap(long, value_name = "order", value_enum, default_value = "index")]
+    montage_order: MontageOrder,
+
+    /// Crop sliding windows of the given size over the images instead of the objects,
+    /// labeled by the objects mostly inside them, eg., for patch classification
+    #[clap(
+        long,
+        value_name = "WxH",
+        value_parser = parse_size,
+        conflicts_with_all = ["union_crop", "pair_crops"]
+    )]
+    windows: Option<(u32, u32)>,
+
+    /// Step between the windows, by default their size (no overlap)
+    #[clap(long, value_name = "WxH", value_parser = parse_size, requires = "windows")]
+    window_stride: Option<(u32, u32)>,
+
+    /// How the windows are labeled by their objects
+    #[clap(long, value_name = "how", value_enum, default_value = "majority")]
+    window_label: WindowLabel,
+
+    /// Label of the windows without objects (eg., background); skipped otherwise
+    #[clap(long, value_name = "label", requires = "windows")]
+    window_empty_label: Option<String>,
+
+    /// Path to store image crops
+    #[clap(short, long, value_name = "dir", required = true)]
+    output_dir: Option<PathBuf>,
+
+    /// Limit image reading to the given megabytes per second
+    #[clap(long, value_name = "MB/s")]
+    max_read_mbps: Option<f64>,
+
+    /// Limit crop writing to the given megabytes per second
+    #[clap(long, value_name = "MB/s")]
+    max_write_mbps: Option<f64>,
+
+    /// Bit depth of the crops: as in the source images (floating-point samples
+    /// as 16-bit), or converted to 8 or 16 bits per sample
+    #[clap(long, value_name = "bits", value_enum, default_value = "keep")]
+    bit_depth: BitDepth,
+
+    /// What to do with the alpha channel of the crops, from the source images
+    /// or --mask-crops: keep it, drop it, or flatten the crops over a color,
+    /// eg., flatten=#000000
+    #[clap(long, value_name = "keep|drop|flatten=#RRGGBB", default_value = "keep")]
+    alpha: AlphaPolicy,
+
+    /// What to do with the ICC color profiles of the source images: embed them
+    /// in the crops, strip them, or convert the crops to sRGB
+    #[clap(long, value_name = "how", value_enum, default_value = "keep")]
+    icc: IccPolicy,
+
+    /// Add the GPS position, altitude and timestamp of the source images, from
+    /// their EXIF metadata, to the entries of the manifest
+    #[clap(long)]
+    exif: bool,
+
+    /// Frame of multi-frame images (TIFF stacks, GIF animations) to crop, or all
+    /// of them, with the frame in the crop filenames, eg., `stack_f3_<id>.png`;
+    /// by default, the first frame
+    #[clap(long, value_name = "n|all")]
+    frame: Option<FrameSelection>,
+
+    /// Embed provenance (source image, box, label, blaise version, run id) as text in the crops
+    #[clap(long)]
+    stamp_metadata: bool,
+
+    /// When to sync the written crops to disk
+    #[clap(long, value_name = "when", value_enum, default_value = "never")]
+    sync: SyncPolicy,
+
+    /// ONNX classification model to route low-confidence or disagreeing crops to a _review directory
+    /// (with the `onnx` feature)
+    #[clap(long, value_name = "model.onnx", requires = "filter_labels")]
+    filter_model: Option<PathBuf>,
+
+    /// Class names of the --filter-model scores (one per line, in the order of the scores)
+    #[clap(long, value_name = "names-file", requires = "filter_model")]
+    filter_labels: Option<PathBuf>,
+
+    /// Minimum classifier confidence for a crop to not be routed for review
+    #[clap(
+        long,
+        value_name = "p",
+        default_value_t = 0.5,
+        requires = "filter_model"
+    )]
+    filter_threshold: f32,
+
+    /// Skip crops with a sharpness (variance of the Laplacian) below the given value
+    #[clap(long, value_name = "t")]
+    min_sharpness: Option<f64>,
+
+    /// Skip crops with a mean brightness (0-255) outside the given range
+    #[clap(long, value_name = "lo,hi", value_parser = quality::parse_exposure_range)]
+    exposure_range: Option<(f64, f64)>,
+
+    /// Route crops that likely contain burned-in overlay text (eg., timestamps) to a _flagged directory
+    #[clap(long)]
+    flag_overlays: bool,
+
+    /// Minimum density of strong edges in a text-line-high band of a crop for it to be flagged
+    #[clap(
+        long,
+        value_name = "fraction",
+        default_value_t = overlay::DEFAULT_EDGE_DENSITY,
+        requires = "flag_overlays"
+    )]
+    overlay_edge_density: f64,
+
+    /// Write crops directly instead of through a temporary file renamed on success
+    #[clap(long)]
+    no_atomic: bool,
+
+    /// Remove the existing contents of a non-empty output directory
+    #[clap(long, conflicts_with = "append")]
+    overwrite: bool,
+
+    /// Add to the existing contents of a non-empty output directory
+    #[clap(long)]
+    append: bool,
+
+    /// Generate a JSON-lines manifest with an entry for each written crop
+    #[clap(short, long, value_name = "jsonl-file")]
+    manifest: Option<PathBuf>,
+
+    /// Order of the manifest lines; `stable` holds them until the end of the run
+    /// so the manifest is the same regardless of thread interleaving
+    #[clap(long, value_name = "order", default_value = "completion")]
+    manifest_order: ManifestOrder,
+
+    /// Make the paths of the images and crops in the outputs (manifest, sinks,
+    /// FiftyOne dataset, montage indices, stamped metadata) relative, replace the
+    /// user name, and leave the host name out of the run record, for sharing
+    #[clap(long)]
+    scrub_paths: bool,
+
+    /// Also write the crops to the given sink: `tar:<file>`, `zip:<file>`,
+    /// `webdataset:<dir>` (tar shards with a .json per crop), `pyramid:<dir>`
+    /// (with JPEG thumbnails of 64 and 256 pixels, or the sizes given as in
+    /// `pyramid=64,256:<dir>`, for web viewers) or, with the `lmdb` feature,
+    /// `lmdb:<dir>`. Can be repeated
+    #[clap(long, value_name = "kind:path", value_parser = sink::parse_spec)]
+    sink: Vec<SinkSpec>,
+
+    /// Number of samples per WebDataset shard
+    #[clap(long, value_name = "N", default_value_t = 1000)]
+    webdataset_shard_size: usize,
+
+    /// Also write the object crops as TensorFlow examples in sharded TFRecord files
+    #[clap(long, value_name = "dir")]
+    tfrecord: Option<PathBuf>,
+
+    /// Number of examples per TFRecord shard
+    #[clap(long, value_name = "N", default_value_t = 1000, requires = "tfrecord")]
+    tfrecord_shard_size: usize,
+
+    /// Also pack the (resized) object crops into an HDF5 file, at 8 bits per sample
+    #[clap(long, value_name = "file", requires = "resize")]
+    hdf5: Option<PathBuf>,
+
+    /// Also pack the object crops, resized to fit squares of --sprite-size, into
+    /// sprite sheets in this directory with a JSON atlas of their positions, for
+    /// web galleries
+    #[clap(long, value_name = "dir")]
+    sprite_sheets: Option<PathBuf>,
+
+    /// Side of the sprites in the sprite sheets, in pixels
+    #[clap(
+        long,
+        value_name = "pixels",
+        default_value_t = 128,
+        requires = "sprite_sheets",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    sprite_size: u32,
+
+    /// Write a metadata.csv in the output directory per the Hugging Face imagefolder convention
+    #[clap(long)]
+    hf_imagefolder: bool,
+
+    /// Name the class directories by zero-padded class index instead of by label,
+    /// with the mapping in <output-dir>/classes.txt
+    #[clap(long)]
+    imagefolder_indices: bool,
+
+    /// How the crops of the objects are numbered in their filenames, eg., `<image>_<id>.png`
+    #[clap(long, value_name = "scheme", value_enum, default_value = "index")]
+    object_ids: ObjectIds,
+
+    /// Make the labels safe as directory names (the manifest keeps the original labels)
+    #[clap(long, value_name = "how", value_enum, default_value = "none")]
+    label_sanitize: LabelSanitize,
+
+    /// Also export the processed images and objects as a FiftyOne dataset
+    #[clap(long, value_name = "dir")]
+    fiftyone: Option<PathBuf>,
+
+    /// Write a checksums file (SHA256SUMS) for the crops in the output directory
+    #[clap(long, value_name = "algorithm")]
+    checksums: Option<ChecksumAlgorithm>,
+
+    /// Generate csv with size, aspect ratio of loaded bounding boxes
+    #[clap(short, long, value_name = "csv-file")]
+    bb_info: Option<PathBuf>,
+
+    /// Report object counts per label by size, as in COCO (small < 32², medium < 96², large)
+    #[clap(long)]
+    size_report: bool,
+
+    /// Show the summary and the estimated crops, and ask for confirmation before cropping
+    #[clap(long)]
+    confirm: bool,
+
+    /// Answer yes to --confirm, eg., in scripts, only showing the estimate
+    #[clap(long, requires = "confirm")]
+    yes: bool,
+
+    /// Do not start if the estimated crops would leave less than this free space
+    /// on the file system of the output directory
+    #[clap(long, value_name = "GB")]
+    min_free: Option<f64>,
+
+    /// Report, per folder, the images with and without annotations, and those referenced
+    /// by annotations but missing, also listing them in the given csv file
+    #[clap(long, value_name = "csv-file")]
+    coverage_report: Option<PathBuf>,
+
+    /// Report the labels found in the same images, writing the number of images
+    /// with each pair of labels to the given csv file, and the fraction of the
+    /// images with the row label to <name>_normalized.csv
+    #[clap(long, value_name = "csv-file")]
+    cooccurrence: Option<PathBuf>,
+
+    /// Report the overlaps of the boxes of different labels in the same images
+    /// (mean and max IoU, boxes contained in the other label's), also writing
+    /// them to the given csv file
+    #[clap(long, value_name = "csv-file")]
+    overlap_report: Option<PathBuf>,
+
+    /// Only crop the objects contained in a box of the given label, eg., parasites
+    /// on their hosts
+    #[clap(long, value_name = "label")]
+    contained_in: Option<String>,
+
+    /// Fraction of the area of a box to be inside another to be contained in it
+    #[clap(long, value_name = "fraction", default_value_t = 1.)]
+    containment: f64,
+
+    /// Regex with the captures of the year, month, day, and optionally the hour,
+    /// minute and second in the image filenames, eg., '(\d{4})(\d{2})(\d{2})T(\d{2})',
+    /// for the image counts per day and hour in the summary
+    #[clap(long, value_name = "regex")]
+    time_pattern: Option<TimePattern>,
+
+    /// Only crop the images with timestamps (per --time-pattern) in the range,
+    /// eg., 2023-05-01..2023-05-02 or 2023-05-01T22:30..
+    #[clap(long, value_name = "start..end", requires = "time_pattern")]
+    time_range: Option<TimeRange>,
+
+    /// Relabel the objects per the rules in the given file, eg., a box of label X
+    /// inside one of label Y becoming X_on_Y, before cropping
+    #[clap(long, value_name = "yaml-file")]
+    rules: Option<PathBuf>,
+
+    /// The boxes are in the map coordinates (eg., UTM) of the GeoTIFF images,
+    /// instead of pixels
+    #[cfg(feature = "geotiff")]
+    #[clap(long)]
+    geo_boxes: bool,
+
+    /// Store crops under per-pose subdirectories: <label>/<pose>/, the pose made
+    /// safe as per --label-sanitize (`slug` if `none`)
+    #[clap(long)]
+    group_by_pose: bool,
+
+    /// Spread the object crops of each label directory over <n> subdirectories
+    /// (00, 01, ...) by a hash of the crop filename, to avoid hotspots on
+    /// parallel filesystems. The shard is recorded in the manifest
+    #[clap(long, value_name = "n", value_parser = clap::value_parser!(u16).range(2..=256))]
+    shard_output: Option<u16>,
+
+    /// Store crops under per-size subdirectories: <output-dir>/<size>/<label>/
+    #[clap(long)]
+    bucket_by_size: bool,
+
+    /// Split the crops into partitions stored as <output-dir>/<partition>/...,
+    /// eg., train=0.8,val=0.2, or 0.8,0.1,0.1 for train, val, and test
+    #[clap(long, value_name = "spec")]
+    split: Option<SplitSpec>,
+
+    /// CSV file with metadata of the images (or their folders), eg., dive or site,
+    /// by image or folder path in the first column
+    #[clap(long, value_name = "csv-file")]
+    metadata: Option<PathBuf>,
+
+    /// Keep all crops from the images with the same value in the given column
+    /// of the --metadata file in the same partition, eg., for site holdouts
+    #[clap(long, value_name = "column", requires_all = ["split", "metadata"])]
+    split_by_metadata: Option<String>,
+
+    /// Keep all crops from the same source image, folder, or track in the same partition
+    #[clap(
+        long,
+        value_name = "group",
+        default_value = "image",
+        requires = "split"
+    )]
+    split_by: SplitBy,
+
+    /// Verbose output (disables progress bars)
+    #[clap(long)]
+    verbose: bool,
+
+    /// Do not show progress bars
+    #[clap(long)]
+    npb: bool,
+
+    /// Show a dashboard of the processing on the terminal instead of the progress bars
+    #[clap(long)]
+    tui: bool,
+
+    /// Serve the processing metrics in the Prometheus format at http://<host>:<port>/metrics
+    #[clap(long, value_name = "port")]
+    metrics_port: Option<u16>,
+
+    /// Address to serve the metrics on, eg., 0.0.0.0 for all the interfaces
+    #[clap(long, value_name = "host", default_value = "127.0.0.1", requires = "metrics_port")]
+    metrics_addr: String,
+
+    /// Write the running counts to <output-dir>/checkpoint.json, and flush the manifest,
+    /// every N annotations
+    #[clap(long, value_name = "N")]
+    checkpoint_every: Option<usize>,
+
+    /// Write the running counts to <output-dir>/checkpoint.json, and flush the manifest,
+    /// every given number of seconds
+    #[clap(long, value_name = "secs")]
+    checkpoint_secs: Option<u64>,
+
+    /// Write the annotation files that failed to be parsed, and why, to the given file
+    #[clap(long, value_name = "file")]
+    list_invalid: Option<PathBuf>,
+
+    /// When to exit with a failure status: none, any, or threshold=<percent>% of failed annotations
+    #[clap(long, value_name = "policy", default_value = "any")]
+    fail_on: FailOn,
+
+    /// Directory for the local copies of the images referenced by http(s) URLs
+    /// [default: blaise-cache in the system temporary directory]
+    #[clap(long, value_name = "dir")]
+    cache_dir: Option<PathBuf>,
+
+    /// Limit the cache directory to this size, evicting the least recently
+    /// used images before downloading (except those needed) and after the run
+    #[clap(long, value_name = "GB")]
+    cache_size: Option<f64>,
+
+    /// Number of parallel downloads of the images referenced by URLs
+    #[clap(long, value_name = "n", default_value_t = 8)]
+    download_jobs: usize,
+
+    /// Number of threads to use (by default, auto: all available, within any container CPU limit)
+    #[clap(short = 'j', long = "cpus", value_name = "auto|N")]
+    cores: Option<Cpus>,
+
+    /// With auto, use only as many threads as physical cores
+    #[clap(long)]
+    physical_cores: bool,
+
+    /// Run the processing threads at lower scheduling priority
+    #[clap(long)]
+    low_priority: bool,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Verify the crops in a directory against its checksums file
+    Verify {
+        /// Directory with the SHA256SUMS file
+        #[clap(value_name = "dir")]
+        dir: PathBuf,
+    },
+
+    /// Report clusters of near-duplicate images (eg., crops) under a directory
+    NearDupes {
+        /// Directory to scan for images
+        #[clap(value_name = "dir")]
+        dir: PathBuf,
+
+        /// Perceptual hash to use
+        #[clap(long, value_name = "kind", default_value = "dhash")]
+        hash: HashKind,
+
+        /// Maximum Hamming distance (in bits, out of 64) between near-duplicate hashes
+        #[clap(long, value_name = "bits", default_value_t = 5)]
+        max_distance: u32,
+
+        /// Write the paths of all but the first image of each cluster to the given file
+        #[clap(long, value_name = "file")]
+        reject_list: Option<PathBuf>,
+    },
+
+    /// Extract unlabeled square patches from the images under a directory,
+    /// ignoring any annotations (eg., for self-supervised pretraining), into a
+    /// flat directory with a manifest
+    Patches {
+        /// Directory to scan for images
+        #[clap(value_name = "dir")]
+        dir: PathBuf,
+
+        /// Output directory
+        #[clap(short, long, value_name = "dir")]
+        output_dir: PathBuf,
+
+        /// Side of the patches, in pixels
+        #[clap(long, value_name = "pixels", default_value_t = 224)]
+        size: u32,
+
+        /// Number of patches per image (at most, with the grid strategy)
+        #[clap(long, value_name = "N", default_value_t = 16)]
+        per_image: usize,
+
+        /// Positions of the patches
+        #[clap(long, value_name = "strategy", default_value = "random")]
+        strategy: PatchStrategy,
+
+        /// Seed of the random positions
+        #[clap(long, value_name = "N", default_value_t = 0)]
+        seed: u64,
+
+        /// Remove the existing contents of the output directory
+        #[clap(long)]
+        overwrite: bool,
+
+        /// Also write the patches to the given sink, as for the crops. Can be repeated
+        #[clap(long, value_name = "kind:path", value_parser = sink::parse_spec)]
+        sink: Vec<SinkSpec>,
+    },
+
+    /// Run as usual with the other options, but only cropping the objects not
+    /// in the manifest of a previous run (eg., with --append, as an incremental
+    /// update after new annotations are added). Union and pair crops including
+    /// new objects are written again
+    Subtract {
+        /// Manifest of the previous run (same input and image options)
+        #[clap(long, value_name = "jsonl-file")]
+        manifest: PathBuf,
+    },
+
+    /// Report the labels of the annotations given with the options of a regular
+    /// run (--output-dir not needed), with their counts
+    Labels {
+        /// Also report the probable duplicate labels: differing only in case,
+        /// underscores, hyphens or spaces, or by a few edits
+        #[clap(long)]
+        suggest_merges: bool,
+
+        /// Maximum number of character edits between similar labels (also at
+        /// most a quarter of the length of the shorter)
+        #[clap(long, value_name = "n", default_value_t = 2, requires = "suggest_merges")]
+        max_edits: usize,
+
+        /// Write a starter label map of the probable duplicates, as
+        /// `label,canonical` CSV lines, to review
+        #[clap(long, value_name = "csv-file", requires = "suggest_merges")]
+        label_map: Option<PathBuf>,
+    },
+
+    /// Write again the crops in the manifest of a previous run, or those of the
+    /// given labels, from their source images and boxes without scanning the
+    /// annotations (eg., with another --resize). Run from the directory of the
+    /// previous run, as the paths in the manifest may be relative to it
+    Recrop {
+        /// Manifest of the previous run, with its sha256 values updated if any
+        #[clap(long, value_name = "jsonl-file")]
+        manifest: PathBuf,
+
+        /// Comma separated list of labels to crop again. Defaults to everything
+        #[clap(long, value_name = "labels", use_value_delimiter = true)]
+        only_labels: Option<Vec<String>>,
+
+        /// Resize the crops (aspect ratio not necessarily preserved)
+        #[clap(
+            long,
+            value_names = &["width", "height"],
+            number_of_values = 2,
+            value_parser = clap::value_parser!(u32).range(1..)
+        )]
+        resize: Option<Vec<u32>>,
+    },
+
+    /// Run the crop jobs (command line arguments) in the given JSON file at
+    /// once, sharing the threads and decoded images
+    Batch {
+        /// Job file, with `{"jobs": [{"name": ..., "args": [...]}, ...]}`
+        #[clap(value_name = "job-file")]
+        job_file: PathBuf,
+
+        /// Memory for the decoded images shared by the jobs, in megabytes
+        #[clap(long, value_name = "MB", default_value_t = 2048)]
+        cache_mb: u64,
+
+        /// Write the reports of the jobs to the given JSON file
+        #[clap(long, value_name = "json-file")]
+        report: Option<PathBuf>,
+    },
+
+    /// Run as a service, with an HTTP/JSON API to submit crop jobs (the
+    /// command line arguments), poll their status and fetch their reports
+    Daemon {
+        /// Address to listen on; other than a loopback address, requires a token
+        #[clap(long, value_name = "host:port", default_value = "127.0.0.1:8080")]
+        listen: String,
+
+        /// Token required in the requests, as `Authorization: Bearer <token>`;
+        /// by default, from the BLAISE_DAEMON_TOKEN environment variable, if set
+        #[clap(long, value_name = "token")]
+        token: Option<String>,
+    },
+}
+
+impl Opts {
+    fn output_dir(&self) -> &Path {
+        self.output_dir.as_deref().unwrap()
+    }
+
+    fn cache_dir(&self) -> PathBuf {
+        match &self.cache_dir {
+            Some(dir) => dir.clone(),
+            None => std::env::temp_dir().join("blaise-cache"),
+        }
+    }
+
+    /// What to do with the contents of a non-empty output directory.
+    fn existing(&self) -> run::Existing {
+        if self.overwrite {
+            run::Existing::Overwrite
+        } else if self.append {
+            run::Existing::Append
+        } else {
+            run::Existing::Refuse
+        }
+    }
+
+    fn strictness(&self) -> Strictness {
+        match (self.strict, self.lenient) {
+            (true, _) => Strictness::Strict,
+            (_, true) => Strictness::Lenient,
+            _ => Strictness::Normal,
+        }
+    }
+
+    /// How the boxes get to pixels at crop time.
+    fn quantization(&self) -> Quantization {
+        Quantization {
+            rounding: self.round,
+            per_edge: self.compat == Some(Compat::Ultralytics),
+        }
+    }
+}
+
+fn parse_label_pair(s: &str) -> Result<(String, String), String> {
+    match s.split_once(',') {
+        Some((a, b)) if !a.is_empty() && !b.is_empty() && !b.contains(',') => {
+            Ok((a.to_string(), b.to_string()))
+        }
+        _ => Err("expected two comma separated labels".to_string()),
+    }
+}
+
+fn parse_offset(s: &str) -> Result<(i64, i64), String> {
+    s.split_once(',')
+        .and_then(|(dx, dy)| Some((dx.trim().parse().ok()?, dy.trim().parse().ok()?)))
+        .ok_or_else(|| "expected two comma separated integers".to_string())
+}
+
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    s.split_once('x')
+        .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+        .filter(|&(w, h)| w > 0 && h > 0)
+        .ok_or_else(|| "expected WxH, with positive integers".to_string())
+}
+
+/// Process exit codes.
+pub(crate) mod exit_code {
+    pub const SUCCESS: i32 = 0;
+    pub const SOME_FAILED: i32 = 2;
+    pub const ALL_FAILED: i32 = 3;
+    pub const CONFIG_ERROR: i32 = 4;
+}
+
+/// Number of processing threads.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Cpus {
+    Auto,
+    Count(usize),
+}
+
+impl std::str::FromStr for Cpus {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Cpus::Auto),
+            _ => match s.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Cpus::Count(n)),
+                _ => Err("expected auto or a positive number".to_string()),
+            },
+        }
+    }
+}
+
+/// Number of threads to use by default: the available cores, within any
+/// cgroup CPU quota (as `num_cpus` reads it), and with `physical_cores`, at
+/// most the physical ones.
+fn auto_threads(physical_cores: bool) -> usize {
+    match physical_cores {
+        true => num_cpus::get_physical().min(num_cpus::get()),
+        false => num_cpus::get(),
+    }
+}
+
+/// When to consider the run as failed, according to the annotations that failed to be processed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FailOn {
+    None,
+    Any,
+    /// Percentage of failed annotations above which the run fails.
+    Threshold(f64),
+}
+
+impl std::str::FromStr for FailOn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FailOn::None),
+            "any" => Ok(FailOn::Any),
+            _ => s
+                .strip_prefix("threshold=")
+                .map(|p| p.strip_suffix('%').unwrap_or(p))
+                .and_then(|p| p.parse::<f64>().ok())
+                .filter(|p| (0. ..=100.).contains(p))
+                .map(FailOn::Threshold)
+                .ok_or_else(|| "expected none, any, or threshold=<percent>%".to_string()),
+        }
+    }
+}
+
+impl FailOn {
+    fn exit_code(&self, failed: usize, total: usize) -> i32 {
+        let fails = match self {
+            FailOn::None => false,
+            FailOn::Any => failed > 0,
+            FailOn::Threshold(pct) => failed as f64 * 100. > pct * total as f64,
+        };
+        if !fails {
+            exit_code::SUCCESS
+        } else if failed == total {
+            exit_code::ALL_FAILED
+        } else {
+            exit_code::SOME_FAILED
+        }
+    }
+}
+
+/// Exits with the given code, or, in a run through [ffi], returns it from there.
+fn exit(code: i32) -> ! {
+    if ffi::in_call() {
+        std::panic::resume_unwind(Box::new(ffi::Exit(code)));
+    }
+    std::process::exit(code)
+}
+
+/// Runs the command line tool.
+pub fn main() {
+    let (code, _) = run(std::env::args_os().collect(), None);
+    exit(code);
+}
+
+/// Runs with the given command line, returning the exit code, with the run
+/// record if annotations were processed. In a batch, the jobs share the
+/// threads and the decoded images.
+pub(crate) fn run(args: Vec<OsString>, batch: Option<&Batch>) -> (i32, Option<run::RunRecord>) {
+    let started = Instant::now();
+    let run = run::Run::start();
+    let _ = env_logger::try_init();
+    let mut opts = match Opts::try_parse_from(&args) {
+        Ok(opts) => opts,
+        Err(e) => {
+            let _ = e.print();
+            exit(if e.use_stderr() {
+                exit_code::CONFIG_ERROR
+            } else {
+                exit_code::SUCCESS
+            });
+        }
+    };
+
+    let prior = match &opts.command {
+        Some(Command::Subtract { manifest }) => {
+            if opts.output_dir.is_none() {
+                eprintln!(
+                    "ERROR: subtract requires the options of a regular run, --output-dir included"
+                );
+                exit(exit_code::CONFIG_ERROR);
+            }
+            match PriorCrops::read(manifest) {
+                Ok(prior) => prior,
+                Err(e) => {
+                    eprintln!("ERROR: cannot read manifest {:?}: {}", manifest, e);
+                    exit(exit_code::CONFIG_ERROR);
+                }
+            }
+        }
+        Some(Command::Labels { .. }) => PriorCrops::default(),
+        Some(command) => {
+            run_command(command);
+            return (exit_code::SUCCESS, None);
+        }
+        None => PriorCrops::default(),
+    };
+
+    let problems = opts.problems();
+    if !problems.is_empty() {
+        for problem in &problems {
+            eprintln!("ERROR: {}", problem);
+        }
+        exit(exit_code::CONFIG_ERROR);
+    }
+
+    let classifier = match (&opts.filter_model, &opts.filter_labels) {
+        (Some(path), Some(labels)) => match source::read_names(labels)
+            .and_then(|labels| classifier::load_onnx_model(path, labels))
+        {
+            Ok(classifier) => Some(classifier),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        },
+        _ => None,
+    };
+
+    let exclusion = if !opts.exclude_region.is_empty() || opts.roi_mask.is_some() {
+        match Exclusion::new(&opts.exclude_region, opts.roi_mask.as_deref()) {
+            Ok(exclusion) => Some(exclusion),
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        }
+    } else {
+        None
+    };
+
+    let split_metadata = opts.split_by_metadata.as_ref().map(|column| {
+        let metadata = Metadata::load(opts.metadata.as_ref().unwrap());
+        match metadata.and_then(|metadata| metadata.column(column)) {
+            Ok(column) => column,
+            Err(e) => {
+                eprintln!("ERROR: invalid metadata: {}", e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        }
+    });
+
+    if opts.confirm && !opts.yes && !std::io::stdin().is_terminal() {
+        eprintln!("ERROR: --confirm asks on a terminal; add --yes to proceed without asking");
+        exit(exit_code::CONFIG_ERROR);
+    }
+
+    let pixel_size = opts.pixel_size.as_ref().map(|arg| match PixelSize::new(arg) {
+        Ok(pixel_size) => pixel_size,
+        Err(e) => {
+            eprintln!("ERROR: invalid --pixel-size: {}", e);
+            exit(exit_code::CONFIG_ERROR);
+        }
+    });
+
+    let rules = match &opts.rules {
+        Some(path) => match rules::load(path) {
+            Ok(rules) => rules,
+            Err(e) => {
+                eprintln!("ERROR: invalid rules: {}", e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        },
+        None => Vec::new(),
+    };
+
+    if let Some(input) = opts.input.clone() {
+        if let Err(e) = resolve_input(&mut opts, &input) {
+            eprintln!("ERROR: {}", e);
+            exit(exit_code::CONFIG_ERROR);
+        }
+    }
+    if let Some(Command::Labels {
+        suggest_merges,
+        max_edits,
+        label_map,
+    }) = &opts.command
+    {
+        let annotations = get_annotations(&opts);
+        report_labels(&annotations, *suggest_merges, *max_edits, label_map.as_deref());
+        return (exit_code::SUCCESS, None);
+    }
+
+    let remote = match Remote::parse(&opts.output_dir().to_string_lossy()) {
+        Ok(remote) => remote,
+        Err(e) => {
+            eprintln!("ERROR: {}", e);
+            exit(exit_code::CONFIG_ERROR);
+        }
+    };
+    if remote.is_some() {
+        // these write into the output directory directly, or read from it:
+        let local_only = [
+            ("--checksums", opts.checksums.is_some()),
+            ("--hf-imagefolder", opts.hf_imagefolder),
+            ("--imagefolder-indices", opts.imagefolder_indices),
+            ("--checkpoint-every", opts.checkpoint_every.is_some()),
+            ("--checkpoint-secs", opts.checkpoint_secs.is_some()),
+            ("--append", opts.append),
+            ("--min-free", opts.min_free.is_some()),
+        ];
+        if let Some((option, _)) = local_only.iter().find(|(_, given)| *given) {
+            eprintln!(
+                "ERROR: {} is not supported with a remote output directory",
+                option
+            );
+            exit(exit_code::CONFIG_ERROR);
+        }
+    } else if let Err(e) = run::prepare_output_dir(opts.output_dir(), opts.existing()) {
+        eprintln!("ERROR: {}", e);
+        exit(exit_code::CONFIG_ERROR);
+    }
+    if let Some(labels) = &mut opts.select_labels {
+        for label in labels.iter_mut() {
+            *label = annotation::normalize_label(label, opts.normalize_labels);
+        }
+    }
+
+    let annotations = match &opts.proposals {
+        Some(file) => get_proposals(file, &opts),
+        None => get_annotations(&opts),
+    };
+    #[cfg(feature = "geotiff")]
+    let (annotations, georef) = georeference(annotations, &opts);
+    #[cfg(not(feature = "geotiff"))]
+    let (annotations, georef) = (annotations, HashMap::new());
+    let mut annotations = annotations;
+    guard_boxes(&mut annotations, opts.bad_boxes);
+    if opts.tight_bbox {
+        tighten_boxes(&mut annotations);
+    }
+    if opts.scale_boxes.is_some() || opts.global_offset.is_some() {
+        transform_boxes(&mut annotations, &opts);
+    }
+    if !rules.is_empty() {
+        let relabeled = rules::apply(&rules, &mut annotations);
+        println!("objects relabeled by rules: {}", relabeled);
+    }
+    if let Some(exclusion) = &exclusion {
+        annotations = skip_excluded(annotations, exclusion, opts.quantization());
+    }
+    if let Some(csv_filename) = &opts.overlap_report {
+        show_overlap_report(&annotations, opts.containment, csv_filename);
+    }
+    if let Some(host) = &opts.contained_in {
+        annotations = keep_contained(annotations, host, opts.containment);
+    }
+    if let (Some(pattern), Some(range)) = (&opts.time_pattern, &opts.time_range) {
+        annotations = keep_time_range(annotations, pattern, range);
+    }
+    if matches!(opts.command, Some(Command::Subtract { .. })) {
+        annotations = subtract_prior(annotations, &prior, &opts);
+    }
+    let downloaded = download_remote_images(&annotations, &opts);
+    let mut rare_labels = Vec::new();
+    if let Some(min_count) = opts.min_label_count {
+        let merge_into = opts.merge_rare_into.as_deref();
+        (annotations, rare_labels) =
+            annotation::apply_label_floor(annotations, min_count, merge_into);
+        if let (Some(labels), Some(other)) = (&mut opts.select_labels, merge_into) {
+            labels.push(other.to_string());
+        }
+    }
+    if !annotations.is_empty() {
+        show_annotation_summary(&annotations, &rare_labels, pixel_size.as_ref(), &opts);
+        if opts.confirm || opts.min_free.is_some() {
+            let estimate = estimate_output(&annotations, pixel_size.as_ref(), &downloaded, &opts);
+            estimate.report();
+            if remote.is_none() {
+                check_free_space(&estimate, &opts);
+            }
+            if opts.confirm
+                &&  !opts.yes && !estimate::confirm("\nProceed with the crops?") {
+                println!("Cancelled.");
+                return (exit_code::SUCCESS, None);
+            }
+        }
+        let (failed, by_label) = process_annotations(
+            &opts,
+            &annotations,
+            exclusion.as_ref(),
+            started,
+            &run.id,
+            Prepared {
+                classifier: classifier.as_deref(),
+                prior,
+                downloaded,
+                georef,
+                split_metadata,
+                pixel_size,
+                batch,
+            },
+        );
+        evict_cache(&opts, &HashSet::new());
+        let args = args.iter().map(|a| a.to_string_lossy().into_owned());
+        let mut record = run.record(
+            args.collect(),
+            format!("{:?}", opts),
+            annotations.len(),
+            failed,
+            &by_label,
+        );
+        if opts.scrub_paths {
+            record.scrub(&Scrubber::new());
+        }
+        let written = match &remote {
+            Some(remote) => serde_json::to_string_pretty(&[&record])
+                .map_err(std::io::Error::from)
+                .and_then(|json| remote.put(run::RUN_FILE, (json + "\n").as_bytes())),
+            None => run::write_record(opts.output_dir(), &record, opts.existing()),
+        };
+        if let Err(e) = written {
+            eprintln!("WARN: cannot write {}: {}", run::RUN_FILE, e);
+        }
+        if failed > 0 {
+            println!(
+                "{} of {} annotations failed to be processed",
+                failed,
+                annotations.len()
+            );
+        }
+        return (
+            opts.fail_on.exit_code(failed, annotations.len()),
+            Some(record),
+        );
+    }
+    (exit_code::SUCCESS, None)
+}
+
+/// The exit code of `verify`: as for a run, with the files failing to verify.
+fn verify_exit_code(verification: &Verification) -> i32 {
+    let failed = verification.mismatched.len() + verification.missing.len();
+    if failed == 0 {
+        exit_code::SUCCESS
+    } else if verification.ok == 0 {
+        exit_code::ALL_FAILED
+    } else {
+        exit_code::SOME_FAILED
+    }
+}
+
+fn run_command(command: &Command) {
+    match command {
+        Command::Verify { dir } => match checksum::verify(dir) {
+            Ok(verification) => {
+                for filename in &verification.mismatched {
+                    println!("{}: FAILED", filename);
+                }
+                for filename in &verification.missing {
+                    println!("{}: MISSING", filename);
+                }
+                println!(
+                    "{} OK, {} failed, {} missing",
+                    verification.ok,
+                    verification.mismatched.len(),
+                    verification.missing.len()
+                );
+                exit(verify_exit_code(&verification));
+            }
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        },
+        Command::Subtract { .. } => unreachable!("subtract runs as a regular run"),
+        Command::Labels { .. } => unreachable!("labels reads the annotations of a regular run"),
+        Command::Patches {
+            dir,
+            output_dir,
+            size,
+            per_image,
+            strategy,
+            seed,
+            overwrite,
+            sink,
+        } => {
+            let patching = Patching {
+                size: *size,
+                per_image: *per_image,
+                strategy: *strategy,
+                seed: *seed,
+            };
+            extract_patches(dir, output_dir, &patching, *overwrite, sink);
+        }
+        Command::Recrop {
+            manifest,
+            only_labels,
+            resize,
+        } => recrop(
+            manifest,
+            only_labels.as_deref(),
+            resize.as_deref().map(|r| (r[0], r[1])),
+        ),
+        Command::NearDupes {
+            dir,
+            hash,
+            max_distance,
+            reject_list,
+        } => near_dupes(dir, *hash, *max_distance, reject_list.as_deref()),
+        Command::Batch {
+            job_file,
+            cache_mb,
+            report,
+        } => match batch::run(job_file, *cache_mb, report.as_deref()) {
+            Ok(code) => {
+                if code != exit_code::SUCCESS {
+                    exit(code);
+                }
+            }
+            Err(e) => {
+                eprintln!("ERROR: {}", e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        },
+        Command::Daemon { listen, token } => {
+            let token = (token.clone())
+                .or_else(|| std::env::var("BLAISE_DAEMON_TOKEN").ok())
+                .filter(|token| !token.is_empty());
+            if let Err(e) = daemon::run(listen, token) {
+                eprintln!("ERROR: cannot listen on {}: {}", listen, e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        }
+    }
+}
+
+fn near_dupes(dir: &Path, kind: HashKind, max_distance: u32, reject_list: Option<&Path>) {
+    let mut paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(source::is_image)
+        .map(|e| e.into_path())
+        .collect();
+    paths.sort();
+    println!(
+        "computing {:?} for {} images under {:?}",
+        kind,
+        paths.len(),
+        dir
+    );
+
+    let storage = Storage::new(None, None);
+    let cores = auto_threads(false).min(paths.len()).max(1);
+    let chunk_size = paths.len().div_ceil(cores).max(1);
+    let hashes: Vec<Option<u64>> = thread::scope(|s| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let storage = &storage;
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| match load_image(storage, path) {
+                            Ok(img) => Some(dedup::image_hash(&img, kind)),
+                            Err(e) => {
+                                eprintln!("ERROR: failed to load image {:?}: {:?}", path, e);
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+
+    let (paths, hashes): (Vec<PathBuf>, Vec<u64>) = paths
+        .into_iter()
+        .zip(hashes)
+        .filter_map(|(path, hash)| hash.map(|hash| (path, hash)))
+        .unzip();
+    let clusters = dedup::clusters(&hashes, max_distance);
+
+    let mut rejected: Vec<&PathBuf> = Vec::new();
+    for cluster in &clusters {
+        println!("\n  cluster of {}:", cluster.len());
+        for i in cluster {
+            println!("    {}", paths[*i].display());
+        }
+        rejected.extend(cluster.iter().skip(1).map(|i| &paths[*i]));
+    }
+    println!(
+        "\n{} clusters of near-duplicates, {} images could be rejected",
+        clusters.len(),
+        rejected.len()
+    );
+
+    if let Some(reject_list) = reject_list {
+        let contents: String = rejected
+            .iter()
+            .map(|p| format!("{}\n", p.display()))
+            .collect();
+        std::fs::write(reject_list, contents).unwrap();
+        println!("Wrote rejection list to {:?}", reject_list);
+    }
+}
+
+/// Number of samples per WebDataset shard of the patches.
+const PATCHES_SHARD_SIZE: usize = 1000;
+
+fn extract_patches(
+    dir: &Path,
+    output_dir: &Path,
+    patching: &Patching,
+    overwrite: bool,
+    sink_specs: &[SinkSpec],
+) {
+    let existing = match overwrite {
+        true => run::Existing::Overwrite,
+        false => run::Existing::Refuse,
+    };
+    if let Err(e) = run::prepare_output_dir(output_dir, existing)
+        .and_then(|_| std::fs::create_dir_all(output_dir).map_err(|e| e.to_string()))
+    {
+        eprintln!("ERROR: {}", e);
+        exit(exit_code::CONFIG_ERROR);
+    }
+    let mut sinks = Sinks::default();
+    for spec in sink_specs {
+        match spec.create(PATCHES_SHARD_SIZE) {
+            Ok(sink) => sinks.push(sink),
+            Err(e) => {
+                eprintln!("ERROR: cannot create sink {:?}: {}", spec, e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        }
+    }
+    let manifest = Manifest::new(
+        Some(&output_dir.join("manifest.jsonl")),
+        ManifestOrder::Stable,
+    );
+
+    let mut paths: Vec<PathBuf> = WalkDir::new(dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(source::is_image)
+        .map(|e| e.into_path())
+        .collect();
+    paths.sort();
+    println!(
+        "extracting up to {} patches of {}x{} ({:?}) from {} images under {:?}",
+        patching.per_image,
+        patching.size,
+        patching.size,
+        patching.strategy,
+        paths.len(),
+        dir
+    );
+
+    let storage = Storage::new(None, None);
+    let extract = |path: &PathBuf| -> Result<usize, String> {
+        let img = load_image(&storage, path).map_err(|e| format!("{:?}", e))?;
+        let image = path.to_string_lossy();
+        let relative = path.strip_prefix(dir).unwrap_or(path).to_string_lossy();
+        let corners = patching.positions(img.width(), img.height(), &relative);
+        for (k, (x, y)) in corners.iter().enumerate() {
+            let patch = crop_image(&img, *x, *y, patching.size, patching.size);
+            let name = patches::patch_filename(&relative, k);
+            let out_path = output_dir.join(&name);
+            let bytes = save_image(&storage, &patch, &out_path, false, &[], None)
+                .map_err(|e| format!("cannot save {:?}: {}", out_path, e))?;
+            let bndbox = PixelRect {
+                xmin: *x,
+                ymin: *y,
+                xmax: x + patching.size,
+                ymax: y + patching.size,
+            };
+            let errors = sinks.add(&Crop {
+                kind: CropKind::Patch,
+                image: &patch,
+                bytes: &bytes,
+                path: &out_path,
+                name: Path::new(&name),
+                label: "",
+                source: &image,
+                bndbox: &bndbox,
+            });
+            if let Some(e) = errors.first() {
+                return Err(format!("cannot add {:?}: {}", name, e));
+            }
+            manifest.add(ManifestEntry {
+                kind: CropKind::Patch,
+                crop: out_path.to_string_lossy().to_string(),
+                sha256: None,
+                image: image.to_string(),
+                label: String::new(),
+                object_index: None,
+                object_id: None,
+                pair_indices: None,
+                shard: None,
+                bndbox,
+                frame: None,
+                geo_extent: None,
+                size_mm: None,
+                exif: None,
+                attributes: Default::default(),
+            });
+        }
+        Ok(corners.len())
+    };
+
+    let cores = auto_threads(false).min(paths.len()).max(1);
+    let chunk_size = paths.len().div_ceil(cores).max(1);
+    let counts: Vec<Option<usize>> = thread::scope(|s| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let extract = &extract;
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| match extract(path) {
+                            Ok(count) => Some(count),
+                            Err(e) => {
+                                eprintln!("ERROR: {:?}: {}", path, e);
+                                None
+                            }
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+    sinks.finish();
+    manifest.finish();
+
+    let failed = counts.iter().filter(|c| c.is_none()).count();
+    let too_small = counts.iter().filter(|c| **c == Some(0)).count();
+    println!(
+        "{} patches from {} images, with the manifest in {:?}",
+        counts.iter().flatten().sum::<usize>(),
+        counts.len() - failed - too_small,
+        output_dir.join("manifest.jsonl")
+    );
+    if too_small > 0 {
+        println!("{} images smaller than the patches", too_small);
+    }
+    if failed > 0 {
+        eprintln!("{} images failed", failed);
+        exit(exit_code::SOME_FAILED);
+    }
+}
+
+/// Writes again the crops of the manifest with the given labels, by source
+/// image, updating their checksums in the manifest.
+fn recrop(manifest_path: &Path, labels: Option<&[String]>, resize: Option<(u32, u32)>) {
+    let (mut lines, crops) = match PriorCrop::select(manifest_path, labels) {
+        Ok(selected) => selected,
+        Err(e) => {
+            eprintln!("ERROR: cannot read manifest {:?}: {}", manifest_path, e);
+            exit(exit_code::CONFIG_ERROR);
+        }
+    };
+    // each image (or frame) loaded once:
+    let mut by_image: BTreeMap<(&str, Option<u32>), Vec<&PriorCrop>> = BTreeMap::new();
+    for crop in &crops {
+        by_image.entry((&crop.image, crop.frame)).or_default().push(crop);
+    }
+    let by_image: Vec<_> = by_image.into_iter().collect();
+    println!(
+        "cropping again {} of {} crops, from {} images",
+        crops.len(),
+        lines.len(),
+        by_image.len()
+    );
+
+    let storage = Storage::new(None, None);
+    let recrop_image = |image: &str, frame: Option<u32>, crops: &[&PriorCrop]| {
+        let img = match frame {
+            _ if download::is_url(image) => Err("cannot load images by URL".to_string()),
+            Some(frame) => load_frames(&storage, image, FrameSelection::Index(frame))
+                .map(|(mut frames, _)| frames.remove(0).1)
+                .map_err(|e| format!("{:?}", e)),
+            None => load_image(&storage, image).map_err(|e| format!("{:?}", e)),
+        }?;
+        let mut sums = Vec::new();
+        for crop in crops {
+            let PixelRect { xmin, ymin, xmax, ymax } = crop.bndbox;
+            if xmax > img.width() || ymax > img.height() || xmin >= xmax || ymin >= ymax {
+                return Err(format!("box {:?} not in the image, for {:?}", crop.bndbox, crop.crop));
+            }
+            let cropped = crop_image(&img, xmin, ymin, xmax - xmin, ymax - ymin);
+            let cropped = match resize {
+                Some((width, height)) => resize_image(&cropped, width, height).unwrap(),
+                None => cropped,
+            };
+            let bytes = save_image(&storage, &cropped, &crop.crop, true, &[], None)
+                .map_err(|e| format!("cannot save {:?}: {:?}", crop.crop, e))?;
+            sums.push((crop.line, checksum::to_hex(&checksum::sha256(&bytes))));
+        }
+        Ok(sums)
+    };
+
+    let cores = auto_threads(false).min(by_image.len()).max(1);
+    let chunk_size = by_image.len().div_ceil(cores).max(1);
+    let results: Vec<Result<Vec<(usize, String)>, String>> = thread::scope(|s| {
+        let handles: Vec<_> = by_image
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let recrop_image = &recrop_image;
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|((image, frame), crops)| {
+                            recrop_image(image, *frame, crops).map_err(|e| {
+                                eprintln!("ERROR: {:?}: {}", image, e);
+                                e
+                            })
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    });
+
+    let mut written = 0;
+    let mut updated = false;
+    for (line, sum) in results.iter().flatten().flatten() {
+        written += 1;
+        if let Some(sha256) = lines[*line].get_mut("sha256") {
+            *sha256 = sum.clone().into();
+            updated = true;
+        }
+    }
+    if updated {
+        if let Err(e) = manifest::rewrite(manifest_path, &lines) {
+            eprintln!("ERROR: cannot update manifest {:?}: {}", manifest_path, e);
+            exit(exit_code::SOME_FAILED);
+        }
+    }
+    println!("{} crops written", written);
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    if failed > 0 {
+        eprintln!("{} images failed", failed);
+        exit(exit_code::SOME_FAILED);
+    }
+}
+
+/// Sets the options for the format detected under the --input directory.
+fn resolve_input(opts: &mut Opts, input: &Path) -> Result<(), String> {
+    let detected = detect::detect(input);
+    println!("detected under {:?}:", input);
+    for d in &detected {
+        println!("  {:?}", d);
+    }
+    let chosen = match opts.input_format {
+        Some(format) => detected.into_iter().find(|d| d.format() == format),
+        None => detected.into_iter().next(),
+    };
+    let chosen = chosen.ok_or_else(|| match opts.input_format {
+        Some(format) => format!("no {:?} annotations detected under {:?}", format, input),
+        None => format!("no supported annotations detected under {:?}", input),
+    })?;
+    println!(
+        "using {:?} (use --input-format to override)",
+        chosen.format()
+    );
+    match chosen {
+        Detected::Voc { dir } => opts.pascal = Some(dir),
+        Detected::Yolo {
+            image_dir,
+            label_dir,
+            names,
+        } => opts.yolo = Some(vec![image_dir, label_dir, names]),
+        #[cfg(feature = "coco")]
+        Detected::Coco { file } => opts.coco = Some(file),
+        #[cfg(feature = "supervisely")]
+        Detected::Supervisely { dir } => opts.supervisely = Some(dir),
+    }
+    Ok(())
+}
+
+/// The source of the annotations according to options.
+fn annotation_source(opts: &Opts) -> Box<dyn AnnotationSource + '_> {
+    if let Some(dir) = &opts.pascal {
+        return Box::new(pascal::PascalSource { dir });
+    }
+    #[cfg(feature = "coco")]
+    if let Some(file) = &opts.coco {
+        return Box::new(coco::CocoSource {
+            file,
+            image_dir: opts.image_dir.as_deref(),
+        });
+    }
+    #[cfg(feature = "supervisely")]
+    if let Some(dir) = &opts.supervisely {
+        return Box::new(supervisely::SuperviselySource { dir });
+    }
+    #[cfg(feature = "via")]
+    if let Some(file) = &opts.via {
+        return Box::new(via::ViaSource {
+            file,
+            image_dir: opts.image_dir.as_deref(),
+            label_key: opts.via_label_key.as_deref(),
+        });
+    }
+    #[cfg(feature = "mot")]
+    if let Some(mot) = &opts.mot {
+        return Box::new(mot::MotSource {
+            image_dir: &mot[0],
+            gt_file: &mot[1],
+            names_file: opts.mot_names.as_deref(),
+        });
+    }
+    let yolo = opts.yolo.as_ref().unwrap();
+    Box::new(yolo::YoloSource {
+        image_dir: &yolo[0],
+        label_dir: &yolo[1],
+        names_file: &yolo[2],
+        select_classes: opts.select_classes.clone(),
+        class_remap: opts.class_remap.as_deref(),
+        missing_labels: opts.missing_labels,
+        coord_policy: opts.yolo_coord_policy,
+        keep_polygons: opts.tight_bbox || opts.mask_crops,
+        threads: num_threads(opts),
+    })
+}
+
+/// Whether annotations are given, in any of the formats.
+fn has_annotation_source(opts: &Opts) -> bool {
+    #[allow(unused_mut)]
+    let mut given = opts.pascal.is_some() || opts.yolo.is_some();
+    #[cfg(feature = "coco")]
+    {
+        given |= opts.coco.is_some();
+    }
+    #[cfg(feature = "supervisely")]
+    {
+        given |= opts.supervisely.is_some();
+    }
+    #[cfg(feature = "via")]
+    {
+        given |= opts.via.is_some();
+    }
+    #[cfg(feature = "mot")]
+    {
+        given |= opts.mot.is_some();
+    }
+    given
+}
+
+/// Returns the annotations with the --proposals as objects, labeled by the
+/// annotated objects they overlap most, if annotations are given, or else by
+/// the --proposal-label.
+fn get_proposals(file: &Path, opts: &Opts) -> Vec<Annotation> {
+    let annotated = match has_annotation_source(opts) {
+        true => get_annotations(opts),
+        false if opts.proposal_label.is_some() => Vec::new(),
+        false => {
+            eprintln!("ERROR: --proposals requires annotations to label them, or --proposal-label");
+            exit(exit_code::CONFIG_ERROR);
+        }
+    };
+    let proposals = proposals::load(file).unwrap_or_else(|e| {
+        eprintln!("ERROR: invalid proposals: {}", e);
+        exit(exit_code::CONFIG_ERROR);
+    });
+    let count = proposals.len();
+    let paths: Vec<(String, &Annotation)> = annotated
+        .iter()
+        .map(|annotation| (get_image_path(annotation, opts), annotation))
+        .collect();
+    let folder = file.parent().unwrap_or(Path::new("")).to_string_lossy();
+    let folder = if folder.is_empty() { "." } else { &folder };
+    let (annotations, labeling) = proposals::label(
+        proposals,
+        folder,
+        &paths,
+        opts.proposal_label.as_deref(),
+        opts.proposal_min_iou,
+    );
+    println!("{} proposals in {} images from {:?}", count, annotations.len(), file);
+    labeling.report();
+    annotations
+}
+
+/// Shows the labels with their counts, and with `labels --suggest-merges`,
+/// the probable duplicates.
+fn report_labels(
+    annotations: &[Annotation],
+    suggest_merges: bool,
+    max_edits: usize,
+    label_map: Option<&Path>,
+) {
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for object in annotations.iter().flat_map(|a| a.objects.iter().flatten()) {
+        *counts.entry(object.name.clone()).or_insert(0) += 1;
+    }
+    let mut by_count: Vec<(&String, &usize)> = counts.iter().collect();
+    by_count.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    println!("{} labels:", counts.len());
+    for (label, count) in by_count {
+        println!("  {:>7} {:?}", count, label);
+    }
+    if !suggest_merges {
+        return;
+    }
+    println!();
+    let groups = aliases::suggest_merges(&counts, max_edits);
+    aliases::show(&groups);
+    if let Some(path) = label_map {
+        match aliases::write_label_map(path, &groups) {
+            Ok(count) => println!("Wrote {} label aliases to {:?}", count, path),
+            Err(e) => {
+                eprintln!("ERROR: cannot write {:?}: {}", path, e);
+                exit(exit_code::CONFIG_ERROR);
+            }
+        }
+    }
+}
+
+/// Shows the most frequent labels changed by --normalize-labels.
+fn show_normalized_labels(normalized: &BTreeMap<(String, String), usize>) {
+    const MAX_SHOWN: usize = 10;
+    if normalized.is_empty() {
+        return;
+    }
+    let mut changes: Vec<_> = normalized.iter().collect();
+    changes.sort_by(|a, b| b.1.cmp(a.1));
+    println!(
+        "labels normalized: {} objects, {} distinct labels",
+        changes.iter().map(|(_, count)| *count).sum::<usize>(),
+        changes.len()
+    );
+    for ((from, to), count) in changes.iter().take(MAX_SHOWN) {
+        println!("   {:>7} {:?} -> {:?}", count, from, to);
+    }
+    if changes.len() > MAX_SHOWN {
+        println!("   {:>7} other labels", changes.len() - MAX_SHOWN);
+    }
+}
+
+/// Shows the labels not found in the knowledge base, and those that are
+/// alternate names of a concept, replaced with --kb-correct.
+fn show_concepts(report: &KbReport, corrected: bool) {
+    println!(
+        "labels in the knowledge base: {} objects known, {} with alternate names, {} unknown",
+        report.num_objects(|c| *c == Concept::Known),
+        report.num_objects(|c| matches!(c, Concept::Alias(_))),
+        report.num_objects(|c| *c == Concept::Unknown),
+    );
+    for (label, (concept, count)) in &report.labels {
+        if let Concept::Alias(name) = concept {
+            match corrected {
+                true => println!("   {:>7} {:?} -> {:?}", count, label, name),
+                false => eprintln!(
+                    "WARN: {:?} ({} objects) is an alternate name of {:?} (see --kb-correct)",
+                    label, count, name
+                ),
+            }
+        }
+    }
+    for (label, (concept, count)) in &report.labels {
+        if *concept == Concept::Unknown {
+            eprintln!("WARN: unknown concept {:?} ({} objects)", label, count);
+        }
+    }
+}
+
+/// Returns a list of all annotations according to options.
+fn get_annotations(opts: &Opts) -> Vec<Annotation> {
+    let source = annotation_source(opts);
+    let labels = &opts.select_labels;
+    if labels.is_some() {
+        println!("labels: {:?}", labels);
+    }
+    let mut scan = ScanProgress::new(!opts.verbose && !opts.npb).with_strictness(opts.strictness());
+    let mut found: BTreeSet<String> = BTreeSet::new();
+    let mut normalized: BTreeMap<(String, String), usize> = BTreeMap::new();
+    let mut concepts = None;
+    let filtered = source.scan(&mut scan).map(|scanned| {
+        let normalize = |mut annotation: Annotation| {
+            for object in annotation.objects.iter_mut().flatten() {
+                let name = annotation::normalize_label(&object.name, opts.normalize_labels);
+                if name != object.name {
+                    let from = std::mem::replace(&mut object.name, name.clone());
+                    *normalized.entry((from, name)).or_insert(0) += 1;
+                }
+            }
+            annotation
+        };
+        // all of them, as the labels are looked up before the selection:
+        let mut scanned: Vec<Annotation> = scanned.map(normalize).collect();
+        if let Some(url) = &opts.kb_url {
+            match Kb::new(url).check(&mut scanned, opts.kb_correct) {
+                Ok(report) => concepts = Some(report),
+                Err(e) => {
+                    eprintln!("ERROR: cannot look up the labels in the knowledge base: {}", e);
+                    exit(exit_code::CONFIG_ERROR);
+                }
+            }
+        }
+        let mut annotations: Vec<Annotation> = Vec::new();
+        let mut skipped = 0u32;
+        for annotation in scanned {
+            if labels.is_some() {
+                for object in annotation.objects.iter().flatten() {
+                    if !found.contains(&object.name) {
+                        found.insert(object.name.clone());
+                    }
+                }
+            }
+            match annotation.with_filtered_objects(labels, opts.case_insensitive_labels) {
+                Some(annotation) => annotations.push(annotation),
+                None => skipped += 1,
+            }
+        }
+        (annotations, skipped)
+    });
+    scan.finish();
+    show_normalized_labels(&normalized);
+    if let Some(report) = &concepts {
+        show_concepts(report, opts.kb_correct);
+    }
+    if let Some(labels) = labels {
+        warn_unmatched_labels(labels, &found, opts.case_insensitive_labels);
+    }
+    let (annotations, skipped) = filtered.unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        exit(exit_code::CONFIG_ERROR);
+    });
+    println!(
+        "{} annotations: {} to be processed, {} skipped, {} invalid",
+        source.format(),
+        annotations.len(),
+        skipped,
+        scan.num_invalid()
+    );
+    if let Some(path) = &opts.list_invalid {
+        match scan.write_invalid(path) {
+            Ok(count) => println!("Wrote {} invalid annotation files to {:?}", count, path),
+            Err(e) => eprintln!("WARN: cannot write {:?}: {}", path, e),
+        }
+    }
+    if scan.num_recovered() > 0 {
+        println!("malformed contents left out (--lenient): {}", scan.num_recovered());
+    }
+    if opts.strict && scan.num_invalid() > 0 {
+        eprintln!(
+            "ERROR: {} invalid annotation files (--strict; see --list-invalid)",
+            scan.num_invalid()
+        );
+        exit(exit_code::SOME_FAILED);
+    }
+    annotations
+}
+
+/// Warns about the selected labels that match none of the labels found in the
+/// annotations, suggesting those differing only in case.
+fn warn_unmatched_labels(selected: &[String], found: &BTreeSet<String>, case_insensitive: bool) {
+    for label in selected {
+        let similar: Vec<&String> = found
+            .iter()
+            .filter(|f| f.to_lowercase() == label.to_lowercase())
+            .collect();
+        if found.contains(label) || (case_insensitive && !similar.is_empty()) {
+            continue;
+        }
+        match similar.is_empty() {
+            true => eprintln!("WARN: no objects with the selected label {:?}", label),
+            false => eprintln!(
+                "WARN: no objects with the selected label {:?}, but with {:?} \
+                 (see --case-insensitive-labels)",
+                label, similar
+            ),
+        }
+    }
+}
+
+/// Downloads the images referenced by URL, returning their local copies by URL.
+fn download_remote_images(annotations: &[Annotation], opts: &Opts) -> HashMap<String, PathBuf> {
+    let urls: BTreeSet<&str> = annotations
+        .iter()
+        .map(|annotation| annotation.filename.as_str())
+        .filter(|filename| download::is_url(filename))
+        .collect();
+    if urls.is_empty() {
+        return HashMap::new();
+    }
+    let urls: Vec<String> = urls.into_iter().map(str::to_string).collect();
+    let cache_dir = opts.cache_dir();
+    let needed = urls
+        .iter()
+        .map(|url| download::cache_path(&cache_dir, url))
+        .collect();
+    evict_cache(opts, &needed);
+    download::download_all(
+        &urls,
+        &cache_dir,
+        opts.download_jobs,
+        !opts.verbose && !opts.npb,
+    )
+}
+
+/// Applies --cache-size to the cache directory, keeping the given files.
+fn evict_cache(opts: &Opts, keep: &HashSet<PathBuf>) {
+    if let Some(gb) = opts.cache_size {
+        let (removed, freed) = download::evict(&opts.cache_dir(), (gb * 1e9) as u64, keep);
+        if removed > 0 {
+            println!(
+                "evicted {} cached images ({:.1} MB)",
+                removed,
+                freed as f64 / 1e6
+            );
+        }
+    }
+}
+
+/// Keeps the annotations having some object not cropped in the previous run.
+fn subtract_prior(
+    annotations: Vec<Annotation>,
+    prior: &PriorCrops,
+    opts: &Opts,
+) -> Vec<Annotation> {
+    let total = annotations.len();
+    let annotations: Vec<Annotation> = annotations
+        .into_iter()
+        .filter(|annotation| {
+            let image_path = get_image_path(annotation, opts);
+            let ids = annotation.object_ids(ObjectIds::Stable);
+            ids.iter().any(|id| !prior.contains(&image_path, id))
+        })
+        .collect();
+    println!(
+        "subtracting {} objects of the previous run: {} of {} annotations left",
+        prior.num_objects(),
+        annotations.len(),
+        total
+    );
+    annotations
+}
+
+/// Applies the policy to the inverted and zero-area boxes, reporting how many were found.
+fn guard_boxes(annotations: &mut [Annotation], policy: BoxPolicy) {
+    let (mut inverted, mut zero_area) = (0usize, 0usize);
+    for annotation in annotations.iter_mut() {
+        let Some(objects) = &mut annotation.objects else {
+            continue;
+        };
+        objects.retain_mut(|object| {
+            let defect = match object.bndbox.defect() {
+                Some(defect) => defect,
+                None => return true,
+            };
+            if policy == BoxPolicy::Error {
+                eprintln!(
+                    "ERROR: {:?} box {:?} of {:?} in {}/{}",
+                    defect, object.bndbox, object.name, annotation.folder, annotation.filename
+                );
+                exit(exit_code::CONFIG_ERROR);
+            }
+            match defect {
+                BoxDefect::Inverted => inverted += 1,
+                BoxDefect::ZeroArea => zero_area += 1,
+            }
+            if policy == BoxPolicy::Swap && defect == BoxDefect::Inverted {
+                object.bndbox = object.bndbox.normalized();
+                // still zero-area once swapped:
+                return object.bndbox.defect().is_none();
+            }
+            false
+        });
+    }
+    if inverted > 0 || zero_area > 0 {
+        let action = match policy {
+            BoxPolicy::Swap => "swapped",
+            _ => "skipped",
+        };
+        println!(
+            "defective boxes: {} inverted ({}), {} zero-area (skipped)",
+            inverted, action, zero_area
+        );
+    }
+}
+
+/// Replaces the boxes of the objects having a (non-empty) mask with the bounds of the mask.
+fn tighten_boxes(annotations: &mut [Annotation]) {
+    let mut tightened = 0usize;
+    for object in annotations
+        .iter_mut()
+        .flat_map(|a| a.objects.iter_mut().flatten())
+    {
+        if let Some(bounds) = object.mask.as_ref().map(|m| m.bounds()) {
+            if !bounds.is_empty() && Bndbox::from(bounds) != object.bndbox {
+                object.bndbox = bounds.into();
+                tightened += 1;
+            }
+        }
+    }
+    println!("boxes recomputed from masks: {}", tightened);
+}
+
+/// Applies --scale-boxes and --global-offset to all the boxes.
+fn transform_boxes(annotations: &mut [Annotation], opts: &Opts) {
+    let scale = opts.scale_boxes.unwrap_or(1.);
+    let (dx, dy) = opts.global_offset.unwrap_or((0, 0));
+    println!(
+        "transforming boxes: scale {}, offset ({}, {})",
+        scale, dx, dy
+    );
+    for annotation in annotations {
+        // limited to the image, when its size is known from the annotation:
+        let (width, height) = annotation
+            .image_size
+            .map_or((f64::INFINITY, f64::INFINITY), |size| {
+                (size.width as f64, size.height as f64)
+            });
+        for object in annotation.objects.iter_mut().flatten() {
+            object.bndbox = object
+                .bndbox
+                .scale(scale, scale)
+                .translate(dx as f64, dy as f64)
+                .clamp(width, height);
+        }
+    }
+}
+
+/// Drops the objects mostly inside excluded regions, and any annotations left without objects.
+/// Keeps only the objects contained in a box of the given label.
+fn keep_contained(annotations: Vec<Annotation>, host: &str, containment: f64) -> Vec<Annotation> {
+    let mut skipped = 0usize;
+    let annotations: Vec<Annotation> = annotations
+        .into_iter()
+        .filter_map(|mut annotation| {
+            if let Some(objects) = &mut annotation.objects {
+                let before = objects.len();
+                let contained: Vec<bool> = objects
+                    .iter()
+                    .map(|o| overlap::is_contained_in(o, objects, host, containment))
+                    .collect();
+                let mut contained = contained.into_iter();
+                objects.retain(|_| contained.next().unwrap());
+                skipped += before - objects.len();
+                if objects.is_empty() {
+                    return None;
+                }
+            }
+            Some(annotation)
+        })
+        .collect();
+    println!(
+        "objects not contained in a \"{}\" box: {} skipped",
+        host, skipped
+    );
+    annotations
+}
+
+/// Timestamp of the image of the annotation, from its filename.
+fn image_timestamp(annotation: &Annotation, pattern: &TimePattern) -> Option<Timestamp> {
+    let filename = annotation.filename.rsplit(['/', '\\']).next().unwrap();
+    pattern.timestamp(filename)
+}
+
+/// Keeps the annotations of the images with timestamps in the range.
+fn keep_time_range(
+    annotations: Vec<Annotation>,
+    pattern: &TimePattern,
+    range: &TimeRange,
+) -> Vec<Annotation> {
+    let total = annotations.len();
+    let mut untimed = 0usize;
+    let annotations: Vec<Annotation> = annotations
+        .into_iter()
+        .filter(|annotation| match image_timestamp(annotation, pattern) {
+            Some(timestamp) => range.contains(&timestamp),
+            None => {
+                untimed += 1;
+                false
+            }
+        })
+        .collect();
+    if untimed > 0 {
+        eprintln!(
+            "WARN: {} images without a timestamp matching --time-pattern",
+            untimed
+        );
+    }
+    println!(
+        "annotations outside the time range: {} skipped ({} without a timestamp)",
+        total - annotations.len(),
+        untimed
+    );
+    annotations
+}
+
+/// Reads the georeferencing of the GeoTIFF images, by image path, converting
+/// the boxes from map coordinates to pixels with `--geo-boxes`.
+#[cfg(feature = "geotiff")]
+fn georeference(
+    annotations: Vec<Annotation>,
+    opts: &Opts,
+) -> (Vec<Annotation>, HashMap<String, GeoTransform>) {
+    let mut georef = HashMap::new();
+    let mut skipped = 0usize;
+    let annotations: Vec<Annotation> = annotations
+        .into_iter()
+        .filter_map(|mut annotation| {
+            let image_path = get_image_path(&annotation, opts);
+            let transform = if geo::is_tiff(&image_path) && !download::is_url(&image_path) {
+                geo::read_geotiff(Path::new(&image_path)).unwrap_or_else(|e| {
+                    eprintln!("WARN: cannot read georeferencing of {}: {}", image_path, e);
+                    None
+                })
+            } else {
+                None
+            };
+            if opts.geo_boxes {
+                let Some(transform) = &transform else {
+                    skipped += 1;
+                    return None;
+                };
+                for object in annotation.objects.iter_mut().flatten() {
+                    match transform.pixels(&object.bndbox) {
+                        Some(bndbox) => object.bndbox = bndbox,
+                        None => {
+                            skipped += 1;
+                            return None;
+                        }
+                    }
+                }
+            }
+            if let Some(transform) = transform {
+                georef.insert(image_path, transform);
+            }
+            Some(annotation)
+        })
+        .collect();
+    if opts.geo_boxes {
+        println!(
+            "annotations of images without georeferencing: {} skipped",
+            skipped
+        );
+    }
+    (annotations, georef)
+}
+
+fn skip_excluded(
+    annotations: Vec<Annotation>,
+    exclusion: &Exclusion,
+    quantization: Quantization,
+) -> Vec<Annotation> {
+    let mut skipped = 0usize;
+    let annotations: Vec<Annotation> = annotations
+        .into_iter()
+        .filter_map(|mut annotation| {
+            if let Some(objects) = &mut annotation.objects {
+                let before = objects.len();
+                objects
+                    .retain(|o| !exclusion.is_mostly_excluded(&o.bndbox.to_pixels(quantization)));
+                skipped += before - objects.len();
+                if objects.is_empty() {
+                    return None;
+                }
+            }
+            Some(annotation)
+        })
+        .collect();
+    println!(
+        "objects mostly inside excluded regions: {} skipped",
+        skipped
+    );
+    annotations
+}
+
+fn show_annotation_summary(
+    annotations: &Vec<Annotation>,
+    rare_labels: &[(String, usize)],
+    pixel_size: Option<&PixelSize>,
+    opts: &Opts,
+) {
+    let mut labels: HashMap<String, usize> = HashMap::new();
+    let mut image_paths: HashMap<String, usize> = HashMap::new();
+    let mut total_objects = 0;
+    let mut bb_reporter = BndboxItemReporter::new(
+        opts.bb_info
+            .as_ref()
+            .map(|pb| pb.to_string_lossy().to_string()),
+    );
+    for annotation in annotations {
+        // (invalid pixel sizes are reported when cropping)
+        let pixel_size = pixel_size
+            .and_then(|p| p.of(&get_image_path(annotation, opts)).ok())
+            .flatten();
+        if let Some(objects) = &annotation.objects {
+            for object in objects {
+                let count = labels.entry(object.name.clone()).or_insert(0);
+                *count += 1;
+                total_objects += 1;
+                bb_reporter.add_item(annotation.filename.clone(), object, pixel_size);
+            }
+        }
+        let count = image_paths
+            .entry(get_image_path(annotation, opts).clone())
+            .or_insert(0);
+        *count += 1;
+    }
+    bb_reporter.save();
+    let mut labels: Vec<(&String, &usize)> = labels.iter().collect();
+    labels.sort_by(|a, b| b.1.cmp(a.1));
+
+    println!("\nSummary of loaded annotations:");
+
+    println!(
+        "  {} annotations with {} objects",
+        annotations.len(),
+        total_objects
+    );
+    println!("  {} labels:", labels.len());
+    for (label, count) in labels {
+        println!("   {:>5} \"{}\"", count, label);
+    }
+
+    let flag_counts: Vec<String> = skipped_flags(opts)
+        .iter()
+        .map(|(skip, flag)| {
+            let count = annotations
+                .iter()
+                .flat_map(|a| a.objects.iter().flatten())
+                .filter(|o| o.flag(flag))
+                .count();
+            let skipped = if *skip { " (skipped)" } else { "" };
+            format!("{} {}{}", count, flag, skipped)
+        })
+        .collect();
+    println!("  objects: {}", flag_counts.join(", "));
+
+    if !rare_labels.is_empty() {
+        let action = match &opts.merge_rare_into {
+            Some(other) => format!("merged into \"{}\"", other),
+            None => "skipped".to_string(),
+        };
+        println!(
+            "\n  {} labels with fewer than {} objects, {}:",
+            rare_labels.len(),
+            opts.min_label_count.unwrap(),
+            action
+        );
+        for (label, count) in rare_labels {
+            println!("   {:>5} \"{}\"", count, label);
+        }
+    }
+
+    // if any, show image paths referenced from multiple annotations:
+    let image_paths: Vec<(&String, &usize)> = image_paths.iter().collect();
+    let multi_images = image_paths.iter().filter(|(_, v)| **v > 1);
+    let count = multi_images.clone().count();
+    if count > 0 {
+        println!("\n  Images referenced in multiple annotations:");
+        for (image, count) in multi_images.clone() {
+            println!("    {:>5}  {}", count, image);
+        }
+    }
+
+    if opts.size_report {
+        show_size_report(annotations);
+    }
+    show_pose_report(annotations);
+    if let Some(pattern) = &opts.time_pattern {
+        show_time_report(annotations, pattern);
+    }
+    if let Some(csv_filename) = &opts.coverage_report {
+        show_coverage_report(annotations, opts, csv_filename);
+    }
+    if let Some(csv_filename) = &opts.cooccurrence {
+        show_cooccurrence(annotations, opts, csv_filename);
+    }
+    println!();
+}
+
+/// Shows the object counts by pose, if any pose is given.
+fn show_pose_report(annotations: &[Annotation]) {
+    let objects = || annotations.iter().flat_map(|a| a.objects.iter().flatten());
+    if !objects().any(|o| o.attributes.contains_key(annotation::POSE)) {
+        return;
+    }
+    let mut by_pose: HashMap<&str, usize> = HashMap::new();
+    for object in objects() {
+        *by_pose.entry(object.pose()).or_insert(0) += 1;
+    }
+    let mut poses: Vec<(&str, usize)> = by_pose.into_iter().collect();
+    poses.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(b.0)));
+    println!("\n  Objects by pose:");
+    for (pose, count) in poses {
+        println!("   {:>5} \"{}\"", count, pose);
+    }
+}
+
+/// Estimates the crops of the selected objects (and of their union, with
+/// `--union-crop`), by their sizes as given by the boxes and the crop options,
+/// calibrated with the encoded sizes of a few of them.
+fn estimate_output(
+    annotations: &[Annotation],
+    pixel_size: Option<&PixelSize>,
+    downloaded: &HashMap<String, PathBuf>,
+    opts: &Opts,
+) -> Estimate {
+    let quantization = opts.quantization();
+    // (the union crops are not windowed)
+    let size = |bndbox: &Bndbox, image_path: &str, windowed: bool| -> (u32, u32) {
+        let pixels = bndbox.to_pixels(quantization);
+        let window = (opts.fixed_crop, opts.min_crop);
+        let (width, height) = match window {
+            _ if !windowed => (pixels.width(), pixels.height()),
+            (Some(size), _) => size,
+            (None, Some((w, h))) => (pixels.width().max(w), pixels.height().max(h)),
+            (None, None) => (pixels.width(), pixels.height()),
+        };
+        let image_pixel_size = pixel_size.and_then(|p| p.of(image_path).ok().flatten());
+        match (&opts.resize, opts.normalize_scale, image_pixel_size) {
+            (Some(r), _, _) => (r[0], r[1]),
+            (None, Some(target), Some(pixel_size)) => {
+                scale::normalized_size(width, height, pixel_size, target)
+            }
+            _ => (width, height),
+        }
+    };
+    let bytes_per_pixel = match opts.bit_depth {
+        BitDepth::Sixteen => 6,
+        _ => 3,
+    };
+    let mut estimate = Estimate::new(bytes_per_pixel);
+    let mut crops: Vec<(&Annotation, usize, (u32, u32))> = Vec::new();
+    for annotation in annotations {
+        let image_path = get_image_path(annotation, opts);
+        let selected: Vec<(usize, &Object)> = annotation
+            .objects
+            .iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, object)| is_selected(opts, &opts.select_labels, object))
+            .collect();
+        for (i, object) in &selected {
+            let (width, height) = size(&object.bndbox, &image_path, true);
+            estimate.add(&object.name, width, height);
+            crops.push((annotation, *i, (width, height)));
+        }
+        if opts.union_crop {
+            let boxes = selected.iter().map(|(_, o)| o.bndbox);
+            if let Some(union) = boxes.reduce(|a, b| a.union(&b)) {
+                let (width, height) = size(&union.pad(opts.union_padding as f64), &image_path, false);
+                estimate.add(UNION_DIR, width, height);
+            }
+        }
+    }
+
+    let storage = Storage::new(opts.max_read_mbps, None);
+    for k in estimate::sample_indices(crops.len(), estimate::SAMPLES) {
+        let (annotation, i, (width, height)) = crops[k];
+        let object = &annotation.objects.as_ref().unwrap()[i];
+        let image_path = get_image_path(annotation, opts);
+        let local_path = downloaded
+            .get(&image_path)
+            .map_or(Path::new(&image_path), PathBuf::as_path);
+        let Ok(img) = load_image(&storage, local_path) else {
+            continue;
+        };
+        let pixels = object
+            .bndbox
+            .clamp(img.width() as f64, img.height() as f64)
+            .to_pixels(quantization);
+        let crop = crop_image(&img, pixels.xmin, pixels.ymin, pixels.width(), pixels.height());
+        let crop = match (crop.width(), crop.height()) == (width, height) {
+            true => Some(crop),
+            false => resize_image(&crop, width, height),
+        };
+        let id = &annotation.object_ids(opts.object_ids)[i];
+        let filename = get_crop_filename(annotation, object, id, None);
+        let format = ImageFormat::from_path(&filename).unwrap_or(ImageFormat::Png);
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        if let Some(crop) = crop.map(|crop| convert_bit_depth(crop, opts.bit_depth)) {
+            if crop.write_to(&mut bytes, format).is_ok() {
+                estimate.calibrate(bytes.get_ref().len() as u64, width as u64 * height as u64);
+            }
+        }
+    }
+    estimate
+}
+
+/// Checks the estimated crops against the free space of the output directory:
+/// exits if leaving less than `--min-free`, else warns if they do not fit.
+fn check_free_space(estimate: &Estimate, opts: &Opts) {
+    let projected = estimate.projected_bytes();
+    let free = match estimate::free_space(opts.output_dir()) {
+        Ok(free) => free,
+        Err(e) => {
+            if opts.min_free.is_some() {
+                eprintln!("WARN: cannot check the free space for --min-free: {}", e);
+            }
+            return;
+        }
+    };
+    let min_free = (opts.min_free.unwrap_or(0.) * 1e9) as u64;
+    if projected.saturating_add(min_free) <= free {
+        return;
+    }
+    let (projected, free) = (
+        estimate::format_bytes(projected),
+        estimate::format_bytes(free),
+    );
+    match opts.min_free {
+        Some(min_free) => {
+            eprintln!(
+                "ERROR: the crops (about {}) would leave less than {} GB free for {:?} ({} free)",
+                projected,
+                min_free,
+                opts.output_dir(),
+                free
+            );
+            exit(exit_code::CONFIG_ERROR);
+        }
+        None => eprintln!(
+            "WARN: the crops (about {}) may not fit in the {} free for {:?}",
+            projected,
+            free,
+            opts.output_dir()
+        ),
+    }
+}
+
+/// Shows the image and object counts by day and by hour of the day.
+fn show_time_report(annotations: &[Annotation], pattern: &TimePattern) {
+    let mut by_day: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+    let mut by_hour = [(0usize, 0usize); 24];
+    let mut untimed = 0usize;
+    for annotation in annotations {
+        let Some(timestamp) = image_timestamp(annotation, pattern) else {
+            untimed += 1;
+            continue;
+        };
+        let objects = annotation.objects.as_ref().map_or(0, Vec::len);
+        for counts in [
+            by_day.entry(timestamp.date()).or_default(),
+            &mut by_hour[timestamp.hour as usize],
+        ] {
+            counts.0 += 1;
+            counts.1 += objects;
+        }
+    }
+    println!("\n  Images by day:");
+    println!("    {:>7}{:>8} day", "images", "objects");
+    for (day, (images, objects)) in &by_day {
+        println!("    {:>7}{:>8} {}", images, objects, day);
+    }
+    println!("\n  Images by hour:");
+    println!("    {:>7}{:>8} hour", "images", "objects");
+    for (hour, (images, objects)) in by_hour.iter().enumerate() {
+        if *images > 0 {
+            println!("    {:>7}{:>8} {:02}:00", images, objects, hour);
+        }
+    }
+    if untimed > 0 {
+        println!("    {:>7}{:>8} (no timestamp)", untimed, "");
+    }
+}
+
+fn show_size_report(annotations: &[Annotation]) {
+    const BUCKETS: usize = SizeBucket::ALL.len();
+    let mut by_label: HashMap<&String, [usize; BUCKETS]> = HashMap::new();
+    for object in annotations.iter().flat_map(|a| a.objects.iter().flatten()) {
+        let counts = by_label.entry(&object.name).or_insert([0; BUCKETS]);
+        counts[SizeBucket::of(&object.bndbox) as usize] += 1;
+    }
+    let mut labels: Vec<(&String, [usize; BUCKETS])> = by_label.into_iter().collect();
+    labels.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.iter().sum::<usize>()));
+
+    println!("\n  Objects by size:");
+    let header: Vec<String> = SizeBucket::ALL
+        .iter()
+        .map(|b| format!("{:>7}", b.name()))
+        .collect();
+    println!("    {} label", header.join(""));
+    let mut totals = [0usize; BUCKETS];
+    for (label, counts) in labels {
+        let cols: Vec<String> = counts.iter().map(|c| format!("{:>7}", c)).collect();
+        println!("    {} \"{}\"", cols.join(""), label);
+        for (total, count) in totals.iter_mut().zip(counts) {
+            *total += count;
+        }
+    }
+    let cols: Vec<String> = totals.iter().map(|c| format!("{:>7}", c)).collect();
+    println!("    {} total", cols.join(""));
+}
+
+/// Key of the split group of the object: the value of its image in the
+/// `--split-by-metadata` column, else as given by `--split-by`.
+fn split_key(
+    opts: &Opts,
+    metadata: Option<&MetadataColumn>,
+    image_path: &str,
+    track_id: Option<u32>,
+) -> String {
+    match metadata.and_then(|column| Some((&column.name, column.value(image_path)?))) {
+        Some((name, value)) => format!("{}={}", name, value),
+        None => split::group_key(opts.split_by, image_path, track_id),
+    }
+}
+
+/// Shows the images, objects, and partition of each value of the metadata
+/// column, and the split.
+fn show_metadata_split(
+    opts: &Opts,
+    annotations: &[Annotation],
+    column: &MetadataColumn,
+    split: &Split,
+) {
+    let mut by_value: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut missing = 0;
+    for annotation in annotations {
+        let image_path = get_image_path(annotation, opts);
+        let objects = annotation
+            .objects
+            .iter()
+            .flatten()
+            .filter(|object| is_selected(opts, &opts.select_labels, object))
+            .count();
+        match column.value(&image_path) {
+            Some(value) => {
+                let counts = by_value.entry(value).or_insert((0, 0));
+                counts.0 += 1;
+                counts.1 += objects;
+            }
+            None => missing += 1,
+        }
+    }
+    println!("\n  Images by {}:", column.name);
+    println!("    {:>7}{:>8} partition {}", "images", "objects", column.name);
+    for (value, (images, objects)) in &by_value {
+        let key = format!("{}={}", column.name, value);
+        let partition = split.partition(&key).unwrap_or("-");
+        println!(
+            "    {:>7}{:>8} {:<9} \"{}\"",
+            images, objects, partition, value
+        );
+    }
+    if missing > 0 {
+        eprintln!(
+            "WARN: {} images without {} in the metadata, split by {:?}",
+            missing, column.name, opts.split_by
+        );
+    }
+    split.report(&column.name);
+}
+
+fn show_overlap_report(annotations: &[Annotation], containment: f64, csv_filename: &Path) {
+    let overlaps = Overlaps::new(annotations, containment);
+    overlaps.report();
+    match overlaps.save(csv_filename) {
+        Ok(()) => println!("Wrote overlap report to {:?}", csv_filename),
+        Err(e) => eprintln!("ERROR: cannot write {:?}: {}", csv_filename, e),
+    }
+}
+
+fn show_cooccurrence(annotations: &[Annotation], opts: &Opts, csv_filename: &Path) {
+    let mut by_image: HashMap<String, BTreeSet<&str>> = HashMap::new();
+    for annotation in annotations {
+        by_image
+            .entry(get_image_path(annotation, opts))
+            .or_default()
+            .extend(annotation.objects.iter().flatten().map(|o| o.name.as_str()));
+    }
+    let cooccurrence = Cooccurrence::new(by_image.into_values());
+    cooccurrence.report();
+    match cooccurrence.save(csv_filename) {
+        Ok(normalized_filename) => println!(
+            "Wrote label co-occurrence to {:?} and {:?}",
+            csv_filename, normalized_filename
+        ),
+        Err(e) => eprintln!("ERROR: cannot write {:?}: {}", csv_filename, e),
+    }
+}
+
+fn show_coverage_report(annotations: &[Annotation], opts: &Opts, csv_filename: &Path) {
+    let annotated: Vec<PathBuf> = annotations
+        .iter()
+        .map(|a| PathBuf::from(get_image_path(a, opts)))
+        .collect();
+
+    let image_dirs: Vec<PathBuf> = match (&opts.image_dir, &opts.yolo) {
+        (Some(dir), _) => vec![dir.clone()],
+        (_, Some(yolo)) => vec![yolo[0].clone()],
+        _ => {
+            let mut dirs: Vec<PathBuf> = annotated
+                .iter()
+                .filter_map(|p| p.parent().map(Path::to_path_buf))
+                .collect();
+            dirs.sort();
+            dirs.dedup();
+            dirs
+        }
+    };
+    let on_disk: Vec<PathBuf> = image_dirs
+        .iter()
+        .flat_map(|dir| {
+            WalkDir::new(dir)
+                .into_iter()
+                .filter_map(|e| e.ok())
+                .filter(source::is_image)
+                .map(|e| e.into_path())
+        })
+        .collect();
+
+    let coverage = coverage::Coverage::new(&annotated, &on_disk);
+    coverage.report();
+    coverage.save(csv_filename);
+}
+
+fn get_image_path(annotation: &Annotation, opts: &Opts) -> String {
+    if download::is_url(&annotation.filename) {
+        return annotation.filename.clone();
+    }
+    match &opts.image_dir {
+        Some(dir) => format!("{}/{}", dir.to_str().unwrap(), annotation.filename),
+        None => format!("{}/{}", annotation.folder, annotation.filename),
+    }
+}
+
+/// Number of labels in the live tally above the progress bars.
+const TALLY_LABELS: usize = 10;
+
+fn progress_style() -> ProgressStyle {
+    ProgressStyle::with_template("{prefix:.bold.dim} {bar:40.green/yellow} {pos:>7}/{len:7}")
+        .unwrap()
+}
+
+/// Number of threads to use according to the options.
+fn num_threads(opts: &Opts) -> usize {
+    match opts.cores.unwrap_or(Cpus::Auto) {
+        Cpus::Auto => auto_threads(opts.physical_cores),
+        Cpus::Count(n) => n,
+    }
+}
+
+/// Processes the annotations, returning the number of annotations that failed,
+/// and the number of crops by label.
+fn process_annotations(
+    opts: &Opts,
+    annotations: &[Annotation],
+    exclusion: Option<&Exclusion>,
+    started: Instant,
+    run_id: &str,
+    prepared: Prepared,
+) -> (usize, HashMap<String, usize>) {
+    let cores = match prepared.batch {
+        Some(batch) if opts.cores.is_none() => (num_threads(opts) / batch.jobs).max(1),
+        _ => num_threads(opts),
+    };
+    let cores = cores.min(annotations.len());
+    let result = do_process_annotations(
+        opts,
+        annotations,
+        exclusion,
+        cores,
+        run_id,
+        prepared,
+    );
+    let elapsed = started.elapsed();
+    if elapsed > Duration::from_secs(1) {
+        println!("(Done in {})", HumanDuration(elapsed));
+    }
+    result
+}
+
+fn do_process_annotations(
+    opts: &Opts,
+    annotations: &[Annotation],
+    exclusion: Option<&Exclusion>,
+    cores: usize,
+    run_id: &str,
+    prepared: Prepared,
+) -> (usize, HashMap<String, usize>) {
+    debug!("dispatching process in {} threads", cores);
+
+    let labels = || -> Vec<String> {
+        annotations
+            .iter()
+            .flat_map(|a| a.objects.iter().flatten())
+            .map(|o| o.name.clone())
+            .collect()
+    };
+    let outputs = Outputs::create(opts, labels).unwrap_or_else(|e| {
+        eprintln!("ERROR: {}", e);
+        exit(exit_code::CONFIG_ERROR);
+    });
+    let batch = prepared.batch;
+    let shared = Shared::new(opts, annotations, exclusion, cores, run_id, outputs, prepared);
+    let shared = &shared;
+    if let Some(batch) = batch {
+        // the images read through the cache shared with the other jobs
+        let paths = annotations
+            .iter()
+            .filter(|_| opts.frame.is_none())
+            .map(|annotation| {
+                local_image_path(shared, &get_image_path(annotation, opts)).to_path_buf()
+            });
+        batch.will_read(paths);
+    }
+    precreate_class_dirs(opts, annotations, shared);
+
+    let cores = cores.min(annotations.len());
+    let num_annotations = annotations.len();
+    let annotations_per_thread = num_annotations / cores;
+    let extra_annotations_last_thread = num_annotations % cores;
+
+    let (tx, rx) = mpsc::channel();
+    shared
+        .metrics
+        .annotations
+        .store(annotations.len() as u64, Ordering::Relaxed);
+    let metrics_listener = opts.metrics_port.map(|port| {
+        let addr = (opts.metrics_addr.as_str(), port);
+        match std::net::TcpListener::bind(addr) {
+            Ok(listener) => {
+                println!(
+                    "serving metrics at http://{}:{}/metrics\n",
+                    opts.metrics_addr, port
+                );
+                listener
+            }
+            Err(e) => {
+                eprintln!(
+                    "ERROR: cannot serve metrics on {}:{}: {}",
+                    opts.metrics_addr, port, e
+                );
+                exit(exit_code::CONFIG_ERROR);
+            }
+        }
+    });
+
+    let done = AtomicBool::new(false);
+    thread::scope(|s| {
+        if let Some(monitor) = &shared.monitor {
+            s.spawn(|| monitor.run(&done, &shared.metrics.labels));
+        }
+        if let Some(listener) = &metrics_listener {
+            s.spawn(|| {
+                metrics::serve(listener, &done, || {
+                    let (bytes_read, bytes_written) = shared.storage.bytes();
+                    shared.metrics.render(bytes_read, bytes_written)
+                })
+            });
+        }
+        let mut workers = Vec::new();
+        let m = MultiProgress::new();
+        m.set_move_cursor(true);
+        m.set_draw_target(indicatif::ProgressDrawTarget::stdout_with_hz(1));
+        let sty = progress_style();
+        let show_bars = !opts.verbose && !opts.npb && !opts.tui;
+        if show_bars {
+            // live tally of the crops above the progress bars:
+            let header = m.add(ProgressBar::new_spinner());
+            header.set_style(ProgressStyle::with_template("{msg}").unwrap());
+            let done = &done;
+            s.spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    header.set_message(shared.metrics.labels.summary(TALLY_LABELS));
+                    thread::sleep(Duration::from_millis(250));
+                }
+                header.finish_and_clear();
+            });
+        }
+
+        for th in 0..cores {
+            let section_lo = th * annotations_per_thread;
+            let section_hi = section_lo + annotations_per_thread + {
+                if th == cores - 1 {
+                    extra_annotations_last_thread
+                } else {
+                    0
+                }
+            };
+
+            if section_lo < section_hi {
+                let pb = if show_bars {
+                    let pb = m.add(ProgressBar::new((section_hi - section_lo) as u64));
+                    pb.set_style(sty.clone());
+                    pb.set_prefix(format!("[{:>02}]", th));
+                    Some(pb)
+                } else {
+                    None
+                };
+
+                let c_tx = tx.clone();
+                workers.push(s.spawn(move || {
+                    if opts.low_priority {
+                        lower_thread_priority();
+                    }
+                    let section = &annotations[section_lo..section_hi];
+                    let result = process_section(opts, section, th, pb, shared);
+                    c_tx.send(result).unwrap();
+                }));
+            }
+        }
+        // the dashboard, if any, is to end even if a worker panics:
+        let joined: Vec<_> = workers.into_iter().map(|w| w.join()).collect();
+        let panicked = joined.iter().any(Result::is_err);
+        done.store(true, Ordering::Relaxed);
+        if panicked {
+            panic!("a processing thread panicked");
+        }
+    });
+
+    drop(tx);
+    shared.storage.sync();
+    shared.outputs.finish();
+    if let Some(checkpoint) = &shared.checkpoint {
+        checkpoint.remove();
+    }
+
+    let mut by_label: HashMap<String, usize> = HashMap::new();
+    let mut sum_crops = 0usize;
+    let mut failed = 0usize;
+    for (by_label_child, failed_child) in &rx {
+        for (label, count) in by_label_child {
+            let entry = by_label.entry(label).or_insert(0);
+            *entry += count;
+            sum_crops += count;
+        }
+        failed += failed_child;
+    }
+    println!("\nCompleted a total of {} crops.", sum_crops);
+    show_by_label(&by_label);
+    shared.quality.report();
+    if opts.min_crop.is_some() {
+        println!(
+            "Expanded {} boxes to the minimum crop size",
+            shared.metrics.boxes_expanded.load(Ordering::Relaxed)
+        );
+    }
+    if opts.max_aspect_ratio.is_some() {
+        let slivers = shared.slivers.lock().unwrap();
+        println!(
+            "Skipped {} objects exceeding the maximum aspect ratio",
+            slivers.values().sum::<usize>()
+        );
+        if !slivers.is_empty() {
+            show_by_label(&slivers);
+        }
+    }
+    (failed, by_label)
+}
+
+/// Lowers the scheduling priority of the calling thread (on Linux, the nice value is per thread).
+#[cfg(unix)]
+fn lower_thread_priority() {
+    // SAFETY: plain system call on the calling thread, no memory is involved.
+    let res = unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, 10) };
+    if res != 0 {
+        eprintln!(
+            "WARN: cannot lower thread priority: {:?}",
+            std::io::Error::last_os_error()
+        );
+    }
+}
+
+#[cfg(not(unix))]
+fn lower_thread_priority() {
+    eprintln!("WARN: --low-priority not supported on this platform");
+}
+
+/// What the steps before the processing leave for it.
+struct Prepared<'a> {
+    /// With `--filter-model`.
+    classifier: Option<&'a dyn CropClassifier>,
+    prior: PriorCrops,
+    downloaded: HashMap<String, PathBuf>,
+    georef: HashMap<String, GeoTransform>,
+    split_metadata: Option<MetadataColumn>,
+    pixel_size: Option<PixelSize>,
+    batch: Option<&'a Batch<'a>>,
+}
+
+/// Resources shared by the processing threads.
+struct Shared<'a> {
+    storage: Storage,
+    classifier: Option<&'a dyn CropClassifier>,
+    exclusion: Option<&'a Exclusion>,
+    quality: QualityFilter,
+    outputs: Outputs,
+    split: Option<Split>,
+    /// Metadata of the images to split by, instead of `--split-by`.
+    split_metadata: Option<MetadataColumn>,
+    /// Directory names for the labels, where different from the label.
+    class_dirs: HashMap<String, String>,
+    /// Tags added to the crop filenames of the images whose filenames collide.
+    filename_tags: HashMap<String, String>,
+    run_id: String,
+    /// With `--scrub-paths`.
+    scrubber: Option<Scrubber>,
+    monitor: Option<Monitor>,
+    metrics: Metrics,
+    /// Objects skipped for exceeding `--max-aspect-ratio`, by label.
+    slivers: Mutex<HashMap<String, usize>>,
+    /// Objects already cropped, with `subtract`.
+    prior: PriorCrops,
+    /// Local copies of the images referenced by URL.
+    downloaded: HashMap<String, PathBuf>,
+    /// Georeferencing of the images, by path.
+    georef: HashMap<String, GeoTransform>,
+    /// Physical scale of the images.
+    pixel_size: Option<PixelSize>,
+    /// Decoded images shared with the other jobs of a batch.
+    images: Option<&'a ImageCache>,
+    checkpoint: Option<Checkpoint>,
+}
+
+impl<'a> Shared<'a> {
+    /// Sets up the resources for processing the annotations in `cores` threads,
+    /// reporting the split if requested.
+    fn new(
+        opts: &Opts,
+        annotations: &[Annotation],
+        exclusion: Option<&'a Exclusion>,
+        cores: usize,
+        run_id: &str,
+        outputs: Outputs,
+        prepared: Prepared<'a>,
+    ) -> Shared<'a> {
+        let split_metadata = prepared.split_metadata.as_ref();
+        let split = opts.split.as_ref().map(|spec| {
+            let split = Split::new(
+                spec,
+                annotations.iter().flat_map(|annotation| {
+                    let image_path = get_image_path(annotation, opts);
+                    annotation
+                        .objects
+                        .iter()
+                        .flatten()
+                        .filter(|object| is_selected(opts, &opts.select_labels, object))
+                        .map(move |object| {
+                            let key = split_key(opts, split_metadata, &image_path, object.track_id);
+                            (key, object.name.as_str())
+                        })
+                }),
+            );
+            match split_metadata {
+                Some(column) => show_metadata_split(opts, annotations, column, &split),
+                None => split.report(&format!("{:?}", opts.split_by)),
+            }
+            println!();
+            split
+        });
+        Shared {
+            storage: Storage::new(opts.max_read_mbps, opts.max_write_mbps)
+                .with_sync(opts.sync)
+                .with_remote(Remote::parse(&opts.output_dir().to_string_lossy()).unwrap()),
+            classifier: prepared.classifier,
+            exclusion,
+            quality: QualityFilter::new(opts.min_sharpness, opts.exposure_range),
+            outputs,
+            split,
+            class_dirs: get_class_dirs(opts, annotations),
+            filename_tags: get_filename_tags(opts, annotations),
+            run_id: run_id.to_string(),
+            scrubber: opts.scrub_paths.then(Scrubber::new),
+            monitor: opts.tui.then(|| Monitor::new(cores)),
+            metrics: Metrics::default(),
+            slivers: Mutex::new(HashMap::new()),
+            prior: prepared.prior,
+            downloaded: prepared.downloaded,
+            georef: prepared.georef,
+            pixel_size: prepared.pixel_size,
+            split_metadata: prepared.split_metadata,
+            images: prepared.batch.map(|batch| batch.images),
+            checkpoint: (opts.checkpoint_every.is_some() || opts.checkpoint_secs.is_some()).then(
+                || {
+                    Checkpoint::new(
+                        opts.output_dir(),
+                        cores,
+                        annotations.len(),
+                        opts.checkpoint_every,
+                        opts.checkpoint_secs.map(Duration::from_secs),
+                    )
+                },
+            ),
+        }
+    }
+
+    /// Reports a problem with an image or crop, on the dashboard if shown.
+    fn report(&self, message: String) {
+        match &self.monitor {
+            Some(monitor) => monitor.error(message),
+            None => eprintln!("{}", message),
+        }
+    }
+
+    /// The path of an image or crop as written in the outputs.
+    fn exported(&self, path: &str) -> String {
+        match &self.scrubber {
+            Some(scrubber) => scrubber.path(path),
+            None => path.to_string(),
+        }
+    }
+
+    /// Name of the directory for the crops of the given label.
+    fn class_dir<'s>(&'s self, label: &'s str) -> &'s str {
+        self.class_dirs.get(label).map_or(label, String::as_str)
+    }
+
+    /// Partition for the crop of the given object, if splitting.
+    fn partition(&self, opts: &Opts, image_path: &str, object: &Object) -> Option<&str> {
+        self.split.as_ref().and_then(|split| {
+            split.partition(&split_key(
+                opts,
+                self.split_metadata.as_ref(),
+                image_path,
+                object.track_id,
+            ))
+        })
+    }
+
+    /// Partition of the crops of the image as a whole (eg., its windows),
+    /// grouped as its untracked objects are.
+    fn image_partition(&self, opts: &Opts, image_path: &str) -> Option<&str> {
+        self.split.as_ref().map(|split| {
+            let metadata = self.split_metadata.as_ref();
+            split.partition_or_hash(&split_key(opts, metadata, image_path, None))
+        })
+    }
+}
+
+/// Processes a section of the annotations, returning the crop counts by label
+/// and the number of annotations that failed.
+fn process_section(
+    opts: &Opts,
+    annotations: &[Annotation],
+    th: usize,
+    pb: Option<ProgressBar>,
+    shared: &Shared,
+) -> (HashMap<String, usize>, usize) {
+    let mut by_label: HashMap<String, usize> = HashMap::new();
+    let mut sum_crops = 0usize;
+    let mut failed = 0usize;
+
+    for (i, annotation) in annotations.iter().enumerate() {
+        let (num_crops, ok) = process_annotation(
+            annotation,
+            opts,
+            &opts.select_labels,
+            &mut by_label,
+            shared,
+            opts.verbose,
+        );
+        sum_crops += num_crops;
+        Metrics::inc(&shared.metrics.processed);
+        if !ok {
+            failed += 1;
+            Metrics::inc(&shared.metrics.failed);
+        }
+        if let Some(checkpoint) = &shared.checkpoint {
+            if checkpoint.update(th, i + 1, failed, &by_label) {
+                shared.outputs.manifest.flush();
+            }
+        }
+
+        if let Some(monitor) = &shared.monitor {
+            monitor.update(th, i + 1, annotations.len(), failed);
+        } else if let Some(ref pb) = pb {
+            pb.inc(1);
+        } else if i % 10 == 0 {
+            println!(
+                "[{:>02}] Processing annotation {} of {}  ({} crops so far)",
+                th,
+                i + 1,
+                annotations.len(),
+                sum_crops
+            );
+        }
+    }
+
+    (by_label, failed)
+}
+
+fn show_by_label(by_label: &HashMap<String, usize>) {
+    let mut labels: Vec<(&String, &usize)> = by_label.iter().collect();
+    labels.sort_by(|a, b| b.1.cmp(a.1));
+    let mut tot_crops = 0usize;
+    for (label, total) in labels {
+        let quoted = format!("\"{}\"", label);
+        println!("  {total:>5} {quoted:<40}");
+        tot_crops += total;
+    }
+    println!("  {tot_crops:>5} total");
+}
+
+/// The path of the image to read: the download of a remote image.
+fn local_image_path<'p>(shared: &'p Shared, image_path: &'p str) -> &'p Path {
+    shared
+        .downloaded
+        .get(image_path)
+        .map_or(Path::new(image_path), PathBuf::as_path)
+}
+
+/// Whether the object is to be cropped according to the options.
+fn is_selected(opts: &Opts, labels: &Option<Vec<String>>, object: &Object) -> bool {
+    is_candidate(opts, labels, object) && !is_sliver(opts, object)
+}
+
+/// Whether the box of the object exceeds `--max-aspect-ratio`.
+fn is_sliver(opts: &Opts, object: &Object) -> bool {
+    opts.max_aspect_ratio
+        .is_some_and(|max_ar| object.bndbox.aspect_ratio() > max_ar)
+}
+
+/// Whether the object is selected by the options other than `--max-aspect-ratio`.
+fn is_candidate(opts: &Opts, labels: &Option<Vec<String>>, object: &Object) -> bool {
+    if object.bndbox.to_pixels(opts.quantization()).is_empty() {
+        return false;
+    }
+    if let Some(labels) = &labels {
+        let accept_name =
+            annotation::is_label_in(&object.name, labels, opts.case_insensitive_labels);
+        if !accept_name {
+            return false;
+        }
+    };
+    for (skip, flag) in skipped_flags(opts) {
+        if skip && object.flag(flag) {
+            return false;
+        }
+    }
+    object.has_attributes(&opts.attributes)
+}
+
+/// The object flags, with whether to skip the objects having them.
+fn skipped_flags(opts: &Opts) -> [(bool, &'static str); 3] {
+    [
+        (opts.skip_difficult, annotation::DIFFICULT),
+        (opts.skip_truncated, annotation::TRUNCATED),
+        (opts.skip_occluded, annotation::OCCLUDED),
+    ]
+}
+
+/// Returns the output directory, or the given partition under it.
+fn get_out_base_dir(opts: &Opts, partition: Option<&str>) -> PathBuf {
+    match partition {
+        Some(partition) => opts.output_dir().join(partition),
+        None => opts.output_dir().to_path_buf(),
+    }
+}
+
+/// Directory names for the selected labels, per --imagefolder-indices (also
+/// writing the classes.txt mapping) or --label-sanitize.
+fn get_class_dirs(opts: &Opts, annotations: &[Annotation]) -> HashMap<String, String> {
+    let labels: BTreeSet<String> = annotations
+        .iter()
+        .flat_map(|a| a.objects.iter().flatten())
+        .filter(|object| is_selected(opts, &opts.select_labels, object))
+        .map(|object| object.name.clone())
+        .collect();
+    if opts.imagefolder_indices {
+        let index = ClassIndex::new(labels.iter().cloned());
+        match index.write(opts.output_dir()) {
+            Ok(path) => println!("Wrote class index to {:?}\n", path),
+            Err(e) => eprintln!("WARN: cannot write class index: {}", e),
+        }
+        return labels
+            .into_iter()
+            .map(|label| {
+                let dir = index.dir(&label).unwrap().to_string();
+                (label, dir)
+            })
+            .collect();
+    }
+    let mut by_dir: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    for label in &labels {
+        let dir = annotation::sanitize_label(label, opts.label_sanitize);
+        by_dir.entry(dir).or_default().push(label);
+    }
+    let mut class_dirs = HashMap::new();
+    for (dir, labels) in by_dir {
+        if labels.len() > 1 {
+            eprintln!(
+                "WARN: labels {:?} share the output directory {:?}",
+                labels, dir
+            );
+        }
+        for label in labels.into_iter().filter(|label| *label != dir) {
+            class_dirs.insert(label.to_string(), dir.clone());
+        }
+    }
+    class_dirs
+}
+
+/// Creates upfront the class directories for the selected objects, so the
+/// processing threads don't contend on the filesystem for them.
+/// Directories for routed crops, unions and pairs are created as needed.
+fn precreate_class_dirs(opts: &Opts, annotations: &[Annotation], shared: &Shared) {
+    let mut dirs = HashSet::new();
+    for annotation in annotations {
+        let image_path = get_image_path(annotation, opts);
+        for object in annotation.objects.iter().flatten() {
+            if is_selected(opts, &opts.select_labels, object) {
+                let partition = shared.partition(opts, &image_path, object);
+                let class_dir = shared.class_dir(&object.name);
+                dirs.insert(get_out_class_dir(opts, partition, class_dir, object));
+            }
+        }
+    }
+    if let Some(shards) = opts.shard_output {
+        dirs = dirs
+            .into_iter()
+            .flat_map(|dir| (0..shards).map(move |shard| dir.join(format!("{:02x}", shard))))
+            .collect();
+    }
+    debug!("pre-creating {} class directories", dirs.len());
+    for dir in dirs {
+        if let Err(e) = shared.storage.create_dir(&dir) {
+            eprintln!("WARN: cannot create {:?}: {}", dir, e);
+        }
+    }
+}
+
+/// Returns the directory where the crops for the given object are stored,
+/// `class_dir` being the one for its label.
+/// Crops of tracked objects are grouped in a subdirectory per track.
+fn get_out_class_dir(
+    opts: &Opts,
+    partition: Option<&str>,
+    class_dir: &str,
+    object: &Object,
+) -> PathBuf {
+    let mut dir = get_out_base_dir(opts, partition);
+    if opts.bucket_by_size {
+        dir.push(SizeBucket::of(&object.bndbox).name());
+    }
+    dir.push(class_dir);
+    if opts.group_by_pose {
+        // the pose is free text, never used as is
+        let how = match opts.label_sanitize {
+            LabelSanitize::None => LabelSanitize::Slug,
+            how => how,
+        };
+        dir.push(annotation::sanitize_label(object.pose(), how));
+    }
+    if let Some(track_id) = object.track_id {
+        dir.push(format!("track_{}", track_id));
+    }
+    dir
+}
+
+/// Name of the subdirectory, among `shards`, for the crop with the given filename.
+fn get_shard(crop_filename: &str, shards: u16) -> String {
+    let hash = checksum::sha256(crop_filename.as_bytes());
+    format!("{:02x}", u16::from_be_bytes([hash[0], hash[1]]) % shards)
+}
+
+fn get_crop_filename(
+    annotation: &Annotation,
+    object: &Object,
+    id: &str,
+    tag: Option<&str>,
+) -> String {
+    match (object.track_id, annotation.frame, tag) {
+        (Some(_), Some(frame), None) => format!("frame_{}.png", frame),
+        (Some(_), Some(frame), Some(tag)) => format!("frame_{}_{}.png", frame, tag),
+        (_, _, None) => transform_filename(annotation.image_name(), id),
+        (_, _, Some(tag)) => transform_filename(&tag_filename(annotation.image_name(), tag), id),
+    }
+}
+
+/// Adds the tag to the file stem, eg., `a.jpg` -> `a_<tag>.jpg`.
+fn tag_filename(filename: &str, tag: &str) -> String {
+    let path = Path::new(filename);
+    let mut tagged = path.with_extension("").into_os_string();
+    tagged.push(format!("_{}", tag));
+    let mut tagged = PathBuf::from(tagged);
+    if let Some(ext) = path.extension() {
+        tagged.set_extension(ext);
+    }
+    tagged.to_string_lossy().to_string()
+}
+
+/// Tags for the images having the same filename as others (eg., `frame_0001.png`
+/// in different folders), whose crops would otherwise overwrite each other:
+/// a hash of the image folder.
+fn get_filename_tags(opts: &Opts, annotations: &[Annotation]) -> HashMap<String, String> {
+    let mut by_filename: HashMap<&str, BTreeSet<String>> = HashMap::new();
+    for annotation in annotations {
+        let mut objects = annotation.objects.iter().flatten();
+        if objects.any(|object| is_selected(opts, &opts.select_labels, object)) {
+            by_filename
+                .entry(annotation.image_name())
+                .or_default()
+                .insert(get_image_path(annotation, opts));
+        }
+    }
+    let mut tags = HashMap::new();
+    let mut collisions = 0;
+    for image_paths in by_filename.into_values().filter(|paths| paths.len() > 1) {
+        collisions += 1;
+        for image_path in image_paths {
+            let folder = Path::new(&image_path).parent().unwrap_or(Path::new(""));
+            let hash = checksum::sha256(folder.to_string_lossy().as_bytes());
+            tags.insert(image_path, checksum::to_hex(&hash[..4]));
+        }
+    }
+    if collisions > 0 {
+        println!(
+            "{} filenames shared by {} images in different folders; \
+             their crop filenames get a hash of the folder\n",
+            collisions,
+            tags.len()
+        );
+    }
+    tags
+}
+
+fn transform_filename(filename: &str, id: &str) -> String {
+    let mut path = PathBuf::from(filename);
+    path.set_extension("");
+    let adjusted = path.to_str().unwrap();
+    debug!(
+        "transform_filename: '{}' id={} => '{}'",
+        filename, id, adjusted
+    );
+    // Note: not to jpeg as in python version as some input PNGs would trigger:
+    //  Unsupported(UnsupportedError { format: Exact(Jpeg), kind: Color(Rgb16) })
+    format!("{}_{}.png", adjusted, id)
+}
+
+/// Name of the subdirectory (under the output directory) for union crops.
+const UNION_DIR: &str = "_union";
+
+fn transform_union_filename(filename: &str) -> String {
+    let mut path = PathBuf::from(filename);
+    path.set_extension("");
+    format!("{}_union.png", path.to_str().unwrap())
+}
+
+/// Name of the subdirectory (under the output directory) for pair crops.
+const PAIRS_DIR: &str = "_pairs";
+
+fn transform_pair_filename(filename: &str, id_a: &str, id_b: &str) -> String {
+    let mut path = PathBuf::from(filename);
+    path.set_extension("");
+    format!("{}_{}_{}.png", path.to_str().unwrap(), id_a, id_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn object(name: &str, attributes: &[(&str, &str)]) -> Object {
+        Object {
+            name: name.to_string(),
+            bndbox: Bndbox {
+                xmin: 0.,
+                ymin: 0.,
+                xmax: 10.,
+                ymax: 10.,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: attributes
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    fn annotation(folder: &str, filename: &str, objects: Vec<Object>) -> Annotation {
+        Annotation {
+            folder: folder.to_string(),
+            filename: filename.to_string(),
+            objects: Some(objects),
+            frame: None,
+            image_size: None,
+        }
+    }
+
+    fn opts(args: &[&str]) -> Opts {
+        let base = ["blaise", "-p", "in", "-o", "out"];
+        Opts::try_parse_from(base.iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn attribute_filters() {
+        let occluded = object("Aegina", &[("occluded", "true"), ("life-stage", "adult")]);
+        let visible = object("Aegina", &[("occluded", "false"), ("life-stage", "adult")]);
+        let bare = object("Aegina", &[]);
+        let selected = |args: &[&str]| {
+            let opts = opts(args);
+            [&occluded, &visible, &bare].map(|o| is_selected(&opts, &None, o))
+        };
+        assert_eq!(selected(&[]), [true, true, true]);
+        assert_eq!(selected(&["--attr", "occluded=false"]), [false, true, false]);
+        assert_eq!(selected(&["--attr", "life-stage=adult"]), [true, true, false]);
+        assert_eq!(
+            selected(&["--attr", "life-stage=adult", "--attr", "occluded=true"]),
+            [true, false, false]
+        );
+        assert_eq!(selected(&["--attr", "life-stage=larva"]), [false, false, false]);
+        assert_eq!(selected(&["--attr", "occluded="]), [false, false, false]);
+        for invalid in ["occluded", "=false"] {
+            assert!(Opts::try_parse_from(["blaise", "-p", "in", "--attr", invalid]).is_err());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn low_priority() {
+        // SAFETY: plain system calls on the calling thread.
+        let nice = || unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+        let (before, lowered) = std::thread::spawn(move || {
+            let before = nice();
+            lower_thread_priority();
+            (before, nice())
+        })
+        .join()
+        .unwrap();
+        assert_eq!(lowered, (before + 10).min(19));
+        // only the worker thread is affected:
+        assert_eq!(nice(), before);
+    }
+
+    #[test]
+    fn boxes_clamped_to_image_size() {
+        let mut annotations = [
+            annotation("d", "a.png", vec![object("Aegina", &[])]),
+            annotation("d", "b.png", vec![object("Aegina", &[])]),
+        ];
+        annotations[0].image_size = Some(imagesize::ImageSize {
+            width: 15,
+            height: 12,
+        });
+        transform_boxes(&mut annotations, &opts(&["--scale-boxes", "2"]));
+        let bndbox = |a: &Annotation| a.objects.as_ref().unwrap()[0].bndbox;
+        assert_eq!((bndbox(&annotations[0]).xmax, bndbox(&annotations[0]).ymax), (15., 12.));
+        assert_eq!((bndbox(&annotations[1]).xmax, bndbox(&annotations[1]).ymax), (20., 20.));
+    }
+
+    #[test]
+    fn max_aspect_ratio() {
+        let with_box = |width: f64, height: f64| {
+            let mut object = object("Aegina", &[]);
+            object.bndbox.xmax = width;
+            object.bndbox.ymax = height;
+            object
+        };
+        let objects = [with_box(10., 10.), with_box(30., 10.), with_box(2., 100.)];
+        let slivers = |args: &[&str]| {
+            let opts = opts(args);
+            for object in &objects {
+                assert!(is_candidate(&opts, &None, object));
+                assert_eq!(is_selected(&opts, &None, object), !is_sliver(&opts, object));
+            }
+            objects.each_ref().map(|o| is_sliver(&opts, o))
+        };
+        assert_eq!(slivers(&[]), [false, false, false]);
+        assert_eq!(slivers(&["--max-aspect-ratio", "3"]), [false, false, true]);
+        assert_eq!(slivers(&["--max-ar", "2.5"]), [false, true, true]);
+        assert_eq!(slivers(&["--max-aspect-ratio", "1"]), [false, true, true]);
+    }
+
+    #[test]
+    fn estimate_calibrated() {
+        let objects = vec![object("Aegina", &[]), object("Aegina", &[])];
+        let found = [annotation("data/imgs", "IMG_TEST.png", objects)];
+        let estimate = estimate_output(&found, None, &HashMap::new(), &opts(&[]));
+        assert_eq!((estimate.crops, estimate.pixels), (2, 200));
+        let (bytes, pixels) = estimate.encoded.unwrap();
+        assert_eq!(pixels, 200);
+        assert!(bytes > 0 && bytes != estimate.bytes());
+        assert_eq!(estimate.projected_bytes(), bytes);
+
+        let resized = estimate_output(&found, None, &HashMap::new(), &opts(&["-r", "20", "30"]));
+        assert_eq!(resized.pixels, 1200);
+        assert_eq!(resized.encoded.map(|(_, pixels)| pixels), Some(1200));
+
+        let missing = [annotation("no/such/dir", "IMG_TEST.png", vec![object("Aegina", &[])])];
+        let uncalibrated = estimate_output(&missing, None, &HashMap::new(), &opts(&[]));
+        assert_eq!(uncalibrated.encoded, None);
+        assert_eq!(uncalibrated.projected_bytes(), uncalibrated.bytes());
+    }
+
+    #[test]
+    fn argument_groups() {
+        use clap::error::ErrorKind;
+        let parse = |args: &[&str]| Opts::try_parse_from(["blaise", "-o", "out"].iter().chain(args));
+        let error = |args: &[&str]| parse(args).unwrap_err().kind();
+        assert!(parse(&["-p", "in"]).is_ok());
+        assert!(parse(&["--proposals", "p.csv"]).is_ok());
+        assert!(parse(&["-p", "in", "--proposals", "p.csv"]).is_ok());
+        assert!(parse(&["verify", "out"]).is_ok());
+        assert_eq!(error(&[]), ErrorKind::MissingRequiredArgument);
+        assert_eq!(error(&["-p", "in", "--input", "in"]), ErrorKind::ArgumentConflict);
+        assert_eq!(error(&["-p", "in", "-y", "i", "l", "n"]), ErrorKind::ArgumentConflict);
+        assert_eq!(error(&["-p", "in", "-r", "0", "10"]), ErrorKind::ValueValidation);
+        assert_eq!(error(&["-p", "in", "-r", "10"]), ErrorKind::WrongNumberOfValues);
+        assert_eq!(
+            error(&["-p", "in", "--case-insensitive-labels"]),
+            ErrorKind::MissingRequiredArgument
+        );
+        assert!(parse(&["-p", "in", "--case-insensitive-labels", "-L", "Aegina"]).is_ok());
+    }
+
+    #[test]
+    fn cpus() {
+        assert_eq!("auto".parse::<Cpus>(), Ok(Cpus::Auto));
+        assert_eq!("3".parse::<Cpus>(), Ok(Cpus::Count(3)));
+        assert!("0".parse::<Cpus>().is_err());
+        assert!(auto_threads(true) <= auto_threads(false));
+        assert_eq!(num_threads(&opts(&["-j", "3"])), 3);
+        assert_eq!(num_threads(&opts(&["--cpus", "auto"])), num_cpus::get());
+    }
+
+    #[test]
+    fn fail_on() {
+        assert_eq!("none".parse(), Ok(FailOn::None));
+        assert_eq!("any".parse(), Ok(FailOn::Any));
+        assert_eq!("threshold=5%".parse(), Ok(FailOn::Threshold(5.)));
+        assert_eq!("threshold=12.5".parse(), Ok(FailOn::Threshold(12.5)));
+        assert!("threshold=101%".parse::<FailOn>().is_err());
+        assert!("threshold=x".parse::<FailOn>().is_err());
+        assert!("some".parse::<FailOn>().is_err());
+
+        assert_eq!(FailOn::Any.exit_code(0, 10), exit_code::SUCCESS);
+        assert_eq!(FailOn::Any.exit_code(1, 10), exit_code::SOME_FAILED);
+        assert_eq!(FailOn::Any.exit_code(10, 10), exit_code::ALL_FAILED);
+        assert_eq!(FailOn::None.exit_code(10, 10), exit_code::SUCCESS);
+        let threshold = FailOn::Threshold(10.);
+        assert_eq!(threshold.exit_code(1, 10), exit_code::SUCCESS);
+        assert_eq!(threshold.exit_code(2, 10), exit_code::SOME_FAILED);
+        assert_eq!(threshold.exit_code(10, 10), exit_code::ALL_FAILED);
+    }
+
+    #[test]
+    fn metrics_addr() {
+        let args = ["blaise", "-p", "in", "-o", "out", "--metrics-port", "9100"];
+        let opts = Opts::try_parse_from(args).unwrap();
+        assert_eq!(opts.metrics_addr, "127.0.0.1");
+        let opts = Opts::try_parse_from(args.into_iter().chain(["--metrics-addr", "0.0.0.0"]));
+        assert_eq!(opts.unwrap().metrics_addr, "0.0.0.0");
+        let args = ["blaise", "-p", "in", "-o", "out", "--metrics-addr", "0.0.0.0"];
+        assert!(Opts::try_parse_from(args).is_err());
+    }
+
+    #[test]
+    fn verify_exit_codes() {
+        let verification = |ok: usize, mismatched: &[&str], missing: &[&str]| Verification {
+            ok,
+            mismatched: mismatched.iter().map(|s| s.to_string()).collect(),
+            missing: missing.iter().map(|s| s.to_string()).collect(),
+        };
+        assert_eq!(verify_exit_code(&verification(2, &[], &[])), exit_code::SUCCESS);
+        assert_eq!(verify_exit_code(&verification(0, &[], &[])), exit_code::SUCCESS);
+        let some = verification(1, &["a.png"], &[]);
+        assert_eq!(verify_exit_code(&some), exit_code::SOME_FAILED);
+        let all = verification(0, &["a.png"], &["b.png"]);
+        assert_eq!(verify_exit_code(&all), exit_code::ALL_FAILED);
+    }
+
+    #[test]
+    fn crop_name_collisions() {
+        let opts = opts(&[]);
+        let annotations = [
+            annotation("dive1", "frame_0001.png", vec![object("Aegina", &[])]),
+            annotation("dive2", "frame_0001.png", vec![object("Aegina", &[])]),
+            annotation("dive2", "frame_0002.png", vec![object("Aegina", &[])]),
+        ];
+        let tags = get_filename_tags(&opts, &annotations);
+        let tag = |folder: &str| checksum::to_hex(&checksum::sha256(folder.as_bytes())[..4]);
+        assert_eq!(
+            tags,
+            HashMap::from([
+                ("dive1/frame_0001.png".to_string(), tag("dive1")),
+                ("dive2/frame_0001.png".to_string(), tag("dive2")),
+            ])
+        );
+        let names: Vec<String> = annotations
+            .iter()
+            .map(|a| {
+                let tag = tags.get(&get_image_path(a, &opts)).map(String::as_str);
+                get_crop_filename(a, &a.objects.as_ref().unwrap()[0], "0", tag)
+            })
+            .collect();
+        assert_eq!(
+            names,
+            [
+                format!("frame_0001_{}_0.png", tag("dive1")),
+                format!("frame_0001_{}_0.png", tag("dive2")),
+                "frame_0002_0.png".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn shards() {
+        for (crops, shards) in [(10, 2), (500, 16), (5000, 256)] {
+            let dirs: Vec<String> = (0..shards).map(|shard| format!("{:02x}", shard)).collect();
+            let mut used = HashMap::new();
+            for i in 0..crops {
+                let name = format!("frame_{:04}_{}.png", i / 3, i % 3);
+                let shard = get_shard(&name, shards);
+                assert_eq!(shard, get_shard(&name, shards));
+                assert!(dirs.contains(&shard), "{} not among {} shards", shard, shards);
+                *used.entry(shard).or_insert(0) += 1;
+            }
+            assert_eq!(used.values().sum::<usize>(), crops);
+            assert_eq!(used.len(), shards as usize, "{} crops, {} shards", crops, shards);
+        }
+    }
+
+    #[test]
+    fn pose_dir() {
+        let opts = Opts::try_parse_from(["blaise", "-p", "in", "-o", "out", "--group-by-pose"]);
+        let opts = opts.unwrap();
+        let object = object("Aegina", &[(annotation::POSE, "../Left side")]);
+        let dir = get_out_class_dir(&opts, None, "Aegina", &object);
+        assert_eq!(dir, Path::new("out/Aegina/_Left_side"));
+        let args = ["blaise", "-p", "in", "-o", "out", "--group-by-pose"];
+        let opts = Opts::try_parse_from(args.into_iter().chain(["--label-sanitize", "hex"]));
+        let dir = get_out_class_dir(&opts.unwrap(), None, "Aegina", &object);
+        assert_eq!(dir, Path::new("out/Aegina/2e2e2f4c6566742073696465"));
+    }
+}
@@ -0,0 +1,859 @@
+//! The crop pipeline: loading the image of an annotation, then cropping,
+//! transforming and saving each of its selected objects, and handing the
+//! crops to the other outputs.
+
+use log::debug;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{
+    get_crop_filename, get_image_path, get_out_base_dir, get_out_class_dir, get_shard,
+    is_candidate, is_sliver, local_image_path, tag_filename, transform_pair_filename,
+    transform_union_filename, Opts, Shared, PAIRS_DIR, UNION_DIR,
+};
+use crate::annotation::{Annotation, Mask, Object, ObjectIds};
+use crate::classifier::{self, REVIEW_DIR};
+use crate::exif::{self, ExifInfo};
+use crate::geometry::PixelRect;
+use crate::icc::{self, IccPolicy};
+use crate::image::{
+    apply_alpha, convert_bit_depth, crop_image, load_frames, mask_image, resize_image, save_image,
+    FrameSelection,
+};
+use crate::manifest::{CropKind, ManifestEntry};
+use crate::metrics::Metrics;
+use crate::montage::{Tile, MONTAGE_DIR};
+use crate::overlay::FLAGGED_DIR;
+use crate::sink::Crop;
+#[cfg(feature = "underwater")]
+use crate::underwater;
+use crate::windows::{WindowLabel, WINDOWS_DIR};
+use crate::{geo, montage, overlay, scale, stamp, windows};
+use ::image::DynamicImage;
+
+/// Counts a crop of the label, for the thread and in the live tally.
+fn count_crop(by_label: &mut HashMap<String, usize>, shared: &Shared, label: String) {
+    shared.metrics.labels.inc(&label);
+    *by_label.entry(label).or_insert(0) += 1;
+}
+
+/// Processes an annotation, returning the number of crops and
+/// whether all was successful (image loaded and all crops saved).
+pub(super) fn process_annotation(
+    annotation: &Annotation,
+    opts: &Opts,
+    labels: &Option<Vec<String>>,
+    by_label: &mut HashMap<String, usize>,
+    shared: &Shared,
+    verbose: bool,
+) -> (usize, bool) {
+    let storage = &shared.storage;
+    let Annotation {
+        folder, filename, ..
+    } = annotation;
+
+    if verbose {
+        println!("process_annotation: for image: {}/{}", folder, filename);
+    }
+
+    let image_path = get_image_path(annotation, opts);
+    let local_path = local_image_path(shared, &image_path);
+    let pixel_size = match shared.pixel_size.as_ref().map(|p| p.of(&image_path)) {
+        Some(Ok(pixel_size)) => pixel_size,
+        Some(Err(e)) => {
+            shared.report(format!("ERROR: {} of {}", e, image_path));
+            return (0, false);
+        }
+        None => None,
+    };
+    if opts.normalize_scale.is_some() && pixel_size.is_none() {
+        shared.report(format!(
+            "WARN: no pixel size for {} (see --pixel-size), skipped",
+            image_path
+        ));
+        return (0, true);
+    }
+    let frame_count = std::cell::Cell::new(1);
+    let load_first = || {
+        let (mut frames, count) = load_frames(storage, local_path, FrameSelection::Index(0))?;
+        frame_count.set(count);
+        Ok(frames.remove(0).1)
+    };
+    let frames = match (opts.frame, shared.images) {
+        (None, Some(images)) => images.get(local_path, load_first).map(|img| vec![(None, img)]),
+        (None, None) => load_first().map(|img| vec![(None, Arc::new(img))]),
+        (Some(selection), _) => load_frames(storage, local_path, selection).map(|(frames, _)| {
+            let frames = frames.into_iter();
+            frames.map(|(i, img)| (Some(i), Arc::new(img))).collect()
+        }),
+    };
+    let frames = match frames {
+        Ok(frames) => frames,
+        Err(e) => {
+            shared.report(format!(
+                "ERROR: failed to load image {}: {:?}",
+                image_path, e
+            ));
+            return (0, false);
+        }
+    };
+    if frame_count.get() > 1 {
+        shared.report(format!(
+            "WARN: cropping the first of the {} frames of {} (see --frame)",
+            frame_count.get(),
+            image_path
+        ));
+    }
+
+    let icc_profile = match opts.icc {
+        IccPolicy::Strip => None,
+        _ => icc::read_profile(storage, local_path),
+    };
+    let to_srgb = match (opts.icc, &icc_profile) {
+        (IccPolicy::SrgbConvert, Some(profile)) => match icc::Profile::parse(profile) {
+            Ok(profile) => Some(profile),
+            Err(e) => {
+                shared.report(format!(
+                    "WARN: cannot convert {} to sRGB, keeping its color profile: {}",
+                    image_path, e
+                ));
+                None
+            }
+        },
+        _ => None,
+    };
+    // the converted crops are written without profile:
+    let icc_profile = icc_profile.filter(|_| to_srgb.is_none());
+    let exif = match opts.exif {
+        true => exif::read_exif(storage, local_path),
+        false => None,
+    };
+
+    let mut num_crops = 0usize;
+    let mut ok = true;
+    for (frame, img) in frames {
+        let source = Source {
+            img: &img,
+            frame,
+            icc_profile: icc_profile.as_deref(),
+            to_srgb: to_srgb.as_ref(),
+            pixel_size,
+            exif: exif.as_ref(),
+        };
+        let (frame_crops, frame_ok) = crop_frame(annotation, source, opts, labels, by_label, shared);
+        num_crops += frame_crops;
+        ok &= frame_ok;
+    }
+    (num_crops, ok)
+}
+
+/// An image, or frame of one, to crop.
+#[derive(Clone, Copy)]
+struct Source<'a> {
+    img: &'a DynamicImage,
+    frame: Option<u32>,
+    /// ICC profile to embed in the crops.
+    icc_profile: Option<&'a [u8]>,
+    /// Conversion of the crops to sRGB.
+    to_srgb: Option<&'a icc::Profile>,
+    /// Pixel size in mm, if known.
+    pixel_size: Option<f64>,
+    /// EXIF data of the image, with `--exif`.
+    exif: Option<&'a ExifInfo>,
+}
+
+/// Crops the objects of the annotation in the image, or the given frame of it,
+/// returning the number of crops and whether all were saved.
+fn crop_frame(
+    annotation: &Annotation,
+    source: Source,
+    opts: &Opts,
+    labels: &Option<Vec<String>>,
+    by_label: &mut HashMap<String, usize>,
+    shared: &Shared,
+) -> (usize, bool) {
+    let Shared {
+        storage,
+        classifier,
+        outputs,
+        ..
+    } = shared;
+    let Source { img, frame, .. } = source;
+    let verbose = opts.verbose;
+    let objects = &annotation.objects;
+    let ids = annotation.object_ids(opts.object_ids);
+    // for the manifest and `subtract`, whatever the ids in the crop names:
+    let stable_ids = match opts.object_ids {
+        ObjectIds::Stable => ids.clone(),
+        ObjectIds::Index => annotation.object_ids(ObjectIds::Stable),
+    };
+
+    let mut num_crops = 0usize;
+
+    let image_path = get_image_path(annotation, opts);
+    let exported_path = shared.exported(&image_path);
+    // the frame, if selected with --frame, is also tagged in the crop filenames:
+    let tag = match (shared.filename_tags.get(&image_path), frame) {
+        (Some(tag), Some(frame)) => Some(format!("{}_f{}", tag, frame)),
+        (Some(tag), None) => Some(tag.clone()),
+        (None, Some(frame)) => Some(format!("f{}", frame)),
+        (None, None) => None,
+    };
+    let tag = tag.as_deref();
+    let out_filename = match tag {
+        Some(tag) => tag_filename(annotation.image_name(), tag),
+        None => annotation.image_name().to_string(),
+    };
+    // write failures make the annotation count as failed, for --fail-on:
+    let save_failed = std::cell::Cell::new(false);
+    let fail = |message: String| {
+        shared.report(message);
+        save_failed.set(true);
+        Metrics::inc(&shared.metrics.save_failures);
+    };
+
+    let (image_width, image_height) = (img.width(), img.height());
+
+    let quantization = opts.quantization();
+
+    // crop, and resize if so indicated:
+    let make_crop = |bndbox: &PixelRect, mask: Option<&Mask>| -> Option<DynamicImage> {
+        let PixelRect {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        } = bndbox;
+        let x = *xmin;
+        let y = *ymin;
+        let width = xmax - xmin;
+        let height = ymax - ymin;
+
+        if verbose {
+            println!(
+                "  cropping left {} right {} upper {} lower {}",
+                xmin, xmax, ymin, ymax
+            );
+        }
+        let cropped = crop_image(img, x, y, width, height);
+        let cropped = match source.to_srgb {
+            Some(profile) => icc::to_srgb(&cropped, profile),
+            None => cropped,
+        };
+        let cropped = match mask {
+            Some(mask) => mask_image(&cropped, x, y, mask),
+            None => cropped,
+        };
+        let cropped = apply_alpha(cropped, opts.alpha);
+        #[cfg(feature = "underwater")]
+        let cropped = match opts.underwater_correct {
+            Some(method) => underwater::correct(&cropped, method),
+            None => cropped,
+        };
+        let size = match (&opts.resize, opts.normalize_scale, source.pixel_size) {
+            (Some(r), _, _) => Some((r[0], r[1])),
+            (None, Some(target), Some(pixel_size)) => Some(scale::normalized_size(
+                cropped.width(),
+                cropped.height(),
+                pixel_size,
+                target,
+            )),
+            _ => None,
+        };
+        if let Some((width, height)) = size {
+            let resized = resize_image(&cropped, width, height);
+            if resized.is_none() {
+                shared.report(format!(
+                    "WARN: not resizing empty crop {:?} of {}",
+                    bndbox, image_path
+                ));
+            }
+            resized
+        } else {
+            Some(cropped)
+        }
+        .map(|crop| convert_bit_depth(crop, opts.bit_depth))
+    };
+
+    let save_crop =
+        |crop: &DynamicImage, out_path: &Path, label: &str, bndbox: &PixelRect, kind: CropKind| {
+            let stamp = if opts.stamp_metadata {
+                stamp::provenance(&exported_path, bndbox, label, &shared.run_id)
+            } else {
+                Vec::new()
+            };
+            let atomic = !opts.no_atomic;
+            let bytes = match save_image(storage, crop, out_path, atomic, &stamp, source.icc_profile) {
+                Ok(bytes) => bytes,
+                Err(e) => {
+                    fail(format!("ERROR: cannot save {:?}: {:?}", out_path, e));
+                    return false;
+                }
+            };
+            Metrics::inc(&shared.metrics.crops_written);
+            if let Some(transform) = shared.georef.get(&image_path) {
+                let world_file = transform.world_file(bndbox, crop.width(), crop.height());
+                let path = geo::world_file_path(out_path);
+                if let Err(e) = storage.write(&path, world_file.as_bytes(), false) {
+                    fail(format!("ERROR: cannot save {:?}: {}", path, e));
+                }
+            }
+            let name = out_path.strip_prefix(opts.output_dir()).unwrap_or(out_path);
+            let errors = outputs.sinks.add(&Crop {
+                kind,
+                image: crop,
+                bytes: &bytes,
+                path: out_path,
+                name,
+                label,
+                source: &exported_path,
+                bndbox,
+            });
+            for e in &errors {
+                fail(format!("ERROR: cannot add {:?}: {}", name, e));
+            }
+            true
+        };
+
+    let geo_extent = |bndbox: &PixelRect| {
+        shared
+            .georef
+            .get(&image_path)
+            .map(|transform| transform.extent(bndbox))
+    };
+
+    let selected: Vec<(usize, &Object)> = match objects {
+        Some(objects) => objects
+            .iter()
+            .enumerate()
+            .filter(|(_, object)| is_candidate(opts, labels, object))
+            .filter(|(i, object)| {
+                let sliver = is_sliver(opts, object);
+                if sliver {
+                    debug!("skipping sliver object {}", i);
+                    *shared
+                        .slivers
+                        .lock()
+                        .unwrap()
+                        .entry(object.name.clone())
+                        .or_insert(0) += 1;
+                }
+                !sliver
+            })
+            .collect(),
+        None => {
+            debug!("no objects");
+            Vec::new()
+        }
+    };
+
+    if let Some(size) = opts.windows {
+        let stride = opts.window_stride.unwrap_or(size);
+        let objects: Vec<&Object> = selected.iter().map(|(_, object)| *object).collect();
+        let partition = shared.image_partition(opts, &image_path);
+        for window in windows::windows((image_width, image_height), size, stride) {
+            let label = windows::window_label(&window, &objects, opts.window_label)
+                .or_else(|| opts.window_empty_label.clone());
+            let Some(label) = label else {
+                continue;
+            };
+            let out_dir = match opts.window_label {
+                WindowLabel::Majority => {
+                    get_out_base_dir(opts, partition).join(shared.class_dir(&label))
+                }
+                WindowLabel::Multi => get_out_base_dir(opts, partition).join(WINDOWS_DIR),
+            };
+            if let Err(e) = storage.create_dir(&out_dir) {
+                shared.report(format!("ERROR: cannot create {:?}: {}", out_dir, e));
+            }
+            let out_path = out_dir.join(windows::window_filename(&out_filename, &window));
+            let saved = make_crop(&window, None)
+                .is_some_and(|crop| save_crop(&crop, &out_path, &label, &window, CropKind::Window));
+            if !saved {
+                continue;
+            }
+            num_crops += 1;
+
+            count_crop(by_label, shared, label.clone());
+
+            outputs.manifest.add(ManifestEntry {
+                kind: CropKind::Window,
+                crop: shared.exported(&out_path.to_string_lossy()),
+                sha256: outputs.checksum(&out_path),
+                image: exported_path.clone(),
+                label,
+                object_index: None,
+                object_id: None,
+                pair_indices: None,
+                shard: None,
+                bndbox: window,
+                frame,
+                geo_extent: geo_extent(&window),
+                size_mm: None,
+                exif: source.exif.cloned(),
+                attributes: Default::default(),
+            });
+        }
+        return (num_crops, !save_failed.get());
+    }
+
+    let mut object_crops: Vec<(&Object, String)> = Vec::new();
+    let mut tiles: Vec<Tile> = Vec::new();
+
+    for (i, object) in &selected {
+        let Object { name, bndbox, .. } = object;
+        debug!("object: i={} name={}", i, name);
+        if shared.prior.contains(&image_path, &stable_ids[*i]) {
+            debug!("skipping object {} cropped in the previous run", i);
+            continue;
+        }
+
+        let mask = object.mask.as_ref().filter(|_| opts.mask_crops);
+        let window = match (opts.fixed_crop, opts.min_crop) {
+            (Some((width, height)), _) => bndbox.window(
+                width as f64,
+                height as f64,
+                image_width as f64,
+                image_height as f64,
+            ),
+            (None, Some((width, height))) => {
+                let expanded = bndbox.expand_to(
+                    width as f64,
+                    height as f64,
+                    image_width as f64,
+                    image_height as f64,
+                );
+                if expanded != *bndbox {
+                    Metrics::inc(&shared.metrics.boxes_expanded);
+                }
+                expanded
+            }
+            (None, None) => *bndbox,
+        };
+        let pixels = window.to_pixels(quantization);
+        let crop = make_crop(&pixels, mask);
+        if let Some(crop) = &crop {
+            if !shared.quality.accept(crop) {
+                debug!("skipping low quality crop of object {}", i);
+                continue;
+            }
+        }
+
+        let partition = shared.partition(opts, &image_path, object);
+        let class_dir = shared.class_dir(name);
+        let mut out_class_dir = get_out_class_dir(opts, partition, class_dir, object);
+        // crops with overlay text, or doubted by the classifier, are routed for review:
+        let flagged = opts.flag_overlays
+            && crop
+                .as_ref()
+                .is_some_and(|crop| overlay::has_overlay_text(crop, opts.overlay_edge_density));
+        let review = match (*classifier, &crop) {
+            (Some(classifier), Some(crop)) if !flagged => match classifier.classify(crop) {
+                Ok((predicted, confidence)) => {
+                    debug!("classified {} as {} ({})", name, predicted, confidence);
+                    classifier::needs_review(name, &predicted, confidence, opts.filter_threshold)
+                }
+                Err(e) => {
+                    shared.report(format!(
+                        "WARN: cannot classify crop of {}: {}",
+                        image_path, e
+                    ));
+                    true
+                }
+            },
+            _ => false,
+        };
+        let routed_dir = match (flagged, review) {
+            (true, _) => Some(FLAGGED_DIR),
+            (_, true) => Some(REVIEW_DIR),
+            _ => None,
+        };
+        if let Some(routed_dir) = routed_dir {
+            let relative = out_class_dir.strip_prefix(opts.output_dir()).unwrap();
+            out_class_dir = opts.output_dir().join(routed_dir).join(relative);
+        }
+        let crop_filename = get_crop_filename(annotation, object, &ids[*i], tag);
+        let shard = opts
+            .shard_output
+            .map(|shards| get_shard(&crop_filename, shards));
+        if let Some(shard) = &shard {
+            out_class_dir.push(shard);
+        }
+        if let Err(e) = storage.create_dir(&out_class_dir) {
+            shared.report(format!("ERROR: cannot create {:?}: {}", out_class_dir, e));
+        }
+        let out_path = out_class_dir.join(crop_filename);
+        let Some(crop) = crop else {
+            continue;
+        };
+        if !save_crop(&crop, &out_path, name, &pixels, CropKind::Object) {
+            continue;
+        }
+        if opts.montage {
+            tiles.push(Tile {
+                id: ids[*i].clone(),
+                label: name.clone(),
+                bndbox: pixels,
+                crop,
+            });
+        }
+        num_crops += 1;
+
+        // (a routed crop is only counted under its directory)
+        let counted = match routed_dir {
+            Some(routed_dir) => format!("{}/{}", routed_dir, name),
+            None => name.to_string(),
+        };
+        count_crop(by_label, shared, counted);
+
+        outputs.manifest.add(ManifestEntry {
+            kind: CropKind::Object,
+            crop: shared.exported(&out_path.to_string_lossy()),
+            sha256: outputs.checksum(&out_path),
+            image: exported_path.clone(),
+            label: name.to_string(),
+            object_index: Some(*i),
+            object_id: Some(stable_ids[*i].clone()),
+            pair_indices: None,
+            shard,
+            bndbox: pixels,
+            frame,
+            geo_extent: geo_extent(&pixels),
+            size_mm: source
+                .pixel_size
+                .map(|pixel_size| scale::size_mm(bndbox, pixel_size)),
+            exif: source.exif.cloned(),
+            attributes: object.attributes.clone(),
+        });
+        object_crops.push((object, shared.exported(&out_path.to_string_lossy())));
+    }
+
+    if let Some(fiftyone) = &outputs.fiftyone {
+        fiftyone.add(&exported_path, image_width, image_height, &object_crops);
+    }
+
+    if !tiles.is_empty() {
+        montage::sort(&mut tiles, opts.montage_order);
+        let (image, index) = montage::compose(&exported_path, &tiles, opts.montage_tile);
+        let partition = shared.partition(opts, &image_path, selected[0].1);
+        let out_dir = get_out_base_dir(opts, partition).join(MONTAGE_DIR);
+        if let Err(e) = storage.create_dir(&out_dir) {
+            shared.report(format!("ERROR: cannot create {:?}: {}", out_dir, e));
+        }
+        let (montage_filename, index_filename) = montage::montage_filenames(&out_filename);
+        let out_path = out_dir.join(montage_filename);
+        if let Err(e) = save_image(storage, &image, &out_path, !opts.no_atomic, &[], None) {
+            fail(format!("ERROR: cannot save {:?}: {:?}", out_path, e));
+        }
+        let index_path = out_dir.join(index_filename);
+        let index = serde_json::to_string_pretty(&index).unwrap();
+        if let Err(e) = storage.write(&index_path, index.as_bytes(), !opts.no_atomic) {
+            fail(format!("ERROR: cannot save {:?}: {}", index_path, e));
+        }
+    }
+
+    if opts.union_crop && !selected.is_empty() {
+        let unpadded = selected
+            .iter()
+            .map(|(_, object)| object.bndbox)
+            .reduce(|a, b| a.union(&b))
+            .unwrap();
+        let mut union = unpadded
+            .pad(opts.union_padding as f64)
+            .clamp(image_width as f64, image_height as f64)
+            .to_pixels(quantization);
+        if let Some(exclusion) = shared.exclusion {
+            union = exclusion.clip_padding(&unpadded.to_pixels(quantization), &union);
+        }
+
+        let partition = shared.partition(opts, &image_path, selected[0].1);
+        let out_dir = get_out_base_dir(opts, partition).join(UNION_DIR);
+        if let Err(e) = storage.create_dir(&out_dir) {
+            shared.report(format!("ERROR: cannot create {:?}: {}", out_dir, e));
+        }
+        let out_path = out_dir.join(transform_union_filename(&out_filename));
+        let mut names: Vec<&str> = selected.iter().map(|(_, o)| o.name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        let label = names.join(",");
+        let saved = make_crop(&union, None)
+            .is_some_and(|crop| save_crop(&crop, &out_path, &label, &union, CropKind::Union));
+        if saved {
+            num_crops += 1;
+
+            count_crop(by_label, shared, UNION_DIR.to_string());
+
+            outputs.manifest.add(ManifestEntry {
+                kind: CropKind::Union,
+                crop: shared.exported(&out_path.to_string_lossy()),
+                sha256: outputs.checksum(&out_path),
+                image: exported_path.clone(),
+                label,
+                object_index: None,
+                object_id: None,
+                pair_indices: None,
+                shard: None,
+                bndbox: union,
+                frame,
+                geo_extent: geo_extent(&union),
+                size_mm: None,
+                exif: source.exif.cloned(),
+                attributes: Default::default(),
+            });
+        }
+    }
+
+    if let (Some(pair_labels), Some(max_distance)) = (&opts.pair_crops, opts.max_distance) {
+        let (label_a, label_b) = pair_labels;
+        let pair_name = format!("{}_{}", label_a, label_b);
+        for (i, a) in selected.iter().filter(|(_, o)| &o.name == label_a) {
+            for (j, b) in selected.iter().filter(|(_, o)| &o.name == label_b) {
+                // with same labels, consider each pair only once:
+                if i == j || (label_a == label_b && i > j) {
+                    continue;
+                }
+                if shared.prior.contains(&image_path, &stable_ids[*i])
+                    && shared.prior.contains(&image_path, &stable_ids[*j])
+                {
+                    continue;
+                }
+                if a.bndbox.distance(&b.bndbox) > max_distance {
+                    continue;
+                }
+                let pair = a.bndbox.union(&b.bndbox).to_pixels(quantization);
+
+                let partition = shared.partition(opts, &image_path, a);
+                let out_dir = get_out_base_dir(opts, partition)
+                    .join(PAIRS_DIR)
+                    .join(&pair_name);
+                if let Err(e) = storage.create_dir(&out_dir) {
+                    shared.report(format!("ERROR: cannot create {:?}: {}", out_dir, e));
+                }
+                let out_path = out_dir.join(transform_pair_filename(&out_filename, &ids[*i], &ids[*j]));
+                let label = format!("{},{}", label_a, label_b);
+                let saved = make_crop(&pair, None)
+                    .is_some_and(|crop| save_crop(&crop, &out_path, &label, &pair, CropKind::Pair));
+                if !saved {
+                    continue;
+                }
+                num_crops += 1;
+
+                count_crop(by_label, shared, format!("{}/{}", PAIRS_DIR, pair_name));
+
+                outputs.manifest.add(ManifestEntry {
+                    kind: CropKind::Pair,
+                    crop: shared.exported(&out_path.to_string_lossy()),
+                    sha256: outputs.checksum(&out_path),
+                    image: exported_path.clone(),
+                    label,
+                    object_index: None,
+                    object_id: None,
+                    pair_indices: Some((*i, *j)),
+                    shard: None,
+                    bndbox: pair,
+                    frame,
+                    geo_extent: geo_extent(&pair),
+                    size_mm: None,
+                    exif: source.exif.cloned(),
+                    attributes: Default::default(),
+                });
+            }
+        }
+    }
+
+    (num_crops, !save_failed.get())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Outputs, Prepared};
+    use super::*;
+    use crate::annotation::Bndbox;
+    use crate::classifier::CropClassifier;
+    use crate::manifest::PriorCrops;
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    fn object(name: &str, (xmin, ymin, xmax, ymax): (f64, f64, f64, f64)) -> Object {
+        Object {
+            name: name.to_string(),
+            bndbox: Bndbox {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        }
+    }
+
+    /// Crops the objects of `img.png` (40x30) in a new directory with the given
+    /// options, returning the directory and what `process_annotation` returns.
+    fn crop(
+        name: &str,
+        args: &[&str],
+        objects: Vec<Object>,
+    ) -> (PathBuf, (usize, bool), HashMap<String, usize>) {
+        crop_classified(name, args, objects, None)
+    }
+
+    /// As [crop], with the given classifier of the crops.
+    fn crop_classified(
+        name: &str,
+        args: &[&str],
+        objects: Vec<Object>,
+        classifier: Option<&dyn CropClassifier>,
+    ) -> (PathBuf, (usize, bool), HashMap<String, usize>) {
+        let dir = format!("blaise-crop-{}-{}", name, std::process::id());
+        let dir = std::env::temp_dir().join(dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let img = ::image::RgbImage::new(40, 30);
+        img.save(dir.join("img.png")).unwrap();
+        let out = dir.join("out");
+        let base = ["blaise", "-p", "in", "-o", out.to_str().unwrap()];
+        let opts = Opts::try_parse_from(base.iter().chain(args)).unwrap();
+        let annotation = Annotation {
+            folder: dir.to_str().unwrap().to_string(),
+            filename: "img.png".to_string(),
+            objects: Some(objects),
+            frame: None,
+            image_size: None,
+        };
+        let annotations = [annotation];
+        let outputs = Outputs::create(&opts, Vec::new).unwrap();
+        let prepared = Prepared {
+            classifier,
+            prior: PriorCrops::default(),
+            downloaded: HashMap::new(),
+            georef: HashMap::new(),
+            split_metadata: None,
+            pixel_size: None,
+            batch: None,
+        };
+        let shared = Shared::new(&opts, &annotations, None, 1, "test", outputs, prepared);
+        let mut by_label = HashMap::new();
+        let result = process_annotation(
+            &annotations[0],
+            &opts,
+            &opts.select_labels,
+            &mut by_label,
+            &shared,
+            false,
+        );
+        shared.outputs.finish();
+        (dir, result, by_label)
+    }
+
+    /// The sizes of the crops written under the directory, by path relative to it.
+    fn crop_sizes(dir: &Path) -> Vec<(String, (u32, u32))> {
+        let mut sizes: Vec<_> = walkdir::WalkDir::new(dir)
+            .into_iter()
+            .map(Result::unwrap)
+            .filter(|entry| entry.file_type().is_file())
+            .map(|entry| {
+                let path = entry.path().strip_prefix(dir).unwrap();
+                let size = ::image::image_dimensions(entry.path()).unwrap();
+                (path.parent().unwrap().to_str().unwrap().to_string(), size)
+            })
+            .collect();
+        sizes.sort();
+        sizes
+    }
+
+    #[test]
+    fn crops() {
+        let objects = vec![
+            object("Aurelia", (0., 0., 10., 10.)),
+            object("Bathochordaeus", (5., 5., 25., 20.)),
+            object("Aurelia", (20., 10., 30., 30.)),
+        ];
+        let (dir, result, by_label) = crop("crops", &[], objects);
+        assert_eq!(result, (3, true));
+        assert_eq!(by_label["Aurelia"], 2);
+        assert_eq!(by_label["Bathochordaeus"], 1);
+        assert_eq!(
+            crop_sizes(&dir.join("out")),
+            [
+                ("Aurelia".to_string(), (10, 10)),
+                ("Aurelia".to_string(), (10, 20)),
+                ("Bathochordaeus".to_string(), (20, 15)),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn selected_labels() {
+        let objects = vec![
+            object("Aurelia", (0., 0., 10., 10.)),
+            object("Bathochordaeus", (5., 5., 25., 20.)),
+        ];
+        let (dir, result, by_label) = crop("selected", &["-L", "Bathochordaeus"], objects);
+        assert_eq!(result, (1, true));
+        assert!(!by_label.contains_key("Aurelia"));
+        let sizes = crop_sizes(&dir.join("out"));
+        assert_eq!(sizes, [("Bathochordaeus".to_string(), (20, 15))]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn flagged() {
+        let objects = vec![
+            object("Aurelia", (0., 0., 10., 10.)),
+            object("Aurelia", (20., 10., 30., 30.)),
+        ];
+        // (with no minimum density of edges, every crop is flagged)
+        let args = ["--flag-overlays", "--overlay-edge-density", "0"];
+        let (dir, result, by_label) = crop("flagged", &args, objects);
+        assert_eq!(result, (2, true));
+        assert_eq!(by_label.len(), 1);
+        assert_eq!(by_label["_flagged/Aurelia"], 2);
+        let sizes = crop_sizes(&dir.join("out"));
+        assert_eq!(sizes.len(), 2);
+        assert!(sizes.iter().all(|(dir, _)| dir == "_flagged/Aurelia"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Classifies every crop as Aurelia.
+    struct Aurelia;
+
+    impl CropClassifier for Aurelia {
+        fn classify(&self, _: &DynamicImage) -> Result<(String, f32), String> {
+            Ok(("Aurelia".to_string(), 0.9))
+        }
+    }
+
+    #[test]
+    fn review() {
+        let objects = vec![
+            object("Aurelia", (0., 0., 10., 10.)),
+            object("Bathochordaeus", (5., 5., 25., 20.)),
+        ];
+        let (dir, result, by_label) = crop_classified("review", &[], objects, Some(&Aurelia));
+        assert_eq!(result, (2, true));
+        assert_eq!(by_label["Aurelia"], 1);
+        assert_eq!(by_label["_review/Bathochordaeus"], 1);
+        assert_eq!(by_label.values().sum::<usize>(), 2);
+        assert_eq!(
+            crop_sizes(&dir.join("out")),
+            [
+                ("Aurelia".to_string(), (10, 10)),
+                ("_review/Bathochordaeus".to_string(), (20, 15)),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_image() {
+        let objects = vec![object("Aurelia", (0., 0., 10., 10.))];
+        let (dir, result, by_label) = crop("missing", &["--image-dir", "none"], objects);
+        assert_eq!(result, (0, false));
+        assert!(by_label.is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
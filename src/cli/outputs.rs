@@ -0,0 +1,147 @@
+//! The outputs written besides the crops themselves: the manifest, the sinks
+//! (TFRecord, HDF5, sprite sheets, ...), the FiftyOne dataset and checksums.
+
+use std::io;
+use std::path::Path;
+
+use super::Opts;
+use crate::checksum::ChecksumWriter;
+use crate::fiftyone::FiftyOneWriter;
+use crate::hdf5::Hdf5Writer;
+use crate::image::BitDepth;
+use crate::imagefolder::ImageFolderMetadata;
+use crate::manifest::Manifest;
+use crate::sink::{self, CropSink, Sinks};
+use crate::sprites::SpriteSheets;
+use crate::tfrecord::TfRecordWriter;
+
+/// Additional outputs, shared by the processing threads.
+pub(super) struct Outputs {
+    pub(super) manifest: Manifest,
+    /// The other destinations of the crops.
+    pub(super) sinks: Sinks,
+    pub(super) fiftyone: Option<FiftyOneWriter>,
+    pub(super) checksums: Option<ChecksumWriter>,
+}
+
+impl Outputs {
+    /// Creates the outputs requested in the options; `labels` gives the labels
+    /// of the objects, for the outputs that list them upfront.
+    pub(super) fn create(opts: &Opts, labels: impl Fn() -> Vec<String>) -> Result<Outputs, String> {
+        // (through a staging copy if remote)
+        fn created<S: CropSink + 'static>(
+            path: &Path,
+            create: impl FnOnce(&Path) -> io::Result<S>,
+        ) -> Result<Box<dyn CropSink>, String> {
+            match sink::create(path, create) {
+                Ok(sink) => Ok(sink),
+                Err(e) => Err(format!("cannot create {:?}: {}", path, e)),
+            }
+        }
+        let mut sinks = Sinks::default();
+        if let Some(dir) = &opts.tfrecord {
+            let shard_size = opts.tfrecord_shard_size;
+            let tfrecord = |dir: &Path| TfRecordWriter::new(dir, shard_size, labels());
+            sinks.push(created(dir, tfrecord)?);
+        }
+        if let Some(path) = &opts.hdf5 {
+            if opts.bit_depth == BitDepth::Sixteen {
+                return Err("the HDF5 dataset is 8-bit; not supported with --bit-depth 16".into());
+            }
+            let r = opts.resize.as_ref().unwrap();
+            let hdf5 = |path: &Path| Hdf5Writer::new(path, r[0], r[1], labels());
+            sinks.push(created(path, hdf5)?);
+        }
+        if opts.hf_imagefolder {
+            let dir = opts.output_dir();
+            match ImageFolderMetadata::new(dir) {
+                Ok(metadata) => sinks.push(Box::new(metadata)),
+                Err(e) => return Err(format!("cannot create {:?}: {}", dir, e)),
+            }
+        }
+        if let Some(dir) = &opts.sprite_sheets {
+            let sprite_size = opts.sprite_size;
+            sinks.push(created(dir, |dir| SpriteSheets::create(dir, sprite_size))?);
+        }
+        for spec in &opts.sink {
+            match spec.create(opts.webdataset_shard_size) {
+                Ok(sink) => sinks.push(sink),
+                Err(e) => return Err(format!("cannot create sink {:?}: {}", spec, e)),
+            }
+        }
+        Ok(Outputs {
+            manifest: Manifest::new(opts.manifest.as_deref(), opts.manifest_order),
+            sinks,
+            fiftyone: (opts.fiftyone.as_deref())
+                .map(|dir| FiftyOneWriter::new(dir, !opts.scrub_paths)),
+            checksums: opts
+                .checksums
+                .map(|_| ChecksumWriter::new(opts.output_dir())),
+        })
+    }
+
+    /// Records the checksum of the crop, if so requested, also returning it.
+    pub(super) fn checksum(&self, crop_path: &Path) -> Option<String> {
+        self.checksums.as_ref().and_then(|c| c.add(crop_path))
+    }
+
+    pub(super) fn finish(&self) {
+        self.manifest.finish();
+        self.sinks.finish();
+        if let Some(fiftyone) = &self.fiftyone {
+            fiftyone.finish();
+        }
+        if let Some(checksums) = &self.checksums {
+            checksums.finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn opts(args: &[&str]) -> Opts {
+        Opts::try_parse_from(["blaise", "-p", "in"].iter().chain(args)).unwrap()
+    }
+
+    #[test]
+    fn create() {
+        let dir = std::env::temp_dir().join(format!("blaise-outputs-{}", std::process::id()));
+        let out = dir.join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        let tfrecord = dir.join("tf");
+        let opts = opts(&[
+            "-o",
+            out.to_str().unwrap(),
+            "--tfrecord",
+            tfrecord.to_str().unwrap(),
+            "--hf-imagefolder",
+        ]);
+        let outputs = Outputs::create(&opts, || vec!["Aurelia".into()]).unwrap();
+        assert!(outputs.fiftyone.is_none());
+        assert!(outputs.checksums.is_none());
+        outputs.finish();
+        assert!(tfrecord.join("label_map.pbtxt").exists());
+        assert!(out.join("metadata.csv").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hdf5_is_8_bit() {
+        let opts = opts(&[
+            "-o",
+            "out",
+            "-r",
+            "8",
+            "8",
+            "--bit-depth",
+            "16",
+            "--hdf5",
+            "x.h5",
+        ]);
+        let e = Outputs::create(&opts, Vec::new).err().unwrap();
+        assert!(e.contains("--bit-depth 16"), "{}", e);
+    }
+}
@@ -0,0 +1,276 @@
+//! Checks of the options before reading any annotations, reporting all the
+//! problems at once instead of failing on the first one later in the run.
+
+use std::path::Path;
+
+use super::Opts;
+use crate::{download, source};
+
+impl Opts {
+    /// The problems with the options that would make the run fail, all of them,
+    /// before reading any annotations: missing inputs, an output directory that
+    /// cannot be written, values out of range.
+    pub(super) fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut dir = |option: &str, path: &Path| {
+            if let Err(e) = check_dir(path) {
+                problems.push(format!("{} {:?}: {}", option, path, e));
+            }
+        };
+        if let Some(path) = &self.input {
+            dir("--input", path);
+        }
+        if let Some(path) = &self.pascal {
+            dir("--pascal", path);
+        }
+        if let Some(yolo) = &self.yolo {
+            dir("--yolo image directory", &yolo[0]);
+            dir("--yolo label directory", &yolo[1]);
+        }
+        #[cfg(feature = "mot")]
+        if let Some(mot) = &self.mot {
+            dir("--mot image directory", &mot[0]);
+        }
+        #[cfg(feature = "supervisely")]
+        if let Some(path) = &self.supervisely {
+            dir("--supervisely", path);
+        }
+        if let Some(path) = &self.image_dir {
+            dir("--image-dir", path);
+        }
+
+        let mut files: Vec<(&str, &Path)> = Vec::new();
+        if let Some(yolo) = &self.yolo {
+            files.push(("--yolo names file", &yolo[2]));
+        }
+        #[cfg(feature = "mot")]
+        {
+            if let Some(mot) = &self.mot {
+                files.push(("--mot ground truth file", &mot[1]));
+            }
+            files.extend(self.mot_names.as_deref().map(|p| ("--mot-names", p)));
+        }
+        #[cfg(feature = "coco")]
+        files.extend(self.coco.as_deref().map(|p| ("--coco", p)));
+        #[cfg(feature = "via")]
+        files.extend(self.via.as_deref().map(|p| ("--via", p)));
+        let optional = [
+            ("--proposals", &self.proposals),
+            ("--class-remap", &self.class_remap),
+            ("--roi-mask", &self.roi_mask),
+            ("--filter-model", &self.filter_model),
+            ("--filter-labels", &self.filter_labels),
+            ("--rules", &self.rules),
+            ("--metadata", &self.metadata),
+        ];
+        for (option, path) in optional {
+            files.extend(path.as_deref().map(|p| (option, p)));
+        }
+        for (option, path) in files {
+            if let Err(e) = check_file(path) {
+                problems.push(format!("{} {:?}: {}", option, path, e));
+            }
+        }
+        if let Some(url) = &self.kb_url {
+            if !download::is_url(url) {
+                problems.push(format!("--kb-url {:?}: not an http(s) URL", url));
+            }
+        }
+        // the class names, only when the files are there:
+        if let Some(yolo) = self.yolo.as_ref().filter(|_| problems.is_empty()) {
+            if let Ok(names) = source::read_names(&yolo[2]) {
+                if names.is_empty() {
+                    problems.push(format!("--yolo names file {:?}: no class names", yolo[2]));
+                }
+            }
+        }
+
+        if let Some(output_dir) = &self.output_dir {
+            // remote ones are checked when connecting:
+            if !output_dir.to_string_lossy().contains("://") {
+                if let Err(e) = check_writable(output_dir) {
+                    problems.push(format!("--output-dir {:?}: {}", output_dir, e));
+                }
+            }
+        }
+
+        let mut range = |option: &str, value: f64, valid: bool, expected: &str| {
+            if !valid || value.is_nan() {
+                problems.push(format!(
+                    "invalid {} {}, expected {}",
+                    option, value, expected
+                ));
+            }
+        };
+        if let Some(factor) = self.scale_boxes {
+            range(
+                "--scale-boxes",
+                factor,
+                factor > 0. && factor.is_finite(),
+                "> 0",
+            );
+        }
+        if let Some(target) = self.normalize_scale {
+            range(
+                "--normalize-scale",
+                target,
+                target > 0. && target.is_finite(),
+                "> 0",
+            );
+        }
+        if let Some(ratio) = self.max_aspect_ratio {
+            range("--max-aspect-ratio", ratio, ratio >= 1., ">= 1");
+        }
+        if let Some(distance) = self.max_distance {
+            range("--max-distance", distance, distance >= 0., ">= 0");
+        }
+        let iou = self.proposal_min_iou;
+        range(
+            "--proposal-min-iou",
+            iou,
+            (0. ..=1.).contains(&iou),
+            "0 to 1",
+        );
+        let containment = self.containment;
+        range(
+            "--containment",
+            containment,
+            containment > 0. && containment <= 1.,
+            "> 0 and <= 1",
+        );
+        let density = self.overlay_edge_density;
+        range(
+            "--overlay-edge-density",
+            density,
+            (0. ..=1.).contains(&density),
+            "0 to 1",
+        );
+        let threshold = self.filter_threshold as f64;
+        range(
+            "--filter-threshold",
+            threshold,
+            (0. ..=1.).contains(&threshold),
+            "0 to 1",
+        );
+        problems
+    }
+}
+
+fn check_dir(path: &Path) -> Result<(), String> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => Err("not a directory".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn check_file(path: &Path) -> Result<(), String> {
+    if path.is_dir() {
+        return Err("a directory, expected a file".to_string());
+    }
+    std::fs::File::open(path)
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+/// Whether files can be created in the directory, or in its closest existing
+/// ancestor if it does not exist yet (it is created by the run).
+fn check_writable(dir: &Path) -> Result<(), String> {
+    let existing = dir
+        .ancestors()
+        .find(|d| d.as_os_str().is_empty() || d.exists())
+        .unwrap_or(dir);
+    let existing = match existing.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => existing,
+    };
+    if !existing.is_dir() {
+        return Err(format!("{:?} is not a directory", existing));
+    }
+    let probe = existing.join(format!(".blaise-write-test-{}", std::process::id()));
+    match std::fs::File::create(&probe) {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            Ok(())
+        }
+        Err(e) => Err(format!("cannot write in {:?}: {}", existing, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn problems() {
+        let dir = std::env::temp_dir().join(format!("blaise-problems-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("images")).unwrap();
+        std::fs::write(dir.join("names.txt"), "\n").unwrap();
+        std::fs::write(dir.join("file"), "").unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+        let problems = |args: &[&str]| {
+            let args = ["blaise"].iter().chain(args);
+            Opts::try_parse_from(args).unwrap().problems()
+        };
+
+        let (images, names) = (path("images"), path("names.txt"));
+        let out = path("out");
+        assert!(problems(&["-p", &images, "-o", &out]).is_empty());
+        assert_eq!(
+            problems(&["-y", &images, &images, &names, "-o", &out]),
+            [format!(
+                "--yolo names file {:?}: no class names",
+                dir.join("names.txt")
+            )]
+        );
+
+        let (missing, file) = (path("missing"), path("file"));
+        let unwritable = path("file/out");
+        let problems = problems(&[
+            "-y",
+            &missing,
+            &file,
+            &images,
+            "--rules",
+            &missing,
+            "--kb-url",
+            "ftp://kb",
+            "-o",
+            &unwritable,
+            "--scale-boxes",
+            "0",
+            "--max-aspect-ratio",
+            "0.5",
+            "--proposal-min-iou",
+            "NaN",
+            "--containment",
+            "1.5",
+        ]);
+        let expected = [
+            "--yolo image directory",
+            "--yolo label directory \"",
+            "--yolo names file",
+            "--rules",
+            "--kb-url \"ftp://kb\": not an http(s) URL",
+            "--output-dir",
+            "invalid --scale-boxes 0, expected > 0",
+            "invalid --max-aspect-ratio 0.5, expected >= 1",
+            "invalid --proposal-min-iou NaN, expected 0 to 1",
+            "invalid --containment 1.5, expected > 0 and <= 1",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{:#?}", problems);
+        for (problem, expected) in problems.iter().zip(expected) {
+            assert!(
+                problem.starts_with(expected),
+                "{:?}, expected {:?}",
+                problem,
+                expected
+            );
+        }
+        assert!(problems[1].ends_with(": not a directory"));
+        assert!(problems[2].ends_with(": a directory, expected a file"));
+        assert!(problems[5].contains("is not a directory"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
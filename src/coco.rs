@@ -0,0 +1,395 @@
+use crate::annotation;
+use crate::scan::ScanProgress;
+use crate::source::{AnnotationSource, Annotations, Issue, Strictness};
+use imagesize::ImageSize;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+type Res<T> = Result<T, Box<dyn Error>>;
+
+/// A COCO JSON file, with the image file names relative to `image_dir`,
+/// by default the directory of the file.
+pub struct CocoSource<'a> {
+    pub file: &'a Path,
+    pub image_dir: Option<&'a Path>,
+}
+
+impl AnnotationSource for CocoSource<'_> {
+    fn format(&self) -> &'static str {
+        "Coco"
+    }
+
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String> {
+        println!("getting coco annotations from {:?}", self.file);
+        let image_dir = self
+            .image_dir
+            .or_else(|| self.file.parent())
+            .unwrap_or(Path::new("."));
+        scan.seen();
+        let annotations = read_to_string(self.file)
+            .map_err(|e| e.to_string())
+            .and_then(|src| {
+                let on_issue = |issue| scan.issue(self.file, issue);
+                parse_coco_with(&image_dir.to_string_lossy(), &src, on_issue)
+                    .map_err(|e| e.to_string())
+            })
+            .map_err(|e| format!("invalid coco file {:?}: {}", self.file, e))?;
+        scan.parsed();
+        Ok(Box::new(annotations.into_iter()))
+    }
+}
+
+/// Parses a COCO object detection dataset (`images`, `annotations`, `categories`),
+/// returning an annotation for each image, with the given folder.
+pub fn parse_coco(folder: &str, src: &str) -> Res<Vec<annotation::Annotation>> {
+    parse_coco_with(folder, src, |issue| Strictness::Normal.accepts(&issue))
+}
+
+/// As `parse_coco`, with the issues given to `on_issue`: the invalid masks are
+/// malformed (left out if accepted), and the annotations of unknown categories
+/// or images, and the images without size, are irregular.
+pub fn parse_coco_with(
+    folder: &str,
+    src: &str,
+    mut on_issue: impl FnMut(Issue) -> bool,
+) -> Res<Vec<annotation::Annotation>> {
+    let mut check = |issue: Issue| -> Res<()> {
+        match on_issue(issue.clone()) {
+            true => Ok(()),
+            false => Err(issue.reason().into()),
+        }
+    };
+    let dataset: Dataset = serde_json::from_str(src)?;
+    let names: HashMap<u64, &str> = dataset
+        .categories
+        .iter()
+        .map(|c| (c.id, c.name.as_str()))
+        .collect();
+
+    let mut by_image: HashMap<u64, Vec<annotation::Object>> = HashMap::new();
+    for ann in &dataset.annotations {
+        let name = match names.get(&ann.category_id) {
+            Some(name) => name.to_string(),
+            None => {
+                let reason = format!("annotation of unknown category {}", ann.category_id);
+                check(Issue::Irregular(reason))?;
+                format!("class_{}", ann.category_id)
+            }
+        };
+        let mask = match &ann.segmentation {
+            Some(Segmentation::Rle(rle)) => match decode_rle(rle) {
+                Ok(mask) => Some(mask),
+                Err(e) => {
+                    check(Issue::Malformed(format!("invalid mask: {}", e)))?;
+                    None
+                }
+            },
+            _ => None,
+        };
+        by_image
+            .entry(ann.image_id)
+            .or_default()
+            .push(annotation::Object {
+                name,
+                bndbox: to_bndbox(&ann.bbox),
+                track_id: None,
+                id: ann.id.map(|id| id.to_string()),
+                mask,
+                attributes: ann
+                    .attributes
+                    .iter()
+                    .map(|(key, value)| (key.clone(), attribute_value(value)))
+                    .collect(),
+            });
+    }
+
+    let mut annotations = Vec::new();
+    for image in dataset.images {
+        let objects = by_image.remove(&image.id);
+        let image_size = match (image.width, image.height) {
+            (Some(width), Some(height)) => Some(ImageSize { width, height }),
+            _ => {
+                check(Issue::Irregular(format!("image {} without size", image.id)))?;
+                None
+            }
+        };
+        annotations.push(annotation::Annotation {
+            folder: folder.to_string(),
+            filename: image.file_name,
+            objects,
+            frame: None,
+            image_size,
+        });
+    }
+    // left out:
+    let mut unknown: Vec<&u64> = by_image.keys().collect();
+    unknown.sort();
+    for image_id in unknown {
+        check(Issue::Irregular(format!(
+            "annotations of unknown image {}",
+            image_id
+        )))?;
+    }
+    Ok(annotations)
+}
+
+/// Decodes an RLE mask, with counts either as a list or in the compressed string form.
+/// Runs are in column-major order, alternating unset and set pixels.
+fn decode_rle(rle: &Rle) -> Res<annotation::Mask> {
+    let [height, width] = rle.size;
+    let counts: Vec<u64> = match &rle.counts {
+        RleCounts::Uncompressed(counts) => counts.clone(),
+        RleCounts::Compressed(s) => decompress_counts(s)?,
+    };
+    let total: u64 = counts.iter().sum();
+    if total != width as u64 * height as u64 {
+        return Err(format!("RLE counts add to {}, not {}x{}", total, height, width).into());
+    }
+    // column-major set flags:
+    let mut set = Vec::with_capacity(total as usize);
+    for (i, count) in counts.iter().enumerate() {
+        set.extend(std::iter::repeat_n(i % 2 == 1, *count as usize));
+    }
+    Ok(annotation::Mask::from_fn(width, height, |x, y| {
+        set[(x * height + y) as usize]
+    }))
+}
+
+/// Decodes the counts in the compressed RLE string form (as in pycocotools):
+/// LEB128-like variable length values in characters offset by 48, with counts
+/// after the first two given as differences from the count two positions earlier.
+fn decompress_counts(s: &str) -> Res<Vec<u64>> {
+    let bytes = s.as_bytes();
+    let mut counts: Vec<i64> = Vec::new();
+    let mut p = 0;
+    while p < bytes.len() {
+        let mut x: i64 = 0;
+        let mut k = 0;
+        loop {
+            let c = *bytes.get(p).ok_or("truncated RLE string")? as i64 - 48;
+            x |= (c & 0x1f) << (5 * k);
+            p += 1;
+            k += 1;
+            if c & 0x20 == 0 {
+                if c & 0x10 != 0 {
+                    x |= -1 << (5 * k);
+                }
+                break;
+            }
+        }
+        if counts.len() > 2 {
+            x += counts[counts.len() - 2];
+        }
+        counts.push(x);
+    }
+    counts
+        .into_iter()
+        .map(|c| u64::try_from(c).map_err(|_| "negative RLE count".into()))
+        .collect()
+}
+
+/// Attribute value as a string (JSON strings without quotes).
+fn attribute_value(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        _ => value.to_string(),
+    }
+}
+
+/// Whether the JSON source looks like a COCO dataset.
+pub fn is_coco(src: &str) -> bool {
+    serde_json::from_str::<Dataset>(src).is_ok()
+}
+
+/// Converts a COCO `[x, y, width, height]` box.
+fn to_bndbox(bbox: &[f64; 4]) -> annotation::Bndbox {
+    let [x, y, width, height] = *bbox;
+    annotation::Bndbox {
+        xmin: x.max(0.),
+        ymin: y.max(0.),
+        xmax: (x + width).max(0.),
+        ymax: (y + height).max(0.),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Dataset {
+    images: Vec<Image>,
+    annotations: Vec<Annotation>,
+    categories: Vec<Category>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    id: u64,
+    file_name: String,
+    width: Option<usize>,
+    height: Option<usize>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Annotation {
+    id: Option<u64>,
+    image_id: u64,
+    category_id: u64,
+    bbox: [f64; 4],
+    /// As in CVAT and Datumaro exports.
+    #[serde(default)]
+    attributes: BTreeMap<String, Value>,
+    segmentation: Option<Segmentation>,
+}
+
+/// Only RLE masks are used; polygons are ignored.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Segmentation {
+    Rle(Rle),
+    #[allow(dead_code)]
+    Polygons(Value),
+}
+
+#[derive(Debug, Deserialize)]
+struct Rle {
+    /// Height and width.
+    size: [u32; 2],
+    counts: RleCounts,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum RleCounts {
+    Uncompressed(Vec<u64>),
+    Compressed(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct Category {
+    id: u64,
+    name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry;
+    use pretty_assertions::assert_eq;
+
+    const COCO1: &str = r#"{
+        "images": [
+            {"id": 1, "file_name": "a.png", "width": 640, "height": 480},
+            {"id": 2, "file_name": "b.png", "width": 640, "height": 480}
+        ],
+        "annotations": [
+            {"id": 10, "image_id": 1, "category_id": 3, "bbox": [10.4, 20, 30, 40.6], "area": 1218, "iscrowd": 0,
+             "attributes": {"occluded": false, "life-stage": "adult", "count": 2}},
+            {"id": 11, "image_id": 1, "category_id": 9, "bbox": [0, 0, 5, 5], "area": 25, "iscrowd": 0}
+        ],
+        "categories": [{"id": 3, "name": "Aegina"}]
+    }"#;
+
+    #[test]
+    fn issues() {
+        let mut issues = Vec::new();
+        let annotations = parse_coco_with("imgs", COCO1, |issue| {
+            issues.push(issue);
+            true
+        })
+        .unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(
+            issues,
+            [Issue::Irregular(
+                "annotation of unknown category 9".to_string()
+            )]
+        );
+        let strict = parse_coco_with("imgs", COCO1, |issue| Strictness::Strict.accepts(&issue));
+        assert!(strict.is_err());
+    }
+
+    #[test]
+    fn coco1() {
+        assert!(is_coco(COCO1));
+        assert!(!is_coco(r#"{"images": []}"#));
+        let annotations = parse_coco("imgs", COCO1).unwrap();
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[1].objects, None);
+        assert_eq!(
+            annotations[0].image_size,
+            Some(ImageSize {
+                width: 640,
+                height: 480
+            })
+        );
+        let objects = annotations[0].objects.as_ref().unwrap();
+        assert_eq!(objects[0].name, "Aegina");
+        assert_eq!(
+            objects[0].bndbox,
+            annotation::Bndbox {
+                xmin: 10.4,
+                ymin: 20.,
+                xmax: 10.4 + 30.,
+                ymax: 20. + 40.6,
+            }
+        );
+        assert_eq!(objects[1].name, "class_9");
+
+        let attributes: Vec<(&str, &str)> = objects[0]
+            .attributes
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        assert_eq!(
+            attributes,
+            vec![
+                ("count", "2"),
+                ("life-stage", "adult"),
+                ("occluded", "false")
+            ]
+        );
+        let filter = |s: &str| annotation::parse_attribute(s).unwrap();
+        assert!(objects[0].has_attributes(&[filter("occluded=false"), filter("life-stage=adult")]));
+        assert!(!objects[0].has_attributes(&[filter("occluded=true")]));
+        assert!(!objects[1].has_attributes(&[filter("occluded=false")]));
+    }
+
+    #[test]
+    fn rle() {
+        // 3x4 image (h x w), column-major: columns 1 and 2 rows 1-2, and column 3 row 0
+        let uncompressed = Rle {
+            size: [3, 4],
+            counts: RleCounts::Uncompressed(vec![4, 2, 1, 3, 2]),
+        };
+        let mask = decode_rle(&uncompressed).unwrap();
+        assert_eq!(
+            mask.bounds(),
+            geometry::PixelRect {
+                xmin: 1,
+                ymin: 0,
+                xmax: 4,
+                ymax: 3,
+            }
+        );
+        assert!(mask.contains(1, 1) && mask.contains(2, 2) && mask.contains(3, 0));
+        assert!(!mask.contains(1, 0) && !mask.contains(2, 0));
+        assert!(!mask.contains(3, 2));
+
+        // same in the compressed form (as produced by pycocotools):
+        assert_eq!(decompress_counts("42111").unwrap(), vec![4, 2, 1, 3, 2]);
+        assert_eq!(decompress_counts("451M").unwrap(), vec![4, 5, 1, 2]);
+        let compressed = Rle {
+            size: [3, 4],
+            counts: RleCounts::Compressed("42111".to_string()),
+        };
+        assert_eq!(decode_rle(&compressed).unwrap(), mask);
+
+        assert!(decode_rle(&Rle {
+            size: [3, 3],
+            counts: RleCounts::Uncompressed(vec![4, 2]),
+        })
+        .is_err());
+    }
+}
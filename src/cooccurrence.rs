@@ -0,0 +1,139 @@
+//! Label co-occurrence: the number of images with each pair of labels, eg., to
+//! tell whether multi-label or hierarchical models are needed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+/// Number of label pairs shown in the report.
+const MAX_PAIRS: usize = 10;
+
+pub struct Cooccurrence {
+    /// The labels, in order.
+    pub labels: Vec<String>,
+    /// Number of images with both labels, by label index; the diagonal is the
+    /// number of images with the label.
+    pub counts: Vec<Vec<usize>>,
+}
+
+impl Cooccurrence {
+    /// From the labels in each image.
+    pub fn new<'a>(images: impl IntoIterator<Item = BTreeSet<&'a str>>) -> Cooccurrence {
+        let images: Vec<BTreeSet<&str>> = images.into_iter().collect();
+        let index: BTreeMap<&str, usize> = images
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<BTreeSet<&str>>()
+            .into_iter()
+            .enumerate()
+            .map(|(i, label)| (label, i))
+            .collect();
+        let mut counts = vec![vec![0; index.len()]; index.len()];
+        for labels in &images {
+            for a in labels {
+                for b in labels {
+                    counts[index[a]][index[b]] += 1;
+                }
+            }
+        }
+        Cooccurrence {
+            labels: index.into_keys().map(str::to_string).collect(),
+            counts,
+        }
+    }
+
+    /// The fraction of the images with the label of each row that also have
+    /// the label of the column.
+    pub fn normalized(&self) -> Vec<Vec<f64>> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, row)| row.iter().map(|&n| n as f64 / row[i] as f64).collect())
+            .collect()
+    }
+
+    pub fn report(&self) {
+        let mut pairs: Vec<(usize, usize, usize)> = (0..self.labels.len())
+            .flat_map(|i| (i + 1..self.labels.len()).map(move |j| (i, j)))
+            .map(|(i, j)| (self.counts[i][j], i, j))
+            .filter(|(n, _, _)| *n > 0)
+            .collect();
+        if pairs.is_empty() {
+            return;
+        }
+        pairs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| (a.1, a.2).cmp(&(b.1, b.2))));
+        println!("\n  Labels in the same images (top {} pairs):", MAX_PAIRS);
+        for (n, i, j) in pairs.into_iter().take(MAX_PAIRS) {
+            println!(
+                "   {:>5} \"{}\" + \"{}\"",
+                n, self.labels[i], self.labels[j]
+            );
+        }
+    }
+
+    /// Writes the counts to the given CSV file, and the normalized matrix to
+    /// one with `_normalized` appended to the name, returning its path.
+    pub fn save(&self, csv_filename: &Path) -> csv::Result<PathBuf> {
+        self.write(csv_filename, &self.counts)?;
+        let mut name = csv_filename.file_stem().unwrap_or_default().to_owned();
+        name.push("_normalized.csv");
+        let normalized_filename = csv_filename.with_file_name(name);
+        let normalized: Vec<Vec<String>> = self
+            .normalized()
+            .iter()
+            .map(|row| row.iter().map(|f| format!("{:.4}", f)).collect())
+            .collect();
+        self.write(&normalized_filename, &normalized)?;
+        Ok(normalized_filename)
+    }
+
+    fn write<T: ToString>(&self, csv_filename: &Path, rows: &[Vec<T>]) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_path(csv_filename)?;
+        wtr.write_record(std::iter::once("label").chain(self.labels.iter().map(String::as_str)))?;
+        for (label, row) in self.labels.iter().zip(rows) {
+            let values = row.iter().map(ToString::to_string);
+            wtr.write_record(std::iter::once(label.clone()).chain(values))?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cooccurrence() {
+        let images = [
+            vec!["fish", "squid"],
+            vec!["fish"],
+            vec!["fish", "squid", "jelly"],
+            vec!["jelly"],
+        ];
+        let cooccurrence =
+            Cooccurrence::new(images.iter().map(|labels| labels.iter().copied().collect()));
+        assert_eq!(cooccurrence.labels, ["fish", "jelly", "squid"]);
+        assert_eq!(
+            cooccurrence.counts,
+            [[3, 1, 2], [1, 2, 1], [2, 1, 2]].map(Vec::from)
+        );
+        let normalized = cooccurrence.normalized();
+        assert_eq!(normalized[0], [1., 1. / 3., 2. / 3.]);
+        assert_eq!(normalized[2], [1., 0.5, 1.]);
+
+        let dir = std::env::temp_dir().join(format!("blaise-cooccurrence-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let normalized_filename = cooccurrence.save(&dir.join("labels.csv")).unwrap();
+        assert_eq!(normalized_filename, dir.join("labels_normalized.csv"));
+        assert_eq!(
+            std::fs::read_to_string(dir.join("labels.csv")).unwrap(),
+            "label,fish,jelly,squid\nfish,3,1,2\njelly,1,2,1\nsquid,2,1,2\n"
+        );
+        assert!(std::fs::read_to_string(&normalized_filename)
+            .unwrap()
+            .contains("\nfish,1.0000,0.3333,0.6667\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
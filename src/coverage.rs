@@ -0,0 +1,139 @@
+//! Annotation coverage of the images on disk, by folder (eg., dive).
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// On disk and referenced by annotations.
+    Annotated,
+    /// On disk but not referenced by any annotation.
+    Unannotated,
+    /// Referenced by annotations but not on disk.
+    Missing,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct FolderCoverage {
+    pub annotated: usize,
+    pub unannotated: usize,
+    pub missing: usize,
+}
+
+#[derive(serde::Serialize)]
+struct Row<'a> {
+    folder: &'a str,
+    image: &'a str,
+    status: Status,
+}
+
+pub struct Coverage {
+    pub images: BTreeMap<PathBuf, Status>,
+}
+
+impl Coverage {
+    pub fn new(annotated: &[PathBuf], on_disk: &[PathBuf]) -> Coverage {
+        let annotated: BTreeSet<PathBuf> = annotated.iter().map(|p| normalize(p)).collect();
+        let on_disk: BTreeSet<PathBuf> = on_disk.iter().map(|p| normalize(p)).collect();
+        let mut images = BTreeMap::new();
+        for path in &on_disk {
+            let status = match annotated.contains(path) {
+                true => Status::Annotated,
+                false => Status::Unannotated,
+            };
+            images.insert(path.clone(), status);
+        }
+        for path in annotated.difference(&on_disk) {
+            images.insert(path.clone(), Status::Missing);
+        }
+        Coverage { images }
+    }
+
+    pub fn by_folder(&self) -> BTreeMap<&Path, FolderCoverage> {
+        let mut by_folder: BTreeMap<&Path, FolderCoverage> = BTreeMap::new();
+        for (path, status) in &self.images {
+            let folder = by_folder
+                .entry(path.parent().unwrap_or(Path::new("")))
+                .or_default();
+            match status {
+                Status::Annotated => folder.annotated += 1,
+                Status::Unannotated => folder.unannotated += 1,
+                Status::Missing => folder.missing += 1,
+            }
+        }
+        by_folder
+    }
+
+    pub fn report(&self) {
+        println!("\n  Annotation coverage by folder:");
+        println!(
+            "    {:>9}{:>12}{:>9} folder",
+            "annotated", "unannotated", "missing"
+        );
+        for (folder, c) in self.by_folder() {
+            println!(
+                "    {:>9}{:>12}{:>9} {}",
+                c.annotated,
+                c.unannotated,
+                c.missing,
+                folder.display()
+            );
+        }
+    }
+
+    /// Writes a CSV listing the images with their status.
+    pub fn save(&self, csv_filename: &Path) {
+        let mut wtr = csv::Writer::from_path(csv_filename).unwrap();
+        for (path, status) in &self.images {
+            let folder = path.parent().unwrap_or(Path::new(""));
+            wtr.serialize(Row {
+                folder: &folder.to_string_lossy(),
+                image: &path.to_string_lossy(),
+                status: *status,
+            })
+            .unwrap();
+        }
+        wtr.flush().unwrap();
+        println!("Wrote coverage report to {:?}", csv_filename);
+    }
+}
+
+/// Lexically normalized path (no `.` components or repeated separators).
+fn normalize(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != std::path::Component::CurDir)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn coverage() {
+        let annotated: Vec<PathBuf> = ["d1/a.png", "./d1//b.png", "d2/x.png"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let on_disk: Vec<PathBuf> = ["d1/a.png", "d1/b.png", "d1/c.png", "d3/y.png"]
+            .iter()
+            .map(PathBuf::from)
+            .collect();
+        let coverage = Coverage::new(&annotated, &on_disk);
+        assert_eq!(coverage.images[Path::new("d1/b.png")], Status::Annotated);
+        assert_eq!(coverage.images[Path::new("d1/c.png")], Status::Unannotated);
+        assert_eq!(coverage.images[Path::new("d2/x.png")], Status::Missing);
+
+        let by_folder = coverage.by_folder();
+        let folder = |annotated, unannotated, missing| FolderCoverage {
+            annotated,
+            unannotated,
+            missing,
+        };
+        assert_eq!(by_folder[Path::new("d1")], folder(2, 1, 0));
+        assert_eq!(by_folder[Path::new("d2")], folder(0, 0, 1));
+        assert_eq!(by_folder[Path::new("d3")], folder(0, 1, 0));
+    }
+}
@@ -0,0 +1,383 @@
+//! Service mode (`blaise daemon`): a small HTTP/JSON API to submit crop jobs,
+//! poll their status and fetch their reports, so the tool can be driven by a
+//! workflow manager instead of over SSH.
+//!
+//! - `POST /jobs` with `{"args": [...]}`, the command line arguments as with
+//!   the C interface, queues a job, returning its status (`201`).
+//! - `GET /jobs` lists the status of all jobs.
+//! - `GET /jobs/<id>` returns the status of the job: `queued`, `running`,
+//!   `done` or `failed` (non-zero exit code), with the exit code once ended.
+//! - `GET /jobs/<id>/report` returns the report of an ended job as with
+//!   `blaise_run` (`409` while not ended).
+//!
+//! The jobs run one at a time, in the order submitted, each with the threads
+//! given in its arguments. Relative paths are from the daemon's directory.
+//!
+//! With a token (`--token`, or `BLAISE_DAEMON_TOKEN`), every request must
+//! give it as `Authorization: Bearer <token>` (`401` otherwise). Without one,
+//! the daemon only listens on a loopback address, as anyone reaching it could
+//! run jobs with its permissions.
+
+use crate::ffi;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// Maximum size of a request body.
+const MAX_BODY: usize = 1 << 20;
+
+#[derive(Deserialize)]
+struct JobSpec {
+    args: Vec<String>,
+}
+
+enum State {
+    Queued,
+    Running,
+    Ended(Value),
+}
+
+struct Job {
+    args: Vec<String>,
+    state: State,
+}
+
+impl Job {
+    fn status(&self, id: usize) -> Value {
+        let (status, exit_code) = match &self.state {
+            State::Queued => ("queued", None),
+            State::Running => ("running", None),
+            State::Ended(report) => {
+                let exit_code = report["exit_code"].as_i64();
+                let status = if exit_code == Some(0) {
+                    "done"
+                } else {
+                    "failed"
+                };
+                (status, exit_code)
+            }
+        };
+        json!({"id": id, "status": status, "exit_code": exit_code, "args": self.args})
+    }
+}
+
+struct Daemon {
+    jobs: Mutex<Vec<Job>>,
+    queue: Mutex<Sender<usize>>,
+    /// The token required in the requests, if any.
+    token: Option<String>,
+}
+
+impl Daemon {
+    /// Whether the request with the given `Authorization` header is allowed.
+    fn authorized(&self, authorization: Option<&str>) -> bool {
+        let Some(token) = &self.token else {
+            return true;
+        };
+        let given = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        // (in constant time for tokens of the same length)
+        given.is_some_and(|given| {
+            given.len() == token.len()
+                && given
+                    .bytes()
+                    .zip(token.bytes())
+                    .fold(0, |diff, (a, b)| diff | (a ^ b))
+                    == 0
+        })
+    }
+}
+
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+impl Response {
+    fn new(status: u16, body: Value) -> Self {
+        Self { status, body }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::new(status, json!({ "error": message }))
+    }
+}
+
+/// Serves the API on the given address, eg., `127.0.0.1:8080`, requiring the
+/// token in the requests if given.
+pub fn run(listen: &str, token: Option<String>) -> io::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    let addr = listener.local_addr()?;
+    check_exposure(addr, token.as_deref())?;
+    println!("blaise daemon listening on {}", addr);
+    serve(listener, token);
+    Ok(())
+}
+
+/// Refuses to listen on a non-loopback address without a token.
+fn check_exposure(addr: SocketAddr, token: Option<&str>) -> io::Result<()> {
+    if token.is_none() && !addr.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "a --token is required to listen on a non-loopback address",
+        ));
+    }
+    Ok(())
+}
+
+fn serve(listener: TcpListener, token: Option<String>) {
+    let (sender, receiver) = mpsc::channel::<usize>();
+    let daemon = Arc::new(Daemon {
+        jobs: Mutex::new(Vec::new()),
+        queue: Mutex::new(sender),
+        token,
+    });
+
+    let worker = Arc::clone(&daemon);
+    thread::spawn(move || {
+        for id in receiver {
+            let args = {
+                let mut jobs = worker.jobs.lock().unwrap();
+                let job = &mut jobs[id - 1];
+                job.state = State::Running;
+                job.args.clone()
+            };
+            println!("job {}: started", id);
+            let report = ffi::run_args(args, None);
+            println!("job {}: ended with exit code {}", id, report["exit_code"]);
+            worker.jobs.lock().unwrap()[id - 1].state = State::Ended(report);
+        }
+    });
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = Arc::clone(&daemon);
+                thread::spawn(move || {
+                    if let Err(e) = handle(&daemon, stream) {
+                        eprintln!("WARN: daemon connection: {}", e);
+                    }
+                });
+            }
+            Err(e) => eprintln!("WARN: daemon connection: {}", e),
+        }
+    }
+}
+
+fn handle(daemon: &Daemon, stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.trim().eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_string());
+            }
+        }
+    }
+
+    let response = if !daemon.authorized(authorization.as_deref()) {
+        Response::error(401, "missing or invalid token")
+    } else if content_length > MAX_BODY {
+        Response::error(413, "request too large")
+    } else {
+        let mut body = vec![0; content_length];
+        reader.read_exact(&mut body)?;
+        let mut parts = request_line.split_whitespace();
+        let method = parts.next().unwrap_or_default();
+        let path = parts.next().unwrap_or_default();
+        respond(daemon, method, path, &body)
+    };
+    write_response(&stream, &response)
+}
+
+fn respond(daemon: &Daemon, method: &str, path: &str, body: &[u8]) -> Response {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match (method, segments.as_slice()) {
+        ("POST", ["jobs"]) => submit(daemon, body),
+        ("GET", ["jobs"]) => {
+            let jobs = daemon.jobs.lock().unwrap();
+            let statuses: Vec<Value> = jobs
+                .iter()
+                .enumerate()
+                .map(|(i, job)| job.status(i + 1))
+                .collect();
+            Response::new(200, Value::from(statuses))
+        }
+        ("GET", ["jobs", id, rest @ ..]) if rest.len() <= 1 => {
+            let jobs = daemon.jobs.lock().unwrap();
+            let job = match id.parse::<usize>() {
+                Ok(id) if id >= 1 && id <= jobs.len() => (id, &jobs[id - 1]),
+                _ => return Response::error(404, "no such job"),
+            };
+            match rest {
+                [] => Response::new(200, job.1.status(job.0)),
+                ["report"] => match &job.1.state {
+                    State::Ended(report) => Response::new(200, report.clone()),
+                    _ => Response::error(409, "job not ended"),
+                },
+                _ => Response::error(404, "not found"),
+            }
+        }
+        (_, ["jobs", ..]) => Response::error(405, "method not allowed"),
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn submit(daemon: &Daemon, body: &[u8]) -> Response {
+    let spec: JobSpec = match serde_json::from_slice(body) {
+        Ok(spec) => spec,
+        Err(e) => return Response::error(400, &format!("invalid job spec: {}", e)),
+    };
+    let mut jobs = daemon.jobs.lock().unwrap();
+    jobs.push(Job {
+        args: spec.args,
+        state: State::Queued,
+    });
+    let id = jobs.len();
+    if daemon.queue.lock().unwrap().send(id).is_err() {
+        return Response::error(503, "job queue stopped");
+    }
+    Response::new(201, jobs[id - 1].status(id))
+}
+
+fn write_response(mut stream: &TcpStream, response: &Response) -> io::Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        _ => "Service Unavailable",
+    };
+    let body = response.body.to_string();
+    let challenge = match response.status {
+        401 => "WWW-Authenticate: Bearer\r\n",
+        _ => "",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\n{}Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        reason,
+        challenge,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        request_with(addr, method, path, body, "")
+    }
+
+    /// As `request`, with the given additional headers.
+    fn request_with(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        body: &str,
+        headers: &str,
+    ) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: {}\r\n\r\n{}",
+            method,
+            path,
+            headers,
+            body.len(),
+            body
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[test]
+    fn jobs() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, None));
+
+        let (status, _) = request(addr, "POST", "/jobs", "{}");
+        assert_eq!(status, 400);
+        let (status, job) = request(addr, "POST", "/jobs", r#"{"args": ["--no-such-option"]}"#);
+        assert_eq!(status, 201);
+        assert_eq!(job["id"], 1);
+
+        let mut job = job;
+        for _ in 0..100 {
+            if job["status"] != "queued" && job["status"] != "running" {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+            job = request(addr, "GET", "/jobs/1", "").1;
+        }
+        assert_eq!(job["status"], "failed");
+        assert_eq!(job["exit_code"], 4);
+        let (status, report) = request(addr, "GET", "/jobs/1/report", "");
+        assert_eq!(status, 200);
+        assert_eq!(report["exit_code"], 4);
+
+        let (_, jobs) = request(addr, "GET", "/jobs", "");
+        assert_eq!(jobs.as_array().unwrap().len(), 1);
+        assert_eq!(request(addr, "GET", "/jobs/2", "").0, 404);
+        assert_eq!(request(addr, "DELETE", "/jobs/1", "").0, 405);
+        assert_eq!(request(addr, "GET", "/status", "").0, 404);
+    }
+
+    #[test]
+    fn token() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, Some("s3cret".to_string())));
+
+        let (status, body) = request(addr, "GET", "/jobs", "");
+        assert_eq!(status, 401);
+        assert_eq!(body["error"], "missing or invalid token");
+        let with = |token: &str, method: &str, path: &str| {
+            let header = format!("Authorization: Bearer {}\r\n", token);
+            request_with(addr, method, path, "", &header).0
+        };
+        assert_eq!(with("s3cre", "GET", "/jobs"), 401);
+        assert_eq!(with("s3creT", "GET", "/jobs"), 401);
+        assert_eq!(with("s3cret", "GET", "/jobs"), 200);
+        // every request, not only the submissions:
+        assert_eq!(request(addr, "GET", "/jobs/1/report", "").0, 401);
+        assert_eq!(with("s3cret", "GET", "/jobs/1/report"), 404);
+    }
+
+    #[test]
+    fn exposure() {
+        let any: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+        let loopback: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+        assert!(check_exposure(loopback, None).is_ok());
+        assert!(check_exposure("[::1]:8080".parse().unwrap(), None).is_ok());
+        let e = check_exposure(any, None).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+        assert!(check_exposure(any, Some("s3cret")).is_ok());
+    }
+}
@@ -0,0 +1,205 @@
+//! Perceptual hashing and near-duplicate clustering of images.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HashKind {
+    /// Difference hash
+    Dhash,
+    /// DCT-based perceptual hash
+    Phash,
+}
+
+pub fn image_hash(img: &DynamicImage, kind: HashKind) -> u64 {
+    match kind {
+        HashKind::Dhash => dhash(img),
+        HashKind::Phash => phash(img),
+    }
+}
+
+/// 64-bit difference hash: sign of horizontal gradients on a 9x8 grayscale thumbnail.
+pub fn dhash(img: &DynamicImage) -> u64 {
+    let small = img.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = small.get_pixel(x, y)[0];
+            let right = small.get_pixel(x + 1, y)[0];
+            hash = (hash << 1) | (left > right) as u64;
+        }
+    }
+    hash
+}
+
+/// 64-bit perceptual hash: low frequency DCT coefficients of a 32x32 grayscale
+/// thumbnail compared against their median.
+pub fn phash(img: &DynamicImage) -> u64 {
+    const N: usize = 32;
+    let small = img
+        .resize_exact(N as u32, N as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = small.pixels().map(|p| p[0] as f64).collect();
+
+    let cos: Vec<f64> = (0..8 * N)
+        .map(|i| {
+            let (u, x) = (i / N, i % N);
+            ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * N) as f64).cos()
+        })
+        .collect();
+    let mut coefs = Vec::with_capacity(64);
+    for v in 0..8 {
+        for u in 0..8 {
+            let mut sum = 0.;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += pixels[y * N + x] * cos[u * N + x] * cos[v * N + y];
+                }
+            }
+            coefs.push(sum);
+        }
+    }
+    // median excluding the DC term:
+    let mut sorted: Vec<f64> = coefs[1..].to_vec();
+    sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let median = sorted[sorted.len() / 2];
+    coefs
+        .iter()
+        .fold(0u64, |hash, c| (hash << 1) | (*c > median) as u64)
+}
+
+pub fn hamming(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Groups the hashes into clusters of items (transitively) within the given
+/// Hamming distance. Only clusters with more than one item are returned,
+/// each sorted by index, and larger clusters first.
+pub fn clusters(hashes: &[u64], max_distance: u32) -> Vec<Vec<usize>> {
+    let mut tree = BkTree::default();
+    for (i, hash) in hashes.iter().enumerate() {
+        tree.insert(*hash, i);
+    }
+
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn find(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    for (i, hash) in hashes.iter().enumerate() {
+        for j in tree.find(*hash, max_distance) {
+            let (a, b) = (find(&mut parent, i), find(&mut parent, j));
+            if a != b {
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut by_root: std::collections::BTreeMap<usize, Vec<usize>> = Default::default();
+    for i in 0..hashes.len() {
+        let root = find(&mut parent, i);
+        by_root.entry(root).or_default().push(i);
+    }
+    let mut clusters: Vec<Vec<usize>> = by_root.into_values().filter(|c| c.len() > 1).collect();
+    clusters.sort_by_key(|c| std::cmp::Reverse(c.len()));
+    clusters
+}
+
+/// BK-tree over Hamming distance for near neighbor queries.
+#[derive(Default)]
+struct BkTree {
+    nodes: Vec<BkNode>,
+}
+
+struct BkNode {
+    hash: u64,
+    index: usize,
+    children: Vec<(u32, usize)>,
+}
+
+impl BkTree {
+    fn insert(&mut self, hash: u64, index: usize) {
+        let new_node = BkNode {
+            hash,
+            index,
+            children: Vec::new(),
+        };
+        if self.nodes.is_empty() {
+            self.nodes.push(new_node);
+            return;
+        }
+        let mut current = 0;
+        loop {
+            let distance = hamming(self.nodes[current].hash, hash);
+            match self.nodes[current]
+                .children
+                .iter()
+                .find(|(d, _)| *d == distance)
+            {
+                Some((_, child)) => current = *child,
+                None => {
+                    let id = self.nodes.len();
+                    self.nodes.push(new_node);
+                    self.nodes[current].children.push((distance, id));
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Indices of the items within the given distance of the hash.
+    fn find(&self, hash: u64, max_distance: u32) -> Vec<usize> {
+        let mut result = Vec::new();
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+        while let Some(current) = pending.pop() {
+            let node = &self.nodes[current];
+            let distance = hamming(node.hash, hash);
+            if distance <= max_distance {
+                result.push(node.index);
+            }
+            for (d, child) in &node.children {
+                if d.abs_diff(distance) <= max_distance {
+                    pending.push(*child);
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn hashes_of_similar_images() {
+        let img = image::open("data/imgs/IMG_TEST.png").unwrap();
+        let smaller = img.resize_exact(200, 150, FilterType::Triangle);
+        for kind in [HashKind::Dhash, HashKind::Phash] {
+            let distance = hamming(image_hash(&img, kind), image_hash(&smaller, kind));
+            assert!(distance <= 4, "{:?}: {}", kind, distance);
+        }
+    }
+
+    #[test]
+    fn clustering() {
+        let hashes = [
+            0b0000,
+            0xffff_0000,
+            0b0001,
+            0b0011,
+            0xffff_0001,
+            0xf0f0_f0f0,
+        ];
+        assert_eq!(clusters(&hashes, 1), vec![vec![0, 2, 3], vec![1, 4]]);
+        assert_eq!(clusters(&hashes, 0), Vec::<Vec<usize>>::new());
+    }
+}
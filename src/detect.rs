@@ -0,0 +1,190 @@
+//! Detection of the annotation format(s) present under an input directory.
+
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[cfg(feature = "coco")]
+use crate::coco;
+use crate::pascal;
+#[cfg(feature = "supervisely")]
+use crate::supervisely;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InputFormat {
+    /// Pascal VOC XML files
+    Voc,
+    /// YOLO txt files with a names file
+    Yolo,
+    /// COCO JSON file
+    #[cfg(feature = "coco")]
+    Coco,
+    /// Supervisely project
+    #[cfg(feature = "supervisely")]
+    Supervisely,
+}
+
+/// A detected input, with the paths to use for it.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Detected {
+    Voc {
+        dir: PathBuf,
+    },
+    Yolo {
+        image_dir: PathBuf,
+        label_dir: PathBuf,
+        names: PathBuf,
+    },
+    #[cfg(feature = "coco")]
+    Coco {
+        file: PathBuf,
+    },
+    #[cfg(feature = "supervisely")]
+    Supervisely {
+        dir: PathBuf,
+    },
+}
+
+impl Detected {
+    pub fn format(&self) -> InputFormat {
+        match self {
+            Detected::Voc { .. } => InputFormat::Voc,
+            Detected::Yolo { .. } => InputFormat::Yolo,
+            #[cfg(feature = "coco")]
+            Detected::Coco { .. } => InputFormat::Coco,
+            #[cfg(feature = "supervisely")]
+            Detected::Supervisely { .. } => InputFormat::Supervisely,
+        }
+    }
+}
+
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+fn is_names_file(path: &Path) -> bool {
+    path.extension() == Some("names".as_ref()) || path.file_name() == Some("classes.txt".as_ref())
+}
+
+/// Returns the inputs detected under the given directory, in order of preference:
+/// a Supervisely project, COCO files, VOC files, and YOLO files.
+pub fn detect(dir: &Path) -> Vec<Detected> {
+    #[cfg(feature = "supervisely")]
+    let supervisely_meta = read_to_string(dir.join(supervisely::META_FILE))
+        .is_ok_and(|src| supervisely::parse_meta(&src).is_ok());
+
+    #[cfg(feature = "coco")]
+    let mut coco_files = Vec::new();
+    let mut voc_found = false;
+    let mut names_files = Vec::new();
+    let mut txt_dirs: HashMap<PathBuf, usize> = HashMap::new();
+    let mut image_dirs: HashMap<PathBuf, usize> = HashMap::new();
+
+    for entry in WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+        let parent = path.parent().unwrap_or(dir).to_path_buf();
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "xml" if !voc_found => {
+                voc_found = read_to_string(path).is_ok_and(|src| pascal::parse_xml(&src).is_ok());
+            }
+            #[cfg(feature = "coco")]
+            "json" if read_to_string(path).is_ok_and(|src| coco::is_coco(&src)) => {
+                coco_files.push(path.to_path_buf());
+            }
+            _ if is_names_file(path) => names_files.push(path.to_path_buf()),
+            "txt" => *txt_dirs.entry(parent).or_insert(0) += 1,
+            e if IMAGE_EXTENSIONS.contains(&e) => *image_dirs.entry(parent).or_insert(0) += 1,
+            _ => {}
+        }
+    }
+
+    let mut detected: Vec<Detected> = Vec::new();
+    #[cfg(feature = "supervisely")]
+    if supervisely_meta {
+        detected.push(Detected::Supervisely {
+            dir: dir.to_path_buf(),
+        });
+    }
+    #[cfg(feature = "coco")]
+    detected.extend(coco_files.into_iter().map(|file| Detected::Coco { file }));
+    if voc_found {
+        detected.push(Detected::Voc {
+            dir: dir.to_path_buf(),
+        });
+    }
+    let most_files = |dirs: HashMap<PathBuf, usize>| {
+        dirs.into_iter()
+            .max_by(|a, b| a.1.cmp(&b.1).then_with(|| b.0.cmp(&a.0)))
+            .map(|(dir, _)| dir)
+    };
+    if let (Some(names), Some(label_dir)) = (names_files.into_iter().next(), most_files(txt_dirs)) {
+        // with the common images/ and labels/ layout, prefer the sibling images directory:
+        let sibling = label_dir
+            .parent()
+            .map(|p| p.join("images"))
+            .filter(|d| label_dir.file_name() == Some("labels".as_ref()) && d.is_dir());
+        if let Some(image_dir) = sibling.or_else(|| most_files(image_dirs)) {
+            detected.push(Detected::Yolo {
+                image_dir,
+                label_dir,
+                names,
+            });
+        }
+    }
+    detected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::fs::{create_dir_all, write};
+
+    #[test]
+    fn detection() {
+        let dir = std::env::temp_dir().join("blaise_detect_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        create_dir_all(dir.join("images")).unwrap();
+        create_dir_all(dir.join("labels")).unwrap();
+        write(dir.join("images/a.png"), "").unwrap();
+        write(dir.join("labels/a.txt"), "0 0.5 0.5 0.1 0.1\n").unwrap();
+        write(dir.join("obj.names"), "Aegina\n").unwrap();
+        write(dir.join("other.json"), "{}").unwrap();
+        assert_eq!(
+            detect(&dir),
+            vec![Detected::Yolo {
+                image_dir: dir.join("images"),
+                label_dir: dir.join("labels"),
+                names: dir.join("obj.names"),
+            }]
+        );
+
+        #[cfg(feature = "coco")]
+        {
+            write(
+                dir.join("coco.json"),
+                r#"{"images": [], "annotations": [], "categories": []}"#,
+            )
+            .unwrap();
+            let detected = detect(&dir);
+            assert_eq!(detected.len(), 2);
+            assert_eq!(
+                detected[0],
+                Detected::Coco {
+                    file: dir.join("coco.json")
+                }
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
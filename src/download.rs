@@ -0,0 +1,378 @@
+//! Download of the images referenced by http(s) URLs in the annotations (eg.,
+//! framegrab URLs in M3 exports) to a local cache directory, ahead of the crop
+//! workers. Transfers go through `curl`, with a number of them in parallel.
+//!
+//! Each transfer is checked against the `Content-Length` of the response and,
+//! when the server gives one, its MD5 digest: the `Content-MD5` header, else
+//! an `ETag` of 32 hex digits (as S3 gives for objects uploaded in one part).
+//! Each cached file then has a `.sha256` sidecar with the checksum of the
+//! accepted copy, checked when reused so that a copy altered in the cache
+//! since (eg., truncated by a full disk) is fetched again.
+//! The modification time of a cached file is that of its last use, for the
+//! least recently used eviction with `--cache-size`.
+
+use crate::checksum;
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Whether the image reference is an http(s) URL.
+pub fn is_url(s: &str) -> bool {
+    s.starts_with("http://") || s.starts_with("https://")
+}
+
+/// Last path segment of the URL, without query or fragment.
+pub fn url_filename(url: &str) -> &str {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Location of the cached copy of the URL in the cache directory.
+pub fn cache_path(cache_dir: &Path, url: &str) -> PathBuf {
+    let name = checksum::to_hex(&checksum::sha256(url.as_bytes())[..8]);
+    match Path::new(url_filename(url)).extension() {
+        Some(ext) => cache_dir.join(format!("{}.{}", name, ext.to_string_lossy())),
+        None => cache_dir.join(name),
+    }
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_os_string();
+    name.push(".sha256");
+    path.with_file_name(name)
+}
+
+/// Whether the cached copy exists and matches its checksum.
+fn is_valid(path: &Path) -> bool {
+    match (fs::read(path), fs::read_to_string(sidecar_path(path))) {
+        (Ok(bytes), Ok(expected)) => checksum::to_hex(&checksum::sha256(&bytes)) == expected.trim(),
+        _ => false,
+    }
+}
+
+/// What the final response (after any redirects) says about its body.
+#[derive(Debug, Default, PartialEq)]
+struct Expected {
+    length: Option<u64>,
+    md5: Option<[u8; 16]>,
+}
+
+/// Reads the headers of the final response from those dumped by curl.
+fn parse_headers(dump: &str) -> Expected {
+    let mut content_md5 = None;
+    let mut etag_md5 = None;
+    let mut expected = Expected::default();
+    for line in dump.lines() {
+        if line.starts_with("HTTP/") {
+            // a new response, after a redirect:
+            (content_md5, etag_md5, expected) = (None, None, Expected::default());
+            continue;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "content-length" => expected.length = value.parse().ok(),
+            "content-md5" => content_md5 = decode_base64(value).and_then(|d| d.try_into().ok()),
+            "etag" => etag_md5 = decode_hex(value.trim_matches('"')).and_then(|d| d.try_into().ok()),
+            _ => {}
+        }
+    }
+    expected.md5 = content_md5.or(etag_md5);
+    expected
+}
+
+/// Checks the body against what the response says about it.
+fn check_transfer(bytes: &[u8], expected: &Expected) -> Result<(), String> {
+    if let Some(length) = expected.length.filter(|&l| l != bytes.len() as u64) {
+        return Err(format!("incomplete transfer: {} of {} bytes", bytes.len(), length));
+    }
+    if let Some(md5) = expected.md5.filter(|md5| *md5 != checksum::md5(bytes)) {
+        return Err(format!(
+            "MD5 {} differs from the {} given by the server",
+            checksum::to_hex(&checksum::md5(bytes)),
+            checksum::to_hex(&md5)
+        ));
+    }
+    Ok(())
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut bytes = Vec::new();
+    let (mut bits, mut nbits) = (0u32, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let value = ALPHABET.iter().position(|&a| a == c)? as u32;
+        bits = (bits << 6) | value;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            bytes.push((bits >> nbits) as u8);
+            bits &= (1 << nbits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Fetches the URL into the cache, unless already there.
+/// Returns whether it was downloaded.
+fn fetch(url: &str, path: &Path) -> Result<bool, String> {
+    if is_valid(path) {
+        // marked as used:
+        if let Err(e) = File::options()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            log::debug!("cannot update the time of {:?}: {}", path, e);
+        }
+        return Ok(false);
+    }
+    // through a partial file so an interrupted transfer is never taken as complete:
+    let mut part = path.as_os_str().to_os_string();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    // (no URL globbing, as of brackets in the filenames, and the URL never taken as an option)
+    let output = Command::new("curl")
+        .args(["--fail", "--silent", "--show-error", "--location", "--globoff"])
+        .args(["--retry", "3", "--connect-timeout", "30", "--dump-header", "-", "--output"])
+        .arg(&part)
+        .arg("--")
+        .arg(url)
+        .output()
+        .map_err(|e| format!("cannot run curl: {}", e))?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&part);
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let bytes = fs::read(&part).map_err(|e| e.to_string())?;
+    let expected = parse_headers(&String::from_utf8_lossy(&output.stdout));
+    if let Err(e) = check_transfer(&bytes, &expected) {
+        let _ = fs::remove_file(&part);
+        return Err(e);
+    }
+    let digest = checksum::to_hex(&checksum::sha256(&bytes));
+    fs::write(sidecar_path(path), digest + "\n").map_err(|e| e.to_string())?;
+    fs::rename(&part, path).map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// Downloads the URLs to `cache_dir` with `jobs` transfers in parallel.
+/// Returns the local copies by URL; failures are reported and left out.
+pub fn download_all(
+    urls: &[String],
+    cache_dir: &Path,
+    jobs: usize,
+    show_progress: bool,
+) -> HashMap<String, PathBuf> {
+    if let Err(e) = fs::create_dir_all(cache_dir) {
+        eprintln!(
+            "ERROR: cannot create cache directory {:?}: {}",
+            cache_dir, e
+        );
+        return HashMap::new();
+    }
+    let pb = if show_progress {
+        let pb = ProgressBar::new(urls.len() as u64);
+        pb.set_style(
+            ProgressStyle::with_template("downloading {bar:40.green/yellow} {pos:>7}/{len:7}")
+                .unwrap(),
+        );
+        pb
+    } else {
+        ProgressBar::hidden()
+    };
+    let next = AtomicUsize::new(0);
+    let downloaded = AtomicUsize::new(0);
+    let local = Mutex::new(HashMap::new());
+    std::thread::scope(|s| {
+        for _ in 0..jobs.clamp(1, urls.len().max(1)) {
+            s.spawn(|| {
+                while let Some(url) = urls.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let path = cache_path(cache_dir, url);
+                    match fetch(url, &path) {
+                        Ok(fetched) => {
+                            if fetched {
+                                downloaded.fetch_add(1, Ordering::Relaxed);
+                            }
+                            local.lock().unwrap().insert(url.clone(), path);
+                        }
+                        Err(e) => pb.suspend(|| eprintln!("ERROR: cannot download {}: {}", url, e)),
+                    }
+                    pb.inc(1);
+                }
+            });
+        }
+    });
+    pb.finish_and_clear();
+    let local = local.into_inner().unwrap();
+    println!(
+        "remote images: {} downloaded, {} cached, {} failed",
+        downloaded.load(Ordering::Relaxed),
+        local.len() - downloaded.load(Ordering::Relaxed),
+        urls.len() - local.len()
+    );
+    local
+}
+
+/// Removes the least recently used files from the cache directory, except
+/// those in `keep`, until their total size is at most `max_bytes`.
+/// Returns the number of files removed and their total size.
+pub fn evict(cache_dir: &Path, max_bytes: u64, keep: &HashSet<PathBuf>) -> (usize, u64) {
+    let Ok(entries) = fs::read_dir(cache_dir) else {
+        return (0, 0);
+    };
+    let mut files: Vec<(SystemTime, u64, PathBuf)> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            let ext = path.extension().and_then(|e| e.to_str());
+            if matches!(ext, Some("sha256" | "part")) {
+                return None;
+            }
+            let metadata = entry.metadata().ok().filter(|m| m.is_file())?;
+            Some((metadata.modified().ok()?, metadata.len(), path))
+        })
+        .collect();
+    let mut total: u64 = files.iter().map(|(_, size, _)| size).sum();
+    files.sort();
+    let (mut removed, mut freed) = (0, 0);
+    for (_, size, path) in files {
+        if total <= max_bytes {
+            break;
+        }
+        if keep.contains(&path) {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => {
+                let _ = fs::remove_file(sidecar_path(&path));
+                total -= size;
+                removed += 1;
+                freed += size;
+            }
+            Err(e) => eprintln!("WARN: cannot evict {:?}: {}", path, e),
+        }
+    }
+    (removed, freed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn urls() {
+        assert!(is_url("https://m3.shore.mbari.org/framegrabs/x.png"));
+        assert!(!is_url("imgs/x.png"));
+        assert_eq!(url_filename("http://host/a/b.jpg?t=1#f"), "b.jpg");
+        let path = cache_path(Path::new("cache"), "http://host/a/b.jpg?t=1");
+        assert_eq!(path.extension().unwrap(), "jpg");
+        assert_ne!(
+            path,
+            cache_path(Path::new("cache"), "http://host/a/b.jpg?t=2")
+        );
+    }
+
+    #[test]
+    fn transfer_checks() {
+        let dump = "HTTP/1.1 302 Found\r\nContent-Length: 0\r\nLocation: /b\r\n\r\n\
+                    HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\
+                    ETag: \"900150983cd24fb0d6963f7d28e17f72\"\r\n\r\n";
+        let expected = parse_headers(dump);
+        assert_eq!(expected.length, Some(3));
+        assert_eq!(expected.md5, Some(checksum::md5(b"abc")));
+        assert_eq!(check_transfer(b"abc", &expected), Ok(()));
+        assert_eq!(
+            check_transfer(b"ab", &expected),
+            Err("incomplete transfer: 2 of 3 bytes".to_string())
+        );
+        assert!(check_transfer(b"abd", &expected)
+            .unwrap_err()
+            .starts_with("MD5 "));
+
+        // Content-MD5 over the ETag, not taken as a digest unless 32 hex digits:
+        let dump = "ETag: \"5d41-402abc\"\ncontent-md5: kAFQmDzST7DWlj99KOF/cg==\n";
+        assert_eq!(
+            parse_headers(dump),
+            Expected {
+                length: None,
+                md5: Some(checksum::md5(b"abc"))
+            }
+        );
+        assert_eq!(parse_headers("ETag: W/\"5d41-402abc\"\n"), Expected::default());
+    }
+
+    #[test]
+    fn download_and_reuse() {
+        let dir = std::env::temp_dir().join(format!("blaise-download-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("source.png");
+        fs::write(&source, b"not really a png").unwrap();
+        let cache_dir = dir.join("cache");
+        let url = format!("file://{}", source.display());
+        let missing = format!("file://{}", dir.join("missing.png").display());
+
+        let local = download_all(&[url.clone(), missing.clone()], &cache_dir, 2, false);
+        assert_eq!(local.len(), 1);
+        let path = &local[&url];
+        assert_eq!(fs::read(path).unwrap(), b"not really a png");
+        assert!(is_valid(path));
+        assert_eq!(fetch(&url, path), Ok(false));
+
+        // a corrupted copy is fetched again:
+        fs::write(path, b"truncated").unwrap();
+        assert_eq!(fetch(&url, path), Ok(true));
+        assert_eq!(fs::read(path).unwrap(), b"not really a png");
+
+        // brackets and braces are part of the name, not a glob:
+        let source = dir.join("frame[1]{a,b}.png");
+        fs::write(&source, b"frame").unwrap();
+        let url = format!("file://{}", source.display());
+        let path = cache_path(&cache_dir, &url);
+        assert_eq!(fetch(&url, &path), Ok(true));
+        assert_eq!(fs::read(&path).unwrap(), b"frame");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn lru_eviction() {
+        let dir = std::env::temp_dir().join(format!("blaise-evict-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let now = SystemTime::now();
+        let mut paths = Vec::new();
+        for (i, age) in [30, 10, 20, 40].into_iter().enumerate() {
+            let path = dir.join(format!("{}.png", i));
+            fs::write(&path, [0u8; 100]).unwrap();
+            fs::write(sidecar_path(&path), "x").unwrap();
+            let file = File::options().write(true).open(&path).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age))
+                .unwrap();
+            paths.push(path);
+        }
+        // the oldest one is kept as in use, so the next two oldest go:
+        let keep = HashSet::from([paths[3].clone()]);
+        assert_eq!(evict(&dir, 250, &keep), (2, 200));
+        assert!(paths[1].exists() && paths[3].exists());
+        assert!(!paths[0].exists() && !paths[2].exists());
+        assert!(!sidecar_path(&paths[0]).exists());
+        assert_eq!(evict(&dir, 250, &HashSet::new()), (0, 0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
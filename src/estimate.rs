@@ -0,0 +1,181 @@
+//! Estimate of the output of a run, from the boxes and the crop options,
+//! calibrated by encoding a few crops, shown for the confirmation of the run
+//! with `--confirm`, and checked against the free space with `--min-free`.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+/// Number of labels shown in the estimate.
+const MAX_LABELS: usize = 10;
+
+/// Number of crops encoded to calibrate the estimate.
+pub const SAMPLES: usize = 5;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Estimate {
+    pub crops: usize,
+    pub by_label: BTreeMap<String, usize>,
+    /// Pixels of all the crops.
+    pub pixels: u64,
+    /// Uncompressed size of the pixels.
+    pub bytes_per_pixel: u64,
+    /// Size and pixels of the sample crops, encoded.
+    pub encoded: Option<(u64, u64)>,
+}
+
+impl Estimate {
+    pub fn new(bytes_per_pixel: u64) -> Estimate {
+        Estimate {
+            bytes_per_pixel,
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, label: &str, width: u32, height: u32) {
+        self.crops += 1;
+        *self.by_label.entry(label.to_string()).or_insert(0) += 1;
+        self.pixels += width as u64 * height as u64;
+    }
+
+    /// Size of the crops, uncompressed.
+    pub fn bytes(&self) -> u64 {
+        self.pixels * self.bytes_per_pixel
+    }
+
+    /// Adds the size of an encoded sample crop with the given pixels.
+    pub fn calibrate(&mut self, bytes: u64, pixels: u64) {
+        let (total_bytes, total_pixels) = self.encoded.get_or_insert((0, 0));
+        *total_bytes += bytes;
+        *total_pixels += pixels;
+    }
+
+    /// Size of the crops, at the bytes per pixel of the encoded samples if any,
+    /// else uncompressed.
+    pub fn projected_bytes(&self) -> u64 {
+        match self.encoded {
+            Some((bytes, pixels)) if pixels > 0 => {
+                (self.pixels as f64 * bytes as f64 / pixels as f64).ceil() as u64
+            }
+            _ => self.bytes(),
+        }
+    }
+
+    pub fn report(&self) {
+        println!("\n  Estimated output:");
+        let mut labels: Vec<(&String, &usize)> = self.by_label.iter().collect();
+        labels.sort_by(|a, b| b.1.cmp(a.1));
+        for (label, count) in labels.iter().take(MAX_LABELS) {
+            println!("   {:>7} \"{}\"", count, label);
+        }
+        if labels.len() > MAX_LABELS {
+            println!("   {:>7} other labels", labels.len() - MAX_LABELS);
+        }
+        match self.encoded {
+            Some(_) => println!(
+                "   {:>7} crops, about {} ({} uncompressed)",
+                self.crops,
+                format_bytes(self.projected_bytes()),
+                format_bytes(self.bytes())
+            ),
+            None => println!(
+                "   {:>7} crops, {} uncompressed",
+                self.crops,
+                format_bytes(self.bytes())
+            ),
+        }
+    }
+}
+
+/// Indices of the samples, spread over the items.
+pub fn sample_indices(count: usize, samples: usize) -> Vec<usize> {
+    let samples = samples.min(count);
+    (0..samples).map(|k| k * count / samples).collect()
+}
+
+/// Space available on the file system with the path, or with its nearest
+/// existing ancestor.
+#[cfg(unix)]
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let existing = path
+        .ancestors()
+        .find(|p| p.exists())
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let c_path = CString::new(existing.as_os_str().as_bytes())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: the path is NUL-terminated, and stat valid for writing.
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_path: &Path) -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "free space not available on this platform",
+    ))
+}
+
+/// The size in kB, MB, etc. (powers of 1000).
+pub fn format_bytes(bytes: u64) -> String {
+    let units = ["bytes", "kB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000. && unit < units.len() - 1 {
+        value /= 1000.;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{} bytes", bytes),
+        _ => format!("{:.1} {}", value, units[unit]),
+    }
+}
+
+/// Asks the question on the terminal, true for a yes; no by default.
+pub fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    match io::stdin().lock().read_line(&mut answer) {
+        Ok(_) => matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate() {
+        let mut estimate = Estimate::new(3);
+        estimate.add("FOO", 100, 50);
+        estimate.add("BAR", 10, 10);
+        estimate.add("FOO", 20, 20);
+        assert_eq!(estimate.crops, 3);
+        assert_eq!(estimate.by_label["FOO"], 2);
+        assert_eq!(estimate.pixels, 5500);
+        assert_eq!(estimate.bytes(), 16500);
+        assert_eq!(estimate.projected_bytes(), 16500);
+        estimate.calibrate(300, 1000);
+        estimate.calibrate(100, 1000);
+        assert_eq!(estimate.projected_bytes(), 1100);
+
+        assert_eq!(sample_indices(100, 5), [0, 20, 40, 60, 80]);
+        assert_eq!(sample_indices(3, 5), [0, 1, 2]);
+        assert!(sample_indices(0, 5).is_empty());
+        #[cfg(unix)]
+        assert!(free_space(&std::env::temp_dir().join("no/such/dir")).unwrap() > 0);
+
+        assert_eq!(format_bytes(999), "999 bytes");
+        assert_eq!(format_bytes(16500), "16.5 kB");
+        assert_eq!(format_bytes(2_340_000_000), "2.3 GB");
+    }
+}
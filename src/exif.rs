@@ -0,0 +1,334 @@
+//! GPS position, altitude and timestamp of the source images, from their EXIF
+//! metadata (`--exif`), for the manifest: in JPEG (`APP1`), PNG (`eXIf`) and
+//! TIFF images.
+
+use crate::storage::Storage;
+use serde::Serialize;
+use std::path::Path;
+
+/// The EXIF data of an image used in the manifest.
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ExifInfo {
+    /// Degrees, negative to the south.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    /// Degrees, negative to the west.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// Meters, negative below sea level.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    /// Original date and time, as `YYYY-MM-DDThh:mm:ss` (camera local time).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<String>,
+}
+
+impl ExifInfo {
+    fn is_empty(&self) -> bool {
+        *self == ExifInfo::default()
+    }
+}
+
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const DATE_TIME: u16 = 0x0132;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const GPS_LATITUDE_REF: u16 = 1;
+const GPS_LATITUDE: u16 = 2;
+const GPS_LONGITUDE_REF: u16 = 3;
+const GPS_LONGITUDE: u16 = 4;
+const GPS_ALTITUDE_REF: u16 = 5;
+const GPS_ALTITUDE: u16 = 6;
+
+/// The EXIF data of the image, if any.
+pub fn read_exif(storage: &Storage, path: &Path) -> Option<ExifInfo> {
+    let bytes = storage.read(path).ok()?;
+    parse(&bytes)
+}
+
+/// The EXIF data in the encoded image, if any.
+pub fn parse(bytes: &[u8]) -> Option<ExifInfo> {
+    let tiff = if bytes.starts_with(&[0xff, 0xd8]) {
+        jpeg_exif(bytes)?
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_exif(bytes)?
+    } else {
+        bytes
+    };
+    let info = parse_tiff(tiff)?;
+    (!info.is_empty()).then_some(info)
+}
+
+/// The TIFF structure in the `Exif` APP1 segment.
+fn jpeg_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xff {
+        let marker = bytes[pos + 1];
+        // start of scan: no more metadata
+        if marker == 0xda {
+            return None;
+        }
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let segment = bytes.get(pos + 4..pos + 2 + length)?;
+        if marker == 0xe1 && segment.starts_with(b"Exif\0\0") {
+            return Some(&segment[6..]);
+        }
+        pos += 2 + length;
+    }
+    None
+}
+
+/// The data of the `eXIf` chunk.
+fn png_exif(bytes: &[u8]) -> Option<&[u8]> {
+    let mut pos = 8;
+    while pos + 8 <= bytes.len() {
+        let length = u32::from_be_bytes(bytes[pos..pos + 4].try_into().unwrap()) as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = bytes.get(pos + 8..pos + 8 + length)?;
+        match kind {
+            b"eXIf" => return Some(data),
+            b"IDAT" | b"IEND" => return None,
+            _ => pos += 12 + length,
+        }
+    }
+    None
+}
+
+/// A TIFF structure (header and IFDs), in either byte order.
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+/// An IFD entry, with the offset of its value.
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    offset: usize,
+}
+
+impl Tiff<'_> {
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self.data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u16::from_le_bytes(bytes),
+            false => u16::from_be_bytes(bytes),
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self.data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match self.little_endian {
+            true => u32::from_le_bytes(bytes),
+            false => u32::from_be_bytes(bytes),
+        })
+    }
+
+    fn entries(&self, ifd: usize) -> Option<Vec<Entry>> {
+        let count = self.u16(ifd)? as usize;
+        (0..count)
+            .map(|i| {
+                let at = ifd + 2 + 12 * i;
+                let kind = self.u16(at + 2)?;
+                let count = self.u32(at + 4)?;
+                let size = match kind {
+                    3 => 2,
+                    4 | 9 => 4,
+                    5 | 10 => 8,
+                    _ => 1,
+                } * count as usize;
+                let offset = match size <= 4 {
+                    true => at + 8,
+                    false => self.u32(at + 8)? as usize,
+                };
+                Some(Entry {
+                    tag: self.u16(at)?,
+                    kind,
+                    count,
+                    offset,
+                })
+            })
+            .collect()
+    }
+
+    fn offset(&self, entry: &Entry) -> Option<usize> {
+        match entry.kind {
+            4 => self.u32(entry.offset).map(|v| v as usize),
+            _ => None,
+        }
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<String> {
+        let bytes = self.data.get(entry.offset..entry.offset + entry.count as usize)?;
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        Some(String::from_utf8_lossy(&bytes[..end]).trim().to_string())
+    }
+
+    fn rational(&self, entry: &Entry, index: usize) -> Option<f64> {
+        if entry.kind != 5 || index >= entry.count as usize {
+            return None;
+        }
+        let at = entry.offset + 8 * index;
+        let (numerator, denominator) = (self.u32(at)?, self.u32(at + 4)?);
+        (denominator != 0).then(|| numerator as f64 / denominator as f64)
+    }
+
+    /// Degrees from degrees, minutes and seconds.
+    fn degrees(&self, entry: &Entry) -> Option<f64> {
+        let dms = [0, 1, 2].map(|i| self.rational(entry, i));
+        Some(dms[0]? + dms[1].unwrap_or(0.) / 60. + dms[2].unwrap_or(0.) / 3600.)
+    }
+}
+
+fn parse_tiff(data: &[u8]) -> Option<ExifInfo> {
+    let little_endian = match data.get(..4)? {
+        b"II*\0" => true,
+        b"MM\0*" => false,
+        _ => return None,
+    };
+    let tiff = Tiff {
+        data,
+        little_endian,
+    };
+    let ifd0 = tiff.entries(tiff.u32(4)? as usize)?;
+    let find = |entries: &[Entry], tag| entries.iter().position(|e| e.tag == tag);
+    let mut info = ExifInfo::default();
+
+    let exif_ifd = find(&ifd0, EXIF_IFD)
+        .and_then(|i| tiff.offset(&ifd0[i]))
+        .and_then(|offset| tiff.entries(offset))
+        .unwrap_or_default();
+    let date_time = find(&exif_ifd, DATE_TIME_ORIGINAL)
+        .map(|i| &exif_ifd[i])
+        .or_else(|| find(&ifd0, DATE_TIME).map(|i| &ifd0[i]));
+    info.timestamp = date_time
+        .and_then(|entry| tiff.ascii(entry))
+        .and_then(|s| timestamp(&s));
+
+    let gps = find(&ifd0, GPS_IFD)
+        .and_then(|i| tiff.offset(&ifd0[i]))
+        .and_then(|offset| tiff.entries(offset))
+        .unwrap_or_default();
+    let gps_value = |tag| find(&gps, tag).map(|i| &gps[i]);
+    let reference = |tag| gps_value(tag).and_then(|e| tiff.ascii(e));
+    let signed = |value: f64, negative: bool| if negative { -value } else { value };
+    info.latitude = gps_value(GPS_LATITUDE)
+        .and_then(|e| tiff.degrees(e))
+        .map(|v| signed(v, reference(GPS_LATITUDE_REF).as_deref() == Some("S")));
+    info.longitude = gps_value(GPS_LONGITUDE)
+        .and_then(|e| tiff.degrees(e))
+        .map(|v| signed(v, reference(GPS_LONGITUDE_REF).as_deref() == Some("W")));
+    let below_sea_level = gps_value(GPS_ALTITUDE_REF)
+        .and_then(|e| tiff.data.get(e.offset))
+        .is_some_and(|v| *v == 1);
+    info.altitude = gps_value(GPS_ALTITUDE)
+        .and_then(|e| tiff.rational(e, 0))
+        .map(|v| signed(v, below_sea_level));
+    Some(info)
+}
+
+/// `YYYY:MM:DD hh:mm:ss` as `YYYY-MM-DDThh:mm:ss`, if valid.
+fn timestamp(s: &str) -> Option<String> {
+    let (date, time) = s.split_once(' ')?;
+    let date: Vec<&str> = date.split(':').collect();
+    let valid = |parts: &[&str], lengths: &[usize]| {
+        parts.len() == lengths.len()
+            && parts
+                .iter()
+                .zip(lengths)
+                .all(|(p, n)| p.len() == *n && p.bytes().all(|b| b.is_ascii_digit()))
+    };
+    let time_parts: Vec<&str> = time.split(':').collect();
+    // unknown dates are given as blanks or zeros:
+    if !valid(&date, &[4, 2, 2]) || !valid(&time_parts, &[2, 2, 2]) || date[0] == "0000" {
+        return None;
+    }
+    Some(format!("{}-{}-{}T{}", date[0], date[1], date[2], time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Little-endian TIFF with IFD0 (pointers to the Exif and GPS IFDs).
+    fn tiff() -> Vec<u8> {
+        let mut data = b"II*\0".to_vec();
+        data.extend(8u32.to_le_bytes());
+        let entry = |data: &mut Vec<u8>, tag: u16, kind: u16, count: u32, value: u32| {
+            data.extend(tag.to_le_bytes());
+            data.extend(kind.to_le_bytes());
+            data.extend(count.to_le_bytes());
+            data.extend(value.to_le_bytes());
+        };
+        // IFD0 at 8, 2 entries: 8 + 2 + 24 + 4 = 38
+        let exif_ifd = 38u32;
+        // Exif IFD, 1 entry: 38 + 2 + 12 + 4 = 56, then the date (20 bytes)
+        let date = 56u32;
+        let gps_ifd = 76u32;
+        // GPS IFD, 6 entries: 76 + 2 + 72 + 4 = 154, then the rationals
+        let lat = 154u32;
+        let lon = lat + 24;
+        let alt = lon + 24;
+        data.extend(2u16.to_le_bytes());
+        entry(&mut data, EXIF_IFD, 4, 1, exif_ifd);
+        entry(&mut data, GPS_IFD, 4, 1, gps_ifd);
+        data.extend(0u32.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        entry(&mut data, DATE_TIME_ORIGINAL, 2, 20, date);
+        data.extend(0u32.to_le_bytes());
+        data.extend(b"2023:05:01 22:15:03\0");
+        data.extend(6u16.to_le_bytes());
+        entry(&mut data, GPS_LATITUDE_REF, 2, 2, u32::from_le_bytes(*b"N\0\0\0"));
+        entry(&mut data, GPS_LATITUDE, 5, 3, lat);
+        entry(&mut data, GPS_LONGITUDE_REF, 2, 2, u32::from_le_bytes(*b"W\0\0\0"));
+        entry(&mut data, GPS_LONGITUDE, 5, 3, lon);
+        entry(&mut data, GPS_ALTITUDE_REF, 1, 1, 1);
+        entry(&mut data, GPS_ALTITUDE, 5, 1, alt);
+        data.extend(0u32.to_le_bytes());
+        for (numerator, denominator) in [(36, 1), (48, 1), (18, 1), (121, 1), (54, 1), (36, 1)] {
+            data.extend((numerator as u32).to_le_bytes());
+            data.extend((denominator as u32).to_le_bytes());
+        }
+        data.extend(12345u32.to_le_bytes());
+        data.extend(10u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn gps() {
+        let expected = ExifInfo {
+            latitude: Some(36.805),
+            longitude: Some(-121.91),
+            altitude: Some(-1234.5),
+            timestamp: Some("2023-05-01T22:15:03".to_string()),
+        };
+        let info = parse(&tiff()).unwrap();
+        assert_eq!(info.timestamp, expected.timestamp);
+        assert_eq!(info.altitude, expected.altitude);
+        approx::assert_abs_diff_eq!(info.latitude.unwrap(), 36.805, epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(info.longitude.unwrap(), -121.91, epsilon = 1e-9);
+
+        // in a JPEG APP1 segment, after an APP0 one:
+        let mut jpeg = vec![0xff, 0xd8, 0xff, 0xe0, 0, 4, 0, 0, 0xff, 0xe1];
+        let tiff = tiff();
+        jpeg.extend((tiff.len() as u16 + 8).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(&tiff);
+        jpeg.extend([0xff, 0xda]);
+        assert_eq!(parse(&jpeg).unwrap().timestamp, expected.timestamp);
+
+        // in a PNG eXIf chunk:
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend((tiff.len() as u32).to_be_bytes());
+        png.extend(b"eXIf");
+        png.extend(&tiff);
+        png.extend([0; 4]);
+        assert_eq!(parse(&png).unwrap().altitude, expected.altitude);
+
+        assert_eq!(parse(b"\xff\xd8\xff\xda"), None);
+        assert_eq!(parse(b"not an image"), None);
+        assert_eq!(timestamp("0000:00:00 00:00:00"), None);
+        assert_eq!(timestamp("    :  :     :  :  "), None);
+    }
+}
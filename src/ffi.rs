@@ -0,0 +1,148 @@
+//! C interface, so desktop tools can crop in-process instead of running the
+//! command, with the library built as a cdylib (`target/*/libblaise.so`):
+//!
+//! ```c
+//! char *blaise_run(const char *config_json);
+//! void blaise_free(char *report_json);
+//! ```
+//!
+//! The config is `{"args": [...]}`, with the command line arguments (without
+//! the program name). The report is `{"exit_code": n, "run": {...}}`, with
+//! the record as in `run.json`, or null if no annotations were processed, and
+//! an `"error"` if the config is invalid or the run panicked. The messages
+//! are written to stdout and stderr as with the command.
+
+use crate::batch::Batch;
+use crate::cli::{self, exit_code};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::ffi::{c_char, CStr, CString, OsString};
+use std::panic::{self, AssertUnwindSafe};
+
+/// Exit code of a run through [run_args], unwinding back to it.
+pub(crate) struct Exit(pub i32);
+
+thread_local! {
+    static IN_CALL: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Whether in a run through [run_args] on this thread.
+pub(crate) fn in_call() -> bool {
+    IN_CALL.with(Cell::get)
+}
+
+#[derive(Deserialize)]
+struct Config {
+    args: Vec<String>,
+}
+
+/// Exit code for an invalid config or a panic.
+const ERROR: i32 = exit_code::CONFIG_ERROR;
+
+/// Runs as the command with the given config, returning the report JSON.
+fn run_json(config_json: &str) -> String {
+    let config: Config = match serde_json::from_str(config_json) {
+        Ok(config) => config,
+        Err(e) => {
+            let error = format!("invalid config: {}", e);
+            return json!({"exit_code": ERROR, "error": error}).to_string();
+        }
+    };
+    run_args(config.args, None).to_string()
+}
+
+/// Runs as the command with the given arguments (without the program name),
+/// returning the report.
+pub(crate) fn run_args(args: Vec<String>, batch: Option<&Batch>) -> Value {
+    let args: Vec<OsString> = std::iter::once("blaise".to_string())
+        .chain(args)
+        .map(OsString::from)
+        .collect();
+
+    IN_CALL.with(|c| c.set(true));
+    let result = panic::catch_unwind(AssertUnwindSafe(|| cli::run(args, batch)));
+    IN_CALL.with(|c| c.set(false));
+    match result {
+        Ok((code, record)) => json!({"exit_code": code, "run": record}),
+        Err(payload) => match payload.downcast::<Exit>() {
+            Ok(exit) => json!({"exit_code": exit.0, "run": null}),
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|s| s.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                json!({"exit_code": ERROR, "error": format!("panicked: {}", message)})
+            }
+        },
+    }
+}
+
+/// Runs as the command with the given config, returning the report, to be
+/// released with [blaise_free].
+///
+/// # Safety
+///
+/// `config_json` must be a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn blaise_run(config_json: *const c_char) -> *mut c_char {
+    let report = if config_json.is_null() {
+        json!({"exit_code": ERROR, "error": "null config"}).to_string()
+    } else {
+        run_json(&CStr::from_ptr(config_json).to_string_lossy())
+    };
+    // the report has no NULs, being JSON:
+    CString::new(report).unwrap().into_raw()
+}
+
+/// Releases a report returned by [blaise_run].
+///
+/// # Safety
+///
+/// `report_json` must come from [blaise_run] and not be released already.
+#[no_mangle]
+pub unsafe extern "C" fn blaise_free(report_json: *mut c_char) {
+    if !report_json.is_null() {
+        drop(CString::from_raw(report_json));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(config: &str) -> Value {
+        let config = CString::new(config).unwrap();
+        unsafe {
+            let report = blaise_run(config.as_ptr());
+            let value = serde_json::from_str(CStr::from_ptr(report).to_str().unwrap()).unwrap();
+            blaise_free(report);
+            value
+        }
+    }
+
+    #[test]
+    fn run() {
+        let report = call("[]");
+        assert_eq!(report["exit_code"], ERROR);
+        assert!(report["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid config"));
+
+        // configuration errors are returned instead of exiting:
+        let report = call(r#"{"args": ["--no-such-option"]}"#);
+        assert_eq!(report["exit_code"], 4);
+        assert!(!in_call());
+
+        let out = std::env::temp_dir().join(format!("blaise-ffi-{}", std::process::id()));
+        let config = json!({"args": ["-p", "data", "-o", out, "--npb"]});
+        let report = call(&config.to_string());
+        assert_eq!(report["exit_code"], 0);
+        assert_eq!(report["run"]["crops"], 1);
+        assert_eq!(report["run"]["args"][1], "-p");
+        assert!(out.join("run.json").exists());
+        std::fs::remove_dir_all(&out).unwrap();
+    }
+}
@@ -0,0 +1,158 @@
+//! Export in the FiftyOne dataset format (`fiftyone.types.FiftyOneDataset`):
+//! `metadata.json` and `samples.json` with one sample per source image, the selected
+//! objects as detections in the `ground_truth` field, and source images referenced
+//! by absolute path (as given with `--scrub-paths`).
+
+use serde_json::{json, Value};
+use std::fs::{create_dir_all, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::annotation::{Object, SizeBucket};
+
+pub struct FiftyOneWriter {
+    dir: PathBuf,
+    /// Whether to reference the source images by absolute path, else as given.
+    absolute_paths: bool,
+    samples: Mutex<Vec<Value>>,
+}
+
+impl FiftyOneWriter {
+    pub fn new(dir: &Path, absolute_paths: bool) -> Self {
+        create_dir_all(dir).unwrap();
+        Self {
+            dir: dir.to_path_buf(),
+            absolute_paths,
+            samples: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Adds a sample for the given image with detections for the given (object, crop path) pairs.
+    pub fn add(
+        &self,
+        image_path: &str,
+        image_width: u32,
+        image_height: u32,
+        objects: &[(&Object, String)],
+    ) {
+        let filepath = match self.absolute_paths {
+            true => std::fs::canonicalize(image_path)
+                .map(|p| p.to_string_lossy().to_string())
+                .unwrap_or_else(|_| image_path.to_string()),
+            false => image_path.to_string(),
+        };
+        let (w, h) = (image_width as f64, image_height as f64);
+        let detections: Vec<Value> = objects
+            .iter()
+            .map(|(object, crop)| {
+                let b = &object.bndbox;
+                let mut detection = json!({
+                    "_cls": "Detection",
+                    "label": object.name,
+                    "bounding_box": [
+                        b.xmin / w,
+                        b.ymin / h,
+                        b.width() / w,
+                        b.height() / h,
+                    ],
+                    "tags": [SizeBucket::of(b).name()],
+                    "crop": crop,
+                });
+                for (key, value) in &object.attributes {
+                    detection
+                        .as_object_mut()
+                        .unwrap()
+                        .entry(key.clone())
+                        .or_insert_with(|| json!(value));
+                }
+                detection
+            })
+            .collect();
+        let sample = json!({
+            "filepath": filepath,
+            "tags": [],
+            "metadata": {
+                "_cls": "ImageMetadata",
+                "width": image_width,
+                "height": image_height,
+            },
+            "ground_truth": {
+                "_cls": "Detections",
+                "detections": detections,
+            },
+        });
+        self.samples.lock().unwrap().push(sample);
+    }
+
+    pub fn finish(&self) {
+        let mut samples = self.samples.lock().unwrap();
+        samples.sort_by(|a, b| a["filepath"].as_str().cmp(&b["filepath"].as_str()));
+
+        let metadata = json!({
+            "name": self.dir.file_name().map(|n| n.to_string_lossy().to_string()),
+            "media_type": "image",
+        });
+        let writer = BufWriter::new(File::create(self.dir.join("metadata.json")).unwrap());
+        serde_json::to_writer_pretty(writer, &metadata).unwrap();
+
+        let writer = BufWriter::new(File::create(self.dir.join("samples.json")).unwrap());
+        serde_json::to_writer(writer, &json!({ "samples": *samples })).unwrap();
+        println!(
+            "Wrote FiftyOne dataset with {} samples to {:?}",
+            samples.len(),
+            self.dir
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::Bndbox;
+
+    #[test]
+    fn samples() {
+        let dir = std::env::temp_dir().join(format!("blaise-fiftyone-{}", std::process::id()));
+        let writer = FiftyOneWriter::new(&dir, false);
+        let object = Object {
+            name: "Aegina".to_string(),
+            bndbox: Bndbox {
+                xmin: 10.,
+                ymin: 20.,
+                xmax: 50.,
+                ymax: 40.,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: [("occluded".to_string(), "false".to_string())].into(),
+        };
+        writer.add("imgs/b.png", 100, 200, &[(&object, "Aegina/b_0.png".to_string())]);
+        writer.add("imgs/a.png", 100, 200, &[]);
+        writer.finish();
+
+        let read = |name| -> Value {
+            serde_json::from_reader(File::open(dir.join(name)).unwrap()).unwrap()
+        };
+        assert_eq!(read("metadata.json")["media_type"], "image");
+        let samples = read("samples.json");
+        let samples = samples["samples"].as_array().unwrap();
+        let paths: Vec<_> = samples.iter().map(|s| s["filepath"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["imgs/a.png", "imgs/b.png"]);
+        assert_eq!(samples[0]["ground_truth"]["detections"], json!([]));
+        assert_eq!(samples[1]["metadata"]["width"], 100);
+        assert_eq!(
+            samples[1]["ground_truth"]["detections"],
+            json!([{
+                "_cls": "Detection",
+                "label": "Aegina",
+                "bounding_box": [0.1, 0.1, 0.4, 0.1],
+                "tags": ["small"],
+                "crop": "Aegina/b_0.png",
+                "occluded": "false",
+            }])
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+//! Georeferenced images, eg., GeoTIFF survey mosaics: boxes given in map
+//! coordinates (eg., UTM) with `--geo-boxes`, and the map extent of the crops,
+//! in the manifest and in a world file next to each crop.
+
+use crate::geometry::{PixelRect, Rect};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// Affine transform from pixel (column, row) to map coordinates, with the
+/// reference system if known.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoTransform {
+    /// As in GDAL: `x = c[0] + c[1] * col + c[2] * row`,
+    /// `y = c[3] + c[4] * col + c[5] * row`, with the pixel corners at whole
+    /// coordinates.
+    pub coeffs: [f64; 6],
+    pub epsg: Option<u16>,
+}
+
+/// Extent of a crop in map coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct GeoExtent {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub epsg: Option<u16>,
+}
+
+impl GeoTransform {
+    pub fn map_point(&self, col: f64, row: f64) -> (f64, f64) {
+        let c = &self.coeffs;
+        (
+            c[0] + c[1] * col + c[2] * row,
+            c[3] + c[4] * col + c[5] * row,
+        )
+    }
+
+    /// Pixel coordinates of the map point, if the transform is invertible.
+    #[cfg_attr(not(feature = "geotiff"), allow(dead_code))]
+    pub fn pixel_point(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let c = &self.coeffs;
+        let det = c[1] * c[5] - c[2] * c[4];
+        if det == 0. {
+            return None;
+        }
+        let (dx, dy) = (x - c[0], y - c[3]);
+        Some(((c[5] * dx - c[2] * dy) / det, (c[1] * dy - c[4] * dx) / det))
+    }
+
+    /// Map extent of the pixel rectangle.
+    pub fn extent(&self, rect: &PixelRect) -> GeoExtent {
+        let corners = corners(&Rect::from(*rect)).map(|(col, row)| self.map_point(col, row));
+        let bounds = bounds(&corners);
+        GeoExtent {
+            xmin: bounds.xmin,
+            ymin: bounds.ymin,
+            xmax: bounds.xmax,
+            ymax: bounds.ymax,
+            epsg: self.epsg,
+        }
+    }
+
+    /// Pixel rectangle covering the map box.
+    #[cfg_attr(not(feature = "geotiff"), allow(dead_code))]
+    pub fn pixels(&self, map_box: &Rect) -> Option<Rect> {
+        let mut corners = [(0., 0.); 4];
+        for (pixel, (x, y)) in corners.iter_mut().zip(self::corners(map_box)) {
+            *pixel = self.pixel_point(x, y)?;
+        }
+        Some(bounds(&corners))
+    }
+
+    /// World file for the crop of the pixel rectangle, with the given size
+    /// (resized or not).
+    pub fn world_file(&self, rect: &PixelRect, width: u32, height: u32) -> String {
+        let c = &self.coeffs;
+        let sx = rect.width() as f64 / width as f64;
+        let sy = rect.height() as f64 / height as f64;
+        // the world file has the center of the upper left pixel:
+        let (x, y) = self.map_point(
+            rect.xmin as f64 + 0.5 * sx,
+            rect.ymin as f64 + 0.5 * sy,
+        );
+        let lines = [c[1] * sx, c[4] * sx, c[2] * sy, c[5] * sy, x, y];
+        lines.map(|v| format!("{}\n", v)).concat()
+    }
+}
+
+fn corners(r: &Rect) -> [(f64, f64); 4] {
+    [
+        (r.xmin, r.ymin),
+        (r.xmax, r.ymin),
+        (r.xmin, r.ymax),
+        (r.xmax, r.ymax),
+    ]
+}
+
+fn bounds(points: &[(f64, f64)]) -> Rect {
+    let fold = |f: fn(f64, f64) -> f64, init: f64, get: fn(&(f64, f64)) -> f64| {
+        points.iter().map(get).fold(init, f)
+    };
+    Rect {
+        xmin: fold(f64::min, f64::INFINITY, |p| p.0),
+        ymin: fold(f64::min, f64::INFINITY, |p| p.1),
+        xmax: fold(f64::max, f64::NEG_INFINITY, |p| p.0),
+        ymax: fold(f64::max, f64::NEG_INFINITY, |p| p.1),
+    }
+}
+
+/// Path of the world file for the image, eg., `x.pgw` for `x.png`.
+pub fn world_file_path(image_path: &Path) -> PathBuf {
+    let ext = image_path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let mut chars = ext.chars();
+    let world_ext = match (chars.next(), chars.last()) {
+        (Some(first), Some(last)) => format!("{}{}w", first, last),
+        _ => format!("{}w", ext),
+    };
+    image_path.with_extension(world_ext)
+}
+
+/// Whether the image may be a GeoTIFF, by its extension.
+#[cfg_attr(not(feature = "geotiff"), allow(dead_code))]
+pub fn is_tiff(path: &str) -> bool {
+    let path = path.to_lowercase();
+    path.ends_with(".tif") || path.ends_with(".tiff")
+}
+
+#[cfg(feature = "geotiff")]
+pub use self::geotiff::read_geotiff;
+
+#[cfg(feature = "geotiff")]
+mod geotiff {
+    use super::GeoTransform;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::path::Path;
+    use tiff::decoder::Decoder;
+    use tiff::tags::Tag;
+
+    const GT_RASTER_TYPE: u16 = 1025;
+    const RASTER_PIXEL_IS_POINT: u16 = 2;
+    const GEOGRAPHIC_TYPE: u16 = 2048;
+    const PROJECTED_CS_TYPE: u16 = 3072;
+    const USER_DEFINED: u16 = 32767;
+
+    /// The georeferencing of the GeoTIFF image, if any.
+    pub fn read_geotiff(path: &Path) -> Result<Option<GeoTransform>, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = Decoder::new(BufReader::new(file)).map_err(|e| e.to_string())?;
+        let mut f64s = |tag| -> Result<Option<Vec<f64>>, String> {
+            match decoder.find_tag(tag).map_err(|e| e.to_string())? {
+                Some(value) => value.into_f64_vec().map(Some).map_err(|e| e.to_string()),
+                None => Ok(None),
+            }
+        };
+        let transformation = f64s(Tag::ModelTransformationTag)?;
+        let tiepoint = f64s(Tag::ModelTiepointTag)?;
+        let scale = f64s(Tag::ModelPixelScaleTag)?;
+        let mut coeffs = match (transformation, tiepoint, scale) {
+            (Some(m), _, _) if m.len() >= 8 => [m[3], m[0], m[1], m[7], m[4], m[5]],
+            (None, Some(t), Some(s)) if t.len() >= 6 && s.len() >= 2 => {
+                let (i, j, x, y) = (t[0], t[1], t[3], t[4]);
+                [x - i * s[0], s[0], 0., y + j * s[1], 0., -s[1]]
+            }
+            _ => return Ok(None),
+        };
+
+        let keys = match decoder.find_tag(Tag::GeoKeyDirectoryTag) {
+            Ok(Some(value)) => value.into_u16_vec().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+        // header (version, revision, minor revision, number of keys), then
+        // the keys (id, location, count, value), with the values inline:
+        let key = |id: u16| {
+            keys.get(4..)
+                .unwrap_or_default()
+                .chunks_exact(4)
+                .find(|k| k[0] == id && k[1] == 0)
+                .map(|k| k[3])
+        };
+        if key(GT_RASTER_TYPE) == Some(RASTER_PIXEL_IS_POINT) {
+            // the coordinates are of the pixel centers:
+            coeffs[0] -= 0.5 * (coeffs[1] + coeffs[2]);
+            coeffs[3] -= 0.5 * (coeffs[4] + coeffs[5]);
+        }
+        let epsg = key(PROJECTED_CS_TYPE)
+            .or_else(|| key(GEOGRAPHIC_TYPE))
+            .filter(|&code| code != USER_DEFINED);
+        Ok(Some(GeoTransform { coeffs, epsg }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// 0.5 m pixels from (500000, 4000000), north up.
+    const UTM: GeoTransform = GeoTransform {
+        coeffs: [500000., 0.5, 0., 4000000., 0., -0.5],
+        epsg: Some(32610),
+    };
+
+    #[test]
+    fn transform() {
+        let rect = PixelRect {
+            xmin: 10,
+            ymin: 20,
+            xmax: 30,
+            ymax: 60,
+        };
+        let extent = UTM.extent(&rect);
+        assert_eq!(
+            extent,
+            GeoExtent {
+                xmin: 500005.,
+                ymin: 3999970.,
+                xmax: 500015.,
+                ymax: 3999990.,
+                epsg: Some(32610),
+            }
+        );
+        let map_box = Rect {
+            xmin: extent.xmin,
+            ymin: extent.ymin,
+            xmax: extent.xmax,
+            ymax: extent.ymax,
+        };
+        assert_eq!(UTM.pixels(&map_box), Some(Rect::from(rect)));
+
+        let rotated = GeoTransform {
+            coeffs: [100., 0.6, 0.8, 200., 0.8, -0.6],
+            epsg: None,
+        };
+        let (col, row) = rotated.pixel_point(110., 190.).unwrap();
+        let (x, y) = rotated.map_point(col, row);
+        assert_relative_eq!(x, 110., epsilon = 1e-9);
+        assert_relative_eq!(y, 190., epsilon = 1e-9);
+    }
+
+    #[test]
+    fn world_file() {
+        let rect = PixelRect {
+            xmin: 10,
+            ymin: 20,
+            xmax: 30,
+            ymax: 60,
+        };
+        assert_eq!(
+            UTM.world_file(&rect, 20, 40),
+            "0.5\n0\n0\n-0.5\n500005.25\n3999989.75\n"
+        );
+        // resized to half:
+        assert_eq!(
+            UTM.world_file(&rect, 10, 20),
+            "1\n0\n0\n-1\n500005.5\n3999989.5\n"
+        );
+        assert_eq!(
+            world_file_path(Path::new("out/FOO/x_0.png")),
+            Path::new("out/FOO/x_0.pgw")
+        );
+        assert_eq!(world_file_path(Path::new("x.jpeg")), Path::new("x.jgw"));
+    }
+
+    #[cfg(feature = "geotiff")]
+    #[test]
+    fn geotiff() {
+        use tiff::encoder::{colortype, TiffEncoder};
+        use tiff::tags::Tag;
+
+        let path = std::env::temp_dir().join(format!("blaise-geo-{}.tif", std::process::id()));
+        let mut encoder = TiffEncoder::new(std::fs::File::create(&path).unwrap()).unwrap();
+        let mut image = encoder.new_image::<colortype::RGB8>(4, 2).unwrap();
+        let dir = image.encoder();
+        dir.write_tag(Tag::ModelPixelScaleTag, &[0.5, 0.5, 0.][..])
+            .unwrap();
+        dir.write_tag(
+            Tag::ModelTiepointTag,
+            &[0., 0., 0., 500000., 4000000., 0.][..],
+        )
+        .unwrap();
+        let keys: [u16; 12] = [1, 1, 0, 2, 1025, 0, 1, 1, 3072, 0, 1, 32610];
+        dir.write_tag(Tag::GeoKeyDirectoryTag, &keys[..]).unwrap();
+        image.write_data(&[0u8; 4 * 2 * 3]).unwrap();
+
+        assert_eq!(read_geotiff(&path).unwrap(), Some(UTM));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,671 @@
+//! Box geometry shared by the annotation parsers and the cropping code.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl Point {
+    pub fn new(x: f64, y: f64) -> Self {
+        Point { x, y }
+    }
+
+    /// Whether the point is inside the polygon given by its vertices (even-odd rule).
+    pub fn in_polygon(&self, vertices: &[Point]) -> bool {
+        let mut inside = false;
+        let mut j = vertices.len().wrapping_sub(1);
+        for (i, a) in vertices.iter().enumerate() {
+            let b = vertices[j];
+            if (a.y > self.y) != (b.y > self.y)
+                && self.x < (b.x - a.x) * (self.y - a.y) / (b.y - a.y) + a.x
+            {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+/// Axis-aligned rectangle in (possibly fractional) pixel coordinates,
+/// with `xmax` and `ymax` exclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+pub struct Rect {
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+}
+
+impl Rect {
+    /// Rectangle with the given center and size.
+    pub fn from_center(x: f64, y: f64, width: f64, height: f64) -> Rect {
+        Rect {
+            xmin: x - width / 2.,
+            ymin: y - height / 2.,
+            xmax: x + width / 2.,
+            ymax: y + height / 2.,
+        }
+    }
+
+    /// Smallest rectangle containing the points, if any.
+    pub fn bounding(points: &[Point]) -> Option<Rect> {
+        let first = points.first()?;
+        let init = Rect {
+            xmin: first.x,
+            ymin: first.y,
+            xmax: first.x,
+            ymax: first.y,
+        };
+        Some(points.iter().fold(init, |r, p| Rect {
+            xmin: r.xmin.min(p.x),
+            ymin: r.ymin.min(p.y),
+            xmax: r.xmax.max(p.x),
+            ymax: r.ymax.max(p.y),
+        }))
+    }
+
+    pub fn width(&self) -> f64 {
+        self.xmax - self.xmin
+    }
+
+    pub fn height(&self) -> f64 {
+        self.ymax - self.ymin
+    }
+
+    pub fn center(&self) -> Point {
+        Point::new((self.xmin + self.xmax) / 2., (self.ymin + self.ymax) / 2.)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() <= 0. || self.height() <= 0.
+    }
+
+    pub fn area(&self) -> f64 {
+        if self.is_empty() {
+            0.
+        } else {
+            self.width() * self.height()
+        }
+    }
+
+    pub fn aspect_ratio(&self) -> f64 {
+        let max = self.width().max(self.height());
+        let min = self.width().min(self.height());
+        max / min
+    }
+
+    /// The defect of the rectangle as an annotated box, if any.
+    pub fn defect(&self) -> Option<BoxDefect> {
+        if self.xmax < self.xmin || self.ymax < self.ymin {
+            Some(BoxDefect::Inverted)
+        } else if self.xmax == self.xmin || self.ymax == self.ymin {
+            Some(BoxDefect::ZeroArea)
+        } else {
+            None
+        }
+    }
+
+    /// Rectangle with the min and max coordinates swapped where inverted.
+    pub fn normalized(&self) -> Rect {
+        Rect {
+            xmin: self.xmin.min(self.xmax),
+            ymin: self.ymin.min(self.ymax),
+            xmax: self.xmin.max(self.xmax),
+            ymax: self.ymin.max(self.ymax),
+        }
+    }
+
+    /// Smallest rectangle containing both rectangles.
+    pub fn union(&self, other: &Rect) -> Rect {
+        Rect {
+            xmin: self.xmin.min(other.xmin),
+            ymin: self.ymin.min(other.ymin),
+            xmax: self.xmax.max(other.xmax),
+            ymax: self.ymax.max(other.ymax),
+        }
+    }
+
+    /// Common part of both rectangles, if not empty.
+    pub fn intersection(&self, other: &Rect) -> Option<Rect> {
+        let r = Rect {
+            xmin: self.xmin.max(other.xmin),
+            ymin: self.ymin.max(other.ymin),
+            xmax: self.xmax.min(other.xmax),
+            ymax: self.ymax.min(other.ymax),
+        };
+        (!r.is_empty()).then_some(r)
+    }
+
+    /// Intersection over union (0 for disjoint or empty rectangles).
+    pub fn iou(&self, other: &Rect) -> f64 {
+        let inter = self.intersection(other).map_or(0., |r| r.area());
+        let union = self.area() + other.area() - inter;
+        if union > 0. {
+            inter / union
+        } else {
+            0.
+        }
+    }
+
+    /// Fraction of the area inside the other rectangle (0 for an empty rectangle).
+    pub fn containment(&self, other: &Rect) -> f64 {
+        let area = self.area();
+        if area > 0. {
+            self.intersection(other).map_or(0., |r| r.area()) / area
+        } else {
+            0.
+        }
+    }
+
+    /// Distance between the closest edges of the rectangles (0 if they overlap).
+    pub fn distance(&self, other: &Rect) -> f64 {
+        let dx = (other.xmin - self.xmax).max(self.xmin - other.xmax).max(0.);
+        let dy = (other.ymin - self.ymax).max(self.ymin - other.ymax).max(0.);
+        dx.hypot(dy)
+    }
+
+    /// Rectangle expanded by `pad` on each side (shrunk if negative).
+    pub fn pad(&self, pad: f64) -> Rect {
+        Rect {
+            xmin: self.xmin - pad,
+            ymin: self.ymin - pad,
+            xmax: self.xmax + pad,
+            ymax: self.ymax + pad,
+        }
+    }
+
+    /// Rectangle limited to `[0, width] x [0, height]`.
+    pub fn clamp(&self, width: f64, height: f64) -> Rect {
+        Rect {
+            xmin: self.xmin.clamp(0., width),
+            ymin: self.ymin.clamp(0., height),
+            xmax: self.xmax.clamp(0., width),
+            ymax: self.ymax.clamp(0., height),
+        }
+    }
+
+    /// Rectangle with the coordinates multiplied by the given factors.
+    pub fn scale(&self, sx: f64, sy: f64) -> Rect {
+        Rect {
+            xmin: self.xmin * sx,
+            ymin: self.ymin * sy,
+            xmax: self.xmax * sx,
+            ymax: self.ymax * sy,
+        }
+    }
+
+    pub fn translate(&self, dx: f64, dy: f64) -> Rect {
+        Rect {
+            xmin: self.xmin + dx,
+            ymin: self.ymin + dy,
+            xmax: self.xmax + dx,
+            ymax: self.ymax + dy,
+        }
+    }
+
+    /// Window of the given size centered on this rectangle, shifted inward to
+    /// stay within `[0, image_width] x [0, image_height]` (and limited to it
+    /// if larger than the image).
+    pub fn window(&self, width: f64, height: f64, image_width: f64, image_height: f64) -> Rect {
+        let fit = |center: f64, size: f64, limit: f64| {
+            let size = size.min(limit);
+            let min = (center - size / 2.).clamp(0., limit - size);
+            (min, min + size)
+        };
+        let center = self.center();
+        let (xmin, xmax) = fit(center.x, width, image_width);
+        let (ymin, ymax) = fit(center.y, height, image_height);
+        Rect {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        }
+    }
+
+    /// Rectangle grown, if smaller, to at least the given size, centered on
+    /// this one and shifted inward like `window`. Returned as is otherwise.
+    pub fn expand_to(
+        &self,
+        min_width: f64,
+        min_height: f64,
+        image_width: f64,
+        image_height: f64,
+    ) -> Rect {
+        if self.width() >= min_width && self.height() >= min_height {
+            return *self;
+        }
+        self.window(
+            self.width().max(min_width),
+            self.height().max(min_height),
+            image_width,
+            image_height,
+        )
+    }
+
+    /// The pixels for the rectangle according to the given quantization.
+    pub fn to_pixels(self, quantization: Quantization) -> PixelRect {
+        let Quantization { rounding, per_edge } = quantization;
+        let xmin = rounding.apply(self.xmin);
+        let ymin = rounding.apply(self.ymin);
+        let (xmax, ymax) = if per_edge {
+            (rounding.apply(self.xmax), rounding.apply(self.ymax))
+        } else {
+            (
+                xmin + rounding.apply(self.width()),
+                ymin + rounding.apply(self.height()),
+            )
+        };
+        PixelRect {
+            xmin,
+            ymin,
+            xmax: xmax.max(xmin),
+            ymax: ymax.max(ymin),
+        }
+    }
+}
+
+impl From<PixelRect> for Rect {
+    fn from(r: PixelRect) -> Self {
+        Rect {
+            xmin: r.xmin as f64,
+            ymin: r.ymin as f64,
+            xmax: r.xmax as f64,
+            ymax: r.ymax as f64,
+        }
+    }
+}
+
+/// Rectangle in whole pixels, as used for cropping, masks and image regions.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PixelRect {
+    pub xmin: u32,
+    pub ymin: u32,
+    pub xmax: u32,
+    pub ymax: u32,
+}
+
+impl PixelRect {
+    pub fn width(&self) -> u32 {
+        self.xmax - self.xmin
+    }
+
+    pub fn height(&self) -> u32 {
+        self.ymax - self.ymin
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width() == 0 || self.height() == 0
+    }
+
+    /// Whether the pixel at (x, y) is in the rectangle.
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        self.xmin <= x && x < self.xmax && self.ymin <= y && y < self.ymax
+    }
+}
+
+/// Problem with the extent of an annotated box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxDefect {
+    /// Some max coordinate is below its min.
+    Inverted,
+    /// Some side has zero length.
+    ZeroArea,
+}
+
+/// What to do with the defective boxes found in the annotations.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BoxPolicy {
+    /// Swap the coordinates of inverted boxes; skip zero-area boxes
+    Swap,
+    /// Skip the objects with defective boxes
+    Skip,
+    /// Stop with an error
+    Error,
+}
+
+/// How coordinates get to whole pixels.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum Rounding {
+    Floor,
+    #[default]
+    Round,
+    Ceil,
+}
+
+impl Rounding {
+    /// The rounded value, saturating at zero.
+    pub fn apply(self, v: f64) -> u32 {
+        match self {
+            Rounding::Floor => v.floor() as u32,
+            Rounding::Round => v.round() as u32,
+            Rounding::Ceil => v.ceil() as u32,
+        }
+    }
+}
+
+/// Rectangle quantization policy. By default `xmax` is the quantized `xmin`
+/// plus the quantized width (same for y), so equally sized boxes give equally
+/// sized crops; with `per_edge` each edge is quantized on its own.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Quantization {
+    pub rounding: Rounding,
+    pub per_edge: bool,
+}
+
+/// Conversion compatibility with other tools.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compat {
+    /// Each box edge quantized on its own, as in the ultralytics converter
+    Ultralytics,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// Deterministic xorshift generator for the property checks.
+    struct Gen(u64);
+
+    impl Gen {
+        fn next(&mut self) -> f64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 >> 11) as f64 / (1u64 << 53) as f64
+        }
+
+        fn rect(&mut self) -> Rect {
+            let (x, y) = (self.next() * 500., self.next() * 500.);
+            Rect {
+                xmin: x,
+                ymin: y,
+                xmax: x + self.next() * 200. + 0.1,
+                ymax: y + self.next() * 200. + 0.1,
+            }
+        }
+    }
+
+    /// Checks the property for many random pairs of rectangles.
+    fn check(property: impl Fn(Rect, Rect)) {
+        let mut gen = Gen(0x9e3779b97f4a7c15);
+        for _ in 0..1000 {
+            property(gen.rect(), gen.rect());
+        }
+    }
+
+    fn rect(width: f64, height: f64) -> Rect {
+        Rect {
+            xmin: 10.,
+            ymin: 20.,
+            xmax: 10. + width,
+            ymax: 20. + height,
+        }
+    }
+
+    fn contains(outer: &Rect, inner: &Rect) -> bool {
+        outer.xmin <= inner.xmin
+            && outer.ymin <= inner.ymin
+            && inner.xmax <= outer.xmax
+            && inner.ymax <= outer.ymax
+    }
+
+    #[test]
+    fn distance() {
+        let a = rect(10., 10.);
+        let overlapping = Rect {
+            xmin: 15.,
+            ymin: 25.,
+            xmax: 40.,
+            ymax: 40.,
+        };
+        let right_below = Rect {
+            xmin: 23.,
+            ymin: 34.,
+            xmax: 40.,
+            ymax: 40.,
+        };
+        assert_eq!(a.distance(&overlapping), 0.);
+        assert_eq!(a.distance(&right_below), 5.);
+        assert_eq!(right_below.distance(&a), 5.);
+    }
+
+    #[test]
+    fn union_padded() {
+        let a = rect(5., 5.);
+        let b = Rect {
+            xmin: 30.,
+            ymin: 2.,
+            xmax: 40.,
+            ymax: 22.,
+        };
+        let union = a.union(&b);
+        assert_eq!(
+            union,
+            Rect {
+                xmin: 10.,
+                ymin: 2.,
+                xmax: 40.,
+                ymax: 25.,
+            }
+        );
+        assert_eq!(
+            union.pad(5.).clamp(42., 100.),
+            Rect {
+                xmin: 5.,
+                ymin: 0.,
+                xmax: 42.,
+                ymax: 30.,
+            }
+        );
+    }
+
+    #[test]
+    fn scale_translate() {
+        let b = rect(30., 40.);
+        let unbounded = f64::INFINITY;
+        assert_eq!(b.scale(1., 1.).translate(0., 0.), b);
+        assert_eq!(b.scale(1.5, 1.5).xmax, 60.);
+        assert_eq!(
+            b.scale(2., 2.)
+                .translate(-25., 5.)
+                .clamp(unbounded, unbounded),
+            Rect {
+                xmin: 0.,
+                ymin: 45.,
+                xmax: 55.,
+                ymax: 125.,
+            }
+        );
+    }
+
+    #[test]
+    fn expand_to() {
+        let r = Rect::from_center(50., 40., 10., 20.);
+        assert_eq!(r.expand_to(10., 20., 400., 300.), r);
+        assert_eq!(
+            r.expand_to(32., 16., 400., 300.),
+            Rect::from_center(50., 40., 32., 20.)
+        );
+        let corner = Rect::from_center(398., 5., 4., 4.).expand_to(32., 32., 400., 300.);
+        assert_eq!(
+            (corner.xmin, corner.ymin, corner.xmax, corner.ymax),
+            (368., 0., 400., 32.)
+        );
+    }
+
+    #[test]
+    fn defects() {
+        let r = Rect::from_center(50., 40., 10., 20.);
+        assert_eq!(r.defect(), None);
+        let inverted = Rect {
+            xmin: r.xmax,
+            xmax: r.xmin,
+            ..r
+        };
+        assert_eq!(inverted.defect(), Some(BoxDefect::Inverted));
+        assert_eq!(inverted.normalized(), r);
+        assert_eq!(r.normalized(), r);
+        let flat = Rect { ymax: r.ymin, ..r };
+        assert_eq!(flat.defect(), Some(BoxDefect::ZeroArea));
+    }
+
+    #[test]
+    fn window() {
+        let r = Rect::from_center(50., 40., 10., 20.);
+        assert_eq!(
+            r.window(30., 30., 400., 300.),
+            Rect::from_center(50., 40., 30., 30.)
+        );
+        // shifted inward at the borders:
+        let w = r.window(120., 100., 400., 300.);
+        assert_eq!((w.xmin, w.ymin, w.xmax, w.ymax), (0., 0., 120., 100.));
+        let corner = Rect::from_center(395., 295., 4., 4.).window(120., 100., 400., 300.);
+        assert_eq!((corner.xmin, corner.ymin), (280., 200.));
+        // limited to the image:
+        let w = r.window(500., 100., 400., 300.);
+        assert_eq!((w.xmin, w.width()), (0., 400.));
+        check(|a, b| {
+            let (width, height) = (b.width(), b.height());
+            let w = a.window(width, height, 600., 600.);
+            assert_relative_eq!(w.width(), width, epsilon = 1e-9);
+            assert_relative_eq!(w.height(), height, epsilon = 1e-9);
+            assert!(contains(&Rect::from_center(300., 300., 600., 600.), &w));
+        });
+    }
+
+    #[test]
+    fn union_intersection() {
+        check(|a, b| {
+            let union = a.union(&b);
+            assert!(contains(&union, &a) && contains(&union, &b));
+            assert_eq!(union, b.union(&a));
+            assert_eq!(a.intersection(&b), b.intersection(&a));
+            if let Some(inter) = a.intersection(&b) {
+                assert!(contains(&a, &inter) && contains(&b, &inter));
+                assert!(a.distance(&b) == 0.);
+            } else {
+                assert!(a.iou(&b) == 0.);
+            }
+        });
+    }
+
+    #[test]
+    fn iou() {
+        check(|a, b| {
+            let iou = a.iou(&b);
+            assert!((0. ..=1.).contains(&iou));
+            assert_relative_eq!(iou, b.iou(&a));
+            assert_relative_eq!(a.iou(&a), 1.);
+        });
+        let a = Rect::from_center(10., 10., 10., 10.);
+        assert_relative_eq!(a.iou(&a.translate(5., 0.)), 50. / 150.);
+    }
+
+    #[test]
+    fn containment() {
+        check(|a, b| {
+            let c = a.containment(&b);
+            assert!((0. ..=1.).contains(&c));
+            assert_eq!(c == 1., contains(&b, &a));
+        });
+        let a = Rect::from_center(10., 10., 10., 10.);
+        assert_relative_eq!(a.containment(&a.translate(5., 0.)), 0.5);
+        assert_eq!(a.containment(&a.pad(1.)), 1.);
+    }
+
+    #[test]
+    fn transforms() {
+        check(|a, b| {
+            let p = b.width().min(10.);
+            let padded = a.pad(p);
+            assert!(contains(&padded, &a));
+            assert_relative_eq!(padded.pad(-p).xmax, a.xmax, epsilon = 1e-9);
+
+            let clamped = a.clamp(400., 300.);
+            assert!(contains(
+                &Rect::from_center(200., 150., 400., 300.),
+                &clamped
+            ));
+            assert_eq!(clamped.clamp(400., 300.), clamped);
+
+            let scaled = a.scale(2., 0.5);
+            assert_relative_eq!(scaled.width(), a.width() * 2.);
+            assert_relative_eq!(scaled.scale(0.5, 2.).ymin, a.ymin);
+            assert_relative_eq!(a.translate(3., -4.).center().x, a.center().x + 3.);
+        });
+    }
+
+    #[test]
+    fn quantization() {
+        check(|a, _| {
+            let q = |rounding, per_edge| a.to_pixels(Quantization { rounding, per_edge });
+            let p = q(Rounding::Round, false);
+            assert_eq!(p.width(), a.width().round() as u32);
+            let p = q(Rounding::Round, true);
+            assert!((p.xmax as f64 - a.xmax).abs() <= 0.5);
+            let (floor, ceil) = (q(Rounding::Floor, true), q(Rounding::Ceil, true));
+            assert!(floor.xmin as f64 <= a.xmin && a.xmin <= ceil.xmin as f64);
+            assert!(floor.ymax as f64 <= a.ymax && a.ymax <= ceil.ymax as f64);
+        });
+
+        // left edge at 10.4 px, right edge at 20.6 px (width 10.2):
+        let r = Rect {
+            xmin: 10.4,
+            ymin: 0.,
+            xmax: 20.6,
+            ymax: 1.,
+        };
+        let xs = |rounding, per_edge| {
+            let p = r.to_pixels(Quantization { rounding, per_edge });
+            (p.xmin, p.xmax)
+        };
+        assert_eq!(xs(Rounding::Round, false), (10, 20));
+        assert_eq!(xs(Rounding::Round, true), (10, 21));
+        assert_eq!(xs(Rounding::Floor, true), (10, 20));
+        assert_eq!(xs(Rounding::Ceil, false), (11, 22));
+        assert_eq!(xs(Rounding::Ceil, true), (11, 21));
+
+        let negative = Rect {
+            xmin: -3.,
+            ymin: -1.,
+            xmax: 5.,
+            ymax: 2.,
+        };
+        assert_eq!(
+            negative.to_pixels(Quantization::default()),
+            PixelRect {
+                xmin: 0,
+                ymin: 0,
+                xmax: 8,
+                ymax: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn polygons() {
+        let triangle = [
+            Point::new(10., 10.),
+            Point::new(20., 10.),
+            Point::new(10., 20.),
+        ];
+        assert!(Point::new(12., 12.).in_polygon(&triangle));
+        assert!(!Point::new(18., 18.).in_polygon(&triangle));
+        assert_eq!(
+            Rect::bounding(&triangle),
+            Some(Rect {
+                xmin: 10.,
+                ymin: 10.,
+                xmax: 20.,
+                ymax: 20.,
+            })
+        );
+        assert_eq!(Rect::bounding(&[]), None);
+    }
+}
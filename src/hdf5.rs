@@ -0,0 +1,416 @@
+//! Minimal HDF5 writer for fixed-size crops.
+//!
+//! The file has a version 2 superblock and a root group with compact (header) links to
+//! three contiguous datasets:
+//! - `images`: `u8` array of shape (N, height, width, 3)
+//! - `labels`: `u32` array of shape (N), index into `label_names`
+//! - `label_names`: fixed-length UTF-8 strings
+//!
+//! Image data is streamed right after the superblock as crops are added; all the metadata
+//! is written by `finish`.
+
+use image::DynamicImage;
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const SUPERBLOCK_SIZE: u64 = 48;
+const UNDEFINED_ADDRESS: u64 = u64::MAX;
+
+pub struct Hdf5Writer {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    label_names: Vec<String>,
+    state: Mutex<State>,
+}
+
+struct State {
+    writer: BufWriter<File>,
+    labels: Vec<u32>,
+}
+
+impl Hdf5Writer {
+    pub fn new(
+        path: &Path,
+        width: u32,
+        height: u32,
+        mut label_names: Vec<String>,
+    ) -> io::Result<Self> {
+        label_names.sort();
+        label_names.dedup();
+        let mut writer = BufWriter::new(File::create(path)?);
+        // superblock placeholder, written in `finish`:
+        writer.write_all(&[0u8; SUPERBLOCK_SIZE as usize])?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            width,
+            height,
+            label_names,
+            state: Mutex::new(State {
+                writer,
+                labels: Vec::new(),
+            }),
+        })
+    }
+
+    pub fn add(&self, img: &DynamicImage, label: &str) -> io::Result<()> {
+        if img.width() != self.width || img.height() != self.height {
+            eprintln!(
+                "WARN: not adding {}x{} crop to {:?}",
+                img.width(),
+                img.height(),
+                self.path
+            );
+            return Ok(());
+        }
+        let Ok(label) = self.label_names.binary_search_by(|l| l.as_str().cmp(label)) else {
+            return Err(io::Error::other(format!(
+                "label {:?} is not in the label names of {:?}",
+                label, self.path
+            )));
+        };
+        let label = label as u32;
+        let rgb = img.to_rgb8();
+        let mut state = self.state.lock().unwrap();
+        state.writer.write_all(rgb.as_raw())?;
+        state.labels.push(label);
+        Ok(())
+    }
+
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let n = state.labels.len() as u64;
+        let images_size = n * self.height as u64 * self.width as u64 * 3;
+        let mut pos = SUPERBLOCK_SIZE + images_size;
+
+        let labels_addr = pos;
+        let labels_data: Vec<u8> = state.labels.iter().flat_map(|l| l.to_le_bytes()).collect();
+        pos += labels_data.len() as u64;
+
+        let name_len = self
+            .label_names
+            .iter()
+            .map(|l| l.len())
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let names_addr = pos;
+        let mut names_data = Vec::new();
+        for name in &self.label_names {
+            names_data.extend_from_slice(name.as_bytes());
+            names_data.resize(names_data.len() + name_len - name.len(), 0);
+        }
+        pos += names_data.len() as u64;
+
+        let images_header = dataset_header(
+            &[n, self.height as u64, self.width as u64, 3],
+            &fixed_point_type(1),
+            SUPERBLOCK_SIZE,
+            images_size,
+        );
+        let images_header_addr = pos;
+        pos += images_header.len() as u64;
+
+        let labels_header = dataset_header(
+            &[n],
+            &fixed_point_type(4),
+            labels_addr,
+            labels_data.len() as u64,
+        );
+        let labels_header_addr = pos;
+        pos += labels_header.len() as u64;
+
+        let names_header = dataset_header(
+            &[self.label_names.len() as u64],
+            &string_type(name_len as u32),
+            names_addr,
+            names_data.len() as u64,
+        );
+        let names_header_addr = pos;
+        pos += names_header.len() as u64;
+
+        let root_header = group_header(&[
+            ("images", images_header_addr),
+            ("labels", labels_header_addr),
+            ("label_names", names_header_addr),
+        ]);
+        let root_header_addr = pos;
+        pos += root_header.len() as u64;
+        let eof = pos;
+
+        let writer = &mut state.writer;
+        for chunk in [
+            &labels_data,
+            &names_data,
+            &images_header,
+            &labels_header,
+            &names_header,
+            &root_header,
+        ] {
+            writer.write_all(chunk)?;
+        }
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&superblock(eof, root_header_addr))?;
+        writer.flush()?;
+        println!("Wrote {} crops to {:?}", n, self.path);
+        Ok(())
+    }
+}
+
+fn superblock(eof: u64, root_header_addr: u64) -> Vec<u8> {
+    let mut buf = b"\x89HDF\r\n\x1a\n".to_vec();
+    // version, size of offsets, size of lengths, file consistency flags:
+    buf.extend_from_slice(&[2, 8, 8, 0]);
+    buf.extend_from_slice(&0u64.to_le_bytes()); // base address
+    buf.extend_from_slice(&UNDEFINED_ADDRESS.to_le_bytes()); // superblock extension
+    buf.extend_from_slice(&eof.to_le_bytes());
+    buf.extend_from_slice(&root_header_addr.to_le_bytes());
+    let checksum = lookup3(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+/// Version 2 object header with the given (type, flags, data) messages.
+fn object_header(messages: &[(u8, u8, Vec<u8>)]) -> Vec<u8> {
+    let chunk_size: usize = messages.iter().map(|(_, _, data)| 4 + data.len()).sum();
+    let mut buf = b"OHDR".to_vec();
+    // version, flags (chunk size stored in 4 bytes):
+    buf.extend_from_slice(&[2, 0b10]);
+    buf.extend_from_slice(&(chunk_size as u32).to_le_bytes());
+    for (msg_type, flags, data) in messages {
+        buf.push(*msg_type);
+        buf.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        buf.push(*flags);
+        buf.extend_from_slice(data);
+    }
+    let checksum = lookup3(&buf);
+    buf.extend_from_slice(&checksum.to_le_bytes());
+    buf
+}
+
+fn dataset_header(dims: &[u64], datatype: &[u8], addr: u64, size: u64) -> Vec<u8> {
+    // dataspace: version 2, rank, flags, simple type, dimensions
+    let mut dataspace = vec![2, dims.len() as u8, 0, 1];
+    for dim in dims {
+        dataspace.extend_from_slice(&dim.to_le_bytes());
+    }
+    // fill value: version 3, late allocation, written only if set by user
+    let fill_value = vec![3, 0b1010];
+    // layout: version 3, contiguous, address, size
+    let mut layout = vec![3, 1];
+    layout.extend_from_slice(&addr.to_le_bytes());
+    layout.extend_from_slice(&size.to_le_bytes());
+
+    object_header(&[
+        (0x01, 0, dataspace),
+        (0x03, 1, datatype.to_vec()),
+        (0x05, 1, fill_value),
+        (0x08, 0, layout),
+    ])
+}
+
+fn group_header(links: &[(&str, u64)]) -> Vec<u8> {
+    // link info: version 0, flags, no fractal heap, no name index
+    let mut link_info = vec![0, 0];
+    link_info.extend_from_slice(&UNDEFINED_ADDRESS.to_le_bytes());
+    link_info.extend_from_slice(&UNDEFINED_ADDRESS.to_le_bytes());
+    // group info: version 0, flags
+    let group_info = vec![0, 0];
+
+    let mut messages = vec![(0x02, 0, link_info), (0x0a, 0, group_info)];
+    for (name, addr) in links {
+        // link: version 1, flags (1-byte name length, hard link), name, address
+        let mut link = vec![1, 0, name.len() as u8];
+        link.extend_from_slice(name.as_bytes());
+        link.extend_from_slice(&addr.to_le_bytes());
+        messages.push((0x06, 0, link));
+    }
+    object_header(&messages)
+}
+
+/// Unsigned little-endian integer datatype.
+fn fixed_point_type(size: u32) -> Vec<u8> {
+    let mut buf = vec![0x10, 0, 0, 0];
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes()); // bit offset
+    buf.extend_from_slice(&(size as u16 * 8).to_le_bytes()); // bit precision
+    buf
+}
+
+/// Fixed-length, null-padded UTF-8 string datatype.
+fn string_type(size: u32) -> Vec<u8> {
+    let mut buf = vec![0x13, 0x11, 0, 0];
+    buf.extend_from_slice(&size.to_le_bytes());
+    buf
+}
+
+/// Bob Jenkins' lookup3 `hashlittle` with initial value 0, as used for HDF5 checksums.
+fn lookup3(data: &[u8]) -> u32 {
+    fn word(k: &[u8]) -> u32 {
+        let mut bytes = [0u8; 4];
+        bytes[..k.len()].copy_from_slice(k);
+        u32::from_le_bytes(bytes)
+    }
+
+    let mut a = 0xdeadbeef_u32.wrapping_add(data.len() as u32);
+    let mut b = a;
+    let mut c = a;
+
+    let mut k = data;
+    while k.len() > 12 {
+        a = a.wrapping_add(word(&k[0..4]));
+        b = b.wrapping_add(word(&k[4..8]));
+        c = c.wrapping_add(word(&k[8..12]));
+
+        a = a.wrapping_sub(c) ^ c.rotate_left(4);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a) ^ a.rotate_left(6);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b) ^ b.rotate_left(8);
+        b = b.wrapping_add(a);
+        a = a.wrapping_sub(c) ^ c.rotate_left(16);
+        c = c.wrapping_add(b);
+        b = b.wrapping_sub(a) ^ a.rotate_left(19);
+        a = a.wrapping_add(c);
+        c = c.wrapping_sub(b) ^ b.rotate_left(4);
+        b = b.wrapping_add(a);
+
+        k = &k[12..];
+    }
+    if k.is_empty() {
+        return c;
+    }
+    a = a.wrapping_add(word(&k[..k.len().min(4)]));
+    if k.len() > 4 {
+        b = b.wrapping_add(word(&k[4..k.len().min(8)]));
+    }
+    if k.len() > 8 {
+        c = c.wrapping_add(word(&k[8..]));
+    }
+
+    c = (c ^ b).wrapping_sub(b.rotate_left(14));
+    a = (a ^ c).wrapping_sub(c.rotate_left(11));
+    b = (b ^ a).wrapping_sub(a.rotate_left(25));
+    c = (c ^ b).wrapping_sub(b.rotate_left(16));
+    a = (a ^ c).wrapping_sub(c.rotate_left(4));
+    b = (b ^ a).wrapping_sub(a.rotate_left(14));
+    c = (c ^ b).wrapping_sub(b.rotate_left(24));
+    c
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn checksum() {
+        assert_eq!(lookup3(b""), 0xdeadbeef);
+        assert_eq!(lookup3(b"Four score and seven years ago"), 0x17770551);
+    }
+
+    #[test]
+    fn superblock_layout() {
+        let sb = superblock(1234, 567);
+        assert_eq!(sb.len() as u64, SUPERBLOCK_SIZE);
+        assert_eq!(&sb[..8], b"\x89HDF\r\n\x1a\n");
+    }
+
+    fn u64_at(file: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(file[pos..pos + 8].try_into().unwrap())
+    }
+
+    /// The (type, data) messages of the object header at `addr`.
+    fn messages(file: &[u8], addr: u64) -> Vec<(u8, &[u8])> {
+        let addr = addr as usize;
+        assert_eq!(&file[addr..addr + 6], b"OHDR\x02\x02");
+        let size = u32::from_le_bytes(file[addr + 6..addr + 10].try_into().unwrap()) as usize;
+        let end = addr + 10 + size;
+        let checksum = u32::from_le_bytes(file[end..end + 4].try_into().unwrap());
+        assert_eq!(lookup3(&file[addr..end]), checksum);
+        let mut messages = Vec::new();
+        let mut pos = addr + 10;
+        while pos < end {
+            let len = u16::from_le_bytes([file[pos + 1], file[pos + 2]]) as usize;
+            messages.push((file[pos], &file[pos + 4..pos + 4 + len]));
+            pos += 4 + len;
+        }
+        messages
+    }
+
+    /// The datasets linked from the root group, with their dimensions and data.
+    fn read_back(file: &[u8]) -> BTreeMap<String, (Vec<u64>, Vec<u8>)> {
+        assert_eq!(lookup3(&file[..44]).to_le_bytes(), file[44..48]);
+        assert_eq!(u64_at(file, 28), file.len() as u64);
+        let mut datasets = BTreeMap::new();
+        for (_, link) in messages(file, u64_at(file, 36)).into_iter().filter(|m| m.0 == 0x06) {
+            let name = String::from_utf8(link[3..3 + link[2] as usize].to_vec()).unwrap();
+            let header = u64_at(link, 3 + link[2] as usize);
+            let mut dims = Vec::new();
+            let mut data = Vec::new();
+            for (msg_type, msg) in messages(file, header) {
+                match msg_type {
+                    0x01 => dims = (0..msg[1] as usize).map(|i| u64_at(msg, 4 + 8 * i)).collect(),
+                    0x08 => {
+                        let (addr, size) = (u64_at(msg, 2) as usize, u64_at(msg, 10) as usize);
+                        data = file[addr..addr + size].to_vec();
+                    }
+                    _ => (),
+                }
+            }
+            datasets.insert(name, (dims, data));
+        }
+        datasets
+    }
+
+    #[test]
+    fn read_written() {
+        let path = std::env::temp_dir().join(format!("blaise-{}.h5", std::process::id()));
+        let labels = vec!["b".to_string(), "a".to_string(), "b".to_string()];
+        let writer = Hdf5Writer::new(&path, 3, 2, labels).unwrap();
+        let mut red = image::RgbImage::new(3, 2);
+        red.pixels_mut().for_each(|p| *p = image::Rgb([255, 0, 0]));
+        let mut blue = image::RgbImage::new(3, 2);
+        blue.pixels_mut().for_each(|p| *p = image::Rgb([0, 0, 255]));
+        writer.add(&DynamicImage::ImageRgb8(red), "b").unwrap();
+        writer.add(&DynamicImage::ImageRgb8(blue), "a").unwrap();
+        assert!(writer.add(&DynamicImage::new_rgb8(3, 2), "c").is_err());
+        writer.finish().unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        let datasets = read_back(&file);
+        assert_eq!(datasets["images"].0, [2, 2, 3, 3]);
+        assert_eq!(&datasets["images"].1[..3], [255, 0, 0]);
+        assert_eq!(&datasets["images"].1[18..21], [0, 0, 255]);
+        assert_eq!(datasets["labels"], (vec![2], vec![1, 0, 0, 0, 0, 0, 0, 0]));
+        assert_eq!(datasets["label_names"], (vec![2], b"ab".to_vec()));
+
+        // read back by the reference library, if available (required in CI)
+        let script = "import sys, h5py\n\
+            f = h5py.File(sys.argv[1], 'r')\n\
+            print(sorted(f.keys()), f['images'].shape, f['images'].dtype)\n\
+            print(f['images'][0, 0, 0].tolist(), f['images'][1, 1, 2].tolist())\n\
+            print(f['labels'][:].tolist(), [n.decode() for n in f['label_names'][:]])\n";
+        let output = std::process::Command::new("python3")
+            .args(["-c", script])
+            .arg(&path)
+            .output();
+        match output {
+            Ok(output) if output.status.success() => assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                "['images', 'label_names', 'labels'] (2, 2, 3, 3) uint8\n\
+                 [255, 0, 0] [0, 0, 255]\n\
+                 [1, 0] ['a', 'b']\n"
+            ),
+            output => assert!(
+                std::env::var_os("BLAISE_REQUIRE_H5PY").is_none(),
+                "h5py cannot read {:?}: {:?}",
+                path,
+                output
+            ),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,383 @@
+//! ICC color profiles of the source images (`--icc`): embedded as such in the
+//! crops (`iCCP` PNG chunk), or used to convert the crops to sRGB, for the
+//! profiles with primaries and tone curves (matrix/TRC, as from most cameras),
+//! RGB or gray.
+
+use crate::storage::Storage;
+use image::codecs::jpeg::JpegDecoder;
+use image::codecs::png::PngDecoder;
+use image::{ColorType, DynamicImage, ImageDecoder, ImageFormat};
+use std::io::Cursor;
+use std::path::Path;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum IccPolicy {
+    /// Embed the profile of the source image in the crops
+    #[default]
+    Keep,
+    /// Write the crops without profile
+    Strip,
+    /// Convert the crops to sRGB (written without profile)
+    SrgbConvert,
+}
+
+/// The ICC profile embedded in the image, if any.
+pub fn read_profile(storage: &Storage, path: &Path) -> Option<Vec<u8>> {
+    let bytes = storage.read(path).ok()?;
+    match ImageFormat::from_path(path).ok()? {
+        ImageFormat::Png => PngDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(Cursor::new(bytes)).ok()?.icc_profile(),
+        #[cfg(feature = "tiff")]
+        ImageFormat::Tiff => image::codecs::tiff::TiffDecoder::new(Cursor::new(bytes))
+            .ok()?
+            .icc_profile(),
+        _ => None,
+    }
+}
+
+/// The `iCCP` chunk (type, data) for the profile, to add to PNG images.
+pub fn png_chunk(profile: &[u8]) -> (&'static [u8; 4], Vec<u8>) {
+    // profile name, then compression method (deflate):
+    let mut data = b"ICC profile\0\0".to_vec();
+    data.extend(zlib_stored(profile));
+    (b"iCCP", data)
+}
+
+/// Zlib stream of the data in uncompressed (stored) deflate blocks.
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(0xffff).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        out.push(blocks.peek().is_none() as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Tone reproduction curve, from the encoded values to linear.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Curve {
+    Gamma(f32),
+    /// Values at evenly spaced inputs, interpolated linearly.
+    Table(Vec<f32>),
+    /// ICC parametric curve (type 4 with the parameters of the simpler types):
+    /// `(a x + b)^g + e` for `x >= d`, `c x + f` otherwise.
+    Parametric([f32; 7]),
+}
+
+impl Curve {
+    fn linear(&self, x: f32) -> f32 {
+        let x = x.clamp(0., 1.);
+        match self {
+            Curve::Gamma(g) => x.powf(*g),
+            Curve::Table(t) => {
+                let pos = x * (t.len() - 1) as f32;
+                let i = (pos as usize).min(t.len() - 2);
+                t[i] + (t[i + 1] - t[i]) * (pos - i as f32)
+            }
+            Curve::Parametric([g, a, b, c, d, e, f]) => match x >= *d {
+                true => (a * x + b).max(0.).powf(*g) + e,
+                false => c * x + f,
+            },
+        }
+    }
+}
+
+/// The conversion to sRGB given by a matrix/TRC profile.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Profile {
+    Rgb {
+        curves: [Curve; 3],
+        /// From linear RGB to XYZ (D50), by rows.
+        to_xyz: [[f32; 3]; 3],
+    },
+    Gray(Curve),
+}
+
+/// From XYZ (D50) to linear sRGB, with the Bradford adaptation to D65.
+const XYZ_D50_TO_SRGB: [[f32; 3]; 3] = [
+    [3.133_856, -1.616_867, -0.490_615],
+    [-0.978_768, 1.916_142, 0.033_454],
+    [0.071_945, -0.228_991, 1.405_243],
+];
+
+impl Profile {
+    pub fn parse(icc: &[u8]) -> Result<Profile, String> {
+        if icc.len() < 132 || &icc[36..40] != b"acsp" {
+            return Err("not an ICC profile".to_string());
+        }
+        if &icc[20..24] != b"XYZ " {
+            return Err("profile connection space not XYZ".to_string());
+        }
+        let tags = Tags(icc);
+        match &icc[16..20] {
+            b"RGB " => {
+                let curves = [b"rTRC", b"gTRC", b"bTRC"].map(|sig| tags.curve(sig));
+                let columns = [b"rXYZ", b"gXYZ", b"bXYZ"].map(|sig| tags.xyz(sig));
+                let [Some(r), Some(g), Some(b)] = curves else {
+                    return Err("no tone curves (not a matrix/TRC profile)".to_string());
+                };
+                let [Some(x), Some(y), Some(z)] = columns else {
+                    return Err("no primaries (not a matrix/TRC profile)".to_string());
+                };
+                let to_xyz = [0, 1, 2].map(|row| [x[row], y[row], z[row]]);
+                Ok(Profile::Rgb {
+                    curves: [r, g, b],
+                    to_xyz,
+                })
+            }
+            b"GRAY" => tags
+                .curve(b"kTRC")
+                .map(Profile::Gray)
+                .ok_or_else(|| "no gray tone curve".to_string()),
+            space => Err(format!(
+                "unsupported color space {:?}",
+                String::from_utf8_lossy(space).trim_end()
+            )),
+        }
+    }
+
+    /// The sRGB values (encoded) of the given ones.
+    fn srgb(&self, rgb: [f32; 3]) -> [f32; 3] {
+        match self {
+            Profile::Rgb { curves, to_xyz } => {
+                let linear = [0, 1, 2].map(|c| curves[c].linear(rgb[c]));
+                let xyz = to_xyz.map(|row| dot(&row, &linear));
+                XYZ_D50_TO_SRGB.map(|row| srgb_encode(dot(&row, &xyz)))
+            }
+            // gray values stay gray, with the same luminance:
+            Profile::Gray(curve) => rgb.map(|v| srgb_encode(curve.linear(v))),
+        }
+    }
+}
+
+fn dot(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn srgb_encode(v: f32) -> f32 {
+    let v = v.clamp(0., 1.);
+    match v <= 0.003_130_8 {
+        true => 12.92 * v,
+        false => 1.055 * v.powf(1. / 2.4) - 0.055,
+    }
+}
+
+/// The tag table of a profile.
+struct Tags<'a>(&'a [u8]);
+
+impl<'a> Tags<'a> {
+    fn get(&self, sig: &[u8; 4]) -> Option<&'a [u8]> {
+        let icc = self.0;
+        let count = u32_at(icc, 128)? as usize;
+        (0..count).find_map(|i| {
+            let entry = icc.get(132 + 12 * i..144 + 12 * i)?;
+            if &entry[..4] != sig {
+                return None;
+            }
+            let offset = u32_at(entry, 4)? as usize;
+            let size = u32_at(entry, 8)? as usize;
+            icc.get(offset..offset.checked_add(size)?)
+        })
+    }
+
+    fn xyz(&self, sig: &[u8; 4]) -> Option<[f32; 3]> {
+        let data = self.get(sig).filter(|d| d.starts_with(b"XYZ "))?;
+        Some([
+            s15_fixed16(data, 8)?,
+            s15_fixed16(data, 12)?,
+            s15_fixed16(data, 16)?,
+        ])
+    }
+
+    fn curve(&self, sig: &[u8; 4]) -> Option<Curve> {
+        let data = self.get(sig)?;
+        match data.get(..4)? {
+            b"curv" => match u32_at(data, 8)? as usize {
+                0 => Some(Curve::Gamma(1.)),
+                1 => Some(Curve::Gamma(u16_at(data, 12)? as f32 / 256.)),
+                n => {
+                    let table = (0..n).map(|i| Some(u16_at(data, 12 + 2 * i)? as f32 / 65535.));
+                    table.collect::<Option<_>>().map(Curve::Table)
+                }
+            },
+            b"para" => {
+                let params = |n: usize| -> Option<Vec<f32>> {
+                    (0..n).map(|i| s15_fixed16(data, 12 + 4 * i)).collect()
+                };
+                let p = match u16_at(data, 8)? {
+                    0 => params(1)?,
+                    1 => params(3)?,
+                    2 => params(4)?,
+                    3 => params(5)?,
+                    4 => params(7)?,
+                    _ => return None,
+                };
+                let (g, a, b) = (p[0], *p.get(1).unwrap_or(&1.), *p.get(2).unwrap_or(&0.));
+                Some(Curve::Parametric(match u16_at(data, 8)? {
+                    0 => [g, 1., 0., 0., 0., 0., 0.],
+                    // zero below -b/a:
+                    1 => [g, a, b, 0., -b / a, 0., 0.],
+                    2 => [g, a, b, 0., -b / a, p[3], p[3]],
+                    3 => [g, a, b, p[3], p[4], 0., 0.],
+                    _ => [g, a, b, p[3], p[4], p[5], p[6]],
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn u16_at(data: &[u8], i: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(data.get(i..i + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], i: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(i..i + 4)?.try_into().ok()?))
+}
+
+fn s15_fixed16(data: &[u8], i: usize) -> Option<f32> {
+    Some(u32_at(data, i)? as i32 as f32 / 65536.)
+}
+
+/// Converts the image to sRGB, keeping its color type.
+pub fn to_srgb(img: &DynamicImage, profile: &Profile) -> DynamicImage {
+    let mut rgba = img.to_rgba32f();
+    for p in rgba.pixels_mut() {
+        let [r, g, b] = profile.srgb([p[0], p[1], p[2]]);
+        (p[0], p[1], p[2]) = (r, g, b);
+    }
+    let converted = DynamicImage::ImageRgba32F(rgba);
+    match img.color() {
+        ColorType::L8 => DynamicImage::ImageLuma8(converted.to_luma8()),
+        ColorType::La8 => DynamicImage::ImageLumaA8(converted.to_luma_alpha8()),
+        ColorType::Rgb8 => DynamicImage::ImageRgb8(converted.to_rgb8()),
+        ColorType::L16 => DynamicImage::ImageLuma16(converted.to_luma16()),
+        ColorType::La16 => DynamicImage::ImageLumaA16(converted.to_luma_alpha16()),
+        ColorType::Rgb16 => DynamicImage::ImageRgb16(converted.to_rgb16()),
+        ColorType::Rgba16 => DynamicImage::ImageRgba16(converted.to_rgba16()),
+        ColorType::Rgb32F => DynamicImage::ImageRgb32F(converted.to_rgb32f()),
+        ColorType::Rgba32F => converted,
+        _ => DynamicImage::ImageRgba8(converted.to_rgba8()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    /// A matrix/TRC profile with the given curve for all channels, and the
+    /// sRGB primaries (D50).
+    fn profile(curve: &[u8]) -> Vec<u8> {
+        let fixed = |v: f64| ((v * 65536.).round() as i32).to_be_bytes();
+        let xyz = |x, y, z| [&b"XYZ \0\0\0\0"[..], &fixed(x), &fixed(y), &fixed(z)].concat();
+        let elements: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"rXYZ", xyz(0.4361, 0.2225, 0.0139)),
+            (b"gXYZ", xyz(0.3851, 0.7169, 0.0971)),
+            (b"bXYZ", xyz(0.1431, 0.0606, 0.7141)),
+            (b"rTRC", curve.to_vec()),
+            (b"gTRC", curve.to_vec()),
+            (b"bTRC", curve.to_vec()),
+        ];
+        let mut icc = vec![0u8; 128];
+        icc[16..20].copy_from_slice(b"RGB ");
+        icc[20..24].copy_from_slice(b"XYZ ");
+        icc[36..40].copy_from_slice(b"acsp");
+        icc.extend((elements.len() as u32).to_be_bytes());
+        let mut offset = 128 + 4 + 12 * elements.len();
+        let mut data: Vec<u8> = Vec::new();
+        for (sig, element) in &elements {
+            icc.extend(*sig);
+            icc.extend((offset as u32).to_be_bytes());
+            icc.extend((element.len() as u32).to_be_bytes());
+            offset += element.len();
+            data.extend(element);
+        }
+        icc.extend(data);
+        icc
+    }
+
+    /// The sRGB curve, as a parametric one.
+    fn srgb_curve() -> Vec<u8> {
+        let params = [2.4, 1. / 1.055, 0.055 / 1.055, 1. / 12.92, 0.04045];
+        let mut curve = b"para\0\0\0\0\0\x03\0\0".to_vec();
+        for p in params {
+            curve.extend(((p * 65536f64).round() as i32).to_be_bytes());
+        }
+        curve
+    }
+
+    #[test]
+    fn parse() {
+        let srgb = Profile::parse(&profile(&srgb_curve())).unwrap();
+        let Profile::Rgb { curves, to_xyz } = &srgb else {
+            panic!("not RGB")
+        };
+        assert_relative_eq!(curves[0].linear(0.5), 0.214, epsilon = 1e-3);
+        assert_relative_eq!(to_xyz[1][1], 0.7169, epsilon = 1e-4);
+
+        let gamma = Profile::parse(&profile(b"curv\0\0\0\0\0\0\0\x01\x02\x33")).unwrap();
+        let Profile::Rgb { curves, .. } = gamma else {
+            panic!("not RGB")
+        };
+        assert_eq!(curves[2], Curve::Gamma(563. / 256.));
+        let table = b"curv\0\0\0\0\0\0\0\x03\0\0\x80\0\xff\xff";
+        assert!(Profile::parse(&profile(table)).is_ok());
+
+        assert!(Profile::parse(b"not a profile").is_err());
+        let mut lab = profile(&srgb_curve());
+        lab[16..20].copy_from_slice(b"Lab ");
+        assert!(Profile::parse(&lab).unwrap_err().contains("\"Lab\""));
+    }
+
+    #[test]
+    fn convert() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(2, 1, |x, _| {
+            image::Rgb([[200, 100, 50], [10, 20, 30]][x as usize])
+        }));
+        // from sRGB: about the same
+        let srgb = Profile::parse(&profile(&srgb_curve())).unwrap();
+        let converted = to_srgb(&img, &srgb);
+        assert_eq!(converted.color(), ColorType::Rgb8);
+        for (a, b) in converted.to_rgb8().pixels().zip(img.to_rgb8().pixels()) {
+            for c in 0..3 {
+                assert!((a[c] as i32 - b[c] as i32).abs() <= 1, "{:?} {:?}", a, b);
+            }
+        }
+        // linear (gamma 1) values are brighter in sRGB:
+        let linear = Profile::parse(&profile(b"curv\0\0\0\0\0\0\0\0")).unwrap();
+        let converted = to_srgb(&img, &linear).to_rgb8();
+        assert!(converted.get_pixel(1, 0)[2] > 30);
+    }
+
+    #[test]
+    fn chunk() {
+        let icc = profile(&srgb_curve());
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(3, 2)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let png = crate::stamp::insert_chunks(png.get_ref(), &[png_chunk(&icc)]).unwrap();
+        let mut decoder = PngDecoder::new(Cursor::new(png)).unwrap();
+        assert_eq!(decoder.icc_profile(), Some(icc));
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(zlib_stored(b""), [0x78, 0x01, 1, 0, 0, 0xff, 0xff, 0, 0, 0, 1]);
+    }
+}
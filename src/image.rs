@@ -1,16 +1,252 @@
-use image::{DynamicImage, ImageResult};
+use image::io::Reader;
+use image::error::{ParameterError, ParameterErrorKind};
+use image::{DynamicImage, ImageError, ImageFormat, ImageResult};
+use std::io::Cursor;
 use std::path::Path;
+use std::str::FromStr;
 
 use log::debug;
 
-pub fn load_image<Q: AsRef<Path>>(path: Q) -> ImageResult<DynamicImage> {
+use crate::annotation::Mask;
+use crate::icc;
+use crate::stamp;
+use crate::storage::Storage;
+
+/// Bit depth of the crops (`--bit-depth`). PNG, the format of the crops, has
+/// no floating-point samples, so those are kept as 16-bit.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    /// As in the source image
+    #[default]
+    Keep,
+    #[value(name = "8")]
+    Eight,
+    #[value(name = "16")]
+    Sixteen,
+}
+
+/// Bits per sample of the image.
+pub fn bits_per_sample(img: &DynamicImage) -> u16 {
+    let color = img.color();
+    color.bits_per_pixel() / color.channel_count() as u16
+}
+
+/// Converts the crop to the given bit depth, keeping its channels.
+pub fn convert_bit_depth(img: DynamicImage, depth: BitDepth) -> DynamicImage {
+    let bits = match (depth, bits_per_sample(&img)) {
+        (BitDepth::Keep, 32) | (BitDepth::Sixteen, _) => 16,
+        (BitDepth::Eight, _) => 8,
+        (BitDepth::Keep, bits) => bits,
+    };
+    if bits == bits_per_sample(&img) {
+        return img;
+    }
+    let color = img.color();
+    match (bits, color.channel_count(), color.has_alpha()) {
+        (8, 1, _) => DynamicImage::ImageLuma8(img.to_luma8()),
+        (8, 2, _) => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+        (8, _, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+        (8, _, true) => DynamicImage::ImageRgba8(img.to_rgba8()),
+        (_, 1, _) => DynamicImage::ImageLuma16(img.to_luma16()),
+        (_, 2, _) => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+        (_, _, false) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        (_, _, true) => DynamicImage::ImageRgba16(img.to_rgba16()),
+    }
+}
+
+/// Frames of multi-frame images to crop (`--frame`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameSelection {
+    Index(u32),
+    All,
+}
+
+impl FromStr for FrameSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(FrameSelection::All),
+            _ => s
+                .parse()
+                .map(FrameSelection::Index)
+                .map_err(|_| format!("expected a frame index or 'all': {}", s)),
+        }
+    }
+}
+
+impl FrameSelection {
+    /// The indices of the selected frames among the given number.
+    fn indices(self, count: u32) -> ImageResult<Vec<u32>> {
+        match self {
+            FrameSelection::All => Ok((0..count).collect()),
+            FrameSelection::Index(i) if i < count => Ok(vec![i]),
+            FrameSelection::Index(i) => Err(ImageError::Parameter(ParameterError::from_kind(
+                ParameterErrorKind::Generic(format!("no frame {} in {} frames", i, count)),
+            ))),
+        }
+    }
+}
+
+pub fn load_image<Q: AsRef<Path>>(storage: &Storage, path: Q) -> ImageResult<DynamicImage> {
     debug!("loading image from {:?}", path.as_ref());
-    image::open(path)
+    let bytes = storage.read(path.as_ref()).map_err(ImageError::IoError)?;
+    decode_image(bytes, path.as_ref())
 }
 
-pub fn crop_image(img: &mut DynamicImage, x: u32, y: u32, width: u32, height: u32) -> DynamicImage {
+fn decode_image(bytes: Vec<u8>, path: &Path) -> ImageResult<DynamicImage> {
+    let format = ImageFormat::from_path(path);
+    #[cfg(feature = "tiff")]
+    if format.as_ref().ok() == Some(&ImageFormat::Tiff) {
+        if let Some(img) = tiff_pages::decode(&bytes, 0)? {
+            return Ok(img);
+        }
+    }
+    let mut reader = Reader::new(Cursor::new(bytes));
+    match format {
+        Ok(format) => reader.set_format(format),
+        Err(_) => reader = reader.with_guessed_format().map_err(ImageError::IoError)?,
+    }
+    reader.decode()
+}
+
+/// Loads the selected frames of the image (multi-page TIFF or animated GIF,
+/// with the respective features), with their indices, also returning the
+/// number of frames in the image: one for the other images.
+pub fn load_frames<Q: AsRef<Path>>(
+    storage: &Storage,
+    path: Q,
+    selection: FrameSelection,
+) -> ImageResult<(Vec<(u32, DynamicImage)>, u32)> {
+    let path = path.as_ref();
+    debug!("loading frames {:?} from {:?}", selection, path);
+    let bytes = storage.read(path).map_err(ImageError::IoError)?;
+    match ImageFormat::from_path(path).ok() {
+        #[cfg(feature = "gif")]
+        Some(ImageFormat::Gif) => {
+            use image::AnimationDecoder;
+            let decoder = image::codecs::gif::GifDecoder::new(Cursor::new(bytes))?;
+            let mut frames = decoder.into_frames().collect_frames()?;
+            let count = frames.len() as u32;
+            let indices = selection.indices(count)?;
+            frames.truncate(indices.last().map_or(0, |&i| i as usize + 1));
+            let frames = frames.into_iter().map(|f| DynamicImage::ImageRgba8(f.into_buffer()));
+            let selected = (0..).zip(frames).filter(|(i, _)| indices.contains(i));
+            Ok((selected.collect(), count))
+        }
+        #[cfg(feature = "tiff")]
+        Some(ImageFormat::Tiff) => {
+            let count = tiff_pages::count(&bytes)?;
+            let mut frames = Vec::new();
+            for i in selection.indices(count)? {
+                let img = match tiff_pages::decode(&bytes, i)? {
+                    Some(img) => img,
+                    None => decode_image(bytes.clone(), path)?,
+                };
+                frames.push((i, img));
+            }
+            Ok((frames, count))
+        }
+        _ => {
+            selection.indices(1)?;
+            Ok((vec![(0, decode_image(bytes, path)?)], 1))
+        }
+    }
+}
+
+/// Copies the given region out of the image, leaving the image untouched so
+/// it can be shared for all the crops (and threads).
+pub fn crop_image(img: &DynamicImage, x: u32, y: u32, width: u32, height: u32) -> DynamicImage {
     debug!("cropping image ...");
-    img.crop(x, y, width, height)
+    img.crop_imm(x, y, width, height)
+}
+
+/// Makes the pixels of the crop (at the given position in the image) outside the mask transparent.
+/// Images over 8 bits per sample are kept at 16 bits.
+pub fn mask_image(img: &DynamicImage, x: u32, y: u32, mask: &Mask) -> DynamicImage {
+    if bits_per_sample(img) > 8 {
+        let mut rgba = img.to_rgba16();
+        for (px, py, pixel) in rgba.enumerate_pixels_mut() {
+            if !mask.contains(x + px, y + py) {
+                pixel[3] = 0;
+            }
+        }
+        return DynamicImage::ImageRgba16(rgba);
+    }
+    let mut rgba = img.to_rgba8();
+    for (px, py, pixel) in rgba.enumerate_pixels_mut() {
+        if !mask.contains(x + px, y + py) {
+            pixel[3] = 0;
+        }
+    }
+    DynamicImage::ImageRgba8(rgba)
+}
+
+/// What to do with the alpha channel of the crops, from the source images or
+/// the masks (`--alpha`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AlphaPolicy {
+    #[default]
+    Keep,
+    /// Removes the channel, leaving the colors of the transparent pixels.
+    Drop,
+    /// Blends the pixels over the given background color.
+    Flatten([u8; 3]),
+}
+
+impl FromStr for AlphaPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('=') {
+            None if s == "keep" => Ok(AlphaPolicy::Keep),
+            None if s == "drop" => Ok(AlphaPolicy::Drop),
+            Some(("flatten", color)) => parse_color(color)
+                .map(AlphaPolicy::Flatten)
+                .ok_or_else(|| format!("expected a #RRGGBB color: {}", color)),
+            _ => Err(format!("expected keep, drop or flatten=#RRGGBB: {}", s)),
+        }
+    }
+}
+
+fn parse_color(s: &str) -> Option<[u8; 3]> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+/// Applies the alpha policy to the crop, keeping its bit depth.
+pub fn apply_alpha(img: DynamicImage, policy: AlphaPolicy) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return img;
+    }
+    let deep = bits_per_sample(&img) > 8;
+    let gray = img.color().channel_count() == 2;
+    match policy {
+        AlphaPolicy::Keep => img,
+        AlphaPolicy::Drop => match (gray, deep) {
+            (true, false) => DynamicImage::ImageLuma8(img.to_luma8()),
+            (true, true) => DynamicImage::ImageLuma16(img.to_luma16()),
+            (false, false) => DynamicImage::ImageRgb8(img.to_rgb8()),
+            (false, true) => DynamicImage::ImageRgb16(img.to_rgb16()),
+        },
+        AlphaPolicy::Flatten(background) => {
+            let background = background.map(|c| c as f32 / 255.);
+            let rgba = img.to_rgba32f();
+            let flat = image::Rgb32FImage::from_fn(img.width(), img.height(), |x, y| {
+                let p = rgba.get_pixel(x, y);
+                image::Rgb([0, 1, 2].map(|c| p[c] * p[3] + background[c] * (1. - p[3])))
+            });
+            let flat = DynamicImage::ImageRgb32F(flat);
+            match deep {
+                false => DynamicImage::ImageRgb8(flat.to_rgb8()),
+                true => DynamicImage::ImageRgb16(flat.to_rgb16()),
+            }
+        }
+    }
 }
 
 pub fn resize_image(img: &DynamicImage, width: u32, height: u32) -> Option<DynamicImage> {
@@ -25,9 +261,142 @@ pub fn resize_image(img: &DynamicImage, width: u32, height: u32) -> Option<Dynam
     }
 }
 
-pub fn save_image<Q: AsRef<Path>>(img: DynamicImage, out_path: Q) {
-    if let Err(e) = img.save(&out_path) {
-        eprintln!("error saving {:?}: {:?}", out_path.as_ref(), e);
+/// Saves the image. If `atomic`, the image is first
+/// written to a temporary file that is renamed on success, so an interrupted run never leaves
+/// truncated images. The `stamp` entries, if any, are embedded as text in PNG images,
+/// as is the ICC profile, if given.
+/// Returns the encoded image as written.
+pub fn save_image<Q: AsRef<Path>>(
+    storage: &Storage,
+    img: &DynamicImage,
+    out_path: Q,
+    atomic: bool,
+    stamp: &[(&str, String)],
+    icc_profile: Option<&[u8]>,
+) -> ImageResult<Vec<u8>> {
+    let out_path = out_path.as_ref();
+    ImageFormat::from_path(out_path)
+        .and_then(|format| {
+            let mut bytes = Cursor::new(Vec::new());
+            img.write_to(&mut bytes, format)?;
+            let mut bytes = bytes.into_inner();
+            if format == ImageFormat::Png {
+                if !stamp.is_empty() {
+                    bytes = stamp::stamp_png(&bytes, stamp).unwrap_or(bytes);
+                }
+                if let Some(profile) = icc_profile {
+                    let chunk = icc::png_chunk(profile);
+                    bytes = stamp::insert_chunks(&bytes, &[chunk]).unwrap_or(bytes);
+                }
+            }
+            Ok(bytes)
+        })
+        .and_then(|bytes| {
+            storage
+                .write(out_path, &bytes, atomic)
+                .map_err(ImageError::IoError)?;
+            Ok(bytes)
+        })
+}
+
+/// TIFF images beyond what the `image` crate decodes: the pages after the
+/// first of multi-page stacks (eg., microscopy), and floating-point images
+/// (eg., multibeam backscatter), with the samples scaled from the range of
+/// values in the image to 16 bits.
+#[cfg(feature = "tiff")]
+mod tiff_pages {
+    use image::{DynamicImage, ImageBuffer, ImageError, ImageResult};
+    use log::debug;
+    use std::io::{self, Cursor};
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::{SampleFormat, Tag};
+    use tiff::ColorType;
+
+    fn error(e: tiff::TiffError) -> ImageError {
+        ImageError::IoError(io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Number of pages in the file.
+    pub fn count(bytes: &[u8]) -> ImageResult<u32> {
+        let mut decoder = Decoder::new(Cursor::new(bytes)).map_err(error)?;
+        let mut count = 1;
+        while decoder.more_images() {
+            decoder.next_image().map_err(error)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Decodes the page, except a first one of integer samples, left to the
+    /// `image` crate.
+    pub fn decode(bytes: &[u8], page: u32) -> ImageResult<Option<DynamicImage>> {
+        let mut decoder = Decoder::new(Cursor::new(bytes)).map_err(error)?;
+        if page > 0 {
+            decoder.seek_to_image(page as usize).map_err(error)?;
+        }
+        let formats = decoder
+            .find_tag_unsigned_vec::<u16>(Tag::SampleFormat)
+            .map_err(error)?
+            .unwrap_or_default();
+        let float = formats
+            .iter()
+            .any(|&f| SampleFormat::from_u16(f) == Some(SampleFormat::IEEEFP));
+        if page == 0 && !float {
+            return Ok(None);
+        }
+        let (width, height) = decoder.dimensions().map_err(error)?;
+        let color = decoder.colortype().map_err(error)?;
+        let img = match decoder.read_image().map_err(error)? {
+            DecodingResult::U8(v) => image8(color, width, height, v),
+            DecodingResult::U16(v) => image16(color, width, height, v),
+            DecodingResult::F32(v) => {
+                let v: Vec<f64> = v.into_iter().map(f64::from).collect();
+                image16(color, width, height, scale(&v))
+            }
+            DecodingResult::F64(v) => image16(color, width, height, scale(&v)),
+            _ => None,
+        };
+        img.map(Some).ok_or_else(|| {
+            let e = format!("unsupported TIFF color type {:?} (page {})", color, page);
+            ImageError::IoError(io::Error::new(io::ErrorKind::Unsupported, e))
+        })
+    }
+
+    fn image8(color: ColorType, width: u32, height: u32, v: Vec<u8>) -> Option<DynamicImage> {
+        match color {
+            ColorType::Gray(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageLuma8),
+            ColorType::GrayA(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageLumaA8),
+            ColorType::RGB(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageRgb8),
+            ColorType::RGBA(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageRgba8),
+            _ => None,
+        }
+    }
+
+    fn image16(color: ColorType, width: u32, height: u32, v: Vec<u16>) -> Option<DynamicImage> {
+        match color {
+            ColorType::Gray(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageLuma16),
+            ColorType::GrayA(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageLumaA16),
+            ColorType::RGB(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageRgb16),
+            ColorType::RGBA(_) => ImageBuffer::from_raw(width, height, v).map(DynamicImage::ImageRgba16),
+            _ => None,
+        }
+    }
+
+    /// Scales the samples from their range to 16 bits, with the non-finite
+    /// ones (eg., NaN for no data) as 0.
+    pub(super) fn scale(samples: &[f64]) -> Vec<u16> {
+        let finite = samples.iter().copied().filter(|v| v.is_finite());
+        let min = finite.clone().fold(f64::INFINITY, f64::min);
+        let max = finite.fold(f64::NEG_INFINITY, f64::max);
+        debug!("scaling floating-point samples from [{}, {}]", min, max);
+        let range = if max > min { max - min } else { 1. };
+        samples
+            .iter()
+            .map(|&v| match v.is_finite() {
+                true => ((v - min) / range * u16::MAX as f64).round() as u16,
+                false => 0,
+            })
+            .collect()
     }
 }
 
@@ -45,7 +414,7 @@ mod tests {
     }
 
     fn get_image() -> DynamicImage {
-        load_image("data/imgs/IMG_TEST.png").unwrap()
+        load_image(&Storage::new(None, None), "data/imgs/IMG_TEST.png").unwrap()
     }
 
     #[test]
@@ -60,7 +429,213 @@ mod tests {
         let y = ymin;
         let width = xmax - xmin;
         let height = ymax - ymin;
-        let mut img = get_image();
-        crop_image(&mut img, x, y, width, height);
+        let img = get_image();
+        let (image_width, image_height) = (img.width(), img.height());
+        crop_image(&img, x, y, width, height);
+        let cropped = crop_image(&img, 1, 2, 3, 4);
+        assert_eq!((cropped.width(), cropped.height()), (3, 4));
+        assert_eq!((img.width(), img.height()), (image_width, image_height));
+    }
+
+    #[test]
+    fn crops_share_the_image() {
+        let img = DynamicImage::ImageRgb8(image::ImageBuffer::from_fn(8, 6, |x, y| {
+            image::Rgb([x as u8, y as u8, 0])
+        }));
+        let original = img.clone();
+        let regions = [(0, 0, 8, 6), (1, 2, 3, 4), (5, 5, 3, 1)];
+        let crops: Vec<DynamicImage> = std::thread::scope(|s| {
+            let img = &img;
+            let handles: Vec<_> = regions
+                .iter()
+                .map(|&(x, y, w, h)| s.spawn(move || crop_image(img, x, y, w, h)))
+                .collect();
+            handles.into_iter().map(|h| h.join().unwrap()).collect()
+        });
+        for (crop, (x, y, w, h)) in crops.iter().zip(regions) {
+            assert_eq!((crop.width(), crop.height()), (w, h));
+            let crop = crop.as_rgb8().unwrap();
+            assert_eq!(crop.get_pixel(0, 0).0, [x as u8, y as u8, 0]);
+            assert_eq!(crop.get_pixel(w - 1, h - 1).0, [(x + w - 1) as u8, (y + h - 1) as u8, 0]);
+        }
+        assert_eq!(img, original);
+    }
+
+    #[test]
+    fn bit_depth() {
+        let gray16 = DynamicImage::ImageLuma16(image::ImageBuffer::from_pixel(
+            4,
+            3,
+            image::Luma([1000u16]),
+        ));
+        let crop = crop_image(&gray16, 1, 1, 2, 2);
+        let kept = convert_bit_depth(crop.clone(), BitDepth::Keep);
+        assert_eq!(kept.color(), image::ColorType::L16);
+        assert_eq!(kept.as_luma16().unwrap().get_pixel(0, 0)[0], 1000);
+        let eight = convert_bit_depth(crop, BitDepth::Eight);
+        assert_eq!(eight.color(), image::ColorType::L8);
+        assert_eq!(eight.as_luma8().unwrap().get_pixel(0, 0)[0], 4);
+        let sixteen = convert_bit_depth(get_image(), BitDepth::Sixteen);
+        assert_eq!(bits_per_sample(&sixteen), 16);
+        assert_eq!(sixteen.color().channel_count(), get_image().color().channel_count());
+        let float = DynamicImage::ImageRgb32F(image::ImageBuffer::new(2, 2));
+        assert_eq!(
+            convert_bit_depth(float, BitDepth::Keep).color(),
+            image::ColorType::Rgb16
+        );
+
+        // saved and loaded back at 16 bits:
+        init();
+        let out_path = format!("{}/bit_depth.png", OUT_DIR);
+        let storage = Storage::new(None, None);
+        save_image(&storage, &kept, &out_path, false, &[], None).unwrap();
+        assert_eq!(
+            load_image(&storage, &out_path).unwrap().color(),
+            image::ColorType::L16
+        );
+        std::fs::remove_file(&out_path).unwrap();
+
+        let mask = Mask::from_fn(4, 3, |_, y| y == 0);
+        let masked = mask_image(&gray16, 0, 0, &mask);
+        assert_eq!(masked.color(), image::ColorType::Rgba16);
+        let rgba = masked.as_rgba16().unwrap();
+        assert_eq!(rgba.get_pixel(0, 0).0, [1000, 1000, 1000, u16::MAX]);
+        assert_eq!(rgba.get_pixel(0, 2)[3], 0);
+    }
+
+    #[cfg(feature = "tiff")]
+    #[test]
+    fn float_tiff() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        assert_eq!(
+            tiff_pages::scale(&[-20., -40., f64::NAN, -30.]),
+            [u16::MAX, 0, 0, 32768]
+        );
+        let mut bytes = Cursor::new(Vec::new());
+        TiffEncoder::new(&mut bytes)
+            .unwrap()
+            .write_image::<colortype::Gray32Float>(2, 1, &[-40.0f32, -20.])
+            .unwrap();
+        let img = tiff_pages::decode(bytes.get_ref(), 0).unwrap().unwrap();
+        assert_eq!(img.as_luma16().unwrap().as_raw(), &[0, u16::MAX]);
+        // not floating-point:
+        let mut bytes = Cursor::new(Vec::new());
+        TiffEncoder::new(&mut bytes)
+            .unwrap()
+            .write_image::<colortype::Gray16>(2, 1, &[1, 2])
+            .unwrap();
+        assert!(tiff_pages::decode(bytes.get_ref(), 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn alpha() {
+        assert_eq!("keep".parse(), Ok(AlphaPolicy::Keep));
+        assert_eq!("drop".parse(), Ok(AlphaPolicy::Drop));
+        assert_eq!(
+            "flatten=#FF8000".parse(),
+            Ok(AlphaPolicy::Flatten([255, 128, 0]))
+        );
+        assert!("flatten".parse::<AlphaPolicy>().is_err());
+        assert!("flatten=#FF80".parse::<AlphaPolicy>().is_err());
+
+        let img = get_image();
+        let mask = Mask::from_fn(img.width(), img.height(), |x, _| x > 0);
+        let masked = mask_image(&crop_image(&img, 0, 0, 2, 1), 0, 0, &mask);
+        let opaque = masked.to_rgba8().get_pixel(1, 0).0;
+        let kept = apply_alpha(masked.clone(), AlphaPolicy::Keep);
+        assert_eq!(kept.color(), image::ColorType::Rgba8);
+        let dropped = apply_alpha(masked.clone(), AlphaPolicy::Drop);
+        assert_eq!(dropped.color(), image::ColorType::Rgb8);
+        let flat = apply_alpha(masked, AlphaPolicy::Flatten([255, 128, 0]));
+        assert_eq!(flat.as_rgb8().unwrap().get_pixel(0, 0).0, [255, 128, 0]);
+        assert_eq!(flat.as_rgb8().unwrap().get_pixel(1, 0).0, opaque[..3]);
+
+        let gray16 = DynamicImage::ImageLumaA16(image::ImageBuffer::from_pixel(
+            1,
+            1,
+            image::LumaA([1000u16, 0]),
+        ));
+        let dropped = apply_alpha(gray16.clone(), AlphaPolicy::Drop);
+        assert_eq!(dropped.as_luma16().unwrap().get_pixel(0, 0)[0], 1000);
+        let flat = apply_alpha(gray16, AlphaPolicy::Flatten([255; 3]));
+        assert_eq!(flat.as_rgb16().unwrap().get_pixel(0, 0).0, [u16::MAX; 3]);
+    }
+
+    #[test]
+    fn frame_selection() {
+        assert_eq!("all".parse(), Ok(FrameSelection::All));
+        assert_eq!("3".parse(), Ok(FrameSelection::Index(3)));
+        assert!("last".parse::<FrameSelection>().is_err());
+        assert_eq!(FrameSelection::All.indices(3).unwrap(), [0, 1, 2]);
+        assert_eq!(FrameSelection::Index(2).indices(3).unwrap(), [2]);
+        assert!(FrameSelection::Index(3).indices(3).is_err());
+
+        let storage = Storage::new(None, None);
+        let (frames, count) =
+            load_frames(&storage, "data/imgs/IMG_TEST.png", FrameSelection::All).unwrap();
+        assert_eq!((frames.len(), count), (1, 1));
+        assert!(load_frames(&storage, "data/imgs/IMG_TEST.png", FrameSelection::Index(1)).is_err());
+    }
+
+    #[cfg(feature = "tiff")]
+    #[test]
+    fn tiff_stack() {
+        use tiff::encoder::{colortype, TiffEncoder};
+
+        init();
+        let path = format!("{}/stack.tif", OUT_DIR);
+        let mut encoder = TiffEncoder::new(std::fs::File::create(&path).unwrap()).unwrap();
+        for z in 0..3u16 {
+            encoder
+                .write_image::<colortype::Gray16>(2, 2, &[z * 100; 4])
+                .unwrap();
+        }
+        drop(encoder);
+        let storage = Storage::new(None, None);
+        let (frames, count) = load_frames(&storage, &path, FrameSelection::All).unwrap();
+        assert_eq!(count, 3);
+        let values: Vec<(u32, u16)> = frames
+            .iter()
+            .map(|(i, img)| (*i, img.as_luma16().unwrap().get_pixel(1, 1)[0]))
+            .collect();
+        assert_eq!(values, [(0, 0), (1, 100), (2, 200)]);
+        let (frames, _) = load_frames(&storage, &path, FrameSelection::Index(1)).unwrap();
+        assert_eq!(frames[0].0, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "gif")]
+    #[test]
+    fn gif_frames() {
+        use image::codecs::gif::GifEncoder;
+        use image::{Frame, Rgba, RgbaImage};
+
+        init();
+        let path = format!("{}/animation.gif", OUT_DIR);
+        let mut encoder = GifEncoder::new(std::fs::File::create(&path).unwrap());
+        for v in [0, 255] {
+            let frame = RgbaImage::from_pixel(2, 2, Rgba([v, v, v, 255]));
+            encoder.encode_frame(Frame::new(frame)).unwrap();
+        }
+        drop(encoder);
+        let storage = Storage::new(None, None);
+        let (frames, count) = load_frames(&storage, &path, FrameSelection::Index(1)).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].1.to_rgba8().get_pixel(0, 0)[0], 255);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn save_atomic() {
+        init();
+
+        let out_path = format!("{}/save_atomic.png", OUT_DIR);
+        let storage = Storage::new(None, None);
+        assert!(save_image(&storage, &get_image(), &out_path, true, &[], None).is_ok());
+        assert!(load_image(&storage, &out_path).is_ok());
+        assert!(!Path::new(&format!("{}/.save_atomic.png.tmp", OUT_DIR)).exists());
+        std::fs::remove_file(&out_path).unwrap();
     }
 }
@@ -0,0 +1,149 @@
+//! `metadata.csv` for the Hugging Face `imagefolder` dataset convention,
+//! and numeric class directories as expected by some torchvision `ImageFolder` scripts.
+
+use crate::geometry::PixelRect;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+#[derive(Debug, serde::Serialize)]
+struct Row<'a> {
+    /// Crop path relative to the output directory.
+    file_name: &'a str,
+    label: &'a str,
+    source: &'a str,
+    /// `[xmin, ymin, xmax, ymax]`
+    bbox: String,
+}
+
+pub struct ImageFolderMetadata {
+    path: PathBuf,
+    base_dir: PathBuf,
+    writer: Mutex<csv::Writer<File>>,
+}
+
+impl ImageFolderMetadata {
+    /// Creates `metadata.csv` in the given output directory.
+    pub fn new(output_dir: &Path) -> std::io::Result<Self> {
+        std::fs::create_dir_all(output_dir)?;
+        let path = output_dir.join("metadata.csv");
+        let writer = csv::Writer::from_path(&path)?;
+        Ok(Self {
+            path,
+            base_dir: output_dir.to_path_buf(),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    pub fn add(
+        &self,
+        crop_path: &Path,
+        label: &str,
+        source: &str,
+        bndbox: &PixelRect,
+    ) -> std::io::Result<()> {
+        let file_name = crop_path.strip_prefix(&self.base_dir).unwrap_or(crop_path);
+        let row = Row {
+            file_name: &file_name.to_string_lossy(),
+            label,
+            source,
+            bbox: format!(
+                "[{}, {}, {}, {}]",
+                bndbox.xmin, bndbox.ymin, bndbox.xmax, bndbox.ymax
+            ),
+        };
+        self.writer.lock().unwrap().serialize(row)?;
+        Ok(())
+    }
+
+    pub fn finish(&self) -> std::io::Result<()> {
+        self.writer.lock().unwrap().flush()?;
+        println!("Wrote imagefolder metadata to {:?}", self.path);
+        Ok(())
+    }
+}
+
+/// Zero-padded index directory for each class, in label order, so the directories
+/// sort (as `ImageFolder` does) in the same order as the indices.
+pub struct ClassIndex {
+    labels: Vec<String>,
+    dirs: HashMap<String, String>,
+}
+
+impl ClassIndex {
+    pub fn new(labels: impl IntoIterator<Item = String>) -> Self {
+        let labels: Vec<String> = labels
+            .into_iter()
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        let width = labels.len().saturating_sub(1).to_string().len();
+        let dirs = labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| (label.clone(), format!("{:0width$}", i, width = width)))
+            .collect();
+        Self { labels, dirs }
+    }
+
+    /// Directory for the label, if known.
+    pub fn dir(&self, label: &str) -> Option<&str> {
+        self.dirs.get(label).map(String::as_str)
+    }
+
+    /// Writes `classes.txt` in the given directory, with `<dir>\t<label>` lines.
+    pub fn write(&self, output_dir: &Path) -> std::io::Result<PathBuf> {
+        std::fs::create_dir_all(output_dir)?;
+        let path = output_dir.join("classes.txt");
+        let contents: String = self
+            .labels
+            .iter()
+            .map(|label| format!("{}\t{}\n", self.dirs[label], label))
+            .collect();
+        std::fs::write(&path, contents)?;
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn metadata() {
+        let dir = std::env::temp_dir().join(format!("blaise-imagefolder-{}", std::process::id()));
+        let metadata = ImageFolderMetadata::new(&dir).unwrap();
+        let bndbox = PixelRect {
+            xmin: 1,
+            ymin: 2,
+            xmax: 30,
+            ymax: 40,
+        };
+        let crop = dir.join("Aegina").join("a_0.png");
+        metadata.add(&crop, "Aegina", "imgs/a.png", &bndbox).unwrap();
+        let elsewhere = Path::new("/elsewhere/b_0.png");
+        metadata.add(elsewhere, "Aegina, juvenile", "imgs/b.png", &bndbox).unwrap();
+        metadata.finish().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(dir.join("metadata.csv")).unwrap(),
+            "file_name,label,source,bbox\n\
+             Aegina/a_0.png,Aegina,imgs/a.png,\"[1, 2, 30, 40]\"\n\
+             /elsewhere/b_0.png,\"Aegina, juvenile\",imgs/b.png,\"[1, 2, 30, 40]\"\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn class_index() {
+        let labels = (0..11).rev().map(|i| format!("label {}", i));
+        let index = ClassIndex::new(labels.chain(["label 3".to_string()]));
+        assert_eq!(index.dir("label 0"), Some("00"));
+        assert_eq!(index.dir("label 10"), Some("02"));
+        assert_eq!(index.dir("label 9"), Some("10"));
+        assert_eq!(index.dir("other"), None);
+
+        let single = ClassIndex::new(["Aegina".to_string()]);
+        assert_eq!(single.dir("Aegina"), Some("0"));
+    }
+}
@@ -0,0 +1,170 @@
+//! Image dimensions scan, in parallel and cached in a sidecar index file
+//! in the scanned directory, keyed by path and modification time.
+
+use indicatif::ProgressBar;
+use std::collections::HashMap;
+use std::fs::{read_to_string, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::UNIX_EPOCH;
+
+/// Name of the cache file in the scanned directory.
+pub const CACHE_FILE: &str = ".blaise-imgsize-cache";
+
+/// Dimensions by relative path, with the modification time they correspond to.
+type Entries = HashMap<String, (u128, (usize, usize))>;
+
+/// (width, height) of an image, or the error reading it.
+type SizeResult = Result<(usize, usize), String>;
+
+pub struct ImageSizeCache {
+    path: PathBuf,
+    entries: Entries,
+}
+
+impl ImageSizeCache {
+    /// Loads the cache of the given directory, if any.
+    pub fn load(dir: &Path) -> Self {
+        let path = dir.join(CACHE_FILE);
+        let entries = read_to_string(&path)
+            .map(|src| parse_entries(&src))
+            .unwrap_or_default();
+        Self { path, entries }
+    }
+
+    /// Returns the (width, height) of the given images under `dir`, using
+    /// the cached values for the unmodified ones and reading the others
+    /// in the given number of threads, reported in `progress`.
+    pub fn sizes(
+        &mut self,
+        dir: &Path,
+        paths: &[PathBuf],
+        threads: usize,
+        progress: &ProgressBar,
+    ) -> Vec<SizeResult> {
+        let keys: Vec<(String, u128)> = paths
+            .iter()
+            .map(|p| {
+                let relative = p.strip_prefix(dir).unwrap_or(p);
+                (relative.to_string_lossy().to_string(), mtime(p))
+            })
+            .collect();
+        let mut results: Vec<Option<SizeResult>> = keys
+            .iter()
+            .map(|(key, mtime)| match self.entries.get(key) {
+                Some((cached_mtime, size)) if cached_mtime == mtime => Some(Ok(*size)),
+                _ => None,
+            })
+            .collect();
+
+        let pending: Vec<usize> = (0..paths.len()).filter(|i| results[*i].is_none()).collect();
+        if !pending.is_empty() {
+            progress.set_length(pending.len() as u64);
+            let chunk_size = pending.len().div_ceil(threads.max(1));
+            let scanned: Vec<(usize, SizeResult)> = thread::scope(|s| {
+                let handles: Vec<_> = pending
+                    .chunks(chunk_size)
+                    .map(|chunk| {
+                        s.spawn(move || {
+                            chunk
+                                .iter()
+                                .map(|i| {
+                                    let size = imagesize::size(&paths[*i])
+                                        .map(|s| (s.width, s.height))
+                                        .map_err(|e| format!("{:?}", e));
+                                    progress.inc(1);
+                                    (*i, size)
+                                })
+                                .collect::<Vec<_>>()
+                        })
+                    })
+                    .collect();
+                handles
+                    .into_iter()
+                    .flat_map(|h| h.join().unwrap())
+                    .collect()
+            });
+            progress.finish_and_clear();
+            for (i, size) in scanned {
+                if let Ok(size) = &size {
+                    let (key, mtime) = &keys[i];
+                    self.entries.insert(key.clone(), (*mtime, *size));
+                }
+                results[i] = Some(size);
+            }
+        }
+        println!(
+            "image sizes: {} cached, {} scanned",
+            paths.len() - pending.len(),
+            pending.len()
+        );
+        results.into_iter().map(Option::unwrap).collect()
+    }
+
+    /// Saves the cache, just warning if not possible (eg., read-only directory).
+    pub fn save(&self) {
+        let write = || -> std::io::Result<()> {
+            let mut writer = BufWriter::new(File::create(&self.path)?);
+            let mut keys: Vec<&String> = self.entries.keys().collect();
+            keys.sort();
+            for key in keys {
+                let (mtime, (width, height)) = self.entries[key];
+                writeln!(writer, "{}\t{}\t{}\t{}", key, mtime, width, height)?;
+            }
+            writer.flush()
+        };
+        if let Err(e) = write() {
+            eprintln!("WARN: cannot save {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Modification time in nanoseconds since the epoch (0 if not available).
+fn mtime(path: &Path) -> u128 {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_nanos())
+}
+
+fn parse_entries(src: &str) -> Entries {
+    src.lines()
+        .filter_map(|line| {
+            let mut parts = line.rsplitn(4, '\t');
+            let height = parts.next()?.parse().ok()?;
+            let width = parts.next()?.parse().ok()?;
+            let mtime = parts.next()?.parse().ok()?;
+            let key = parts.next()?.to_string();
+            Some((key, (mtime, (width, height))))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cache() {
+        let dir = std::env::temp_dir().join("blaise_imgsize_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("data/imgs/IMG_TEST.png", dir.join("a.png")).unwrap();
+        std::fs::write(dir.join("bad.png"), "not an image").unwrap();
+        let paths = vec![dir.join("a.png"), dir.join("bad.png")];
+
+        let mut cache = ImageSizeCache::load(&dir);
+        let sizes = cache.sizes(&dir, &paths, 2, &ProgressBar::hidden());
+        assert_eq!(sizes[0], Ok((400, 300)));
+        assert!(sizes[1].is_err());
+        cache.save();
+
+        let cache = ImageSizeCache::load(&dir);
+        assert_eq!(cache.entries.len(), 1);
+        assert_eq!(cache.entries["a.png"].1, (400, 300));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
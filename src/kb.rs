@@ -0,0 +1,132 @@
+//! Lookup of the labels in the MBARI knowledge base (`--kb-url`), eg.
+//! `https://kb.shore.mbari.org/kb/v1`, so that non-canonical concept names do
+//! not fragment the output classes: the concept service resolves an alternate
+//! name (or a name differing in case) to the concept, `GET <url>/concept/<name>`
+//! giving `{"name": "<canonical name>", ...}`, or 404 if unknown. Requests go
+//! through `curl`, once per distinct label.
+
+use crate::annotation::Annotation;
+use crate::remote::encode_segment;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::process::Command;
+
+/// What the knowledge base says about a label.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Concept {
+    /// The name of a concept.
+    Known,
+    /// An alternate name of the concept with the given name.
+    Alias(String),
+    Unknown,
+}
+
+/// The labels looked up, with their number of objects.
+#[derive(Debug, Default)]
+pub struct KbReport {
+    pub labels: BTreeMap<String, (Concept, usize)>,
+}
+
+impl KbReport {
+    pub fn num_objects(&self, f: impl Fn(&Concept) -> bool) -> usize {
+        self.labels.values().filter(|(c, _)| f(c)).map(|(_, n)| n).sum()
+    }
+}
+
+#[derive(Deserialize)]
+struct ConceptMetadata {
+    name: String,
+}
+
+pub struct Kb {
+    url: String,
+}
+
+impl Kb {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    fn concept_url(&self, name: &str) -> String {
+        format!("{}/concept/{}", self.url, encode_segment(name))
+    }
+
+    /// Looks up the label.
+    pub fn lookup(&self, label: &str) -> Result<Concept, String> {
+        let url = self.concept_url(label);
+        let output = Command::new("curl")
+            .args(["--silent", "--show-error", "--location"])
+            .args(["--retry", "3", "--connect-timeout", "30"])
+            .args(["--write-out", "\n%{http_code}"])
+            .arg(&url)
+            .output()
+            .map_err(|e| format!("cannot run curl: {}", e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "{}: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let (body, status) = stdout.rsplit_once('\n').unwrap_or(("", &stdout));
+        match status.trim() {
+            "200" => parse_concept(label, body).map_err(|e| format!("{}: {}", url, e)),
+            "404" => Ok(Concept::Unknown),
+            status => Err(format!("{}: status {}", url, status)),
+        }
+    }
+
+    /// Looks up the labels of the objects, replacing the alternate names by
+    /// the names of their concepts if `correct`.
+    pub fn check(&self, annotations: &mut [Annotation], correct: bool) -> Result<KbReport, String> {
+        let mut report = KbReport::default();
+        for annotation in annotations.iter_mut() {
+            for object in annotation.objects.iter_mut().flatten() {
+                let (concept, count) = match report.labels.get_mut(&object.name) {
+                    Some(entry) => entry,
+                    None => {
+                        let concept = self.lookup(&object.name)?;
+                        report.labels.entry(object.name.clone()).or_insert((concept, 0))
+                    }
+                };
+                *count += 1;
+                if let (true, Concept::Alias(name)) = (correct, concept) {
+                    object.name = name.clone();
+                }
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn parse_concept(label: &str, body: &str) -> Result<Concept, String> {
+    let metadata: ConceptMetadata = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    Ok(match metadata.name == label {
+        true => Concept::Known,
+        false => Concept::Alias(metadata.name),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concepts() {
+        let kb = Kb::new("https://kb.shore.mbari.org/kb/v1/");
+        assert_eq!(
+            kb.concept_url("Sebastes sp. A/B"),
+            "https://kb.shore.mbari.org/kb/v1/concept/Sebastes%20sp.%20A%2FB"
+        );
+        let body = r#"{"name":"Nanomia bijuga","alternateNames":["Nanomia cara"],"rank":"species"}"#;
+        assert_eq!(parse_concept("Nanomia bijuga", body), Ok(Concept::Known));
+        assert_eq!(
+            parse_concept("Nanomia cara", body),
+            Ok(Concept::Alias("Nanomia bijuga".to_string()))
+        );
+        assert!(parse_concept("x", "<html>").is_err());
+    }
+}
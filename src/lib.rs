@@ -0,0 +1,88 @@
+//! Creates image crops for given annotations.
+//!
+//! The command line tool is [main]; [ffi] has the same for in-process use
+//! from other languages. Without the `pipeline` feature, only the annotation
+//! parsing and [check] are built, eg., for WebAssembly.
+
+#![cfg_attr(not(feature = "pipeline"), allow(dead_code))]
+
+/// Items of the image pipeline and the command line tool.
+macro_rules! pipeline {
+    ($($item:item)*) => {
+        $(#[cfg(feature = "pipeline")] $item)*
+    };
+}
+
+mod annotation;
+pub mod check;
+#[cfg(feature = "coco")]
+mod coco;
+mod detect;
+mod geometry;
+#[cfg(feature = "mot")]
+mod mot;
+mod pascal;
+mod scan;
+mod source;
+#[cfg(feature = "supervisely")]
+mod supervisely;
+#[cfg(feature = "via")]
+mod via;
+mod yolo;
+
+pipeline! {
+    mod aliases;
+    mod archive;
+    mod batch;
+    mod checkpoint;
+    mod checksum;
+    mod classifier;
+    mod cli;
+    mod cooccurrence;
+    mod coverage;
+    mod daemon;
+    mod dedup;
+    mod download;
+    mod estimate;
+    mod exif;
+    pub mod ffi;
+    mod fiftyone;
+    mod geo;
+    mod hdf5;
+    mod icc;
+    mod image;
+    mod imagefolder;
+    mod imgsize;
+    mod kb;
+    mod manifest;
+    mod metadata;
+    mod metrics;
+    mod montage;
+    mod monitor;
+    mod overlap;
+    mod overlay;
+    mod patches;
+    mod proposals;
+    mod pyramid;
+    mod quality;
+    mod remote;
+    mod roi;
+    mod rules;
+    mod scale;
+    mod scrub;
+    mod run;
+    mod sink;
+    mod split;
+    mod sprites;
+    mod stamp;
+    mod storage;
+    mod tfrecord;
+    mod timestamp;
+    mod windows;
+    pub use cli::main;
+}
+
+#[cfg(feature = "lmdb")]
+mod lmdb;
+#[cfg(feature = "underwater")]
+mod underwater;
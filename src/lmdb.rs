@@ -0,0 +1,158 @@
+//! LMDB sink: the crops in an LMDB environment (a directory with `data.mdb`),
+//! as read by the usual dataset loaders. Each crop is stored under its path
+//! relative to the output directory, with its label, source image and box as
+//! JSON under the same key plus `.json`, and all the crop keys, one per line,
+//! under `__keys__`. Writes are committed in batches.
+
+use crate::sink::{Crop, CropSink};
+use heed::types::Bytes;
+use heed::{Database, Env, EnvOpenOptions};
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Key of the list of the crop keys.
+pub const KEYS_KEY: &str = "__keys__";
+
+/// Crops per write transaction.
+const BATCH_SIZE: usize = 256;
+
+/// Maximum size of the database (the file only grows as needed).
+const MAP_SIZE: u64 = 1 << 40;
+
+pub struct LmdbSink {
+    dir: PathBuf,
+    env: Env,
+    db: Database<Bytes, Bytes>,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The entries not yet committed.
+    pending: Vec<(String, Vec<u8>)>,
+    keys: Vec<String>,
+}
+
+fn to_io(e: heed::Error) -> io::Error {
+    match e {
+        heed::Error::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+impl LmdbSink {
+    /// Creates the environment in the directory, replacing any previous contents.
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let mut options = EnvOpenOptions::new();
+        options.map_size(MAP_SIZE.min(usize::MAX as u64) as usize);
+        // SAFETY: the environment is opened once, by this sink, and the
+        // directory is not otherwise written while cropping.
+        let env = unsafe { options.open(dir) }.map_err(to_io)?;
+        let mut txn = env.write_txn().map_err(to_io)?;
+        let db: Database<Bytes, Bytes> = env.create_database(&mut txn, None).map_err(to_io)?;
+        db.clear(&mut txn).map_err(to_io)?;
+        txn.commit().map_err(to_io)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            env,
+            db,
+            state: Mutex::default(),
+        })
+    }
+
+    fn commit(&self, entries: &[(String, Vec<u8>)]) -> io::Result<()> {
+        let mut txn = self.env.write_txn().map_err(to_io)?;
+        for (key, value) in entries {
+            self.db
+                .put(&mut txn, key.as_bytes(), value)
+                .map_err(to_io)?;
+        }
+        txn.commit().map_err(to_io)
+    }
+}
+
+impl CropSink for LmdbSink {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        let key = crop.name.to_string_lossy().to_string();
+        let b = crop.bndbox;
+        let metadata = json!({
+            "label": crop.label,
+            "source": crop.source,
+            "bbox": [b.xmin, b.ymin, b.xmax, b.ymax],
+        });
+
+        let mut state = self.state.lock().unwrap();
+        let json_key = format!("{}.json", key);
+        state
+            .pending
+            .push((json_key, metadata.to_string().into_bytes()));
+        state.pending.push((key.clone(), crop.bytes.to_vec()));
+        state.keys.push(key);
+        if state.pending.len() >= 2 * BATCH_SIZE {
+            let pending = std::mem::take(&mut state.pending);
+            self.commit(&pending)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let mut pending = std::mem::take(&mut state.pending);
+        pending.push((KEYS_KEY.to_string(), state.keys.join("\n").into_bytes()));
+        self.commit(&pending)?;
+        println!("Wrote {} crops to LMDB {:?}", state.keys.len(), self.dir);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::PixelRect;
+    use crate::manifest::CropKind;
+    use image::DynamicImage;
+
+    #[test]
+    fn lmdb() {
+        let dir = std::env::temp_dir().join(format!("blaise-lmdb-{}", std::process::id()));
+        let sink = LmdbSink::create(&dir).unwrap();
+        let image = DynamicImage::new_rgb8(1, 1);
+        let bndbox = PixelRect {
+            xmin: 1,
+            ymin: 2,
+            xmax: 3,
+            ymax: 4,
+        };
+        let names = ["Aegina/a_0.png", "Kelp/b_0.png"];
+        for name in names {
+            sink.add(&Crop {
+                kind: CropKind::Object,
+                image: &image,
+                bytes: name.as_bytes(),
+                path: &Path::new("out").join(name),
+                name: Path::new(name),
+                label: "Aegina",
+                source: "imgs/a.png",
+                bndbox: &bndbox,
+            })
+            .unwrap();
+        }
+        sink.finish().unwrap();
+
+        let txn = sink.env.read_txn().unwrap();
+        let get = |key: &str| sink.db.get(&txn, key.as_bytes()).unwrap().unwrap();
+        assert_eq!(sink.db.len(&txn).unwrap(), 5);
+        assert_eq!(get(KEYS_KEY), b"Aegina/a_0.png\nKelp/b_0.png");
+        assert_eq!(get("Kelp/b_0.png"), b"Kelp/b_0.png");
+        assert_eq!(
+            get("Aegina/a_0.png.json"),
+            br#"{"bbox":[1,2,3,4],"label":"Aegina","source":"imgs/a.png"}"#
+        );
+        drop(txn);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
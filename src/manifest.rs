@@ -0,0 +1,440 @@
+use crate::exif::ExifInfo;
+use crate::geo::GeoExtent;
+use crate::geometry::PixelRect;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum CropKind {
+    /// Crop of a single object.
+    Object,
+    /// Crop covering all the selected objects in an image.
+    Union,
+    /// Crop covering a pair of nearby objects.
+    Pair,
+    /// Unlabeled patch of an image, with `blaise patches`.
+    Patch,
+    /// Sliding window over an image, with `--windows`.
+    Window,
+}
+
+/// A line in the manifest, one for each written crop.
+#[derive(Debug, Serialize)]
+pub struct ManifestEntry {
+    pub kind: CropKind,
+    pub crop: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    pub image: String,
+    pub label: String,
+    /// Index of the object in the annotation (only for `CropKind::Object`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_index: Option<usize>,
+    /// Stable id of the object (only for `CropKind::Object`): its id in the
+    /// source, else a hash of its box, not changing with the objects filtered
+    /// out or inserted, as the index does.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub object_id: Option<String>,
+    /// Indices of the objects in the annotation (only for `CropKind::Pair`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pair_indices: Option<(usize, usize)>,
+    /// Subdirectory of the label directory with the crop, with `--shard-output`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shard: Option<String>,
+    pub bndbox: PixelRect,
+    /// Frame of the multi-frame image, with `--frame`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame: Option<u32>,
+    /// Map extent of the crop, for georeferenced images.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub geo_extent: Option<GeoExtent>,
+    /// Size (width, height) of the object in mm, with `--pixel-size`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size_mm: Option<(f64, f64)>,
+    /// GPS position, altitude and timestamp of the source image, with `--exif`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exif: Option<ExifInfo>,
+    /// Attributes of the object (only for `CropKind::Object`).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+/// Objects cropped in a previous run, from its manifest, by image, with their
+/// stable ids.
+#[derive(Default)]
+pub struct PriorCrops(HashMap<String, HashSet<String>>);
+
+impl PriorCrops {
+    pub fn read(path: &Path) -> io::Result<Self> {
+        let mut objects: HashMap<String, HashSet<String>> = HashMap::new();
+        for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |e: String| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e))
+            };
+            let entry: Value = serde_json::from_str(&line).map_err(|e| invalid(e.to_string()))?;
+            if entry["kind"] != "object" {
+                continue;
+            }
+            match (entry["image"].as_str(), entry["object_id"].as_str()) {
+                (Some(image), Some(id)) => {
+                    objects
+                        .entry(image.to_string())
+                        .or_default()
+                        .insert(id.to_string());
+                }
+                _ => {
+                    return Err(invalid(
+                        "missing image or object_id (manifest of a previous version?)".to_string(),
+                    ))
+                }
+            }
+        }
+        Ok(Self(objects))
+    }
+
+    /// Whether the object of the image, by its stable id, was cropped.
+    pub fn contains(&self, image: &str, object_id: &str) -> bool {
+        self.0.get(image).is_some_and(|ids| ids.contains(object_id))
+    }
+
+    pub fn num_objects(&self) -> usize {
+        self.0.values().map(HashSet::len).sum()
+    }
+}
+
+/// A crop in the manifest of a previous run, to be written again with
+/// `blaise recrop`.
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct PriorCrop {
+    /// Index of the line in the manifest.
+    #[serde(skip)]
+    pub line: usize,
+    pub crop: String,
+    pub image: String,
+    pub label: String,
+    pub bndbox: PixelRect,
+    #[serde(default)]
+    pub frame: Option<u32>,
+}
+
+impl PriorCrop {
+    /// Reads the lines of the manifest, also returning the crops with the given
+    /// labels (all if None).
+    pub fn select(path: &Path, labels: Option<&[String]>) -> io::Result<(Vec<Value>, Vec<Self>)> {
+        let mut lines = Vec::new();
+        let mut crops = Vec::new();
+        for (n, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let invalid = |e: serde_json::Error| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {}", n + 1, e))
+            };
+            let entry: Value = serde_json::from_str(&line).map_err(invalid)?;
+            let crop = Self {
+                line: lines.len(),
+                ..serde_json::from_value(entry.clone()).map_err(invalid)?
+            };
+            if labels.is_none_or(|labels| labels.contains(&crop.label)) {
+                crops.push(crop);
+            }
+            lines.push(entry);
+        }
+        Ok((lines, crops))
+    }
+}
+
+/// Writes the manifest lines again, through a temporary file so the manifest
+/// is never left incomplete.
+pub fn rewrite(path: &Path, lines: &[Value]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_os_string();
+    tmp.push(".tmp");
+    let mut writer = BufWriter::new(File::create(&tmp)?);
+    for line in lines {
+        writeln!(writer, "{}", line)?;
+    }
+    writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/// Order of the manifest lines.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ManifestOrder {
+    /// As the crops are written by the processing threads
+    Completion,
+    /// By source image, then crop kind and object index, rewritten at each checkpoint and at the end
+    Stable,
+}
+
+/// JSON-lines manifest of the written crops, shared by the processing threads.
+pub struct Manifest {
+    writer: Option<Mutex<SyncingWriter>>,
+    /// With `ManifestOrder::Stable`, the path and all the entries, written
+    /// sorted over the manifest on every flush.
+    pending: Option<(PathBuf, Mutex<Vec<ManifestEntry>>)>,
+}
+
+impl Manifest {
+    /// Manifest becomes a no-op if `path` is None.
+    pub fn new(path: Option<&Path>, order: ManifestOrder) -> Self {
+        match (path, order) {
+            (Some(path), ManifestOrder::Stable) => {
+                File::create(path).unwrap_or_else(|e| panic!("cannot create {:?}: {}", path, e));
+                Self {
+                    writer: None,
+                    pending: Some((path.to_path_buf(), Mutex::new(Vec::new()))),
+                }
+            }
+            _ => Self {
+                writer: path.map(|path| Mutex::new(SyncingWriter::create(path))),
+                pending: None,
+            },
+        }
+    }
+
+    pub fn add(&self, entry: ManifestEntry) {
+        if let Some((_, pending)) = &self.pending {
+            pending.lock().unwrap().push(entry);
+        } else if let Some(writer) = &self.writer {
+            let line = serde_json::to_string(&entry).unwrap();
+            writer.lock().unwrap().write_line(&line);
+        }
+    }
+
+    /// Syncs the manifest to disk; in stable order, replaces it with the
+    /// entries so far, sorted.
+    pub fn flush(&self) {
+        if let Some(writer) = &self.writer {
+            writer.lock().unwrap().sync();
+        }
+        if let Some((path, pending)) = &self.pending {
+            let mut entries = pending.lock().unwrap();
+            entries.sort_by(|a, b| {
+                (&a.image, a.kind, a.object_index, a.pair_indices, &a.crop).cmp(&(
+                    &b.image,
+                    b.kind,
+                    b.object_index,
+                    b.pair_indices,
+                    &b.crop,
+                ))
+            });
+            let lines: Vec<Value> = entries
+                .iter()
+                .map(|entry| serde_json::to_value(entry).unwrap())
+                .collect();
+            if let Err(e) = rewrite(path, &lines) {
+                eprintln!("WARN: cannot write {:?}: {}", path, e);
+            }
+        }
+    }
+
+    /// Flushes at the end of the run.
+    pub fn finish(&self) {
+        self.flush();
+    }
+}
+
+/// Line writer that periodically flushes and syncs to disk, so the contents
+/// written so far survive an interrupted run.
+pub struct SyncingWriter {
+    writer: BufWriter<File>,
+    last_sync: Instant,
+}
+
+impl SyncingWriter {
+    const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+    pub fn create(path: &Path) -> Self {
+        let file = File::create(path).unwrap_or_else(|e| panic!("cannot create {:?}: {}", path, e));
+        Self {
+            writer: BufWriter::new(file),
+            last_sync: Instant::now(),
+        }
+    }
+
+    pub fn write_line(&mut self, line: &str) {
+        writeln!(self.writer, "{}", line).unwrap();
+        if self.last_sync.elapsed() >= Self::SYNC_INTERVAL {
+            self.sync();
+        }
+    }
+
+    pub fn sync(&mut self) {
+        self.writer.flush().unwrap();
+        if let Err(e) = self.writer.get_ref().sync_data() {
+            eprintln!("WARN: cannot sync: {:?}", e);
+        }
+        self.last_sync = Instant::now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::{Annotation, Bndbox, Object, ObjectIds};
+
+    fn entry(image: &str, kind: CropKind, object_index: Option<usize>) -> ManifestEntry {
+        ManifestEntry {
+            kind,
+            crop: format!("{}_{:?}.png", image, object_index),
+            sha256: None,
+            image: image.to_string(),
+            label: "Aegina".to_string(),
+            object_index,
+            object_id: object_index.map(|i| format!("id{}", i)),
+            pair_indices: None,
+            shard: None,
+            bndbox: PixelRect {
+                xmin: 0,
+                ymin: 0,
+                xmax: 1,
+                ymax: 1,
+            },
+            frame: None,
+            geo_extent: None,
+            size_mm: None,
+            exif: None,
+            attributes: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn prior_crops() {
+        let path = std::env::temp_dir().join(format!("blaise-prior-{}", std::process::id()));
+        let manifest = Manifest::new(Some(&path), ManifestOrder::Completion);
+        manifest.add(entry("a.png", CropKind::Object, Some(0)));
+        manifest.add(entry("a.png", CropKind::Object, Some(2)));
+        manifest.add(entry("a.png", CropKind::Union, None));
+        manifest.add(entry("b.png", CropKind::Object, Some(1)));
+        manifest.finish();
+
+        let prior = PriorCrops::read(&path).unwrap();
+        assert_eq!(prior.num_objects(), 3);
+        assert!(prior.contains("a.png", "id2"));
+        assert!(!prior.contains("a.png", "id1"));
+        assert!(!prior.contains("c.png", "id0"));
+
+        std::fs::write(&path, "{\"kind\":\"object\"}\n").unwrap();
+        assert!(PriorCrops::read(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recrop_selection() {
+        let path = std::env::temp_dir().join(format!("blaise-recrop-{}", std::process::id()));
+        let manifest = Manifest::new(Some(&path), ManifestOrder::Completion);
+        manifest.add(entry("a.png", CropKind::Object, Some(0)));
+        manifest.add(ManifestEntry {
+            label: "Nanomia".to_string(),
+            frame: Some(3),
+            ..entry("b.png", CropKind::Object, Some(1))
+        });
+        manifest.finish();
+
+        let labels = ["Nanomia".to_string()];
+        let (lines, crops) = PriorCrop::select(&path, Some(&labels)).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(crops.len(), 1);
+        assert_eq!(crops[0].line, 1);
+        assert_eq!(crops[0].image, "b.png");
+        assert_eq!(crops[0].frame, Some(3));
+        assert_eq!(PriorCrop::select(&path, None).unwrap().1.len(), 2);
+
+        rewrite(&path, &lines[1..]).unwrap();
+        assert_eq!(PriorCrop::select(&path, None).unwrap().0, lines[1..]);
+        std::fs::write(&path, "{\"crop\":\"a_0.png\"}\n").unwrap();
+        assert!(PriorCrop::select(&path, None).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn prior_crops_with_inserted_object() {
+        let object = |name: &str, xmin: f64| Object {
+            name: name.to_string(),
+            bndbox: Bndbox {
+                xmin,
+                ymin: 0.,
+                xmax: xmin + 10.,
+                ymax: 10.,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        };
+        let annotation = |objects| Annotation {
+            folder: String::new(),
+            filename: "a.png".to_string(),
+            objects: Some(objects),
+            frame: None,
+            image_size: None,
+        };
+        let path = std::env::temp_dir().join(format!("blaise-inserted-{}", std::process::id()));
+        let manifest = Manifest::new(Some(&path), ManifestOrder::Completion);
+        let before = annotation(vec![object("A", 10.), object("B", 30.)]);
+        for (i, id) in before.object_ids(ObjectIds::Stable).into_iter().enumerate() {
+            manifest.add(ManifestEntry {
+                object_id: Some(id),
+                ..entry("a.png", CropKind::Object, Some(i))
+            });
+        }
+        manifest.finish();
+        let prior = PriorCrops::read(&path).unwrap();
+
+        // a new object before the others, shifting their indices:
+        let after = annotation(vec![object("C", 50.), object("A", 10.), object("B", 30.)]);
+        let new: Vec<bool> = after
+            .object_ids(ObjectIds::Stable)
+            .iter()
+            .map(|id| !prior.contains("a.png", id))
+            .collect();
+        assert_eq!(new, [true, false, false]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stable_order() {
+        let path = std::env::temp_dir().join(format!("blaise-manifest-{}", std::process::id()));
+        let manifest = Manifest::new(Some(&path), ManifestOrder::Stable);
+        manifest.add(entry("b.png", CropKind::Object, Some(0)));
+        manifest.add(entry("a.png", CropKind::Union, None));
+        manifest.add(entry("a.png", CropKind::Object, Some(1)));
+        manifest.add(entry("a.png", CropKind::Object, Some(0)));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        manifest.flush();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 4);
+        manifest.add(entry("a.png", CropKind::Object, Some(2)));
+        manifest.finish();
+
+        let crops: Vec<String> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<serde_json::Value>(line).unwrap()["crop"].to_string()
+            })
+            .collect();
+        assert_eq!(
+            crops,
+            vec![
+                "\"a.png_Some(0).png\"",
+                "\"a.png_Some(1).png\"",
+                "\"a.png_Some(2).png\"",
+                "\"a.png_None.png\"",
+                "\"b.png_Some(0).png\"",
+            ]
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+}
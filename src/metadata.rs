@@ -0,0 +1,123 @@
+//! Image metadata (`--metadata <csv-file>`), eg., the deployment, dive, or site
+//! of the images, to split the crops by (`--split-by-metadata <column>`):
+//!
+//! ```csv
+//! image,dive,site
+//! D1234/,D1234,Monterey Canyon
+//! D1240/img_0001.png,D1240,Davidson Seamount
+//! ```
+//!
+//! The first column gives the image, or a folder with the images, by its path
+//! or the end of it (eg., the filename); the image itself takes precedence,
+//! then the nearest folder.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub struct Metadata {
+    pub columns: Vec<String>,
+    /// The values (after the first column) by image or folder.
+    rows: HashMap<String, Vec<String>>,
+}
+
+impl Metadata {
+    pub fn load(path: &Path) -> Result<Metadata, String> {
+        let src = std::fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+        Metadata::parse(&src).map_err(|e| format!("{:?}: {}", path, e))
+    }
+
+    pub fn parse(src: &str) -> Result<Metadata, String> {
+        let mut reader = csv::Reader::from_reader(src.as_bytes());
+        let header = reader.headers().map_err(|e| e.to_string())?;
+        if header.len() < 2 {
+            return Err("expected an image column and metadata columns".to_string());
+        }
+        let columns = header.iter().skip(1).map(str::to_string).collect();
+        let mut rows = HashMap::new();
+        for record in reader.records() {
+            let record = record.map_err(|e| e.to_string())?;
+            let key = record[0].trim().trim_end_matches(['/', '\\']).to_string();
+            let values = record.iter().skip(1).map(|v| v.trim().to_string());
+            rows.insert(key, values.collect());
+        }
+        Ok(Metadata { columns, rows })
+    }
+
+    /// The given column, for the values of the images.
+    pub fn column(self, name: &str) -> Result<MetadataColumn, String> {
+        match self.columns.iter().position(|c| c == name) {
+            Some(index) => Ok(MetadataColumn {
+                name: name.to_string(),
+                index,
+                metadata: self,
+            }),
+            None => Err(format!(
+                "no column {:?} in the metadata (columns: {})",
+                name,
+                self.columns.join(", ")
+            )),
+        }
+    }
+
+    /// The values for the image, by its path (or the end of it), or that of
+    /// the nearest folder with it.
+    fn row(&self, image_path: &str) -> Option<&[String]> {
+        Path::new(image_path).ancestors().find_map(|path| {
+            let components: Vec<_> = path.components().collect();
+            (0..components.len()).find_map(|i| {
+                let suffix: PathBuf = components[i..].iter().collect();
+                self.rows.get(&*suffix.to_string_lossy()).map(Vec::as_slice)
+            })
+        })
+    }
+}
+
+pub struct MetadataColumn {
+    pub name: String,
+    index: usize,
+    metadata: Metadata,
+}
+
+impl MetadataColumn {
+    /// The value for the image, if given (and not empty).
+    pub fn value(&self, image_path: &str) -> Option<&str> {
+        let row = self.metadata.row(image_path)?;
+        row.get(self.index)
+            .map(String::as_str)
+            .filter(|v| !v.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+image,dive,site
+imgs/D1234/,D1234,Monterey Canyon
+D1240,D1240,Davidson Seamount
+x.png,D9,
+imgs/D1240/special.png,D1241,Davidson Seamount
+";
+
+    #[test]
+    fn values() {
+        let metadata = Metadata::parse(CSV).unwrap();
+        assert_eq!(metadata.columns, ["dive", "site"]);
+        let dive = Metadata::parse(CSV).unwrap().column("dive").unwrap();
+        assert_eq!(dive.value("imgs/D1234/a.png"), Some("D1234"));
+        assert_eq!(dive.value("data/imgs/D1234/a.png"), Some("D1234"));
+        assert_eq!(dive.value("data/imgs/D1240/sub/b.png"), Some("D1240"));
+        assert_eq!(dive.value("imgs/D1240/special.png"), Some("D1241"));
+        assert_eq!(dive.value("other/x.png"), Some("D9"));
+        assert_eq!(dive.value("other/y.png"), None);
+        let site = metadata.column("site").unwrap();
+        assert_eq!(site.value("imgs/D1234/a.png"), Some("Monterey Canyon"));
+        // empty:
+        assert_eq!(site.value("x.png"), None);
+
+        let no_column = Metadata::parse(CSV).unwrap().column("depth");
+        assert!(no_column.err().unwrap().contains("columns: dive, site"));
+        assert!(Metadata::parse("image\nx.png\n").is_err());
+    }
+}
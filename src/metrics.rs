@@ -0,0 +1,254 @@
+//! Processing counters, and their exposition in the Prometheus text format
+//! over HTTP (`--metrics-port`) so long runs can be monitored.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::RwLock;
+use std::time::Duration;
+
+#[derive(Default)]
+pub struct Metrics {
+    pub annotations: AtomicU64,
+    pub processed: AtomicU64,
+    pub failed: AtomicU64,
+    pub crops_written: AtomicU64,
+    pub save_failures: AtomicU64,
+    pub boxes_expanded: AtomicU64,
+    /// Crops by label, for the live tally.
+    pub labels: LabelTally,
+}
+
+/// Running crop counts by label, shared by the processing threads.
+#[derive(Default)]
+pub struct LabelTally(RwLock<HashMap<String, AtomicU64>>);
+
+impl LabelTally {
+    pub fn inc(&self, label: &str) {
+        if let Some(count) = self.0.read().unwrap().get(label) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        let mut counts = self.0.write().unwrap();
+        let count = counts.entry(label.to_string()).or_default();
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// All the counts, by descending count, then by label.
+    pub fn counts(&self) -> Vec<(String, u64)> {
+        let counts = self.0.read().unwrap();
+        let mut counts: Vec<(String, u64)> = counts
+            .iter()
+            .map(|(label, count)| (label.clone(), count.load(Ordering::Relaxed)))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    }
+
+    /// One line with the total and the counts of the top labels.
+    pub fn summary(&self, top: usize) -> String {
+        let counts = self.counts();
+        if counts.is_empty() {
+            return "0 crops".to_string();
+        }
+        let total: u64 = counts.iter().map(|(_, count)| count).sum();
+        let labels: Vec<String> = counts
+            .iter()
+            .take(top)
+            .map(|(label, count)| format!("{} {}", label, count))
+            .collect();
+        let more = match counts.len().saturating_sub(top) {
+            0 => String::new(),
+            n => format!(" (+{} labels)", n),
+        };
+        format!("{} crops: {}{}", total, labels.join(", "), more)
+    }
+}
+
+impl Metrics {
+    pub fn inc(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The metrics in the Prometheus text format, along with the given storage byte counts.
+    pub fn render(&self, bytes_read: u64, bytes_written: u64) -> String {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let annotations = get(&self.annotations);
+        let processed = get(&self.processed);
+        let metrics = [
+            (
+                "annotations",
+                "gauge",
+                "Annotations to process",
+                annotations,
+            ),
+            (
+                "annotations_pending",
+                "gauge",
+                "Annotations not yet processed",
+                annotations.saturating_sub(processed),
+            ),
+            (
+                "annotations_processed_total",
+                "counter",
+                "Annotations processed",
+                processed,
+            ),
+            (
+                "annotations_failed_total",
+                "counter",
+                "Annotations that failed to be processed",
+                get(&self.failed),
+            ),
+            (
+                "crops_written_total",
+                "counter",
+                "Crops written",
+                get(&self.crops_written),
+            ),
+            (
+                "crop_save_failures_total",
+                "counter",
+                "Crops that failed to be saved",
+                get(&self.save_failures),
+            ),
+            (
+                "boxes_expanded_total",
+                "counter",
+                "Boxes expanded to the minimum crop size",
+                get(&self.boxes_expanded),
+            ),
+            (
+                "bytes_read_total",
+                "counter",
+                "Image bytes read",
+                bytes_read,
+            ),
+            (
+                "bytes_written_total",
+                "counter",
+                "Crop bytes written",
+                bytes_written,
+            ),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, value) in metrics {
+            text.push_str(&format!(
+                "# HELP blaise_{name} {help}\n# TYPE blaise_{name} {kind}\nblaise_{name} {value}\n"
+            ));
+        }
+        text
+    }
+}
+
+/// Serves `/metrics` with the text from `render` until `done`.
+pub fn serve(listener: &TcpListener, done: &AtomicBool, render: impl Fn() -> String) {
+    listener.set_nonblocking(true).unwrap();
+    while !done.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = respond(stream, &render) {
+                    eprintln!("WARN: metrics request: {}", e);
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => eprintln!("WARN: metrics connection: {}", e),
+        }
+    }
+}
+
+fn respond(stream: TcpStream, render: impl Fn() -> String) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // skip the headers:
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let path = request_line.split_whitespace().nth(1).unwrap_or("");
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", render()),
+        _ => ("404 Not Found", "see /metrics\n".to_string()),
+    };
+    let mut stream = &stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn render() {
+        let metrics = Metrics::default();
+        metrics.annotations.store(10, Ordering::Relaxed);
+        Metrics::inc(&metrics.processed);
+        Metrics::inc(&metrics.crops_written);
+        let text = metrics.render(100, 50);
+        assert!(text
+            .contains("# TYPE blaise_crops_written_total counter\nblaise_crops_written_total 1\n"));
+        assert!(text.contains("\nblaise_annotations_pending 9\n"));
+        assert!(text.contains("\nblaise_bytes_read_total 100\n"));
+    }
+
+    #[test]
+    fn label_tally() {
+        let tally = LabelTally::default();
+        assert_eq!(tally.summary(10), "0 crops");
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for label in ["Kelp", "Aegina", "Kelp", "Sebastes"] {
+                        tally.inc(label);
+                    }
+                });
+            }
+        });
+        assert_eq!(
+            tally.counts(),
+            [
+                ("Kelp".to_string(), 8),
+                ("Aegina".to_string(), 4),
+                ("Sebastes".to_string(), 4)
+            ]
+        );
+        assert_eq!(tally.summary(2), "16 crops: Kelp 8, Aegina 4 (+1 labels)");
+        assert_eq!(tally.summary(10), "16 crops: Kelp 8, Aegina 4, Sebastes 4");
+    }
+
+    #[test]
+    fn http() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let done = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| serve(&listener, &done, || "blaise_x 1\n".to_string()));
+            let get = |path: &str| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                write!(stream, "GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).unwrap();
+                let mut response = String::new();
+                stream.read_to_string(&mut response).unwrap();
+                response
+            };
+            let response = get("/metrics");
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+            assert!(response.ends_with("\r\n\r\nblaise_x 1\n"));
+            assert!(get("/").starts_with("HTTP/1.1 404"));
+            done.store(true, Ordering::Relaxed);
+        });
+    }
+}
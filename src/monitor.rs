@@ -0,0 +1,203 @@
+//! Terminal dashboard for long interactive runs (`--tui`), in place of the
+//! progress bars: per-thread throughput, per-label running counts, recent
+//! errors and memory usage, drawn with ratatui on the alternate screen (not in
+//! raw mode, so Ctrl-C still interrupts the run).
+
+use crate::metrics::LabelTally;
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::{cursor, execute, terminal};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::widgets::{Block, List, Paragraph, Row, Table};
+use ratatui::{Frame, Terminal};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const REFRESH: Duration = Duration::from_millis(500);
+const MAX_ERRORS: usize = 8;
+const MAX_LABELS: usize = 15;
+
+#[derive(Default, Clone)]
+struct ThreadStats {
+    total: usize,
+    processed: usize,
+    failed: usize,
+}
+
+pub struct Monitor {
+    started: Instant,
+    threads: Vec<Mutex<ThreadStats>>,
+    errors: Mutex<VecDeque<String>>,
+}
+
+impl Monitor {
+    pub fn new(threads: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            threads: (0..threads).map(|_| Default::default()).collect(),
+            errors: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Updates the stats of a processing thread.
+    pub fn update(&self, th: usize, processed: usize, total: usize, failed: usize) {
+        let mut stats = self.threads[th].lock().unwrap();
+        stats.processed = processed;
+        stats.total = total;
+        stats.failed = failed;
+    }
+
+    /// Shows the message among the recent errors.
+    pub fn error(&self, message: String) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == MAX_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(message);
+    }
+
+    /// Redraws the dashboard, with the running crop counts, until `done`,
+    /// then restores the terminal.
+    pub fn run(&self, done: &AtomicBool, labels: &LabelTally) {
+        let mut out = std::io::stdout();
+        let _ = execute!(out, terminal::EnterAlternateScreen, cursor::Hide);
+        match Terminal::new(CrosstermBackend::new(&mut out)) {
+            Ok(mut term) => {
+                while !done.load(Ordering::Relaxed) {
+                    if term.draw(|frame| self.draw(frame, labels)).is_err() {
+                        break;
+                    }
+                    std::thread::sleep(REFRESH);
+                }
+            }
+            Err(e) => log::warn!("cannot draw the dashboard: {}", e),
+        }
+        let _ = execute!(out, cursor::Show, terminal::LeaveAlternateScreen);
+    }
+
+    fn draw(&self, frame: &mut Frame, labels: &LabelTally) {
+        let elapsed = self.started.elapsed().as_secs_f64().max(1e-3);
+        let threads: Vec<ThreadStats> = self
+            .threads
+            .iter()
+            .map(|t| t.lock().unwrap().clone())
+            .collect();
+        let processed: usize = threads.iter().map(|t| t.processed).sum();
+        let total: usize = threads.iter().map(|t| t.total).sum();
+        let memory = match memory_usage() {
+            Some(bytes) => format!("{:.1} MB", bytes as f64 / 1e6),
+            None => "n/a".to_string(),
+        };
+        let summary = format!(
+            "blaise: {}/{} annotations in {:.0}s ({:.1}/s), memory {}",
+            processed,
+            total,
+            elapsed,
+            processed as f64 / elapsed,
+            memory
+        );
+
+        let rows: Vec<Row> = threads
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| t.total > 0)
+            .map(|(th, stats)| {
+                Row::new([
+                    format!("[{:>02}]", th),
+                    format!("{:>6}/{:<6}", stats.processed, stats.total),
+                    format!("{:>6}", stats.failed),
+                    format!("{:>6.1}/s", stats.processed as f64 / elapsed),
+                ])
+            })
+            .collect();
+        let [header, threads_area, bottom] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Length(rows.len() as u16 + 3),
+            Constraint::Min(0),
+        ])
+        .areas(frame.area());
+        let [labels_area, errors_area] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(bottom);
+
+        frame.render_widget(Paragraph::new(summary), header);
+        let widths = [
+            Constraint::Length(6),
+            Constraint::Length(13),
+            Constraint::Length(7),
+            Constraint::Length(9),
+        ];
+        let table = Table::new(rows, widths)
+            .header(Row::new(["thread", "processed", " failed", "    rate"]))
+            .block(Block::bordered().title("threads"));
+        frame.render_widget(table, threads_area);
+
+        let by_label = labels.counts();
+        let title = format!("crops by label ({} labels)", by_label.len());
+        let items = by_label
+            .iter()
+            .take(MAX_LABELS)
+            .map(|(label, count)| format!("{:>7} {}", count, label));
+        frame.render_widget(
+            List::new(items).block(Block::bordered().title(title)),
+            labels_area,
+        );
+        let errors: Vec<String> = self.errors.lock().unwrap().iter().cloned().collect();
+        frame.render_widget(
+            List::new(errors).block(Block::bordered().title("recent errors")),
+            errors_area,
+        );
+    }
+}
+
+/// Resident memory of the process in bytes, where available.
+fn memory_usage() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmRSS:"))?;
+    let kb: u64 = line.split_whitespace().nth(1)?.parse().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+
+    #[test]
+    fn render() {
+        let monitor = Monitor::new(3);
+        let labels = LabelTally::default();
+        for label in ["Aegina", "Kelp", "Kelp", "Aegina", "Kelp", "Kelp", "Aegina", "Kelp"] {
+            labels.inc(label);
+        }
+        labels.inc("Aegina");
+        monitor.update(0, 2, 4, 1);
+        monitor.update(1, 1, 4, 0);
+        for i in 0..MAX_ERRORS + 2 {
+            monitor.error(format!("error {}", i));
+        }
+        let mut term = Terminal::new(TestBackend::new(100, 24)).unwrap();
+        term.draw(|frame| monitor.draw(frame, &labels)).unwrap();
+        let buffer = term.backend().buffer();
+        let lines: Vec<String> = (0..buffer.area.height)
+            .map(|y| {
+                let cells = (0..buffer.area.width).map(|x| buffer[(x, y)].symbol());
+                cells.collect::<String>()
+            })
+            .collect();
+        assert!(lines[0].starts_with("blaise: 3/8 annotations"));
+        assert!(lines.iter().any(|l| l.contains("[00]") && l.contains(" 2/4 ")));
+        assert!(!lines.iter().any(|l| l.contains("[02]")));
+        let labels = lines
+            .iter()
+            .position(|l| l.contains("crops by label (2 labels)"))
+            .unwrap();
+        assert!(lines[labels + 1].starts_with("│      5 Kelp"));
+        assert!(lines[labels + 2].starts_with("│      4 Aegina"));
+        let screen = lines.join("\n");
+        assert!(screen.contains(&format!("error {}", MAX_ERRORS + 1)));
+        assert!(screen.contains("error 2"));
+        assert!(!screen.contains("error 1 "));
+    }
+}
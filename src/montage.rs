@@ -0,0 +1,191 @@
+//! Montage of the object crops of each image (`--montage`), in a grid of
+//! tiles, with a JSON index of the tiles, for context-aware models.
+
+use crate::geometry::PixelRect;
+use crate::image::resize_image;
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+use serde::Serialize;
+
+/// Name of the subdirectory (under the output directory) for the montages.
+pub const MONTAGE_DIR: &str = "_montage";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MontageOrder {
+    /// As the objects in the annotation
+    Index,
+    /// By label, then as in the annotation
+    Label,
+    /// By box area, largest first
+    Area,
+    /// In reading order of the boxes: by row (top), then left
+    Position,
+}
+
+/// An object crop for the montage.
+pub struct Tile {
+    pub id: String,
+    pub label: String,
+    pub bndbox: PixelRect,
+    pub crop: DynamicImage,
+}
+
+/// Index of the montage, written next to it.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct MontageIndex {
+    pub image: String,
+    pub tile_size: (u32, u32),
+    pub columns: u32,
+    pub tiles: Vec<TileEntry>,
+}
+
+/// A tile of the montage, with its object.
+#[derive(Debug, Serialize, PartialEq)]
+pub struct TileEntry {
+    pub id: String,
+    pub label: String,
+    pub bndbox: PixelRect,
+    /// Position of the crop in the montage, resized to fit its tile.
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn sort(tiles: &mut [Tile], order: MontageOrder) {
+    match order {
+        MontageOrder::Index => {}
+        MontageOrder::Label => tiles.sort_by(|a, b| a.label.cmp(&b.label)),
+        MontageOrder::Area => {
+            let area = |t: &Tile| t.bndbox.width() as u64 * t.bndbox.height() as u64;
+            tiles.sort_by_key(|t| std::cmp::Reverse(area(t)));
+        }
+        MontageOrder::Position => {
+            tiles.sort_by_key(|t| (t.bndbox.ymin, t.bndbox.xmin));
+        }
+    }
+}
+
+/// Size of the crop resized to fit the tile, keeping its aspect ratio.
+pub fn fit(width: u32, height: u32, tile: (u32, u32)) -> (u32, u32) {
+    let scale = (tile.0 as f64 / width as f64).min(tile.1 as f64 / height as f64);
+    let scaled = |v: u32, max: u32| ((v as f64 * scale).round() as u32).clamp(1, max);
+    (scaled(width, tile.0), scaled(height, tile.1))
+}
+
+/// The montage of the tiles, in a square-ish grid, each crop centered in its
+/// tile on a transparent background.
+pub fn compose(image: &str, tiles: &[Tile], tile_size: (u32, u32)) -> (DynamicImage, MontageIndex) {
+    let count = tiles.len() as u32;
+    let columns = ((count as f64).sqrt().ceil() as u32).max(1);
+    let rows = count.div_ceil(columns).max(1);
+    let (tile_width, tile_height) = tile_size;
+    let mut montage = RgbaImage::from_pixel(columns * tile_width, rows * tile_height, Rgba([0; 4]));
+    let mut entries = Vec::new();
+    for (k, tile) in tiles.iter().enumerate() {
+        let (col, row) = (k as u32 % columns, k as u32 / columns);
+        let (width, height) = fit(tile.crop.width(), tile.crop.height(), tile_size);
+        let Some(resized) = resize_image(&tile.crop, width, height) else {
+            continue;
+        };
+        let x = col * tile_width + (tile_width - width) / 2;
+        let y = row * tile_height + (tile_height - height) / 2;
+        // fits by construction:
+        let _ = montage.copy_from(&resized.to_rgba8(), x, y);
+        entries.push(TileEntry {
+            id: tile.id.clone(),
+            label: tile.label.clone(),
+            bndbox: tile.bndbox,
+            x,
+            y,
+            width,
+            height,
+        });
+    }
+    let index = MontageIndex {
+        image: image.to_string(),
+        tile_size,
+        columns,
+        tiles: entries,
+    };
+    (DynamicImage::ImageRgba8(montage), index)
+}
+
+/// Names of the montage and its index, eg., `a_montage.png` and `a_montage.json`
+/// for `a.png`.
+pub fn montage_filenames(filename: &str) -> (String, String) {
+    let stem = match filename.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => filename,
+    };
+    (format!("{}_montage.png", stem), format!("{}_montage.json", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tile(label: &str, xmin: u32, ymin: u32, width: u32, height: u32) -> Tile {
+        Tile {
+            id: format!("{}{}", label, xmin),
+            label: label.to_string(),
+            bndbox: PixelRect {
+                xmin,
+                ymin,
+                xmax: xmin + width,
+                ymax: ymin + height,
+            },
+            crop: DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+                width,
+                height,
+                image::Rgb([200, 10, 10]),
+            )),
+        }
+    }
+
+    #[test]
+    fn order() {
+        let tiles = || {
+            vec![
+                tile("B", 50, 0, 10, 10),
+                tile("A", 0, 20, 30, 30),
+                tile("C", 0, 0, 20, 20),
+            ]
+        };
+        let ids = |order| {
+            let mut tiles = tiles();
+            sort(&mut tiles, order);
+            tiles.into_iter().map(|t| t.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(MontageOrder::Index), ["B50", "A0", "C0"]);
+        assert_eq!(ids(MontageOrder::Label), ["A0", "B50", "C0"]);
+        assert_eq!(ids(MontageOrder::Area), ["A0", "C0", "B50"]);
+        assert_eq!(ids(MontageOrder::Position), ["C0", "B50", "A0"]);
+    }
+
+    #[test]
+    fn montage() {
+        let tiles = [
+            tile("A", 0, 0, 100, 50),
+            tile("B", 0, 0, 10, 40),
+            tile("C", 0, 0, 64, 64),
+        ];
+        let (montage, index) = compose("imgs/a.png", &tiles, (64, 64));
+        assert_eq!((montage.width(), montage.height()), (128, 128));
+        assert_eq!(index.columns, 2);
+        let placed: Vec<_> = index
+            .tiles
+            .iter()
+            .map(|t| (t.x, t.y, t.width, t.height))
+            .collect();
+        assert_eq!(placed, [(0, 16, 64, 32), (88, 0, 16, 64), (0, 64, 64, 64)]);
+        let rgba = montage.to_rgba8();
+        assert_eq!(rgba.get_pixel(32, 32)[3], 255);
+        assert_eq!(rgba.get_pixel(32, 8)[3], 0);
+        assert_eq!(rgba.get_pixel(100, 100)[3], 0);
+
+        assert_eq!(
+            montage_filenames("a.png"),
+            ("a_montage.png".to_string(), "a_montage.json".to_string())
+        );
+    }
+}
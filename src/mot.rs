@@ -0,0 +1,274 @@
+use crate::annotation;
+use crate::scan::ScanProgress;
+use crate::source::{self, AnnotationSource, Annotations};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::str::FromStr;
+use walkdir::WalkDir;
+
+type Res<T> = Result<T, Box<dyn Error>>;
+
+/// A MOTChallenge-style ground truth file for the frames in `image_dir`,
+/// with optional class names (for class ids 1, 2, ...).
+pub struct MotSource<'a> {
+    pub image_dir: &'a Path,
+    pub gt_file: &'a Path,
+    pub names_file: Option<&'a Path>,
+}
+
+impl AnnotationSource for MotSource<'_> {
+    fn format(&self) -> &'static str {
+        "Mot"
+    }
+
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String> {
+        let image_dir = self.image_dir;
+        println!(
+            "processing mot annotations with:
+          image_dir: {:?}
+          gt_file:   {:?}
+          mot_names: {:?}",
+            image_dir, self.gt_file, self.names_file
+        );
+
+        // MOT class ids are 1-based:
+        let mot_names: Vec<String> = match self.names_file {
+            Some(path) => source::read_names(path)?,
+            None => Vec::new(),
+        };
+        let class_id_to_name = |class_id: u32| -> String {
+            if 0 < class_id && class_id <= mot_names.len() as u32 {
+                mot_names[class_id as usize - 1].clone()
+            } else {
+                format!("class_{}", class_id)
+            }
+        };
+
+        scan.seen();
+        let records = read_to_string(self.gt_file)
+            .map_err(|e| e.to_string())
+            .and_then(|src| parse_mot(class_id_to_name, &src).map_err(|e| e.to_string()))
+            .map_err(|e| format!("invalid mot file {:?}: {}", self.gt_file, e))?;
+        scan.parsed();
+        println!("mot records loaded: {}", records.len());
+
+        // Frame images are those under image_dir named by frame number (eg., 000001.jpg);
+        // MOTChallenge naming is assumed for frames not found there.
+        let frame_filenames: HashMap<u32, String> = WalkDir::new(image_dir)
+            .max_depth(1)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(source::is_image)
+            .filter_map(|e| {
+                let filename = e.file_name().to_string_lossy().into_owned();
+                frame_number(&filename).map(|frame| (frame, filename))
+            })
+            .collect();
+        println!("frame images: {}", frame_filenames.len());
+        let frame_filename = |frame: u32| -> String {
+            frame_filenames
+                .get(&frame)
+                .cloned()
+                .unwrap_or_else(|| format!("{:06}.jpg", frame))
+        };
+
+        let annotations = to_annotations(&image_dir.to_string_lossy(), frame_filename, records);
+        Ok(Box::new(annotations.into_iter()))
+    }
+}
+
+/// Parses MOTChallenge-style text with lines of the form
+/// `frame, id, bb_left, bb_top, bb_width, bb_height, class[, ...]`
+/// (pixel coordinates; any additional columns are ignored).
+///
+/// Lines with exactly 9 columns are taken as MOTChallenge ground truth, that is,
+/// `frame, id, bb_left, bb_top, bb_width, bb_height, conf, class, visibility`,
+/// where entries with `conf` 0 (to be ignored per the MOT convention) are skipped.
+pub fn parse_mot(class_id_to_name: impl Fn(u32) -> String, src: &str) -> Res<Vec<Record>> {
+    fn parse<F: FromStr>(s: Option<&str>) -> Res<F> {
+        let s = s.ok_or("expected a string")?.trim();
+        s.parse::<F>()
+            .map_err(|_| format!("cannot parse '{}'", s).into())
+    }
+
+    let parse_record = |line: &str| -> Res<Option<Record>> {
+        let parts: Vec<&str> = line.split(',').collect();
+        let class_id: u32 = if parts.len() == 9 {
+            let conf: f64 = parse(parts.get(6).copied())?;
+            if conf == 0. {
+                return Ok(None);
+            }
+            parse(parts.get(7).copied())?
+        } else {
+            parse(parts.get(6).copied())?
+        };
+        let mut parts = parts.into_iter();
+        Ok(Some(Record {
+            frame: parse(parts.next())?,
+            track_id: parse(parts.next())?,
+            left: parse(parts.next())?,
+            top: parse(parts.next())?,
+            width: parse(parts.next())?,
+            height: parse(parts.next())?,
+            name: class_id_to_name(class_id),
+        }))
+    };
+
+    let records = src
+        .split('\n')
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(parse_record)
+        .collect::<Res<Vec<_>>>()?;
+    Ok(records.into_iter().flatten().collect())
+}
+
+/// Gets the frame number from a frame image filename like `000042.jpg`.
+pub fn frame_number(filename: &str) -> Option<u32> {
+    let stem = filename.rsplit_once('.').map_or(filename, |(stem, _)| stem);
+    stem.parse().ok()
+}
+
+/// Groups the records by frame, returning an annotation for each frame.
+pub fn to_annotations(
+    folder: &str,
+    frame_filename: impl Fn(u32) -> String,
+    records: Vec<Record>,
+) -> Vec<annotation::Annotation> {
+    let mut by_frame: BTreeMap<u32, Vec<annotation::Object>> = BTreeMap::new();
+    for record in records {
+        by_frame
+            .entry(record.frame)
+            .or_default()
+            .push(record.into());
+    }
+    by_frame
+        .into_iter()
+        .map(|(frame, mut objects)| {
+            objects.sort_by(|a, b| a.name.cmp(&b.name));
+            annotation::Annotation {
+                folder: folder.to_string(),
+                filename: frame_filename(frame),
+                objects: Some(objects),
+                frame: Some(frame),
+                image_size: None,
+            }
+        })
+        .collect()
+}
+
+impl From<Record> for annotation::Object {
+    fn from(record: Record) -> Self {
+        let xmin = record.left.max(0.);
+        let ymin = record.top.max(0.);
+        let xmax = (record.left + record.width).max(0.);
+        let ymax = (record.top + record.height).max(0.);
+        annotation::Object {
+            name: record.name,
+            bndbox: annotation::Bndbox {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            },
+            track_id: Some(record.track_id),
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Record {
+    pub frame: u32,
+    pub track_id: u32,
+    pub name: String,
+    pub left: f64,
+    pub top: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn class_id_to_name(class_id: u32) -> String {
+        format!("class_{}", class_id)
+    }
+
+    const MOT1: &str = r#"
+        1, 7, 10.5, 20, 30, 40, 2
+        1, 8, 100, 120, 15.2, 12, 1
+        2, 7, 12, 21, 30, 40, 2
+    "#;
+
+    #[test]
+    fn mot1() {
+        let records = parse_mot(class_id_to_name, MOT1).unwrap();
+        assert_eq!(records.len(), 3);
+        assert_eq!(
+            records[1],
+            Record {
+                frame: 1,
+                track_id: 8,
+                name: "class_1".to_string(),
+                left: 100.,
+                top: 120.,
+                width: 15.2,
+                height: 12.,
+            }
+        );
+
+        let annotations = to_annotations("D", |frame| format!("{:06}.jpg", frame), records);
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].filename, "000001.jpg");
+        assert_eq!(annotations[0].frame, Some(1));
+        assert_eq!(
+            annotations[0].objects.as_ref().unwrap()[0],
+            annotation::Object {
+                name: "class_1".to_string(),
+                bndbox: annotation::Bndbox {
+                    xmin: 100.,
+                    ymin: 120.,
+                    xmax: 100. + 15.2,
+                    ymax: 132.,
+                },
+                track_id: Some(8),
+                id: None,
+                mask: None,
+                attributes: Default::default(),
+            }
+        );
+        assert_eq!(annotations[1].objects.as_ref().unwrap().len(), 1);
+    }
+
+    const MOT_GT: &str = r#"
+        1, 1, 912, 484, 97, 109, 0, 7, 1
+        1, 2, 1338, 418, 167, 379, 1, 1, 1
+    "#;
+
+    #[test]
+    fn mot_gt() {
+        let records = parse_mot(class_id_to_name, MOT_GT).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].track_id, 2);
+        assert_eq!(records[0].name, "class_1");
+    }
+
+    #[test]
+    fn frame_numbers() {
+        assert_eq!(frame_number("000042.jpg"), Some(42));
+        assert_eq!(frame_number("7.png"), Some(7));
+        assert_eq!(frame_number("frame_7.png"), None);
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse_mot(class_id_to_name, "1, 7, 10.5, 20").is_err());
+    }
+}
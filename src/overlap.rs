@@ -0,0 +1,183 @@
+//! Overlap of the boxes of different labels in the same images, eg., for
+//! parasite-on-host annotations, and the selection of the objects inside
+//! boxes of a given label (`--contained-in`).
+
+use crate::annotation::{Annotation, Object};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Number of label pairs shown in the report.
+const MAX_PAIRS: usize = 10;
+
+/// Overlaps of the boxes of a pair of labels.
+#[derive(Debug, Default, PartialEq)]
+pub struct PairOverlap {
+    /// Pairs of overlapping boxes.
+    pub overlapping: usize,
+    pub iou_sum: f64,
+    pub max_iou: f64,
+    /// Boxes of the first label contained in a box of the second.
+    pub first_in_second: usize,
+    /// Boxes of the second label contained in a box of the first.
+    pub second_in_first: usize,
+}
+
+#[derive(serde::Serialize)]
+struct Row<'a> {
+    label_a: &'a str,
+    label_b: &'a str,
+    overlapping: usize,
+    mean_iou: String,
+    max_iou: String,
+    a_in_b: usize,
+    b_in_a: usize,
+}
+
+/// Overlaps by pair of labels (in order).
+pub struct Overlaps {
+    pub pairs: BTreeMap<(String, String), PairOverlap>,
+}
+
+impl Overlaps {
+    /// Boxes are contained in another when the given fraction of their area
+    /// is inside it.
+    pub fn new(annotations: &[Annotation], containment: f64) -> Overlaps {
+        let mut pairs: BTreeMap<(String, String), PairOverlap> = BTreeMap::new();
+        for objects in annotations.iter().filter_map(|a| a.objects.as_ref()) {
+            for (i, a) in objects.iter().enumerate() {
+                for b in &objects[i + 1..] {
+                    if a.name == b.name || a.bndbox.intersection(&b.bndbox).is_none() {
+                        continue;
+                    }
+                    let (a, b) = if a.name < b.name { (a, b) } else { (b, a) };
+                    let pair = pairs.entry((a.name.clone(), b.name.clone())).or_default();
+                    let iou = a.bndbox.iou(&b.bndbox);
+                    pair.overlapping += 1;
+                    pair.iou_sum += iou;
+                    pair.max_iou = pair.max_iou.max(iou);
+                    if a.bndbox.containment(&b.bndbox) >= containment {
+                        pair.first_in_second += 1;
+                    }
+                    if b.bndbox.containment(&a.bndbox) >= containment {
+                        pair.second_in_first += 1;
+                    }
+                }
+            }
+        }
+        Overlaps { pairs }
+    }
+
+    pub fn report(&self) {
+        if self.pairs.is_empty() {
+            return;
+        }
+        let mut pairs: Vec<_> = self.pairs.iter().collect();
+        pairs.sort_by_key(|(_, pair)| std::cmp::Reverse(pair.overlapping));
+        println!(
+            "\n  Overlapping boxes of different labels (top {} pairs):",
+            MAX_PAIRS
+        );
+        println!(
+            "    {:>7}{:>9}{:>9}{:>7}{:>7} labels (a + b)",
+            "pairs", "mean iou", "max iou", "a in b", "b in a"
+        );
+        for ((a, b), pair) in pairs.into_iter().take(MAX_PAIRS) {
+            println!(
+                "    {:>7}{:>9.3}{:>9.3}{:>7}{:>7} \"{}\" + \"{}\"",
+                pair.overlapping,
+                pair.iou_sum / pair.overlapping as f64,
+                pair.max_iou,
+                pair.first_in_second,
+                pair.second_in_first,
+                a,
+                b
+            );
+        }
+    }
+
+    pub fn save(&self, csv_filename: &Path) -> csv::Result<()> {
+        let mut wtr = csv::Writer::from_path(csv_filename)?;
+        for ((a, b), pair) in &self.pairs {
+            wtr.serialize(Row {
+                label_a: a,
+                label_b: b,
+                overlapping: pair.overlapping,
+                mean_iou: format!("{:.4}", pair.iou_sum / pair.overlapping as f64),
+                max_iou: format!("{:.4}", pair.max_iou),
+                a_in_b: pair.first_in_second,
+                b_in_a: pair.second_in_first,
+            })?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+/// Whether the object is contained in the box of another object with the
+/// given label.
+pub fn is_contained_in(object: &Object, objects: &[Object], host: &str, containment: f64) -> bool {
+    objects.iter().any(|other| {
+        other.name == host
+            && !std::ptr::eq(other, object)
+            && object.bndbox.containment(&other.bndbox) >= containment
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::Bndbox;
+
+    fn object(name: &str, xmin: f64, ymin: f64, xmax: f64, ymax: f64) -> Object {
+        Object {
+            name: name.to_string(),
+            bndbox: Bndbox {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn overlaps() {
+        let objects = || {
+            vec![
+            object("host", 0., 0., 100., 100.),
+            object("parasite", 10., 10., 20., 20.),
+            object("parasite", 90., 90., 110., 110.),
+            object("parasite", 200., 200., 210., 210.),
+            object("host", 50., 0., 150., 100.),
+            ]
+        };
+        let annotation = Annotation {
+            folder: "f".to_string(),
+            filename: "x.png".to_string(),
+            objects: Some(objects()),
+            frame: None,
+            image_size: None,
+        };
+        let overlaps = Overlaps::new(&[annotation], 1.);
+        assert_eq!(overlaps.pairs.len(), 1);
+        let pair = &overlaps.pairs[&("host".to_string(), "parasite".to_string())];
+        assert_eq!(pair.overlapping, 3);
+        assert_eq!(pair.first_in_second, 0);
+        assert_eq!(pair.second_in_first, 1);
+        assert_eq!(pair.max_iou, 200. / 10200.);
+
+        let objects = objects();
+        let contained: Vec<bool> = objects
+            .iter()
+            .map(|o| is_contained_in(o, &objects, "host", 1.))
+            .collect();
+        assert_eq!(contained, [false, true, false, false, false]);
+        assert!(is_contained_in(&objects[2], &objects, "host", 0.25));
+        // not in itself:
+        assert!(!is_contained_in(&objects[0], &objects, "host", 0.6));
+    }
+}
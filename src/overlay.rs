@@ -0,0 +1,76 @@
+//! Heuristic detection of burned-in overlay text (timestamps, HUD) in crops.
+//!
+//! Overlay text is rendered with sharp, high-contrast strokes, so a horizontal
+//! band of a crop containing it has a much higher density of strong horizontal
+//! gradients than natural (and especially underwater) imagery.
+
+use image::DynamicImage;
+
+/// Name of the subdirectory (under the output directory) for flagged crops.
+pub const FLAGGED_DIR: &str = "_flagged";
+
+/// Default minimum fraction of strong-edge pixels in a band to flag a crop.
+pub const DEFAULT_EDGE_DENSITY: f64 = 0.15;
+
+/// Minimum intensity difference between horizontally adjacent pixels for a strong edge.
+const STRONG_EDGE: i16 = 80;
+
+/// Height in pixels of the bands (about the height of a line of overlay text).
+const BAND_HEIGHT: u32 = 12;
+
+/// Whether the crop likely contains overlay text, that is, whether some
+/// horizontal band has at least the given density of strong edges.
+pub fn has_overlay_text(img: &DynamicImage, min_edge_density: f64) -> bool {
+    let gray = img.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 2 || height == 0 {
+        return false;
+    }
+    let row_edges: Vec<u32> = (0..height)
+        .map(|y| {
+            (0..width - 1)
+                .filter(|x| {
+                    let a = gray.get_pixel(*x, y)[0] as i16;
+                    let b = gray.get_pixel(x + 1, y)[0] as i16;
+                    (a - b).abs() >= STRONG_EDGE
+                })
+                .count() as u32
+        })
+        .collect();
+
+    let band_height = BAND_HEIGHT.min(height) as usize;
+    let band_pixels = (band_height * (width - 1) as usize) as f64;
+    let step = (band_height / 2).max(1);
+    (0..=row_edges.len() - band_height).step_by(step).any(|y| {
+        let edges: u32 = row_edges[y..y + band_height].iter().sum();
+        edges as f64 / band_pixels >= min_edge_density
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn detection() {
+        // smooth gradient, as in natural imagery:
+        let smooth = GrayImage::from_fn(120, 60, |x, y| Luma([(x + y) as u8]));
+        assert!(!has_overlay_text(&smooth.into(), DEFAULT_EDGE_DENSITY));
+
+        // same, with a line of glyph-like white strokes on black:
+        let text = GrayImage::from_fn(120, 60, |x, y| match y {
+            5..=15 if x % 4 == 0 => Luma([255]),
+            5..=15 => Luma([0]),
+            _ => Luma([(x + y) as u8]),
+        });
+        let text: DynamicImage = text.into();
+        assert!(has_overlay_text(&text, DEFAULT_EDGE_DENSITY));
+        assert!(!has_overlay_text(&text, 0.9));
+
+        assert!(!has_overlay_text(
+            &GrayImage::new(1, 1).into(),
+            DEFAULT_EDGE_DENSITY
+        ));
+    }
+}
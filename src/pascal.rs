@@ -1,29 +1,137 @@
 use crate::annotation;
+use crate::scan::ScanProgress;
+use crate::source::{AnnotationSource, Annotations, Issue};
+use imagesize::ImageSize;
 use serde::Deserialize;
 use serde_xml_rs::from_str;
 use serde_xml_rs::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use walkdir::WalkDir;
 
 pub fn parse_xml(src: &str) -> Result<PascalVoc, Error> {
     from_str(src)
 }
 
+/// Parses the XML, with the issues given to `on_issue`: the objects with
+/// coordinates that are not numbers are malformed (left out if accepted), and
+/// an unknown image size or objects without name are irregular.
+pub fn parse_xml_with(
+    src: &str,
+    mut on_issue: impl FnMut(Issue) -> bool,
+) -> Result<PascalVoc, String> {
+    let mut pascal_voc = parse_xml(src).map_err(|e| e.to_string())?;
+    let mut check = |issue: Issue| match on_issue(issue.clone()) {
+        true => Ok(()),
+        false => Err(issue.reason().to_string()),
+    };
+    if pascal_voc.size.width == 0 || pascal_voc.size.height == 0 {
+        check(Issue::Irregular("unknown image size".to_string()))?;
+    }
+    if let Some(objects) = &mut pascal_voc.objects {
+        let mut kept = Vec::new();
+        for object in objects.drain(..) {
+            if object.name.trim().is_empty() {
+                check(Issue::Irregular("object without name".to_string()))?;
+            }
+            let Bndbox {
+                xmin,
+                ymin,
+                xmax,
+                ymax,
+            } = &object.bndbox;
+            if [xmin, ymin, xmax, ymax].iter().all(|v| v.0.is_finite()) {
+                kept.push(object);
+            } else {
+                check(Issue::Malformed(format!(
+                    "invalid coordinates for {}",
+                    object.name
+                )))?;
+            }
+        }
+        *objects = kept;
+    }
+    Ok(pascal_voc)
+}
+
+/// The XML files under a base directory, with the images in the `folder`
+/// given in each file, relative to that directory.
+pub struct PascalSource<'a> {
+    pub dir: &'a Path,
+}
+
+impl AnnotationSource for PascalSource<'_> {
+    fn format(&self) -> &'static str {
+        "Pascal"
+    }
+
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String> {
+        println!("getting pascal annotations under {:?}", self.dir);
+        let base = self.dir.to_string_lossy();
+        let files = WalkDir::new(self.dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.path().is_file() && e.path().extension() == Some("xml".as_ref()));
+        Ok(Box::new(files.filter_map(move |entry| {
+            let path = entry.path();
+            scan.seen();
+            let parsed = read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|src| parse_xml_with(&src, |issue| scan.issue(path, issue)));
+            match parsed {
+                Ok(pascal_voc) => {
+                    scan.parsed();
+                    let mut annotation: annotation::Annotation = pascal_voc.into();
+                    annotation.folder = format!("{}/{}", base, annotation.folder);
+                    Some(annotation)
+                }
+                Err(e) => {
+                    scan.invalid(path, e);
+                    None
+                }
+            }
+        })))
+    }
+}
+
 impl From<PascalVoc> for annotation::Annotation {
     fn from(pascal_voc: PascalVoc) -> Self {
         let folder = pascal_voc.folder;
         let filename = pascal_voc.filename;
+        let Size { width, height, .. } = pascal_voc.size;
+        // some tools write 0 when the size is unknown:
+        let image_size = (width > 0 && height > 0).then_some(ImageSize { width, height });
 
         let objects = match pascal_voc.objects {
             Some(objects) => {
                 let mut objects: Vec<annotation::Object> = objects
                     .into_iter()
-                    .map(|object| annotation::Object {
-                        name: object.name,
-                        bndbox: annotation::Bndbox {
-                            xmin: object.bndbox.xmin.0,
-                            ymin: object.bndbox.ymin.0,
-                            xmax: object.bndbox.xmax.0,
-                            ymax: object.bndbox.ymax.0,
-                        },
+                    .map(|object| {
+                        // flags, if given, are kept as "true"/"false" attributes:
+                        let flags = [
+                            (annotation::TRUNCATED, object.truncated),
+                            (annotation::OCCLUDED, object.occluded),
+                            (annotation::DIFFICULT, object.difficult),
+                        ];
+                        annotation::Object {
+                            name: object.name,
+                            bndbox: annotation::Bndbox {
+                                xmin: object.bndbox.xmin.0,
+                                ymin: object.bndbox.ymin.0,
+                                xmax: object.bndbox.xmax.0,
+                                ymax: object.bndbox.ymax.0,
+                            },
+                            track_id: None,
+                            id: None,
+                            mask: None,
+                            attributes: flags
+                                .into_iter()
+                                .filter_map(|(key, flag)| {
+                                    flag.map(|Flag(f)| (key.to_string(), f.to_string()))
+                                })
+                                .chain(object.pose.map(|pose| (annotation::POSE.to_string(), pose)))
+                                .collect(),
+                        }
                     })
                     .collect();
                 objects.sort_by(|a, b| a.name.cmp(&b.name));
@@ -36,11 +144,13 @@ impl From<PascalVoc> for annotation::Annotation {
             folder,
             filename,
             objects,
+            frame: None,
+            image_size,
         }
     }
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct PascalVoc {
     pub folder: String,
     pub filename: String,
@@ -51,18 +161,22 @@ pub struct PascalVoc {
 
 #[derive(Debug, Deserialize, PartialEq, Eq)]
 pub struct Size {
-    pub width: String,
-    pub height: String,
+    pub width: usize,
+    pub height: usize,
     pub depth: String,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct Object {
     pub name: String,
+    pub pose: Option<String>,
+    pub truncated: Option<Flag>,
+    pub occluded: Option<Flag>,
+    pub difficult: Option<Flag>,
     pub bndbox: Bndbox,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Eq)]
+#[derive(Debug, Deserialize, PartialEq)]
 pub struct Bndbox {
     pub xmin: CoordVal,
     pub ymin: CoordVal,
@@ -70,20 +184,32 @@ pub struct Bndbox {
     pub ymax: CoordVal,
 }
 
-/// Bndbox members can be integers or floats, but we always parse them as u32
-#[derive(Debug, serde_with::DeserializeFromStr, PartialEq, Eq)]
-pub struct CoordVal(pub u32);
+/// Bndbox members can be integers or floats, kept as f64 (NaN if not a
+/// number, left to `parse_xml_with`).
+#[derive(Debug, serde_with::DeserializeFromStr, PartialEq)]
+pub struct CoordVal(pub f64);
 
 impl std::str::FromStr for CoordVal {
     type Err = std::convert::Infallible;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let i = if let Ok(v) = s.parse::<u32>() {
-            v
-        } else {
-            s.parse::<f32>().unwrap() as u32
-        };
-        Ok(CoordVal(i))
+        Ok(CoordVal(s.trim().parse::<f64>().unwrap_or(f64::NAN)))
+    }
+}
+
+/// Object flags are 0 or 1 (also accepting false and true).
+#[derive(Debug, serde_with::DeserializeFromStr, PartialEq, Eq, Clone, Copy)]
+pub struct Flag(pub bool);
+
+impl std::str::FromStr for Flag {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "1" | "true" => Ok(Flag(true)),
+            "0" | "false" | "" => Ok(Flag(false)),
+            _ => Err(format!("invalid flag '{}'", s)),
+        }
     }
 }
 
@@ -191,27 +317,35 @@ mod tests {
             folder: "imgs".to_string(),
             filename: "IMG_TEST.png".to_string(),
             size: Size {
-                width: "400".to_string(),
-                height: "300".to_string(),
+                width: 400,
+                height: 300,
                 depth: "3".to_string(),
             },
             objects: Some(vec![
                 Object {
                     name: "FOO".to_string(),
+                    pose: None,
+                    truncated: None,
+                    occluded: None,
+                    difficult: None,
                     bndbox: Bndbox {
-                        xmin: CoordVal(55),
-                        ymin: CoordVal(145),
-                        xmax: CoordVal(150),
-                        ymax: CoordVal(220),
+                        xmin: CoordVal(55.),
+                        ymin: CoordVal(145.),
+                        xmax: CoordVal(150.),
+                        ymax: CoordVal(220.),
                     },
                 },
                 Object {
                     name: "PENIAGONE_VITREA".to_string(),
+                    pose: Some("Unspecified".to_string()),
+                    truncated: Some(Flag(false)),
+                    occluded: Some(Flag(false)),
+                    difficult: Some(Flag(false)),
                     bndbox: Bndbox {
-                        xmin: CoordVal(55),
-                        ymin: CoordVal(145),
-                        xmax: CoordVal(150),
-                        ymax: CoordVal(220),
+                        xmin: CoordVal(55.),
+                        ymin: CoordVal(145.),
+                        xmax: CoordVal(150.),
+                        ymax: CoordVal(220.1),
                     },
                 },
             ]),
@@ -236,17 +370,21 @@ mod tests {
                 folder: "imgs".to_string(),
                 filename: "IMG_TEST.png".to_string(),
                 size: Size {
-                    width: "400".to_string(),
-                    height: "300".to_string(),
+                    width: 400,
+                    height: 300,
                     depth: "3".to_string(),
                 },
                 objects: Some(vec![Object {
                     name: "FOO".to_string(),
+                    pose: None,
+                    truncated: None,
+                    occluded: None,
+                    difficult: None,
                     bndbox: Bndbox {
-                        xmin: CoordVal(55),
-                        ymin: CoordVal(145),
-                        xmax: CoordVal(150),
-                        ymax: CoordVal(220),
+                        xmin: CoordVal(55.),
+                        ymin: CoordVal(145.),
+                        xmax: CoordVal(150.),
+                        ymax: CoordVal(220.),
                     },
                 },]),
             }
@@ -273,17 +411,21 @@ mod tests {
                 folder: "imgs".to_string(),
                 filename: "IMG_TEST.png".to_string(),
                 size: Size {
-                    width: "400".to_string(),
-                    height: "300".to_string(),
+                    width: 400,
+                    height: 300,
                     depth: "3".to_string(),
                 },
                 objects: Some(vec![Object {
                     name: "PENIAGONE_VITREA".to_string(),
+                    pose: Some("Unspecified".to_string()),
+                    truncated: Some(Flag(false)),
+                    occluded: Some(Flag(false)),
+                    difficult: Some(Flag(false)),
                     bndbox: Bndbox {
-                        xmin: CoordVal(55),
-                        ymin: CoordVal(145),
-                        xmax: CoordVal(150),
-                        ymax: CoordVal(220),
+                        xmin: CoordVal(55.),
+                        ymin: CoordVal(145.),
+                        xmax: CoordVal(150.),
+                        ymax: CoordVal(220.1),
                     },
                 },]),
             }
@@ -301,4 +443,64 @@ mod tests {
         };
         assert_eq!(pascal_voc, expected_pascal_voc2());
     }
+
+    #[test]
+    fn image_size() {
+        let annotation: annotation::Annotation = parse_xml(XML0).unwrap().into();
+        assert_eq!(
+            annotation.image_size,
+            Some(ImageSize {
+                width: 400,
+                height: 300
+            })
+        );
+        let unknown = XML0.replace("<width>400</width>", "<width>0</width>");
+        let annotation: annotation::Annotation = parse_xml(&unknown).unwrap().into();
+        assert_eq!(annotation.image_size, None);
+        let invalid = XML0.replace("<width>400</width>", "<width>wide</width>");
+        assert!(parse_xml(&invalid).is_err());
+    }
+
+    #[test]
+    fn flags_as_attributes() {
+        let annotation: annotation::Annotation = parse_xml(XML2).unwrap().into();
+        assert_eq!(
+            annotation.image_size,
+            Some(ImageSize {
+                width: 400,
+                height: 300
+            })
+        );
+        let objects = annotation.objects.unwrap();
+        assert!(objects[0].attributes.is_empty());
+        assert!(!objects[1].flag(annotation::DIFFICULT));
+        assert_eq!(objects[1].attributes.len(), 4);
+        assert_eq!(objects[1].pose(), "Unspecified");
+
+        let xml = XML2.replace("<difficult>0</difficult>", "<difficult>1</difficult>");
+        let annotation: annotation::Annotation = parse_xml(&xml).unwrap().into();
+        assert!(annotation.objects.unwrap()[1].flag(annotation::DIFFICULT));
+    }
+
+    #[test]
+    fn issues() {
+        use crate::source::Strictness;
+        let parse = |xml: &str, strictness: Strictness| {
+            parse_xml_with(xml, |issue| strictness.accepts(&issue))
+        };
+        let xml = XML2.replace("<xmin>55</xmin>", "<xmin>5x</xmin>");
+        assert_eq!(
+            parse(&xml, Strictness::Normal).unwrap_err(),
+            "invalid coordinates for FOO"
+        );
+        let pascal_voc = parse(&xml, Strictness::Lenient).unwrap();
+        assert_eq!(pascal_voc.objects.unwrap().len(), 1);
+
+        let xml = XML2.replace("<width>400</width>", "<width>0</width>");
+        assert!(parse(&xml, Strictness::Normal).is_ok());
+        assert_eq!(
+            parse(&xml, Strictness::Strict).unwrap_err(),
+            "unknown image size"
+        );
+    }
 }
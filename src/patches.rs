@@ -0,0 +1,127 @@
+//! Unlabeled patches of the images (`blaise patches`), ignoring any annotations,
+//! eg., for self-supervised pretraining: square patches at random positions or
+//! on a grid, written to a flat directory with a manifest.
+
+use crate::checksum;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PatchStrategy {
+    /// At random positions (reproducible with --seed), possibly overlapping
+    Random,
+    /// On a grid of non-overlapping patches, centered, evenly spread if more
+    /// than needed
+    Grid,
+}
+
+/// The patches to extract from each image.
+pub struct Patching {
+    /// Side, in pixels.
+    pub size: u32,
+    pub per_image: usize,
+    pub strategy: PatchStrategy,
+    pub seed: u64,
+}
+
+impl Patching {
+    /// Top-left corners of the patches in an image; none if the image is smaller
+    /// than a patch. `key` (eg., the image path) makes the random positions differ
+    /// by image for the same seed.
+    pub fn positions(&self, width: u32, height: u32, key: &str) -> Vec<(u32, u32)> {
+        positions(width, height, self.size, self.per_image, self.strategy, self.seed, key)
+    }
+}
+
+fn positions(
+    width: u32,
+    height: u32,
+    size: u32,
+    count: usize,
+    strategy: PatchStrategy,
+    seed: u64,
+    key: &str,
+) -> Vec<(u32, u32)> {
+    if size == 0 || width < size || height < size {
+        return Vec::new();
+    }
+    match strategy {
+        PatchStrategy::Random => {
+            let hash = checksum::sha256(key.as_bytes());
+            let mut rng = SplitMix64(seed ^ u64::from_be_bytes(hash[..8].try_into().unwrap()));
+            let mut random = |max: u32| (rng.next() % (max as u64 + 1)) as u32;
+            (0..count)
+                .map(|_| (random(width - size), random(height - size)))
+                .collect()
+        }
+        PatchStrategy::Grid => {
+            let (cols, rows) = (width / size, height / size);
+            let (x0, y0) = ((width - cols * size) / 2, (height - rows * size) / 2);
+            let total = (cols * rows) as usize;
+            let count = count.min(total);
+            (0..count)
+                .map(|k| k * total / count)
+                .map(|i| {
+                    let (col, row) = (i as u32 % cols, i as u32 / cols);
+                    (x0 + col * size, y0 + row * size)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Name of the patch of the image (by its path under the scanned directory),
+/// for a flat directory: eg., `D1/a.png` -> `D1_a_p3.png`.
+pub fn patch_filename(relative_path: &str, index: usize) -> String {
+    let stem = match relative_path.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => relative_path,
+    };
+    format!("{}_p{}.png", stem.replace(['/', '\\'], "_"), index)
+}
+
+/// The SplitMix64 generator, for reproducible positions.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid() {
+        let grid = |w, h, count| positions(w, h, 100, count, PatchStrategy::Grid, 0, "");
+        assert_eq!(grid(320, 210, 16), [(10, 5), (110, 5), (210, 5), (10, 105), (110, 105), (210, 105)]);
+        assert_eq!(grid(320, 210, 2), [(10, 5), (10, 105)]);
+        assert_eq!(grid(100, 100, 4), [(0, 0)]);
+        assert!(grid(99, 300, 4).is_empty());
+    }
+
+    #[test]
+    fn random() {
+        let random = |seed, key| positions(640, 480, 224, 16, PatchStrategy::Random, seed, key);
+        let patches = random(1, "a.png");
+        assert_eq!(patches.len(), 16);
+        assert!(patches.iter().all(|&(x, y)| x <= 640 - 224 && y <= 480 - 224));
+        assert_eq!(patches, random(1, "a.png"));
+        assert_ne!(patches, random(2, "a.png"));
+        assert_ne!(patches, random(1, "b.png"));
+        // the whole image:
+        let whole = positions(224, 224, 224, 3, PatchStrategy::Random, 1, "a.png");
+        assert_eq!(whole, [(0, 0); 3]);
+    }
+
+    #[test]
+    fn filenames() {
+        assert_eq!(patch_filename("D1/a.png", 3), "D1_a_p3.png");
+        assert_eq!(patch_filename("b.tif", 0), "b_p0.png");
+        assert_eq!(patch_filename("noext", 1), "noext_p1.png");
+    }
+}
@@ -0,0 +1,311 @@
+//! Region proposals (`--proposals <csv-file>`), eg., from a saliency detector:
+//! class-agnostic boxes, cropped instead of the annotated objects for weakly
+//! labeled (pretraining) sets:
+//!
+//! ```csv
+//! image,xmin,ymin,xmax,ymax,score
+//! D1234/img_0001.png,120,340,180,400,0.92
+//! ```
+//!
+//! The image is given by its path, relative to the image directory, or by the
+//! end of the path of an annotated image; the score is optional. The proposals
+//! are labeled with `--proposal-label`, or with the label of the annotated
+//! object they overlap most.
+
+use crate::annotation::{Annotation, Bndbox, Object};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct Proposal {
+    pub image: String,
+    pub xmin: f64,
+    pub ymin: f64,
+    pub xmax: f64,
+    pub ymax: f64,
+    pub score: Option<f64>,
+}
+
+impl Proposal {
+    fn bndbox(&self) -> Bndbox {
+        Bndbox {
+            xmin: self.xmin,
+            ymin: self.ymin,
+            xmax: self.xmax,
+            ymax: self.ymax,
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Proposal>, String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    parse(&src).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+pub fn parse(src: &str) -> Result<Vec<Proposal>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(src.as_bytes());
+    reader
+        .deserialize()
+        .map(|record| record.map_err(|e| e.to_string()))
+        .collect()
+}
+
+/// The label of the object the box overlaps most, with an IoU of at least `min_iou`.
+pub fn nearest_label<'a>(bndbox: &Bndbox, objects: &'a [Object], min_iou: f64) -> Option<&'a str> {
+    objects
+        .iter()
+        .map(|object| (object, object.bndbox.iou(bndbox)))
+        .filter(|(_, iou)| *iou > 0. && *iou >= min_iou)
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(object, _)| object.name.as_str())
+}
+
+/// How the proposals were labeled.
+#[derive(Debug, Default, PartialEq)]
+pub struct Labeling {
+    /// By the annotated object they overlap most.
+    pub by_overlap: usize,
+    /// With the given label.
+    pub by_default: usize,
+    /// Without a label, left out.
+    pub skipped: usize,
+    /// Images of the proposals not among the annotated ones.
+    pub images_not_annotated: usize,
+}
+
+impl Labeling {
+    pub fn report(&self) {
+        println!(
+            "proposals: {} labeled by the annotated objects, {} with the proposal label, \
+             {} skipped without a label",
+            self.by_overlap, self.by_default, self.skipped
+        );
+        if self.images_not_annotated > 0 {
+            println!(
+                "   {} images with proposals but not annotated",
+                self.images_not_annotated
+            );
+        }
+    }
+}
+
+/// The annotations with the proposals as objects, one per image. The images are
+/// those of the annotated ones with their path ending with that of the proposals,
+/// else in `folder`. With annotations, the proposals take the label of the object
+/// they overlap most, if any, else `label`.
+pub fn label(
+    proposals: Vec<Proposal>,
+    folder: &str,
+    annotated: &[(String, &Annotation)],
+    label: Option<&str>,
+    min_iou: f64,
+) -> (Vec<Annotation>, Labeling) {
+    let mut by_filename: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, (path, _)) in annotated.iter().enumerate() {
+        let filename = path.rsplit(['/', '\\']).next().unwrap_or(path);
+        by_filename.entry(filename).or_default().push(i);
+    }
+    let find = |image: &str| {
+        let image = image.trim_start_matches("./");
+        let filename = image.rsplit(['/', '\\']).next().unwrap_or(image);
+        let candidates = by_filename.get(filename)?;
+        candidates.iter().copied().find(|&i| {
+            let path = &annotated[i].0;
+            path == image
+                || path
+                    .strip_suffix(image)
+                    .is_some_and(|rest| rest.ends_with(['/', '\\']))
+        })
+    };
+
+    let mut labeling = Labeling::default();
+    let mut found_images: HashMap<String, Option<usize>> = HashMap::new();
+    // by the path of the annotated image, else as given:
+    let mut by_image: BTreeMap<String, (Option<usize>, Vec<Object>)> = BTreeMap::new();
+    for proposal in proposals {
+        let found = *found_images
+            .entry(proposal.image.clone())
+            .or_insert_with(|| find(&proposal.image));
+        let key = match found {
+            Some(i) => annotated[i].0.clone(),
+            None => proposal.image.clone(),
+        };
+        let (found, objects) = by_image.entry(key).or_insert((found, Vec::new()));
+        let bndbox = proposal.bndbox();
+        let annotated_objects = match found {
+            Some(i) => annotated[*i].1.objects.as_deref().unwrap_or_default(),
+            None => &[],
+        };
+        let name = match nearest_label(&bndbox, annotated_objects, min_iou) {
+            Some(name) => {
+                labeling.by_overlap += 1;
+                name
+            }
+            None => match label {
+                Some(label) => {
+                    labeling.by_default += 1;
+                    label
+                }
+                None => {
+                    labeling.skipped += 1;
+                    continue;
+                }
+            },
+        };
+        objects.push(Object {
+            name: name.to_string(),
+            bndbox,
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: proposal
+                .score
+                .map(|score| ("score".to_string(), score.to_string()))
+                .into_iter()
+                .collect(),
+        });
+    }
+
+    let mut annotations = Vec::new();
+    for (image, (found, objects)) in by_image {
+        if found.is_none() && !annotated.is_empty() {
+            labeling.images_not_annotated += 1;
+        }
+        if objects.is_empty() {
+            continue;
+        }
+        annotations.push(match found {
+            Some(i) => {
+                let annotation = annotated[i].1;
+                Annotation {
+                    folder: annotation.folder.clone(),
+                    filename: annotation.filename.clone(),
+                    objects: Some(objects),
+                    frame: annotation.frame,
+                    image_size: annotation.image_size,
+                }
+            }
+            None => {
+                let (dir, filename) = match image.rsplit_once(['/', '\\']) {
+                    Some((dir, filename)) => (format!("{}/{}", folder, dir), filename),
+                    None => (folder.to_string(), image.as_str()),
+                };
+                Annotation {
+                    folder: dir,
+                    filename: filename.to_string(),
+                    objects: Some(objects),
+                    frame: None,
+                    image_size: None,
+                }
+            }
+        });
+    }
+    (annotations, labeling)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CSV: &str = "\
+image, xmin, ymin, xmax, ymax, score
+D1/a.png, 10, 10, 50, 50, 0.9
+D1/a.png, 100, 100, 120, 120,
+a.png, 12, 12, 50, 52, 0.5
+D2/b.png, 0, 0, 10, 10, 0.7
+";
+
+    fn annotated(name: &str, xmin: f64) -> Annotation {
+        Annotation {
+            folder: "imgs/D1".to_string(),
+            filename: "a.png".to_string(),
+            objects: Some(vec![Object {
+                name: name.to_string(),
+                bndbox: Bndbox {
+                    xmin,
+                    ymin: 10.,
+                    xmax: xmin + 40.,
+                    ymax: 50.,
+                },
+                track_id: None,
+                id: None,
+                mask: None,
+                attributes: Default::default(),
+            }]),
+            frame: None,
+            image_size: None,
+        }
+    }
+
+    #[test]
+    fn parse_proposals() {
+        let proposals = parse(CSV).unwrap();
+        assert_eq!(proposals.len(), 4);
+        assert_eq!(proposals[0].image, "D1/a.png");
+        assert_eq!(proposals[0].score, Some(0.9));
+        assert_eq!(proposals[1].score, None);
+        assert!(parse("image,xmin,ymin\nD1/a.png,1,2\n").is_err());
+    }
+
+    #[test]
+    fn labels() {
+        // without annotations:
+        let (annotations, labeling) = label(parse(CSV).unwrap(), "data", &[], Some("object"), 0.5);
+        assert_eq!(annotations.len(), 3);
+        assert_eq!(annotations[0].folder, "data/D1");
+        assert_eq!(annotations[0].filename, "a.png");
+        assert_eq!(annotations[2].folder, "data");
+        let objects = annotations[0].objects.as_ref().unwrap();
+        assert_eq!(objects[0].name, "object");
+        assert_eq!(objects[0].attributes["score"], "0.9");
+        assert!(objects[1].attributes.is_empty());
+        assert_eq!(labeling.by_default, 4);
+
+        // with annotations:
+        let fish = annotated("Fish", 10.);
+        let paths = [("imgs/D1/a.png".to_string(), &fish)];
+        let (annotations, labeling) = label(parse(CSV).unwrap(), "data", &paths, None, 0.5);
+        assert_eq!(
+            labeling,
+            Labeling {
+                by_overlap: 2,
+                by_default: 0,
+                skipped: 2,
+                images_not_annotated: 1,
+            }
+        );
+        // D1/a.png and a.png, both the annotated image:
+        assert_eq!(annotations.len(), 1);
+        assert_eq!(annotations[0].folder, "imgs/D1");
+        let names: Vec<&str> = annotations[0]
+            .objects
+            .iter()
+            .flatten()
+            .map(|o| o.name.as_str())
+            .collect();
+        assert_eq!(names, ["Fish", "Fish"]);
+
+        let (_, labeling) = label(parse(CSV).unwrap(), "data", &paths, Some("object"), 0.5);
+        assert_eq!((labeling.by_overlap, labeling.by_default), (2, 2));
+    }
+
+    #[test]
+    fn nearest() {
+        let objects = [annotated("A", 10.), annotated("B", 30.)]
+            .map(|a| a.objects.unwrap().remove(0));
+        let bndbox = |xmin: f64| Bndbox {
+            xmin,
+            ymin: 10.,
+            xmax: xmin + 40.,
+            ymax: 50.,
+        };
+        assert_eq!(nearest_label(&bndbox(12.), &objects, 0.5), Some("A"));
+        assert_eq!(nearest_label(&bndbox(28.), &objects, 0.5), Some("B"));
+        assert_eq!(nearest_label(&bndbox(200.), &objects, 0.), None);
+        assert_eq!(nearest_label(&bndbox(45.), &objects, 0.5), None);
+        assert_eq!(nearest_label(&bndbox(45.), &objects, 0.1), Some("B"));
+    }
+}
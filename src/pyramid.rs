@@ -0,0 +1,148 @@
+//! Pyramid sink (`--sink pyramid:<dir>`): each crop, as written to the output
+//! directory, plus JPEG thumbnails of it at a few sizes, for the web image
+//! browsers, without a separate thumbnailing pass over the crops:
+//!
+//! ```text
+//! <dir>/full/<name>          the crop
+//! <dir>/64/<name>.jpg        at most 64 pixels on the longest side
+//! <dir>/256/<name>.jpg
+//! <dir>/index.jsonl          a line per crop, with its label, size and files
+//! ```
+//!
+//! where `<name>` is the path of the crop relative to the output directory
+//! (the thumbnails with the `.jpg` extension instead). Crops no larger than a
+//! level are not upscaled, only converted to JPEG.
+
+use crate::sink::{Crop, CropSink};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageOutputFormat};
+use serde_json::json;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Sizes of the thumbnails if not given.
+pub const DEFAULT_SIZES: [u32; 2] = [64, 256];
+
+const JPEG_QUALITY: u8 = 85;
+
+pub struct PyramidSink {
+    dir: PathBuf,
+    sizes: Vec<u32>,
+    index: Mutex<(BufWriter<File>, usize)>,
+}
+
+impl PyramidSink {
+    pub fn create(dir: &Path, sizes: &[u32]) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let index = BufWriter::new(File::create(dir.join("index.jsonl"))?);
+        let mut sizes = sizes.to_vec();
+        sizes.sort_unstable();
+        sizes.dedup();
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            sizes,
+            index: Mutex::new((index, 0)),
+        })
+    }
+
+    fn write(&self, name: &Path, bytes: &[u8]) -> io::Result<()> {
+        let path = self.dir.join(name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, bytes)
+    }
+}
+
+/// The crop with at most `size` pixels on its longest side, as JPEG.
+fn thumbnail(image: &DynamicImage, size: u32) -> io::Result<Vec<u8>> {
+    let rgb = match image.width().max(image.height()) > size {
+        true => image.resize(size, size, FilterType::Triangle).to_rgb8(),
+        false => image.to_rgb8(),
+    };
+    let mut encoded = Cursor::new(Vec::new());
+    DynamicImage::ImageRgb8(rgb)
+        .write_to(&mut encoded, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+        .map_err(io::Error::other)?;
+    Ok(encoded.into_inner())
+}
+
+impl CropSink for PyramidSink {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        let full = Path::new("full").join(crop.name);
+        self.write(&full, crop.bytes)?;
+        let mut levels = serde_json::Map::new();
+        for size in &self.sizes {
+            let name = Path::new(&size.to_string()).join(crop.name.with_extension("jpg"));
+            self.write(&name, &thumbnail(crop.image, *size)?)?;
+            levels.insert(size.to_string(), name.to_string_lossy().into());
+        }
+        let line = json!({
+            "name": crop.name.to_string_lossy(),
+            "label": crop.label,
+            "source": crop.source,
+            "width": crop.image.width(),
+            "height": crop.image.height(),
+            "full": full.to_string_lossy(),
+            "levels": levels,
+        });
+        let mut index = self.index.lock().unwrap();
+        writeln!(index.0, "{}", line)?;
+        index.1 += 1;
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut index = self.index.lock().unwrap();
+        index.0.flush()?;
+        println!(
+            "Wrote {} crops with thumbnails of {:?} pixels to {:?}",
+            index.1, self.sizes, self.dir
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::PixelRect;
+    use crate::manifest::CropKind;
+
+    #[test]
+    fn levels() {
+        let dir = std::env::temp_dir().join(format!("blaise-pyramid-{}", std::process::id()));
+        let sink = PyramidSink::create(&dir, &[256, 64, 64]).unwrap();
+        let image = DynamicImage::new_rgba8(200, 100);
+        let bndbox = PixelRect {
+            xmin: 0,
+            ymin: 0,
+            xmax: 200,
+            ymax: 100,
+        };
+        sink.add(&Crop {
+            kind: CropKind::Object,
+            image: &image,
+            bytes: b"png",
+            path: &dir.join("out/Aegina/a_0.png"),
+            name: Path::new("Aegina/a_0.png"),
+            label: "Aegina",
+            source: "imgs/a.png",
+            bndbox: &bndbox,
+        })
+        .unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(fs::read(dir.join("full/Aegina/a_0.png")).unwrap(), b"png");
+        let size = |path: &str| image::image_dimensions(dir.join(path)).unwrap();
+        assert_eq!(size("64/Aegina/a_0.jpg"), (64, 32));
+        assert_eq!(size("256/Aegina/a_0.jpg"), (200, 100));
+        let index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(dir.join("index.jsonl")).unwrap()).unwrap();
+        assert_eq!(index["levels"]["64"], "64/Aegina/a_0.jpg");
+        assert_eq!(index["width"], 200);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
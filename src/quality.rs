@@ -0,0 +1,126 @@
+//! Image quality measures for filtering out blurry or badly exposed crops.
+
+use image::DynamicImage;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Sharpness as the variance of the Laplacian of the grayscale image.
+pub fn sharpness(img: &DynamicImage) -> f64 {
+    let gray = img.to_luma8();
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.;
+    }
+    let p = |x: u32, y: u32| gray.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.;
+    let mut sum_sq = 0.;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian = p(x - 1, y) + p(x + 1, y) + p(x, y - 1) + p(x, y + 1) - 4. * p(x, y);
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+        }
+    }
+    let n = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / n;
+    sum_sq / n - mean * mean
+}
+
+/// Mean grayscale intensity, in [0, 255].
+pub fn mean_brightness(img: &DynamicImage) -> f64 {
+    let gray = img.to_luma8();
+    let n = gray.pixels().len();
+    if n == 0 {
+        return 0.;
+    }
+    gray.pixels().map(|p| p[0] as f64).sum::<f64>() / n as f64
+}
+
+/// Parses a `lo,hi` brightness range.
+pub fn parse_exposure_range(s: &str) -> Result<(f64, f64), String> {
+    s.split_once(',')
+        .and_then(|(lo, hi)| {
+            Some((
+                lo.trim().parse::<f64>().ok()?,
+                hi.trim().parse::<f64>().ok()?,
+            ))
+        })
+        .filter(|(lo, hi)| 0. <= *lo && lo < hi && *hi <= 255.)
+        .ok_or_else(|| "expected lo,hi with 0 <= lo < hi <= 255".to_string())
+}
+
+/// Crops skipped for their quality, shared by the processing threads.
+#[derive(Default)]
+pub struct QualityFilter {
+    min_sharpness: Option<f64>,
+    exposure_range: Option<(f64, f64)>,
+    blurry: AtomicUsize,
+    badly_exposed: AtomicUsize,
+}
+
+impl QualityFilter {
+    pub fn new(min_sharpness: Option<f64>, exposure_range: Option<(f64, f64)>) -> QualityFilter {
+        QualityFilter {
+            min_sharpness,
+            exposure_range,
+            ..Default::default()
+        }
+    }
+
+    /// Whether the crop passes the quality thresholds, counting it otherwise.
+    pub fn accept(&self, img: &DynamicImage) -> bool {
+        if let Some(min_sharpness) = self.min_sharpness {
+            if sharpness(img) < min_sharpness {
+                self.blurry.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+        if let Some((lo, hi)) = self.exposure_range {
+            let brightness = mean_brightness(img);
+            if brightness < lo || brightness > hi {
+                self.badly_exposed.fetch_add(1, Ordering::Relaxed);
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn report(&self) {
+        if self.min_sharpness.is_some() || self.exposure_range.is_some() {
+            println!(
+                "Skipped crops: {} blurry, {} badly exposed",
+                self.blurry.load(Ordering::Relaxed),
+                self.badly_exposed.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn measures() {
+        let flat: DynamicImage = GrayImage::from_pixel(20, 20, Luma([100])).into();
+        let checkers: DynamicImage = GrayImage::from_fn(20, 20, |x, y| {
+            Luma([if (x + y) % 2 == 0 { 50 } else { 150 }])
+        })
+        .into();
+        assert_eq!(sharpness(&flat), 0.);
+        assert!(sharpness(&checkers) > 1000.);
+        assert_eq!(mean_brightness(&flat), 100.);
+
+        let filter = QualityFilter::new(Some(10.), Some((60., 200.)));
+        assert!(!filter.accept(&flat));
+        assert!(filter.accept(&checkers));
+        let dark: DynamicImage =
+            GrayImage::from_fn(20, 20, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 80 }])).into();
+        assert!(!filter.accept(&dark));
+        assert_eq!(filter.blurry.load(Ordering::Relaxed), 1);
+        assert_eq!(filter.badly_exposed.load(Ordering::Relaxed), 1);
+
+        assert_eq!(parse_exposure_range("20, 230"), Ok((20., 230.)));
+        assert!(parse_exposure_range("230,20").is_err());
+    }
+}
@@ -0,0 +1,589 @@
+//! Remote output directories, so the crops can go straight to object storage:
+//! `s3://bucket/prefix`, or `webdav://host/path` (`webdavs://` for https).
+//! Transfers go through `curl`, with retries on transient errors: one request
+//! per crop, as the crops are small, and no concurrent uploads (the writes are
+//! already spread over the worker threads). The larger outputs, as archives of
+//! the crops (`--sink`), go to S3 in parts of 64 MiB (a single upload being
+//! limited to 5 GB).
+//!
+//! S3 requests are signed (AWS signature v4) with the credentials in
+//! `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and optionally
+//! `AWS_SESSION_TOKEN`, in `AWS_REGION` (default us-east-1). For S3-compatible
+//! stores (eg., MinIO), `AWS_ENDPOINT_URL` gives the endpoint, with path-style
+//! addressing. WebDAV credentials are taken from `~/.netrc`, if any. The
+//! credentials are given to curl in a config on its standard input, to keep
+//! them out of its command line (and `ps`).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Size of the parts of the multipart uploads, for the files larger than that.
+const PART_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Maximum number of parts of an S3 upload.
+const MAX_PARTS: u64 = 10_000;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Remote {
+    S3 {
+        /// The output directory as given.
+        root: String,
+        /// URL of the prefix, to which the keys are appended.
+        base: String,
+        region: String,
+        user: String,
+        session_token: Option<String>,
+    },
+    WebDav {
+        root: String,
+        base: String,
+    },
+}
+
+impl Remote {
+    /// The remote for the output directory, or None if it is local.
+    pub fn parse(dir: &str) -> Result<Option<Remote>, String> {
+        Self::parse_with(dir, |name| std::env::var(name).ok())
+    }
+
+    /// As `parse`, with the configuration (`AWS_*` variables) from `var`.
+    pub fn parse_with(
+        dir: &str,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<Option<Remote>, String> {
+        let env = |name: &str| var(name).filter(|v| !v.is_empty());
+        let dir = dir.trim_end_matches('/');
+        if let Some(rest) = dir.strip_prefix("s3://") {
+            let (bucket, prefix) = rest.split_once('/').unwrap_or((rest, ""));
+            if bucket.is_empty() {
+                return Err(format!("missing bucket in {}", dir));
+            }
+            let region = env("AWS_REGION")
+                .or_else(|| env("AWS_DEFAULT_REGION"))
+                .unwrap_or_else(|| "us-east-1".to_string());
+            let (Some(key_id), Some(secret)) =
+                (env("AWS_ACCESS_KEY_ID"), env("AWS_SECRET_ACCESS_KEY"))
+            else {
+                return Err(
+                    "AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY are required for s3 output"
+                        .to_string(),
+                );
+            };
+            let bucket_url = match env("AWS_ENDPOINT_URL") {
+                Some(endpoint) => format!("{}/{}", endpoint.trim_end_matches('/'), bucket),
+                None => format!("https://{}.s3.{}.amazonaws.com", bucket, region),
+            };
+            let base = match prefix {
+                "" => bucket_url,
+                prefix => format!("{}/{}", bucket_url, encode_path(prefix)),
+            };
+            return Ok(Some(Remote::S3 {
+                root: dir.to_string(),
+                base,
+                region,
+                user: format!("{}:{}", key_id, secret),
+                session_token: env("AWS_SESSION_TOKEN"),
+            }));
+        }
+        let web = [("webdav://", "http://"), ("webdavs://", "https://")];
+        for (scheme, http) in web {
+            if let Some(rest) = dir.strip_prefix(scheme) {
+                let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+                if host.is_empty() {
+                    return Err(format!("missing host in {}", dir));
+                }
+                let base = match path {
+                    "" => format!("{}{}", http, host),
+                    path => format!("{}{}/{}", http, host, encode_path(path)),
+                };
+                return Ok(Some(Remote::WebDav {
+                    root: dir.to_string(),
+                    base,
+                }));
+            }
+        }
+        if dir.contains("://") {
+            return Err(format!("unsupported output location {}", dir));
+        }
+        Ok(None)
+    }
+
+    /// Path of the file relative to the remote root, if under it.
+    pub fn key<'p>(&self, path: &'p Path) -> Option<&'p str> {
+        let (Remote::S3 { root, .. } | Remote::WebDav { root, .. }) = self;
+        let key = path.to_str()?.strip_prefix(root.as_str())?;
+        Some(key.trim_start_matches('/'))
+    }
+
+    fn url(&self, key: &str) -> String {
+        let (Remote::S3 { base, .. } | Remote::WebDav { base, .. }) = self;
+        match key {
+            "" => base.clone(),
+            key => format!("{}/{}", base, encode_path(key)),
+        }
+    }
+
+    /// The curl config with the credentials.
+    fn config(&self) -> String {
+        match self {
+            Remote::S3 {
+                region,
+                user,
+                session_token,
+                ..
+            } => {
+                let mut config = format!(
+                    "aws-sigv4 = {}\nuser = {}\n",
+                    quote(&format!("aws:amz:{}:s3", region)),
+                    quote(user)
+                );
+                if let Some(token) = session_token {
+                    let header = format!("x-amz-security-token: {}", token);
+                    config.push_str(&format!("header = {}\n", quote(&header)));
+                }
+                config
+            }
+            Remote::WebDav { .. } => "netrc-optional\n".to_string(),
+        }
+    }
+
+    /// Runs curl with the arguments, and the credentials on its stdin.
+    fn curl(&self, args: &[&str]) -> io::Result<Output> {
+        let mut child = Command::new("curl")
+            .args(["--silent", "--show-error", "--retry", "3", "--config", "-"])
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        child
+            .stdin
+            .take()
+            .unwrap()
+            .write_all(self.config().as_bytes())?;
+        child.wait_with_output()
+    }
+
+    /// Creates the directory with the given key, with its parents.
+    /// Only needed for WebDAV; S3 has no directories.
+    pub fn create_dir(&self, key: &str) -> io::Result<()> {
+        if let Remote::S3 { .. } = self {
+            return Ok(());
+        }
+        let mut dir = String::new();
+        for component in key.split('/').filter(|c| !c.is_empty()) {
+            if !dir.is_empty() {
+                dir.push('/');
+            }
+            dir.push_str(component);
+            let url = format!("{}/", self.url(&dir));
+            let output = self.curl(&[
+                "--request",
+                "MKCOL",
+                "--output",
+                "/dev/null",
+                "--write-out",
+                "%{http_code}",
+                &url,
+            ])?;
+            let status = String::from_utf8_lossy(&output.stdout);
+            // 405: already there
+            if !matches!(status.trim(), "201" | "405" | "301" | "200") {
+                return Err(io::Error::other(format!(
+                    "cannot create {}: status {} {}",
+                    self.url(&dir),
+                    status.trim(),
+                    String::from_utf8_lossy(&output.stderr).trim()
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Uploads the contents as the file with the given key.
+    pub fn put(&self, key: &str, bytes: &[u8]) -> io::Result<()> {
+        // stdin has the config, so the contents go through a file
+        let upload = TempFile::new(bytes)?;
+        self.upload(&upload.0, &self.url(key)).map(|_| ())
+    }
+
+    /// Uploads the file as the one with the given key; to S3, in parts if large.
+    pub fn put_file(&self, key: &str, path: &Path) -> io::Result<()> {
+        self.put_file_in_parts(key, path, PART_SIZE)
+    }
+
+    fn put_file_in_parts(&self, key: &str, path: &Path, part_size: u64) -> io::Result<()> {
+        let len = fs::metadata(path)?.len();
+        match self {
+            Remote::S3 { .. } if len > part_size => {
+                let part_size = part_size.max(len.div_ceil(MAX_PARTS));
+                self.put_multipart(&self.url(key), path, part_size)
+            }
+            _ => self.upload(path, &self.url(key)).map(|_| ()),
+        }
+    }
+
+    /// Uploads the file in parts of the given size (S3 multipart upload),
+    /// aborting the upload on error.
+    fn put_multipart(&self, url: &str, path: &Path, part_size: u64) -> io::Result<()> {
+        let response = self.request("POST", &format!("{}?uploads", url), None)?;
+        let upload_id = xml_element(&response, "UploadId")
+            .ok_or_else(|| io::Error::other(format!("no upload id for {}", url)))?;
+        let query = format!("uploadId={}", encode_segment(&upload_id));
+        let upload = || -> io::Result<()> {
+            let mut file = File::open(path)?;
+            let mut etags = Vec::new();
+            loop {
+                let mut part = Vec::new();
+                (&mut file).take(part_size).read_to_end(&mut part)?;
+                if part.is_empty() {
+                    break;
+                }
+                let number = etags.len() + 1;
+                let part_url = format!("{}?partNumber={}&{}", url, number, query);
+                let headers = self.upload(&TempFile::new(&part)?.0, &part_url)?;
+                let etag = header(&headers, "ETag").ok_or_else(|| {
+                    io::Error::other(format!("no ETag for part {} of {}", number, url))
+                })?;
+                etags.push(etag);
+            }
+            let body = TempFile::new(complete_multipart(&etags).as_bytes())?;
+            let response = self.request("POST", &format!("{}?{}", url, query), Some(&body.0))?;
+            // (the completion can fail after a 200 status)
+            match xml_element(&response, "Message") {
+                Some(message) if response.contains("<Error>") => Err(io::Error::other(format!(
+                    "cannot upload {}: {}",
+                    url, message
+                ))),
+                _ => Ok(()),
+            }
+        };
+        let result = upload();
+        if result.is_err() {
+            let _ = self.request("DELETE", &format!("{}?{}", url, query), None);
+        }
+        result
+    }
+
+    /// Uploads the file to the URL, returning the headers of the response.
+    fn upload(&self, path: &Path, url: &str) -> io::Result<String> {
+        let path = path.to_string_lossy();
+        let args = ["--output", "/dev/null", "--dump-header", "-"];
+        self.checked(url, &[&args[..], &["--upload-file", &path, url]].concat())
+    }
+
+    /// Sends the request, with the contents of the file as body if given,
+    /// returning the body of the response.
+    fn request(&self, method: &str, url: &str, body: Option<&Path>) -> io::Result<String> {
+        let data = body.map(|path| format!("@{}", path.to_string_lossy()));
+        let mut args = vec!["--request", method];
+        if let Some(data) = &data {
+            args.extend(["--data-binary", data]);
+        }
+        args.push(url);
+        self.checked(url, &args)
+    }
+
+    /// Runs curl, failing on an error status, and returning its output.
+    fn checked(&self, url: &str, args: &[&str]) -> io::Result<String> {
+        let output = self.curl(&[&["--fail"], args].concat())?;
+        if output.status.success() {
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Err(io::Error::other(format!(
+                "cannot upload {}: {}",
+                url,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )))
+        }
+    }
+}
+
+/// A new temporary file with the contents, only readable by the user,
+/// removed when dropped.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(bytes: &[u8]) -> io::Result<TempFile> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "blaise-upload-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(&path)?;
+        let temp = TempFile(path);
+        file.write_all(bytes)?;
+        Ok(temp)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// The text of the first element with the given name in the XML.
+fn xml_element(xml: &str, name: &str) -> Option<String> {
+    let start = xml.find(&format!("<{}>", name))? + name.len() + 2;
+    let end = start + xml[start..].find(&format!("</{}>", name))?;
+    Some(xml[start..end].to_string())
+}
+
+/// The value of the header (of the last response, after any redirects).
+fn header(headers: &str, name: &str) -> Option<String> {
+    let response = headers.rsplit("\r\n\r\n").find(|r| !r.trim().is_empty())?;
+    response.lines().find_map(|line| {
+        let (n, value) = line.split_once(':')?;
+        n.eq_ignore_ascii_case(name).then(|| value.trim().to_string())
+    })
+}
+
+/// The body of the request completing a multipart upload with the given parts.
+fn complete_multipart(etags: &[String]) -> String {
+    let mut body = "<CompleteMultipartUpload>".to_string();
+    for (i, etag) in etags.iter().enumerate() {
+        body.push_str(&format!(
+            "<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+            i + 1,
+            etag
+        ));
+    }
+    body.push_str("</CompleteMultipartUpload>");
+    body
+}
+
+/// Quotes the value for a curl config.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Percent-encodes the path for a URL, keeping the slashes.
+fn encode_path(path: &str) -> String {
+    percent_encode(path, true)
+}
+
+/// Percent-encodes the text as a single URL path segment, slashes included.
+pub(crate) fn encode_segment(segment: &str) -> String {
+    percent_encode(segment, false)
+}
+
+/// Percent-encodes all but the unreserved characters (and with `keep_slash`, '/').
+fn percent_encode(s: &str, keep_slash: bool) -> String {
+    let mut encoded = String::new();
+    for b in s.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encoding() {
+        assert_eq!(encode_path("a b/c%d.png"), "a%20b/c%25d.png");
+        assert_eq!(encode_segment("a b/c%d.png"), "a%20b%2Fc%25d.png");
+        assert_eq!(encode_segment("Ångström~"), "%C3%85ngstr%C3%B6m~");
+    }
+
+    #[test]
+    fn webdav() {
+        assert_eq!(Remote::parse("out/crops"), Ok(None));
+        assert!(Remote::parse("ftp://host/crops").is_err());
+        let remote = Remote::parse("webdav://host:8080/").unwrap().unwrap();
+        assert_eq!(remote.url("a.png"), "http://host:8080/a.png");
+        let remote = Remote::parse("webdavs://host/data lake/").unwrap().unwrap();
+        let path = Path::new("webdavs://host/data lake").join("Aegina/x 1_0.png");
+        assert_eq!(remote.key(&path), Some("Aegina/x 1_0.png"));
+        assert_eq!(remote.key(Path::new("out/x.png")), None);
+        assert_eq!(
+            remote.url("Aegina/x 1_0.png"),
+            "https://host/data%20lake/Aegina/x%201_0.png"
+        );
+    }
+
+    fn env<'v>(vars: &'v [(&str, &str)]) -> impl Fn(&str) -> Option<String> + 'v {
+        |name| {
+            let var = vars.iter().find(|(n, _)| *n == name);
+            var.map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn s3() {
+        let missing = Remote::parse_with("s3://bucket/crops", env(&[("AWS_ACCESS_KEY_ID", "key")]));
+        assert!(missing.is_err());
+        let vars = [
+            ("AWS_ACCESS_KEY_ID", "key"),
+            ("AWS_SECRET_ACCESS_KEY", "se\"cret"),
+            ("AWS_REGION", "us-west-2"),
+            ("AWS_SESSION_TOKEN", ""),
+        ];
+        let remote = Remote::parse_with("s3://bucket/crops/run1", env(&vars))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            remote.url("Kelp/a_0.png"),
+            "https://bucket.s3.us-west-2.amazonaws.com/crops/run1/Kelp/a_0.png"
+        );
+        assert_eq!(
+            remote.config(),
+            "aws-sigv4 = \"aws:amz:us-west-2:s3\"\nuser = \"key:se\\\"cret\"\n"
+        );
+        assert!(remote.create_dir("Kelp").is_ok());
+
+        let vars = [
+            ("AWS_ACCESS_KEY_ID", "key"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("AWS_SESSION_TOKEN", "token"),
+            ("AWS_ENDPOINT_URL", "http://minio:9000/"),
+        ];
+        let remote = Remote::parse_with("s3://bucket", env(&vars)).unwrap().unwrap();
+        assert_eq!(remote.url("a_0.png"), "http://minio:9000/bucket/a_0.png");
+        assert!(remote
+            .config()
+            .ends_with("header = \"x-amz-security-token: token\"\n"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn temp_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let file = TempFile::new(b"crop").unwrap();
+        let mode = fs::metadata(&file.0).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read(&file.0).unwrap(), b"crop");
+        let path = file.0.clone();
+        drop(file);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn responses() {
+        let xml = "<InitiateMultipartUploadResult><Bucket>b</Bucket>\
+                   <UploadId>abc/1</UploadId></InitiateMultipartUploadResult>";
+        assert_eq!(xml_element(xml, "UploadId"), Some("abc/1".to_string()));
+        assert_eq!(xml_element(xml, "Key"), None);
+        let headers = "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\n\
+                       etag: \"e1\"\r\nContent-Length: 0\r\n\r\n";
+        assert_eq!(header(headers, "ETag"), Some("\"e1\"".to_string()));
+        assert_eq!(
+            complete_multipart(&["\"e1\"".to_string(), "\"e2\"".to_string()]),
+            "<CompleteMultipartUpload>\
+             <Part><PartNumber>1</PartNumber><ETag>\"e1\"</ETag></Part>\
+             <Part><PartNumber>2</PartNumber><ETag>\"e2\"</ETag></Part>\
+             </CompleteMultipartUpload>"
+        );
+    }
+
+    /// Reads an HTTP request, returning its request line and body.
+    fn read_request(mut stream: &std::net::TcpStream) -> (String, Vec<u8>) {
+        use std::io::BufRead;
+        let mut reader = io::BufReader::new(stream);
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).unwrap();
+        let mut len = 0;
+        let mut expect_continue = false;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line == "\r\n" {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    len = value.trim().parse().unwrap();
+                }
+                expect_continue |= name.eq_ignore_ascii_case("expect");
+            }
+        }
+        if expect_continue {
+            stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").unwrap();
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body).unwrap();
+        (request_line.trim_end().to_string(), body)
+    }
+
+    #[test]
+    fn multipart() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        // a fake S3 endpoint, until the upload is completed
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let (request_line, body) = read_request(&stream);
+                let (headers, response) = if request_line.contains("?uploads") {
+                    let id = "<UploadId>id/1</UploadId>";
+                    (String::new(), format!("<InitiateMultipartUploadResult>{}</InitiateMultipartUploadResult>", id))
+                } else if request_line.starts_with("PUT") {
+                    let etag = format!("ETag: \"e{}\"\r\n", requests.len());
+                    (etag, String::new())
+                } else {
+                    (String::new(), "<CompleteMultipartUploadResult/>".to_string())
+                };
+                let done = request_line.starts_with("POST") && !request_line.contains("?uploads");
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    headers,
+                    response.len(),
+                    response
+                )
+                .unwrap();
+                requests.push((request_line, String::from_utf8(body).unwrap()));
+                if done {
+                    return requests;
+                }
+            }
+            unreachable!()
+        });
+
+        let vars = [
+            ("AWS_ACCESS_KEY_ID", "key"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("AWS_ENDPOINT_URL", endpoint.as_str()),
+        ];
+        let remote = Remote::parse_with("s3://bucket/crops.tar", env(&vars))
+            .unwrap()
+            .unwrap();
+        let file = TempFile::new(b"0123456789").unwrap();
+        remote.put_file_in_parts("", &file.0, 4).unwrap();
+
+        let requests = server.join().unwrap();
+        let url = "/bucket/crops.tar";
+        let part = |n: usize, body: &str| {
+            let request_line = format!("PUT {}?partNumber={}&uploadId=id%2F1 HTTP/1.1", url, n);
+            (request_line, body.to_string())
+        };
+        let complete = complete_multipart(&["\"e1\"".into(), "\"e2\"".into(), "\"e3\"".into()]);
+        assert_eq!(
+            requests,
+            [
+                (format!("POST {}?uploads HTTP/1.1", url), String::new()),
+                part(1, "0123"),
+                part(2, "4567"),
+                part(3, "89"),
+                (format!("POST {}?uploadId=id%2F1 HTTP/1.1", url), complete),
+            ]
+        );
+    }
+}
@@ -0,0 +1,174 @@
+//! Excluded image regions (eg., burned-in timestamps or HUD overlays),
+//! given as rectangles and/or a mask image.
+
+use image::GrayImage;
+use std::path::Path;
+
+use crate::geometry::PixelRect;
+
+/// Fraction of a box that must be excluded for the box to be skipped.
+pub const MAX_EXCLUDED_FRACTION: f64 = 0.5;
+
+/// Maximum number of samples per axis when estimating the excluded fraction of a box.
+const MAX_SAMPLES: u32 = 64;
+
+pub struct Exclusion {
+    regions: Vec<PixelRect>,
+    /// Pixels below 128 are excluded.
+    mask: Option<GrayImage>,
+}
+
+impl Exclusion {
+    pub fn new(regions: &[PixelRect], mask_path: Option<&Path>) -> Result<Exclusion, String> {
+        let mask = match mask_path {
+            Some(path) => Some(
+                image::open(path)
+                    .map_err(|e| format!("cannot load ROI mask {:?}: {}", path, e))?
+                    .to_luma8(),
+            ),
+            None => None,
+        };
+        Ok(Exclusion {
+            regions: regions.to_vec(),
+            mask,
+        })
+    }
+
+    pub fn is_excluded(&self, x: u32, y: u32) -> bool {
+        self.regions.iter().any(|r| r.contains(x, y))
+            || self.mask.as_ref().is_some_and(|mask| {
+                x < mask.width() && y < mask.height() && mask.get_pixel(x, y)[0] < 128
+            })
+    }
+
+    /// (Estimated) fraction of the box area that is excluded.
+    pub fn excluded_fraction(&self, bndbox: &PixelRect) -> f64 {
+        if bndbox.is_empty() {
+            return 0.;
+        }
+        let step_x = bndbox.width().div_ceil(MAX_SAMPLES);
+        let step_y = bndbox.height().div_ceil(MAX_SAMPLES);
+        let mut samples = 0usize;
+        let mut excluded = 0usize;
+        for y in (bndbox.ymin..bndbox.ymax).step_by(step_y as usize) {
+            for x in (bndbox.xmin..bndbox.xmax).step_by(step_x as usize) {
+                samples += 1;
+                if self.is_excluded(x, y) {
+                    excluded += 1;
+                }
+            }
+        }
+        excluded as f64 / samples as f64
+    }
+
+    pub fn is_mostly_excluded(&self, bndbox: &PixelRect) -> bool {
+        self.excluded_fraction(bndbox) > MAX_EXCLUDED_FRACTION
+    }
+
+    /// Reduces the padding of `padded` around `original` so the padding
+    /// does not include excluded pixels. The left and right padding is first
+    /// clipped along the original rows, then the top and bottom padding
+    /// (including the corners) along the resulting columns.
+    pub fn clip_padding(&self, original: &PixelRect, padded: &PixelRect) -> PixelRect {
+        let mut clipped = *padded;
+        let rows = original.ymin..original.ymax;
+        let column_excluded = |x: u32| rows.clone().any(|y| self.is_excluded(x, y));
+        if let Some(x) = (clipped.xmin..original.xmin)
+            .rev()
+            .find(|x| column_excluded(*x))
+        {
+            clipped.xmin = x + 1;
+        }
+        if let Some(x) = (original.xmax..clipped.xmax).find(|x| column_excluded(*x)) {
+            clipped.xmax = x;
+        }
+        let cols = clipped.xmin..clipped.xmax;
+        let row_excluded = |y: u32| cols.clone().any(|x| self.is_excluded(x, y));
+        if let Some(y) = (clipped.ymin..original.ymin)
+            .rev()
+            .find(|y| row_excluded(*y))
+        {
+            clipped.ymin = y + 1;
+        }
+        if let Some(y) = (original.ymax..clipped.ymax).find(|y| row_excluded(*y)) {
+            clipped.ymax = y;
+        }
+        clipped
+    }
+}
+
+/// Parses an `x,y,w,h` region.
+pub fn parse_region(s: &str) -> Result<PixelRect, String> {
+    let values: Vec<u32> = s
+        .split(',')
+        .map(|v| v.trim().parse::<u32>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "expected x,y,w,h as non-negative integers".to_string())?;
+    match values[..] {
+        [x, y, w, h] if w > 0 && h > 0 => Ok(PixelRect {
+            xmin: x,
+            ymin: y,
+            xmax: x + w,
+            ymax: y + h,
+        }),
+        _ => Err("expected x,y,w,h with positive w and h".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn b(xmin: u32, ymin: u32, xmax: u32, ymax: u32) -> PixelRect {
+        PixelRect {
+            xmin,
+            ymin,
+            xmax,
+            ymax,
+        }
+    }
+
+    #[test]
+    fn regions() {
+        assert_eq!(parse_region("0,10,100,20"), Ok(b(0, 10, 100, 30)));
+        assert!(parse_region("0,10,0,20").is_err());
+        assert!(parse_region("0,10,20").is_err());
+
+        // timestamp overlay along the top:
+        let exclusion = Exclusion::new(&[b(0, 0, 200, 20)], None).unwrap();
+        assert_eq!(exclusion.excluded_fraction(&b(10, 10, 30, 20)), 1.);
+        assert!(exclusion.is_mostly_excluded(&b(10, 5, 30, 25)));
+        assert!(!exclusion.is_mostly_excluded(&b(10, 15, 30, 35)));
+        assert_eq!(exclusion.excluded_fraction(&b(50, 50, 60, 60)), 0.);
+
+        let original = b(50, 40, 80, 60);
+        let padded = b(30, 10, 100, 90);
+        assert_eq!(
+            exclusion.clip_padding(&original, &padded),
+            b(30, 20, 100, 90)
+        );
+    }
+
+    #[test]
+    fn mask() {
+        let mut mask = GrayImage::from_pixel(100, 100, image::Luma([255]));
+        for y in 0..100 {
+            for x in 90..100 {
+                mask.put_pixel(x, y, image::Luma([0]));
+            }
+        }
+        let path = std::env::temp_dir().join("blaise_roi_mask_test.png");
+        mask.save(&path).unwrap();
+        let exclusion = Exclusion::new(&[], Some(&path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(exclusion.is_excluded(95, 0));
+        assert!(!exclusion.is_excluded(89, 0));
+        assert!(!exclusion.is_excluded(150, 0));
+        assert_eq!(
+            exclusion.clip_padding(&b(70, 40, 80, 50), &b(60, 30, 95, 60)),
+            b(60, 30, 90, 60)
+        );
+    }
+}
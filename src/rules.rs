@@ -0,0 +1,229 @@
+//! Relabeling rules (`--rules <file>`), applied to the annotations before
+//! cropping, eg., for host–symbiont conventions:
+//!
+//! ```yaml
+//! # a box of label X inside a box of label Y becomes X_on_Y
+//! - label: X
+//!   inside: Y
+//!   relabel: X_on_Y     # the default
+//!   containment: 0.9    # fraction of the box inside the other (default 1)
+//! ```
+//!
+//! The file is a list of rules in this simple YAML form (or as JSON), also
+//! accepted under a `rules:` key. The rules are checked in order against the
+//! labels as given, the first matching one applying.
+
+use crate::annotation::Annotation;
+use crate::overlap;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::Path;
+
+#[serde_with::serde_as]
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub label: String,
+    pub inside: String,
+    relabel: Option<String>,
+    /// A number, or a string with one (from YAML).
+    #[serde_as(as = "serde_with::PickFirst<(_, serde_with::DisplayFromStr)>")]
+    #[serde(default = "full_containment")]
+    pub containment: f64,
+}
+
+fn full_containment() -> f64 {
+    1.
+}
+
+impl Rule {
+    pub fn relabel(&self) -> String {
+        match &self.relabel {
+            Some(relabel) => relabel.clone(),
+            None => format!("{}_on_{}", self.label, self.inside),
+        }
+    }
+}
+
+pub fn load(path: &Path) -> Result<Vec<Rule>, String> {
+    let src = std::fs::read_to_string(path).map_err(|e| format!("{:?}: {}", path, e))?;
+    parse(&src).map_err(|e| format!("{:?}: {}", path, e))
+}
+
+pub fn parse(src: &str) -> Result<Vec<Rule>, String> {
+    let value = match src.trim_start().starts_with(['[', '{']) {
+        true => serde_json::from_str(src).map_err(|e| e.to_string())?,
+        false => parse_yaml(src)?,
+    };
+    let value = match value {
+        Value::Object(mut map) if map.contains_key("rules") => map.remove("rules").unwrap(),
+        value => value,
+    };
+    serde_json::from_value(value).map_err(|e| format!("invalid rules: {}", e))
+}
+
+/// Parses the YAML subset for the rules: a list of mappings of scalars,
+/// possibly under a single key.
+fn parse_yaml(src: &str) -> Result<Value, String> {
+    let mut key = None;
+    let mut items: Vec<Map<String, Value>> = Vec::new();
+    for (i, line) in src.lines().enumerate() {
+        let error = |message: &str| format!("line {}: {}", i + 1, message);
+        let line = strip_comment(line).trim_end();
+        let content = line.trim_start();
+        if content.is_empty() {
+            continue;
+        }
+        let entry = match content.strip_prefix('-') {
+            Some(rest) if rest.is_empty() || rest.starts_with(' ') => {
+                items.push(Map::new());
+                rest.trim()
+            }
+            _ if line.starts_with(' ') && !items.is_empty() => content,
+            _ if items.is_empty() && key.is_none() => match content.strip_suffix(':') {
+                Some(k) => {
+                    key = Some(k.trim().to_string());
+                    continue;
+                }
+                None => return Err(error("expected a list of rules")),
+            },
+            _ => return Err(error("expected a list item or an indented key")),
+        };
+        if entry.is_empty() {
+            continue;
+        }
+        let (name, value) = entry
+            .split_once(':')
+            .ok_or_else(|| error("expected key: value"))?;
+        let item = items.last_mut().unwrap();
+        item.insert(name.trim().to_string(), Value::from(unquote(value.trim())));
+    }
+    let list = Value::Array(items.into_iter().map(Value::Object).collect());
+    Ok(match key {
+        Some(key) => Value::Object(Map::from_iter([(key, list)])),
+        None => list,
+    })
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(" #") {
+        Some(i) => &line[..i],
+        None if line.trim_start().starts_with('#') => "",
+        None => line,
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .or_else(|| s.strip_prefix('\'').and_then(|s| s.strip_suffix('\'')))
+        .unwrap_or(s)
+}
+
+/// Applies the rules, returning the number of objects relabeled.
+pub fn apply(rules: &[Rule], annotations: &mut [Annotation]) -> usize {
+    let mut relabeled = 0;
+    for objects in annotations.iter_mut().filter_map(|a| a.objects.as_mut()) {
+        let new_names: Vec<Option<String>> = objects
+            .iter()
+            .map(|object| {
+                rules
+                    .iter()
+                    .find(|rule| {
+                        object.name == rule.label
+                            && overlap::is_contained_in(
+                                object,
+                                objects,
+                                &rule.inside,
+                                rule.containment,
+                            )
+                    })
+                    .map(Rule::relabel)
+            })
+            .collect();
+        for (object, new_name) in objects.iter_mut().zip(new_names) {
+            if let Some(new_name) = new_name {
+                object.name = new_name;
+                relabeled += 1;
+            }
+        }
+    }
+    relabeled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::{Bndbox, Object};
+
+    #[test]
+    fn parse_rules() {
+        let src = r#"
+# host-symbiont conventions
+rules:
+  - label: crab
+    inside: coral   # fully
+  - label: "worm"
+    inside: sponge
+    relabel: 'worm: in sponge'
+    containment: 0.5
+"#;
+        let rules = parse(src).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[0].relabel(), "crab_on_coral");
+        assert_eq!(rules[0].containment, 1.);
+        assert_eq!(rules[1].label, "worm");
+        assert_eq!(rules[1].relabel(), "worm: in sponge");
+        assert_eq!(rules[1].containment, 0.5);
+
+        let json = r#"[{"label": "crab", "inside": "coral", "containment": 0.8}]"#;
+        assert_eq!(parse(json).unwrap()[0].containment, 0.8);
+        assert_eq!(parse("- label: 7\n  inside: 12\n").unwrap()[0].label, "7");
+        assert_eq!(parse("- label: crab\n  inside: coral\n").unwrap().len(), 1);
+
+        assert!(parse("- label: crab\n").is_err());
+        assert!(parse("- label: crab\n  inside: coral\n  color: red\n").is_err());
+        assert!(parse("label: crab\n")
+            .unwrap_err()
+            .starts_with("line 1: expected a list"));
+    }
+
+    #[test]
+    fn apply_rules() {
+        let object = |name: &str, xmin: f64, xmax: f64| Object {
+            name: name.to_string(),
+            bndbox: Bndbox {
+                xmin,
+                ymin: 0.,
+                xmax,
+                ymax: 10.,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        };
+        let mut annotations = [Annotation {
+            folder: "f".to_string(),
+            filename: "x.png".to_string(),
+            objects: Some(vec![
+                object("coral", 0., 100.),
+                object("crab", 10., 20.),
+                object("crab", 95., 105.),
+                object("coral", 20., 30.),
+            ]),
+            frame: None,
+            image_size: None,
+        }];
+        let rules = parse("- label: crab\n  inside: coral\n- label: coral\n  inside: coral\n")
+            .unwrap();
+        assert_eq!(apply(&rules, &mut annotations), 2);
+        let names: Vec<&str> = annotations[0]
+            .objects
+            .iter()
+            .flatten()
+            .map(|o| o.name.as_str())
+            .collect();
+        assert_eq!(names, ["coral", "crab_on_coral", "crab", "coral_on_coral"]);
+    }
+}
@@ -0,0 +1,237 @@
+//! Run identification and the `run.json` record in the output directory,
+//! so it can be told which settings produced the crops.
+
+use crate::scrub::Scrubber;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the run record file in the output directory.
+pub const RUN_FILE: &str = "run.json";
+
+/// A run, from its start.
+pub struct Run {
+    pub id: String,
+    started: SystemTime,
+}
+
+impl Run {
+    pub fn start() -> Self {
+        let started = SystemTime::now();
+        let secs = started
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            id: format!("{:x}-{:x}", secs, std::process::id()),
+            started,
+        }
+    }
+
+    pub fn record(
+        &self,
+        args: Vec<String>,
+        options: String,
+        annotations: usize,
+        failed: usize,
+        by_label: &HashMap<String, usize>,
+    ) -> RunRecord {
+        RunRecord {
+            run_id: self.id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_describe: option_env!("BLAISE_GIT_DESCRIBE").map(str::to_string),
+            hostname: hostname(),
+            args,
+            options,
+            started: utc_timestamp(self.started),
+            ended: utc_timestamp(SystemTime::now()),
+            annotations,
+            failed,
+            crops: by_label.values().sum(),
+            crops_by_label: by_label.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct RunRecord {
+    pub run_id: String,
+    pub version: String,
+    /// When built from a git checkout.
+    pub git_describe: Option<String>,
+    pub hostname: Option<String>,
+    pub args: Vec<String>,
+    /// All the options in effect, including defaults.
+    pub options: String,
+    pub started: String,
+    pub ended: String,
+    pub annotations: usize,
+    pub failed: usize,
+    pub crops: usize,
+    pub crops_by_label: BTreeMap<String, usize>,
+}
+
+impl RunRecord {
+    /// Without the host name, and with the paths in the arguments and options
+    /// scrubbed (the program by its name only).
+    pub fn scrub(&mut self, scrubber: &Scrubber) {
+        self.hostname = None;
+        for (i, arg) in self.args.iter_mut().enumerate() {
+            *arg = match (i, Path::new(arg.as_str())) {
+                (0, program) => program.file_name().map_or(arg.clone(), |name| name.to_string_lossy().into_owned()),
+                (_, path) if path.is_absolute() => scrubber.path(arg),
+                _ => scrubber.text(arg),
+            };
+        }
+        self.options = scrubber.text(&self.options);
+    }
+}
+
+/// What to do if the output directory is not empty.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Existing {
+    /// Stop with an error.
+    Refuse,
+    /// Remove the existing contents first.
+    Overwrite,
+    /// Add to the existing contents, keeping the previous runs in the run record.
+    Append,
+}
+
+/// Gets the output directory ready according to `existing`.
+pub fn prepare_output_dir(dir: &Path, existing: Existing) -> Result<(), String> {
+    let is_empty = match fs::read_dir(dir) {
+        Ok(mut entries) => entries.next().is_none(),
+        Err(_) => true,
+    };
+    if is_empty {
+        return Ok(());
+    }
+    match existing {
+        Existing::Refuse => Err(format!(
+            "output directory {:?} is not empty; use --overwrite or --append",
+            dir
+        )),
+        Existing::Overwrite => {
+            println!("removing the existing contents of {:?}", dir);
+            for entry in fs::read_dir(dir).map_err(|e| e.to_string())? {
+                let path = entry.map_err(|e| e.to_string())?.path();
+                let removed = if path.is_dir() {
+                    fs::remove_dir_all(&path)
+                } else {
+                    fs::remove_file(&path)
+                };
+                removed.map_err(|e| format!("cannot remove {:?}: {}", path, e))?;
+            }
+            Ok(())
+        }
+        Existing::Append => Ok(()),
+    }
+}
+
+/// Writes the run record, after those of previous runs if appending.
+/// The file has a JSON array with a record per run, the last one being the latest.
+pub fn write_record(dir: &Path, record: &RunRecord, existing: Existing) -> std::io::Result<()> {
+    let path = dir.join(RUN_FILE);
+    let mut runs: Vec<Value> = match existing {
+        Existing::Append => fs::read_to_string(&path)
+            .ok()
+            .and_then(|src| serde_json::from_str(&src).ok())
+            .unwrap_or_default(),
+        _ => Vec::new(),
+    };
+    runs.push(serde_json::to_value(record)?);
+    fs::create_dir_all(dir)?;
+    fs::write(path, serde_json::to_string_pretty(&runs)? + "\n")
+}
+
+#[cfg(unix)]
+fn hostname() -> Option<String> {
+    let mut buf = [0u8; 256];
+    // SAFETY: the buffer is valid for the given length.
+    let res = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if res != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    Some(String::from_utf8_lossy(&buf[..len]).to_string())
+}
+
+#[cfg(not(unix))]
+fn hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Formats the time as RFC 3339 in UTC, to the second.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // civil from days (Howard Hinnant's algorithm):
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem % 3600 / 60,
+        rem % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(utc_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(utc_timestamp(time), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn output_dir_and_record() {
+        let dir = std::env::temp_dir().join(format!("blaise-run-{}", std::process::id()));
+        assert!(prepare_output_dir(&dir, Existing::Refuse).is_ok());
+
+        let run = Run::start();
+        let by_label = HashMap::from([("Aegina".to_string(), 2)]);
+        let record = run.record(
+            vec!["blaise".to_string()],
+            "opts".to_string(),
+            3,
+            1,
+            &by_label,
+        );
+        assert_eq!(record.crops, 2);
+        write_record(&dir, &record, Existing::Refuse).unwrap();
+
+        assert!(prepare_output_dir(&dir, Existing::Refuse).is_err());
+        assert!(prepare_output_dir(&dir, Existing::Append).is_ok());
+        write_record(&dir, &record, Existing::Append).unwrap();
+        let runs: Vec<Value> =
+            serde_json::from_str(&fs::read_to_string(dir.join(RUN_FILE)).unwrap()).unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[1]["crops_by_label"]["Aegina"], 2);
+
+        assert!(prepare_output_dir(&dir, Existing::Overwrite).is_ok());
+        assert!(dir.is_dir() && !dir.join(RUN_FILE).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
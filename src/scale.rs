@@ -0,0 +1,112 @@
+//! Physical scale of the images (`--pixel-size`), in mm per pixel, for the
+//! real-world size of the objects in the manifest and the crops resized to a
+//! common scale (`--normalize-scale`).
+
+use crate::annotation::Bndbox;
+use crate::metadata::{Metadata, MetadataColumn};
+use std::path::Path;
+
+/// Column of the pixel sizes in a table with other metadata.
+const COLUMN: &str = "mm_per_pixel";
+
+pub enum PixelSize {
+    /// The same for all the images.
+    Global(f64),
+    /// By image or folder, as in a `--metadata` file: in the `mm_per_pixel`
+    /// column, or the only one.
+    Table(MetadataColumn),
+}
+
+impl PixelSize {
+    /// A size (in mm per pixel) or the path of a CSV file with them.
+    pub fn new(arg: &str) -> Result<PixelSize, String> {
+        if let Ok(size) = arg.parse::<f64>() {
+            return positive(size).map(PixelSize::Global);
+        }
+        let metadata = Metadata::load(Path::new(arg))?;
+        let column = match metadata.columns.as_slice() {
+            [_] => metadata.columns[0].clone(),
+            _ => COLUMN.to_string(),
+        };
+        metadata.column(&column).map(PixelSize::Table)
+    }
+
+    /// The pixel size for the image, if given.
+    pub fn of(&self, image_path: &str) -> Result<Option<f64>, String> {
+        match self {
+            PixelSize::Global(size) => Ok(Some(*size)),
+            PixelSize::Table(column) => match column.value(image_path) {
+                Some(value) => value
+                    .parse()
+                    .map_err(|_| format!("invalid pixel size {:?}", value))
+                    .and_then(positive)
+                    .map(Some),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+fn positive(size: f64) -> Result<f64, String> {
+    match size > 0. && size.is_finite() {
+        true => Ok(size),
+        false => Err(format!("invalid pixel size {}, expected > 0", size)),
+    }
+}
+
+/// Size (width, height) of the box in mm, to the µm.
+pub fn size_mm(bndbox: &Bndbox, pixel_size: f64) -> (f64, f64) {
+    let mm = |pixels: f64| (pixels * pixel_size * 1000.).round() / 1000.;
+    (mm(bndbox.width()), mm(bndbox.height()))
+}
+
+/// Size of a crop of the given size resized from the pixel size of its image
+/// to the target one, at least one pixel.
+pub fn normalized_size(width: u32, height: u32, pixel_size: f64, target: f64) -> (u32, u32) {
+    let factor = pixel_size / target;
+    let scale = |v: u32| ((v as f64 * factor).round() as u32).max(1);
+    (scale(width), scale(height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_sizes() {
+        let dir = std::env::temp_dir().join(format!("blaise-scale-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = |name: &str, src: &str| {
+            let path = dir.join(name);
+            std::fs::write(&path, src).unwrap();
+            PixelSize::new(path.to_str().unwrap())
+        };
+        let only = table("only.csv", "image,size\nD1/,0.05\nD1/x.png,0.1\n").unwrap();
+        assert_eq!(only.of("imgs/D1/a.png"), Ok(Some(0.05)));
+        assert_eq!(only.of("imgs/D1/x.png"), Ok(Some(0.1)));
+        assert_eq!(only.of("imgs/D2/a.png"), Ok(None));
+        let named = table("named.csv", "image,dive,mm_per_pixel\nD1,D1,0.2\nD2,D2,-1\n").unwrap();
+        assert_eq!(named.of("D1/a.png"), Ok(Some(0.2)));
+        assert!(named.of("D2/a.png").is_err());
+        assert!(table("other.csv", "image,dive,site\nD1,D1,x\n").is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(PixelSize::new("0.5").unwrap().of("x.png"), Ok(Some(0.5)));
+        assert!(PixelSize::new("0").is_err());
+        assert!(PixelSize::new("no-such-file.csv").is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        let bndbox = Bndbox {
+            xmin: 10.,
+            ymin: 20.,
+            xmax: 40.,
+            ymax: 30.,
+        };
+        assert_eq!(size_mm(&bndbox, 0.0333), (0.999, 0.333));
+        assert_eq!(normalized_size(30, 10, 0.05, 0.1), (15, 5));
+        assert_eq!(normalized_size(30, 1, 0.05, 0.1), (15, 1));
+        assert_eq!(normalized_size(30, 10, 0.2, 0.1), (60, 20));
+    }
+}
@@ -0,0 +1,182 @@
+//! Progress of the annotation scanning phase, keeping the files that failed
+//! to be parsed and why (for `--list-invalid`), and deciding on the issues
+//! found in the files per the `Strictness`. The progress line is only shown
+//! with the `pipeline` feature.
+
+use crate::source::{Issue, Strictness};
+#[cfg(feature = "pipeline")]
+use indicatif::{ProgressBar, ProgressStyle};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "pipeline")]
+use std::time::Duration;
+
+pub struct ScanProgress {
+    #[cfg(feature = "pipeline")]
+    pb: ProgressBar,
+    seen: usize,
+    parsed: usize,
+    invalid: Vec<(PathBuf, String)>,
+    strictness: Strictness,
+    /// Malformed contents left out, when lenient.
+    recovered: usize,
+}
+
+impl ScanProgress {
+    /// The progress line is only shown if `show`.
+    pub fn new(show: bool) -> Self {
+        #[cfg(feature = "pipeline")]
+        let pb = if show {
+            let pb = ProgressBar::new_spinner();
+            pb.set_style(ProgressStyle::with_template("{spinner} scanning: {msg}").unwrap());
+            pb.enable_steady_tick(Duration::from_millis(200));
+            pb
+        } else {
+            ProgressBar::hidden()
+        };
+        #[cfg(not(feature = "pipeline"))]
+        let _ = show;
+        Self {
+            #[cfg(feature = "pipeline")]
+            pb,
+            seen: 0,
+            parsed: 0,
+            invalid: Vec::new(),
+            strictness: Strictness::default(),
+            recovered: 0,
+        }
+    }
+
+    pub fn with_strictness(self, strictness: Strictness) -> Self {
+        Self { strictness, ..self }
+    }
+
+    /// An issue was found in the file: whether to go on with it (warning when
+    /// lenient), or else the parser takes the file as invalid.
+    pub fn issue(&mut self, path: &Path, issue: Issue) -> bool {
+        let accepted = self.strictness.accepts(&issue);
+        if accepted {
+            if matches!(issue, Issue::Malformed(_)) {
+                self.recovered += 1;
+            }
+            if self.strictness == Strictness::Lenient {
+                #[cfg(feature = "pipeline")]
+                self.pb
+                    .suspend(|| eprintln!("WARN: {:?}: {}", path, issue.reason()));
+                #[cfg(not(feature = "pipeline"))]
+                eprintln!("WARN: {:?}: {}", path, issue.reason());
+            }
+        }
+        accepted
+    }
+
+    /// A file was found.
+    pub fn seen(&mut self) {
+        self.seen += 1;
+        self.update();
+    }
+
+    /// A file was parsed successfully.
+    pub fn parsed(&mut self) {
+        self.parsed += 1;
+        self.update();
+    }
+
+    /// A file failed to be parsed (or read).
+    pub fn invalid(&mut self, path: &Path, reason: impl Display) {
+        self.invalid.push((path.to_path_buf(), reason.to_string()));
+        self.update();
+    }
+
+    /// Bar for a step with its own count, eg., the image size scan,
+    /// shown only if this progress is.
+    #[cfg(feature = "pipeline")]
+    pub fn bar(&self, message: &'static str) -> ProgressBar {
+        if self.pb.is_hidden() {
+            return ProgressBar::hidden();
+        }
+        let pb = ProgressBar::new(0);
+        pb.set_style(
+            ProgressStyle::with_template("{msg} {bar:40.green/yellow} {pos:>7}/{len:7}").unwrap(),
+        );
+        pb.set_message(message);
+        pb
+    }
+
+    fn update(&self) {
+        #[cfg(feature = "pipeline")]
+        self.pb.set_message(format!(
+            "{} files seen, {} parsed, {} invalid",
+            self.seen,
+            self.parsed,
+            self.invalid.len()
+        ));
+    }
+
+    pub fn num_invalid(&self) -> usize {
+        self.invalid.len()
+    }
+
+    /// Number of malformed contents left out of the files, when lenient.
+    pub fn num_recovered(&self) -> usize {
+        self.recovered
+    }
+
+    pub fn finish(&self) {
+        #[cfg(feature = "pipeline")]
+        self.pb.finish_and_clear();
+    }
+
+    /// Writes the invalid files with their reasons, one per line, tab separated.
+    pub fn write_invalid(&self, path: &Path) -> std::io::Result<usize> {
+        let contents: String = self
+            .invalid
+            .iter()
+            .map(|(file, reason)| {
+                // keeping each entry in one line:
+                let reason = reason.replace(['\n', '\t'], " ");
+                format!("{}\t{}\n", file.display(), reason)
+            })
+            .collect();
+        std::fs::write(path, contents)?;
+        Ok(self.invalid.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_list() {
+        let mut scan = ScanProgress::new(false);
+        scan.seen();
+        scan.seen();
+        scan.parsed();
+        scan.invalid(Path::new("a/b.xml"), "missing field `filename`\nat line 3");
+        scan.finish();
+        let path = std::env::temp_dir().join(format!("blaise-invalid-{}", std::process::id()));
+        assert_eq!(scan.write_invalid(&path).unwrap(), 1);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "a/b.xml\tmissing field `filename` at line 3\n"
+        );
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn strictness() {
+        let path = Path::new("a.txt");
+        let irregular = || Issue::Irregular("unknown image size".to_string());
+        let malformed = || Issue::Malformed("line 2: cannot parse 'x'".to_string());
+        let mut scan = ScanProgress::new(false);
+        assert!(scan.issue(path, irregular()));
+        assert!(!scan.issue(path, malformed()));
+        let mut scan = ScanProgress::new(false).with_strictness(Strictness::Strict);
+        assert!(!scan.issue(path, irregular()));
+        let mut scan = ScanProgress::new(false).with_strictness(Strictness::Lenient);
+        assert!(scan.issue(path, irregular()));
+        assert!(scan.issue(path, malformed()));
+        assert_eq!(scan.num_recovered(), 1);
+    }
+}
@@ -0,0 +1,137 @@
+//! Scrubbing of the outputs (`--scrub-paths`), before sharing a dataset outside
+//! the institution: the absolute paths of the images and crops are made relative,
+//! the user name is replaced, and the host name left out of the run record.
+
+use std::path::{Path, PathBuf};
+
+/// What replaces the user name.
+const USER: &str = "user";
+
+pub struct Scrubber {
+    current_dir: Option<PathBuf>,
+    home_dir: Option<PathBuf>,
+    user: Option<String>,
+}
+
+impl Scrubber {
+    /// For the current directory and the user of the process.
+    pub fn new() -> Self {
+        let env = |name: &str| std::env::var(name).ok().filter(|v| !v.is_empty());
+        Self::with(
+            std::env::current_dir().ok(),
+            env("HOME").or_else(|| env("USERPROFILE")).map(PathBuf::from),
+            env("USER").or_else(|| env("LOGNAME")).or_else(|| env("USERNAME")),
+        )
+    }
+
+    fn with(current_dir: Option<PathBuf>, home_dir: Option<PathBuf>, user: Option<String>) -> Self {
+        Self {
+            current_dir,
+            home_dir,
+            user,
+        }
+    }
+
+    /// The path as written in the outputs: relative to the current directory if
+    /// under it, else to the home directory (as `~/...`), else to the root; with
+    /// the user name replaced. URLs are kept as given.
+    pub fn path(&self, path: &str) -> String {
+        let as_path = Path::new(path);
+        if path.contains("://") || !as_path.is_absolute() {
+            return self.user_name(path);
+        }
+        let under = |base: &Option<PathBuf>| {
+            base.as_deref()
+                .and_then(|base| as_path.strip_prefix(base).ok())
+                .map(|rest| rest.to_string_lossy().into_owned())
+        };
+        let scrubbed = if let Some(rest) = under(&self.current_dir) {
+            match rest.is_empty() {
+                true => ".".to_string(),
+                false => rest,
+            }
+        } else if let Some(rest) = under(&self.home_dir) {
+            format!("~/{}", rest)
+        } else {
+            let components = as_path.components().filter_map(|c| match c {
+                std::path::Component::Normal(name) => Some(name.to_string_lossy()),
+                _ => None,
+            });
+            components.collect::<Vec<_>>().join("/")
+        };
+        self.user_name(&scrubbed)
+    }
+
+    /// The text (eg., the options of the run) with the current and home
+    /// directories made `.` and `~`, and the user name replaced.
+    pub fn text(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        let mut bases = [(&self.current_dir, "."), (&self.home_dir, "~")];
+        // the longest first, as the current directory is often under the home one:
+        bases.sort_by_key(|(base, _)| std::cmp::Reverse(base.as_ref().map_or(0, |b| b.as_os_str().len())));
+        for (base, replacement) in bases {
+            if let Some(base) = base.as_ref().map(|b| b.to_string_lossy()) {
+                if base.len() > 1 {
+                    text = text.replace(base.as_ref(), replacement);
+                }
+            }
+        }
+        self.user_name(&text)
+    }
+
+    /// With the user name replaced where a path component.
+    fn user_name(&self, text: &str) -> String {
+        let Some(user) = self.user.as_deref().filter(|u| !u.is_empty()) else {
+            return text.to_string();
+        };
+        let mut scrubbed = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(i) = rest.find(user) {
+            let before = rest[..i].chars().next_back();
+            let after = rest[i + user.len()..].chars().next();
+            let separator = |c: Option<char>| c.is_none_or(|c| matches!(c, '/' | '\\' | '"' | ' '));
+            let component = before.is_some_and(|c| c == '/' || c == '\\') && separator(after);
+            scrubbed.push_str(&rest[..i]);
+            scrubbed.push_str(if component { USER } else { user });
+            rest = &rest[i + user.len()..];
+        }
+        scrubbed.push_str(rest);
+        scrubbed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scrubber() -> Scrubber {
+        Scrubber::with(
+            Some(PathBuf::from("/home/alice/work")),
+            Some(PathBuf::from("/home/alice")),
+            Some("alice".to_string()),
+        )
+    }
+
+    #[test]
+    fn paths() {
+        let scrubber = scrubber();
+        assert_eq!(scrubber.path("/home/alice/work/imgs/a.png"), "imgs/a.png");
+        assert_eq!(scrubber.path("/home/alice/data/a.png"), "~/data/a.png");
+        assert_eq!(scrubber.path("/data/alice/dive1/a.png"), "data/user/dive1/a.png");
+        assert_eq!(scrubber.path("./imgs/alice_1.png"), "./imgs/alice_1.png");
+        assert_eq!(scrubber.path("../alice/a.png"), "../user/a.png");
+        assert_eq!(
+            scrubber.path("https://example.org/imgs/a.png"),
+            "https://example.org/imgs/a.png"
+        );
+    }
+
+    #[test]
+    fn text() {
+        let options = r#"Opts { pascal: Some("/home/alice/work/voc"), cache_dir: Some("/home/alice/.cache"), output_dir: Some("/scratch/alice") }"#;
+        assert_eq!(
+            scrubber().text(options),
+            r#"Opts { pascal: Some("./voc"), cache_dir: Some("~/.cache"), output_dir: Some("/scratch/user") }"#
+        );
+    }
+}
@@ -0,0 +1,281 @@
+//! Destinations for the crops besides the output directory, each taking the
+//! crops as they are written, with their metadata. Several can be given at
+//! once (`--sink`, `--tfrecord`, `--hdf5`, `--hf-imagefolder`); the manifest
+//! and checksums are written in all cases.
+//!
+//! A sink to a remote location (`s3://`, `webdav://`) writes to a local
+//! staging copy, uploaded once complete.
+
+use crate::archive::{TarSink, WebDatasetSink, ZipSink};
+use crate::geometry::PixelRect;
+use crate::hdf5::Hdf5Writer;
+use crate::imagefolder::ImageFolderMetadata;
+#[cfg(feature = "lmdb")]
+use crate::lmdb::LmdbSink;
+use crate::manifest::CropKind;
+use crate::pyramid::{self, PyramidSink};
+use crate::remote::Remote;
+use crate::tfrecord::TfRecordWriter;
+use image::DynamicImage;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A crop as written to the output directory.
+pub struct Crop<'a> {
+    pub kind: CropKind,
+    pub image: &'a DynamicImage,
+    /// The encoded crop, as in the output directory.
+    pub bytes: &'a [u8],
+    /// Path of the crop in the output directory.
+    pub path: &'a Path,
+    /// The same, relative to the output directory.
+    pub name: &'a Path,
+    pub label: &'a str,
+    /// Path of the source image.
+    pub source: &'a str,
+    pub bndbox: &'a PixelRect,
+}
+
+pub trait CropSink: Send + Sync {
+    fn add(&self, crop: &Crop) -> io::Result<()>;
+
+    /// Completes the output, once all the crops are added.
+    fn finish(&self) -> io::Result<()>;
+}
+
+/// A sink given with `--sink kind:path`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SinkSpec {
+    Tar(PathBuf),
+    Zip(PathBuf),
+    WebDataset(PathBuf),
+    /// With the sizes of the thumbnails.
+    Pyramid(PathBuf, Vec<u32>),
+    #[cfg(feature = "lmdb")]
+    Lmdb(PathBuf),
+}
+
+pub fn parse_spec(s: &str) -> Result<SinkSpec, String> {
+    let (kind, path) = s
+        .split_once(':')
+        .filter(|(_, path)| !path.is_empty())
+        .ok_or_else(|| format!("expected kind:path, got {:?}", s))?;
+    let path = PathBuf::from(path);
+    // the thumbnail sizes, as in pyramid=64,256:<dir>
+    if let Some(sizes) = kind.strip_prefix("pyramid=") {
+        let sizes = sizes
+            .split(',')
+            .map(|size| size.trim().parse::<u32>().ok().filter(|s| *s > 0))
+            .collect::<Option<Vec<u32>>>()
+            .ok_or_else(|| format!("invalid pyramid sizes {:?}", sizes))?;
+        return Ok(SinkSpec::Pyramid(path, sizes));
+    }
+    match kind {
+        "tar" => Ok(SinkSpec::Tar(path)),
+        "zip" => Ok(SinkSpec::Zip(path)),
+        "webdataset" | "wds" => Ok(SinkSpec::WebDataset(path)),
+        "pyramid" => Ok(SinkSpec::Pyramid(path, pyramid::DEFAULT_SIZES.to_vec())),
+        #[cfg(feature = "lmdb")]
+        "lmdb" => Ok(SinkSpec::Lmdb(path)),
+        #[cfg(not(feature = "lmdb"))]
+        "lmdb" => Err("this build of blaise does not include the `lmdb` feature".to_string()),
+        _ => Err(format!(
+            "unknown sink {:?} (expected tar, zip, webdataset, pyramid or lmdb)",
+            kind
+        )),
+    }
+}
+
+impl SinkSpec {
+    pub fn create(&self, webdataset_shard_size: usize) -> io::Result<Box<dyn CropSink>> {
+        match self {
+            SinkSpec::Tar(path) => create(path, TarSink::create),
+            SinkSpec::Zip(path) => create(path, ZipSink::create),
+            SinkSpec::WebDataset(dir) => create(dir, |dir| {
+                WebDatasetSink::create(dir, webdataset_shard_size)
+            }),
+            SinkSpec::Pyramid(dir, sizes) => create(dir, |dir| PyramidSink::create(dir, sizes)),
+            #[cfg(feature = "lmdb")]
+            SinkSpec::Lmdb(dir) => create(dir, LmdbSink::create),
+        }
+    }
+}
+
+/// Creates the sink to the file or directory at the path with `create`,
+/// through a staging copy if the path is remote.
+pub fn create<S: CropSink + 'static>(
+    path: &Path,
+    create: impl FnOnce(&Path) -> io::Result<S>,
+) -> io::Result<Box<dyn CropSink>> {
+    match Remote::parse(&path.to_string_lossy()).map_err(io::Error::other)? {
+        Some(remote) => {
+            static COUNT: AtomicUsize = AtomicUsize::new(0);
+            let staging = std::env::temp_dir().join(format!(
+                "blaise-sink-{}-{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let mut builder = fs::DirBuilder::new();
+            #[cfg(unix)]
+            std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+            builder.create(&staging)?;
+            let name = path.file_name().unwrap_or("output".as_ref());
+            let local = staging.join(name);
+            let sink = Box::new(create(&local)?);
+            Ok(Box::new(UploadedSink {
+                sink,
+                remote,
+                staging,
+                local,
+            }))
+        }
+        None => Ok(Box::new(create(path)?)),
+    }
+}
+
+/// A sink writing to a local copy of its output, uploaded when finished.
+struct UploadedSink {
+    sink: Box<dyn CropSink>,
+    remote: Remote,
+    /// The temporary directory with the local copy.
+    staging: PathBuf,
+    local: PathBuf,
+}
+
+impl CropSink for UploadedSink {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        self.sink.add(crop)
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        self.sink.finish()?;
+        let result = if self.local.is_dir() {
+            walkdir::WalkDir::new(&self.local)
+                .into_iter()
+                .map(|entry| entry.map_err(io::Error::from))
+                .filter(|entry| !entry.as_ref().is_ok_and(|e| e.file_type().is_dir()))
+                .try_for_each(|entry| {
+                    let path = entry?.into_path();
+                    let key = path.strip_prefix(&self.local).unwrap().to_string_lossy();
+                    if let Some((dir, _)) = key.rsplit_once('/') {
+                        self.remote.create_dir(dir)?;
+                    }
+                    self.remote.put_file(&key, &path)
+                })
+        } else {
+            self.remote.put_file("", &self.local)
+        };
+        let _ = fs::remove_dir_all(&self.staging);
+        result
+    }
+}
+
+/// Several sinks taking the same crops.
+#[derive(Default)]
+pub struct Sinks(Vec<Box<dyn CropSink>>);
+
+impl Sinks {
+    pub fn push(&mut self, sink: Box<dyn CropSink>) {
+        self.0.push(sink);
+    }
+
+    /// Adds the crop to all the sinks, returning the errors.
+    pub fn add(&self, crop: &Crop) -> Vec<io::Error> {
+        self.0
+            .iter()
+            .filter_map(|sink| sink.add(crop).err())
+            .collect()
+    }
+
+    pub fn finish(&self) {
+        for sink in &self.0 {
+            if let Err(e) = sink.finish() {
+                eprintln!("ERROR: cannot complete output: {}", e);
+            }
+        }
+    }
+}
+
+// The TFRecord, HDF5 and imagefolder outputs are for the object crops only.
+
+impl CropSink for TfRecordWriter {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        if crop.kind == CropKind::Object {
+            let format = match crop.name.extension().and_then(|e| e.to_str()) {
+                Some(ext) if ext.eq_ignore_ascii_case("jpg") => "jpeg".to_string(),
+                Some(ext) => ext.to_ascii_lowercase(),
+                None => "png".to_string(),
+            };
+            let size = (crop.image.width(), crop.image.height());
+            TfRecordWriter::add(self, crop.bytes, &format, size, crop.label, crop.source)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        TfRecordWriter::finish(self)
+    }
+}
+
+impl CropSink for Hdf5Writer {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        if crop.kind == CropKind::Object {
+            Hdf5Writer::add(self, crop.image, crop.label)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        Hdf5Writer::finish(self)
+    }
+}
+
+impl CropSink for ImageFolderMetadata {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        if crop.kind == CropKind::Object {
+            ImageFolderMetadata::add(self, crop.path, crop.label, crop.source, crop.bndbox)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        ImageFolderMetadata::finish(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn specs() {
+        assert_eq!(
+            parse_spec("tar:out/crops.tar"),
+            Ok(SinkSpec::Tar(PathBuf::from("out/crops.tar")))
+        );
+        assert_eq!(
+            parse_spec("wds:shards"),
+            Ok(SinkSpec::WebDataset(PathBuf::from("shards")))
+        );
+        assert_eq!(
+            parse_spec("pyramid:thumbs"),
+            Ok(SinkSpec::Pyramid(PathBuf::from("thumbs"), vec![64, 256]))
+        );
+        assert_eq!(
+            parse_spec("pyramid=32,512:thumbs"),
+            Ok(SinkSpec::Pyramid(PathBuf::from("thumbs"), vec![32, 512]))
+        );
+        assert!(parse_spec("pyramid=0:thumbs").is_err());
+        assert!(parse_spec("zip:").is_err());
+        #[cfg(feature = "lmdb")]
+        assert_eq!(
+            parse_spec("lmdb:crops"),
+            Ok(SinkSpec::Lmdb(PathBuf::from("crops")))
+        );
+        #[cfg(not(feature = "lmdb"))]
+        assert!(parse_spec("lmdb:crops").is_err());
+        assert!(parse_spec("crops.tar").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! Annotation sources: each supported format scans its input into the
+//! annotations, reporting the files it cannot parse to the scan progress.
+//!
+//! The annotations from a source have the image directory as their `folder`.
+//! Pascal VOC and YOLO are always available; the other formats are behind the
+//! cargo features of the same name (all on by default).
+
+use crate::annotation::Annotation;
+use crate::scan::ScanProgress;
+use std::fs::read_to_string;
+use std::path::Path;
+use walkdir::DirEntry;
+
+pub type Annotations<'a> = Box<dyn Iterator<Item = Annotation> + 'a>;
+
+/// How strictly the annotation files are taken (`--strict`, `--lenient`), the
+/// same for all the formats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strictness {
+    /// Recover from malformed contents where possible (eg., skipping a
+    /// malformed YOLO line instead of the file), with warnings
+    Lenient,
+    /// Files with malformed contents are invalid; irregularities (eg., an
+    /// unknown image size) are accepted
+    #[default]
+    Normal,
+    /// Files with any issue are invalid, and invalid files fail the run
+    Strict,
+}
+
+/// An issue found while parsing an annotation file.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Issue {
+    /// Accepted unless strict, eg., an unknown image size.
+    Irregular(String),
+    /// Making the file invalid unless lenient, eg., a malformed line, that
+    /// the parser can recover from by leaving out what it concerns.
+    Malformed(String),
+}
+
+impl Issue {
+    pub fn reason(&self) -> &str {
+        match self {
+            Issue::Irregular(reason) | Issue::Malformed(reason) => reason,
+        }
+    }
+}
+
+impl Strictness {
+    /// Whether to go on with a file with the issue, or take it as invalid.
+    pub fn accepts(self, issue: &Issue) -> bool {
+        match (self, issue) {
+            (Strictness::Lenient, _) => true,
+            (Strictness::Normal, Issue::Irregular(_)) => true,
+            (Strictness::Normal, Issue::Malformed(_)) => false,
+            (Strictness::Strict, _) => false,
+        }
+    }
+}
+
+pub trait AnnotationSource {
+    /// Name of the format, for the reports.
+    fn format(&self) -> &'static str;
+
+    /// Scans the input. Files that cannot be parsed are reported to `scan` and
+    /// left out; an error means that the input as a whole cannot be used.
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String>;
+}
+
+/// Reads a class names file (one name per line).
+pub fn read_names(path: &Path) -> Result<Vec<String>, String> {
+    let src = read_to_string(path).map_err(|e| format!("cannot read {:?}: {}", path, e))?;
+    Ok(src
+        .split('\n')
+        .filter(|s| !s.is_empty())
+        .map(|s| s.to_string())
+        .collect())
+}
+
+pub fn is_image(entry: &DirEntry) -> bool {
+    static X: [&str; 3] = ["png", "jpg", "jpeg"];
+    let path = entry.path();
+    path.is_file()
+        && match path.extension() {
+            Some(extension) => X.contains(&extension.to_str().unwrap()),
+            None => false,
+        }
+}
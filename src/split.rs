@@ -0,0 +1,254 @@
+//! Partitioning of the crops into train/val/test-like splits, keeping all
+//! crops from the same source image, folder, or track in the same partition.
+
+use crate::checksum;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Partition names and their (normalized) ratios.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplitSpec {
+    pub parts: Vec<(String, f64)>,
+}
+
+impl std::str::FromStr for SplitSpec {
+    type Err = String;
+
+    /// Parses `train=0.8,val=0.2`, or just `0.8,0.1,0.1` for train, val, and test.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const DEFAULT_NAMES: [&str; 3] = ["train", "val", "test"];
+        let mut parts = Vec::new();
+        for (i, part) in s.split(',').enumerate() {
+            let (name, ratio) = match part.split_once('=') {
+                Some((name, ratio)) if !name.is_empty() => (name.to_string(), ratio),
+                Some(_) => return Err(format!("missing partition name in '{}'", part)),
+                None => match DEFAULT_NAMES.get(i) {
+                    Some(name) => (name.to_string(), part),
+                    None => return Err("at most 3 unnamed partitions".to_string()),
+                },
+            };
+            match ratio.parse::<f64>() {
+                Ok(ratio) if ratio > 0. => parts.push((name, ratio)),
+                _ => return Err(format!("invalid ratio in '{}'", part)),
+            }
+        }
+        if parts.len() < 2 {
+            return Err("expected at least two partitions".to_string());
+        }
+        let mut names: Vec<&String> = parts.iter().map(|(name, _)| name).collect();
+        names.sort();
+        names.dedup();
+        if names.len() != parts.len() {
+            return Err("repeated partition name".to_string());
+        }
+        let total: f64 = parts.iter().map(|(_, ratio)| ratio).sum();
+        for (_, ratio) in parts.iter_mut() {
+            *ratio /= total;
+        }
+        Ok(SplitSpec { parts })
+    }
+}
+
+/// What crops must be kept together in the same partition.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitBy {
+    /// Crops from the same source image
+    Image,
+    /// Crops from images in the same directory (eg., a dive)
+    Folder,
+    /// Crops of the same track (or from the same image, if untracked)
+    Track,
+}
+
+/// Key of the group the crop of an object belongs to.
+pub fn group_key(by: SplitBy, image_path: &str, track_id: Option<u32>) -> String {
+    let folder = || {
+        Path::new(image_path)
+            .parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default()
+    };
+    match (by, track_id) {
+        (SplitBy::Folder, _) => folder(),
+        (SplitBy::Track, Some(track_id)) => format!("{}/track_{}", folder(), track_id),
+        _ => image_path.to_string(),
+    }
+}
+
+/// Assignment of groups to partitions.
+pub struct Split {
+    names: Vec<String>,
+    ratios: Vec<f64>,
+    assignment: HashMap<String, usize>,
+    /// Object counts per label and partition.
+    counts: BTreeMap<String, Vec<usize>>,
+    groups: Vec<usize>,
+}
+
+impl Split {
+    /// Assigns each group, given by the keys of the (key, label) items,
+    /// to a partition, approximating the ratios for every label.
+    /// Larger groups are assigned first, each to the partition where its
+    /// labels are furthest below their target counts.
+    pub fn new<'a>(spec: &SplitSpec, items: impl Iterator<Item = (String, &'a str)>) -> Split {
+        let mut groups: BTreeMap<String, HashMap<&str, usize>> = BTreeMap::new();
+        let mut totals: HashMap<&str, usize> = HashMap::new();
+        for (key, label) in items {
+            *groups.entry(key).or_default().entry(label).or_insert(0) += 1;
+            *totals.entry(label).or_insert(0) += 1;
+        }
+        let total: usize = totals.values().sum();
+
+        let mut ordered: Vec<(String, HashMap<&str, usize>)> = groups.into_iter().collect();
+        ordered.sort_by_key(|(_, labels)| std::cmp::Reverse(labels.values().sum::<usize>()));
+
+        let num_parts = spec.parts.len();
+        let mut counts: HashMap<&str, Vec<usize>> = HashMap::new();
+        let mut part_totals = vec![0usize; num_parts];
+        let mut part_groups = vec![0usize; num_parts];
+        let mut assignment = HashMap::new();
+        for (key, labels) in ordered {
+            let score = |p: usize| -> f64 {
+                let ratio = spec.parts[p].1;
+                let by_label: f64 = labels
+                    .iter()
+                    .map(|(label, n)| {
+                        let label_total = totals[label] as f64;
+                        let current = counts.get(label).map_or(0, |c| c[p]) as f64;
+                        *n as f64 * (ratio * label_total - current) / label_total
+                    })
+                    .sum();
+                let overall = (ratio * total as f64 - part_totals[p] as f64) / total as f64;
+                by_label + 1e-6 * overall
+            };
+            let mut best = 0;
+            for p in 1..num_parts {
+                if score(p) > score(best) + 1e-12 {
+                    best = p;
+                }
+            }
+            for (label, n) in &labels {
+                counts.entry(label).or_insert_with(|| vec![0; num_parts])[best] += n;
+                part_totals[best] += n;
+            }
+            part_groups[best] += 1;
+            assignment.insert(key, best);
+        }
+
+        Split {
+            names: spec.parts.iter().map(|(name, _)| name.clone()).collect(),
+            ratios: spec.parts.iter().map(|(_, ratio)| *ratio).collect(),
+            assignment,
+            counts: counts
+                .into_iter()
+                .map(|(label, c)| (label.to_string(), c))
+                .collect(),
+            groups: part_groups,
+        }
+    }
+
+    /// Name of the partition of the given group.
+    pub fn partition(&self, key: &str) -> Option<&str> {
+        self.assignment.get(key).map(|p| self.names[*p].as_str())
+    }
+
+    /// Name of the partition of the given group, or for a group without
+    /// objects (eg., an image cut in windows), the partition picked by the
+    /// hash of its key, as per the ratios.
+    pub fn partition_or_hash(&self, key: &str) -> &str {
+        if let Some(partition) = self.partition(key) {
+            return partition;
+        }
+        let hash = checksum::sha256(key.as_bytes());
+        let x = u64::from_be_bytes(hash[..8].try_into().unwrap()) as f64 / u64::MAX as f64;
+        let mut cumulative = 0.;
+        for (name, ratio) in self.names.iter().zip(&self.ratios) {
+            cumulative += ratio;
+            if x < cumulative {
+                return name;
+            }
+        }
+        &self.names[self.names.len() - 1]
+    }
+
+    /// Reports the object counts per label and partition.
+    pub fn report(&self, by: &str) {
+        println!("\n  Split by {}:", by);
+        let header: Vec<String> = self.names.iter().map(|n| format!("{:>8}", n)).collect();
+        println!("    {} label", header.join(""));
+        let mut labels: Vec<(&String, &Vec<usize>)> = self.counts.iter().collect();
+        labels.sort_by_key(|(_, counts)| std::cmp::Reverse(counts.iter().sum::<usize>()));
+        let mut totals = vec![0usize; self.names.len()];
+        for (label, counts) in labels {
+            let cols: Vec<String> = counts.iter().map(|c| format!("{:>8}", c)).collect();
+            println!("    {} \"{}\"", cols.join(""), label);
+            for (total, count) in totals.iter_mut().zip(counts) {
+                *total += count;
+            }
+        }
+        let cols: Vec<String> = totals.iter().map(|c| format!("{:>8}", c)).collect();
+        println!("    {} total", cols.join(""));
+        let cols: Vec<String> = self.groups.iter().map(|c| format!("{:>8}", c)).collect();
+        println!("    {} groups", cols.join(""));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parse_spec() {
+        let spec: SplitSpec = "0.8,0.1,0.1".parse().unwrap();
+        assert_eq!(spec.parts[0], ("train".to_string(), 0.8));
+        assert_eq!(spec.parts[2].0, "test");
+        let spec: SplitSpec = "a=3,b=1".parse().unwrap();
+        assert_eq!(
+            spec.parts,
+            vec![("a".to_string(), 0.75), ("b".to_string(), 0.25)]
+        );
+        assert!("1".parse::<SplitSpec>().is_err());
+        assert!("a=1,a=2".parse::<SplitSpec>().is_err());
+        assert!("0.5,-0.5".parse::<SplitSpec>().is_err());
+    }
+
+    #[test]
+    fn partition_without_objects() {
+        let spec: SplitSpec = "0.75,0.25".parse().unwrap();
+        let split = Split::new(&spec, [("a.png".to_string(), "Aegina")].into_iter());
+        assert_eq!(split.partition("a.png"), Some("train"));
+        assert_eq!(split.partition("b.png"), None);
+        assert_eq!(split.partition_or_hash("a.png"), "train");
+        let keys: Vec<String> = (0..1000).map(|i| format!("{}.png", i)).collect();
+        let val = keys.iter().filter(|key| split.partition_or_hash(key) == "val").count();
+        assert!((200..300).contains(&val), "{}", val);
+        // the same every run:
+        assert_eq!(split.partition_or_hash("b.png"), split.partition_or_hash("b.png"));
+    }
+
+    #[test]
+    fn group_keys() {
+        assert_eq!(group_key(SplitBy::Image, "d/x.png", Some(3)), "d/x.png");
+        assert_eq!(group_key(SplitBy::Folder, "d/x.png", None), "d");
+        assert_eq!(group_key(SplitBy::Track, "d/x.png", Some(3)), "d/track_3");
+        assert_eq!(group_key(SplitBy::Track, "d/x.png", None), "d/x.png");
+    }
+
+    #[test]
+    fn stratified_groups() {
+        let spec: SplitSpec = "0.8,0.2".parse().unwrap();
+        let mut items: Vec<(String, &str)> = Vec::new();
+        for i in 0..10 {
+            items.push((format!("img{}", i), "A"));
+            items.push((format!("img{}", i), "A"));
+            items.push((format!("other{}", i), "B"));
+        }
+        let split = Split::new(&spec, items.into_iter());
+        assert_eq!(split.counts["A"], vec![16, 4]);
+        assert_eq!(split.counts["B"], vec![8, 2]);
+        assert_eq!(split.groups, vec![16, 4]);
+        assert!(split.partition("img0").is_some());
+        assert_eq!(split.partition("nope"), None);
+    }
+}
@@ -0,0 +1,199 @@
+//! Sprite sheets of the object crops (`--sprite-sheets <dir>`), for the web
+//! review tool to load a gallery a sheet at a time: the crops, resized to fit
+//! squares of `--sprite-size` pixels keeping their aspect ratio, are packed in
+//! a grid of `SHEET_COLUMNS` × `SHEET_COLUMNS` cells per sheet
+//! (`sheet_0000.png`, ...), on a transparent background, in the order written,
+//! with `atlas.json` giving the sheet and position of each crop by its path
+//! relative to the output directory:
+//!
+//! ```json
+//! {"sprite_size": 128, "columns": 16, "sheets": ["sheet_0000.png"],
+//!  "sprites": {"Aegina/a_0.png": {"sheet": 0, "x": 0, "y": 10, "width": 128, "height": 108, "label": "Aegina"}}}
+//! ```
+
+use crate::image::resize_image;
+use crate::manifest::CropKind;
+use crate::montage;
+use crate::sink::{Crop, CropSink};
+use image::{GenericImage, Rgba, RgbaImage};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Cells per row and column of a sheet.
+pub const SHEET_COLUMNS: u32 = 16;
+
+#[derive(Debug, Serialize, PartialEq)]
+pub struct Sprite {
+    pub sheet: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub label: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Atlas {
+    pub sprite_size: u32,
+    pub columns: u32,
+    pub sheets: Vec<String>,
+    pub sprites: BTreeMap<String, Sprite>,
+}
+
+struct SheetState {
+    /// The sheet being filled, with its number of sprites.
+    sheet: Option<(RgbaImage, u32)>,
+    atlas: Atlas,
+}
+
+pub struct SpriteSheets {
+    dir: PathBuf,
+    size: u32,
+    state: Mutex<SheetState>,
+}
+
+impl SpriteSheets {
+    pub fn create(dir: &Path, size: u32) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            size,
+            state: Mutex::new(SheetState {
+                sheet: None,
+                atlas: Atlas {
+                    sprite_size: size,
+                    columns: SHEET_COLUMNS,
+                    ..Default::default()
+                },
+            }),
+        })
+    }
+
+    /// Writes the sheet being filled, if any.
+    fn save_sheet(&self, state: &mut SheetState) -> io::Result<()> {
+        if let Some((sheet, _)) = state.sheet.take() {
+            let name = &state.atlas.sheets[state.atlas.sheets.len() - 1];
+            sheet
+                .save(self.dir.join(name))
+                .map_err(|e| io::Error::other(format!("cannot save {:?}: {}", name, e)))?;
+        }
+        Ok(())
+    }
+}
+
+impl CropSink for SpriteSheets {
+    fn add(&self, crop: &Crop) -> io::Result<()> {
+        if crop.kind != CropKind::Object {
+            return Ok(());
+        }
+        let cell = (self.size, self.size);
+        let (width, height) = montage::fit(crop.image.width(), crop.image.height(), cell);
+        let Some(resized) = resize_image(crop.image, width, height) else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.sheet.is_none() {
+            let side = SHEET_COLUMNS * self.size;
+            state.sheet = Some((RgbaImage::from_pixel(side, side, Rgba([0; 4])), 0));
+            let name = format!("sheet_{:04}.png", state.atlas.sheets.len());
+            state.atlas.sheets.push(name);
+        }
+        let (sheet, count) = state.sheet.as_mut().unwrap();
+        let (col, row) = (*count % SHEET_COLUMNS, *count / SHEET_COLUMNS);
+        let x = col * self.size + (self.size - width) / 2;
+        let y = row * self.size + (self.size - height) / 2;
+        // fits by construction:
+        let _ = sheet.copy_from(&resized.to_rgba8(), x, y);
+        *count += 1;
+        let full = *count == SHEET_COLUMNS * SHEET_COLUMNS;
+        let sprite = Sprite {
+            sheet: state.atlas.sheets.len() - 1,
+            x,
+            y,
+            width,
+            height,
+            label: crop.label.to_string(),
+        };
+        let id = crop.name.to_string_lossy().into_owned();
+        state.atlas.sprites.insert(id, sprite);
+        if full {
+            self.save_sheet(&mut state)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.save_sheet(&mut state)?;
+        let path = self.dir.join("atlas.json");
+        serde_json::to_writer(BufWriter::new(File::create(&path)?), &state.atlas)?;
+        println!(
+            "Wrote {} sprites in {} sheets to {:?}",
+            state.atlas.sprites.len(),
+            state.atlas.sheets.len(),
+            self.dir
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::PixelRect;
+    use image::DynamicImage;
+
+    #[test]
+    fn sheets() {
+        let dir = std::env::temp_dir().join(format!("blaise-sprites-{}", std::process::id()));
+        let sheets = SpriteSheets::create(&dir, 8).unwrap();
+        let image = DynamicImage::new_rgb8(20, 10);
+        let bndbox = PixelRect {
+            xmin: 0,
+            ymin: 0,
+            xmax: 20,
+            ymax: 10,
+        };
+        let count = SHEET_COLUMNS * SHEET_COLUMNS + 1;
+        for k in 0..count {
+            let name = PathBuf::from(format!("Aegina/a_{}.png", k));
+            let crop = Crop {
+                kind: CropKind::Object,
+                image: &image,
+                bytes: &[],
+                path: &name,
+                name: &name,
+                label: "Aegina",
+                source: "a.png",
+                bndbox: &bndbox,
+            };
+            sheets.add(&crop).unwrap();
+            sheets.add(&Crop { kind: CropKind::Union, ..crop }).unwrap();
+        }
+        sheets.finish().unwrap();
+
+        let state = sheets.state.lock().unwrap();
+        assert_eq!(state.atlas.sheets, ["sheet_0000.png", "sheet_0001.png"]);
+        assert_eq!(state.atlas.sprites.len(), count as usize);
+        assert_eq!(
+            state.atlas.sprites["Aegina/a_1.png"],
+            Sprite {
+                sheet: 0,
+                x: 8,
+                y: 2,
+                width: 8,
+                height: 4,
+                label: "Aegina".to_string(),
+            }
+        );
+        assert_eq!(state.atlas.sprites[&format!("Aegina/a_{}.png", count - 1)].sheet, 1);
+        let side = SHEET_COLUMNS * 8;
+        assert_eq!(image::image_dimensions(dir.join("sheet_0001.png")).unwrap(), (side, side));
+        assert!(dir.join("atlas.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,125 @@
+//! Provenance stamping of the written crops (`--stamp-metadata`), as PNG text chunks.
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// The provenance entries for a crop.
+pub fn provenance(
+    source: &str,
+    bndbox: &crate::geometry::PixelRect,
+    label: &str,
+    run_id: &str,
+) -> Vec<(&'static str, String)> {
+    vec![
+        ("Software", format!("blaise {}", env!("CARGO_PKG_VERSION"))),
+        ("blaise:source", source.to_string()),
+        (
+            "blaise:bndbox",
+            format!(
+                "{},{},{},{}",
+                bndbox.xmin, bndbox.ymin, bndbox.xmax, bndbox.ymax
+            ),
+        ),
+        ("blaise:label", label.to_string()),
+        ("blaise:run-id", run_id.to_string()),
+    ]
+}
+
+/// Adds the entries to the PNG as text chunks right after the header chunk:
+/// `tEXt` for ASCII values, `iTXt` (UTF-8) otherwise.
+/// Returns None if the bytes are not a PNG.
+pub fn stamp_png(png: &[u8], entries: &[(&str, String)]) -> Option<Vec<u8>> {
+    let chunks: Vec<_> = entries
+        .iter()
+        .map(|(keyword, text)| {
+            let mut data = keyword.as_bytes().to_vec();
+            data.push(0);
+            let kind = if text.is_ascii() {
+                b"tEXt"
+            } else {
+                // no compression, and empty language tag and translated keyword:
+                data.extend_from_slice(&[0, 0, 0, 0]);
+                b"iTXt"
+            };
+            data.extend_from_slice(text.as_bytes());
+            (kind, data)
+        })
+        .collect();
+    insert_chunks(png, &chunks)
+}
+
+/// Adds the chunks (type, data) to the PNG right after the header chunk.
+/// Returns None if the bytes are not a PNG.
+pub fn insert_chunks(png: &[u8], chunks: &[(&[u8; 4], Vec<u8>)]) -> Option<Vec<u8>> {
+    // signature, then IHDR: length, type, 13 bytes of data, crc
+    let header_end = PNG_SIGNATURE.len() + 4 + 4 + 13 + 4;
+    if !png.starts_with(PNG_SIGNATURE) || png.len() < header_end || &png[12..16] != b"IHDR" {
+        return None;
+    }
+    let mut out = png[..header_end].to_vec();
+    for (kind, data) in chunks {
+        put_chunk(&mut out, kind, data);
+    }
+    out.extend_from_slice(&png[header_end..]);
+    Some(out)
+}
+
+fn put_chunk(buf: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = buf.len();
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(data);
+    let crc = crc32(&buf[start..]);
+    buf.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// CRC-32 (ISO-HDLC), as used by PNG.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat};
+    use std::io::Cursor;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn stamp() {
+        let mut png = Cursor::new(Vec::new());
+        DynamicImage::new_rgb8(3, 2)
+            .write_to(&mut png, ImageFormat::Png)
+            .unwrap();
+        let png = png.into_inner();
+        let entries = vec![
+            ("blaise:label", "Aegina".to_string()),
+            ("blaise:source", "imgs/café.png".to_string()),
+        ];
+        let stamped = stamp_png(&png, &entries).unwrap();
+        assert_eq!(stamped.len(), png.len() + (12 + 19) + (12 + 32));
+        assert!(stamped.windows(4).any(|w| w == b"tEXt"));
+        assert!(stamped.windows(4).any(|w| w == b"iTXt"));
+
+        // still a valid PNG:
+        let decoded = image::load_from_memory_with_format(&stamped, ImageFormat::Png).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (3, 2));
+
+        assert_eq!(stamp_png(b"GIF89a", &entries), None);
+    }
+}
@@ -0,0 +1,245 @@
+//! File reading and writing for images, with optional rate limiting.
+//! Writes under a remote output directory are uploaded instead.
+
+use crate::remote::Remote;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// When the written files are synced to disk.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncPolicy {
+    /// Left to the operating system
+    Never,
+    /// Each file as it is written
+    PerFile,
+    /// The files written so far, in batches every few seconds
+    Periodic,
+}
+
+pub struct Storage {
+    read_limiter: Option<TokenBucket>,
+    write_limiter: Option<TokenBucket>,
+    sync: SyncPolicy,
+    /// Directories already created.
+    dirs: Mutex<HashSet<PathBuf>>,
+    /// Files written since the last periodic sync, and when that was.
+    unsynced: Mutex<(Vec<PathBuf>, Instant)>,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    remote: Option<Remote>,
+}
+
+impl Storage {
+    const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+
+    /// Creates the storage with the given limits in megabytes per second, if any.
+    pub fn new(max_read_mbps: Option<f64>, max_write_mbps: Option<f64>) -> Self {
+        Self {
+            read_limiter: max_read_mbps.map(|mbps| TokenBucket::new(mbps * 1e6)),
+            write_limiter: max_write_mbps.map(|mbps| TokenBucket::new(mbps * 1e6)),
+            sync: SyncPolicy::Never,
+            dirs: Mutex::new(HashSet::new()),
+            unsynced: Mutex::new((Vec::new(), Instant::now())),
+            bytes_read: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            remote: None,
+        }
+    }
+
+    /// Total bytes read and written so far.
+    pub fn bytes(&self) -> (u64, u64) {
+        (
+            self.bytes_read.load(Ordering::Relaxed),
+            self.bytes_written.load(Ordering::Relaxed),
+        )
+    }
+
+    pub fn with_sync(self, sync: SyncPolicy) -> Self {
+        Self { sync, ..self }
+    }
+
+    /// Files under the root of the remote are uploaded to it.
+    pub fn with_remote(self, remote: Option<Remote>) -> Self {
+        Self { remote, ..self }
+    }
+
+    /// The remote and key for the path, if to be uploaded.
+    fn remote_key<'p>(&self, path: &'p Path) -> Option<(&Remote, &'p str)> {
+        let remote = self.remote.as_ref()?;
+        remote.key(path).map(|key| (remote, key))
+    }
+
+    /// Creates the directory (and parents) unless already done through this storage.
+    pub fn create_dir(&self, dir: &Path) -> io::Result<()> {
+        if self.dirs.lock().unwrap().contains(dir) {
+            return Ok(());
+        }
+        match self.remote_key(dir) {
+            Some((remote, key)) => remote.create_dir(key)?,
+            None => fs::create_dir_all(dir)?,
+        }
+        self.dirs.lock().unwrap().insert(dir.to_path_buf());
+        Ok(())
+    }
+
+    /// Syncs the files written since the last periodic sync.
+    pub fn sync(&self) {
+        let paths = std::mem::take(&mut self.unsynced.lock().unwrap().0);
+        for path in paths {
+            if let Err(e) = fs::File::open(&path).and_then(|f| f.sync_data()) {
+                eprintln!("WARN: cannot sync {:?}: {}", path, e);
+            }
+        }
+    }
+
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let bytes = fs::read(path)?;
+        self.bytes_read
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        if let Some(limiter) = &self.read_limiter {
+            limiter.consume(bytes.len());
+        }
+        Ok(bytes)
+    }
+
+    /// Writes the file. If `atomic`, the contents are first written to a temporary file
+    /// that is renamed on success, so an interrupted run never leaves truncated files.
+    pub fn write(&self, path: &Path, bytes: &[u8], atomic: bool) -> io::Result<()> {
+        if let Some(limiter) = &self.write_limiter {
+            limiter.consume(bytes.len());
+        }
+        // uploads are atomic already:
+        if let Some((remote, key)) = self.remote_key(path) {
+            remote.put(key, bytes)?;
+            self.bytes_written
+                .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            return Ok(());
+        }
+        if !atomic {
+            self.write_file(path, bytes)?;
+            self.written(path);
+            return Ok(());
+        }
+        let filename = path.file_name().unwrap().to_string_lossy();
+        let tmp_path = path.with_file_name(format!(".{}.tmp", filename));
+        let result = self
+            .write_file(&tmp_path, bytes)
+            .and_then(|_| fs::rename(&tmp_path, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        } else {
+            self.written(path);
+        }
+        result
+    }
+
+    fn write_file(&self, path: &Path, bytes: &[u8]) -> io::Result<()> {
+        let mut file = fs::File::create(path)?;
+        file.write_all(bytes)?;
+        self.bytes_written
+            .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+        if self.sync == SyncPolicy::PerFile {
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// Keeps track of the written file for the periodic sync, doing it if due.
+    fn written(&self, path: &Path) {
+        if self.sync != SyncPolicy::Periodic {
+            return;
+        }
+        let due = {
+            let mut unsynced = self.unsynced.lock().unwrap();
+            unsynced.0.push(path.to_path_buf());
+            let due = unsynced.1.elapsed() >= Self::SYNC_INTERVAL;
+            if due {
+                unsynced.1 = Instant::now();
+            }
+            due
+        };
+        if due {
+            self.sync();
+        }
+    }
+}
+
+/// Token bucket shared by the processing threads, with tokens being bytes.
+/// Consumers may go into debt, in which case they wait until it is paid off.
+struct TokenBucket {
+    rate: f64,
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    fn new(bytes_per_sec: f64) -> Self {
+        Self {
+            rate: bytes_per_sec,
+            state: Mutex::new((bytes_per_sec, Instant::now())),
+        }
+    }
+
+    fn consume(&self, bytes: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let (tokens, last) = &mut *state;
+            let now = Instant::now();
+            // refill, allowing a burst of at most one second:
+            *tokens =
+                (*tokens + now.duration_since(*last).as_secs_f64() * self.rate).min(self.rate);
+            *last = now;
+            *tokens -= bytes as f64;
+            if *tokens < 0. {
+                Duration::from_secs_f64(-*tokens / self.rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dirs_and_periodic_sync() {
+        let dir = std::env::temp_dir().join(format!("blaise-storage-{}", std::process::id()));
+        let storage = Storage::new(None, None).with_sync(SyncPolicy::Periodic);
+        let sub = dir.join("a/b");
+        storage.create_dir(&sub).unwrap();
+        assert!(sub.is_dir());
+        // already created, not touched again:
+        fs::remove_dir(&sub).unwrap();
+        storage.create_dir(&sub).unwrap();
+        assert!(!sub.exists());
+
+        storage.create_dir(&dir).unwrap();
+        storage.write(&dir.join("x.txt"), b"x", true).unwrap();
+        storage.write(&dir.join("y.txt"), b"y", false).unwrap();
+        assert_eq!(storage.unsynced.lock().unwrap().0.len(), 2);
+        storage.sync();
+        assert!(storage.unsynced.lock().unwrap().0.is_empty());
+        assert_eq!(storage.read(&dir.join("x.txt")).unwrap(), b"x");
+        assert_eq!(storage.bytes(), (1, 2));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn token_bucket() {
+        let bucket = TokenBucket::new(1000.);
+        let started = Instant::now();
+        bucket.consume(1000);
+        assert!(started.elapsed() < Duration::from_millis(50));
+        bucket.consume(100);
+        assert!(started.elapsed() >= Duration::from_millis(90));
+    }
+}
@@ -0,0 +1,215 @@
+use crate::annotation;
+use crate::scan::ScanProgress;
+use crate::source::{AnnotationSource, Annotations};
+use imagesize::ImageSize;
+use serde::Deserialize;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+use walkdir::WalkDir;
+
+type Res<T> = Result<T, Box<dyn Error>>;
+
+/// Name of the project metadata file of a Supervisely project export.
+pub const META_FILE: &str = "meta.json";
+
+/// A Supervisely project export: `meta.json` and `<dataset>/ann/*.json`,
+/// with the images in the sibling `img` directories.
+pub struct SuperviselySource<'a> {
+    pub dir: &'a Path,
+}
+
+impl AnnotationSource for SuperviselySource<'_> {
+    fn format(&self) -> &'static str {
+        "Supervisely"
+    }
+
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String> {
+        let project_dir = self.dir;
+        println!("getting supervisely annotations under {:?}", project_dir);
+        let classes = read_to_string(project_dir.join(META_FILE))
+            .map_err(|e| e.to_string())
+            .and_then(|src| parse_meta(&src).map_err(|e| e.to_string()))
+            .map_err(|e| {
+                format!(
+                    "invalid supervisely project {:?}: {}: {}",
+                    project_dir, META_FILE, e
+                )
+            })?;
+        println!("supervisely project classes: {}", classes.len());
+
+        let mut annotations = Vec::new();
+        let mut ignored_objects = 0usize;
+        for entry in WalkDir::new(project_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            let path = entry.path();
+            let ann_dir = path
+                .parent()
+                .filter(|p| p.file_name() == Some("ann".as_ref()));
+            let (Some(ann_dir), Some("json")) =
+                (ann_dir, path.extension().and_then(|e| e.to_str()))
+            else {
+                continue;
+            };
+            // annotation files are named after the image, eg., img/a.jpg -> ann/a.jpg.json
+            let image_dir = ann_dir.with_file_name("img");
+            let filename = path.file_stem().unwrap().to_string_lossy();
+            scan.seen();
+            let parsed = read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|src| {
+                    parse_ann(&image_dir.to_string_lossy(), &filename, &src)
+                        .map_err(|e| e.to_string())
+                });
+            match parsed {
+                Ok((annotation, ignored)) => {
+                    scan.parsed();
+                    ignored_objects += ignored;
+                    annotations.push(annotation);
+                }
+                Err(e) => scan.invalid(path, e),
+            }
+        }
+        if ignored_objects > 0 {
+            println!("non-rectangle objects ignored: {}", ignored_objects);
+        }
+        Ok(Box::new(annotations.into_iter()))
+    }
+}
+
+/// Parses the project `meta.json`, returning the class titles.
+pub fn parse_meta(src: &str) -> Res<Vec<String>> {
+    let meta: Meta = serde_json::from_str(src)?;
+    Ok(meta.classes.into_iter().map(|c| c.title).collect())
+}
+
+/// Parses the annotation JSON of an image, taking its rectangle objects.
+/// Returns the annotation and the number of non-rectangle objects ignored.
+pub fn parse_ann(folder: &str, filename: &str, src: &str) -> Res<(annotation::Annotation, usize)> {
+    let ann: Ann = serde_json::from_str(src)?;
+    let mut ignored = 0;
+    let mut objects = Vec::new();
+    for object in ann.objects {
+        match (object.geometry_type.as_str(), &object.points.exterior[..]) {
+            ("rectangle", [[x1, y1], [x2, y2]]) => objects.push(annotation::Object {
+                name: object.class_title,
+                bndbox: annotation::Bndbox {
+                    xmin: x1.min(*x2).max(0.),
+                    ymin: y1.min(*y2).max(0.),
+                    xmax: x1.max(*x2).max(0.),
+                    ymax: y1.max(*y2).max(0.),
+                },
+                track_id: None,
+                id: object.id.map(|id| id.to_string()),
+                mask: None,
+                attributes: Default::default(),
+            }),
+            ("rectangle", _) => return Err("rectangle without two exterior points".into()),
+            _ => ignored += 1,
+        }
+    }
+    let annotation = annotation::Annotation {
+        folder: folder.to_string(),
+        filename: filename.to_string(),
+        objects: if objects.is_empty() {
+            None
+        } else {
+            Some(objects)
+        },
+        frame: None,
+        image_size: ann.size.map(|size| ImageSize {
+            width: size.width,
+            height: size.height,
+        }),
+    };
+    Ok((annotation, ignored))
+}
+
+#[derive(Debug, Deserialize)]
+struct Meta {
+    classes: Vec<MetaClass>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MetaClass {
+    title: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Ann {
+    size: Option<Size>,
+    objects: Vec<Object>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Size {
+    width: usize,
+    height: usize,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Object {
+    id: Option<u64>,
+    class_title: String,
+    geometry_type: String,
+    #[serde(default)]
+    points: Points,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Points {
+    exterior: Vec<[f64; 2]>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const ANN1: &str = r#"{
+        "description": "",
+        "tags": [],
+        "size": {"height": 480, "width": 640},
+        "objects": [
+            {
+                "id": 1, "classId": 7, "classTitle": "Aegina", "geometryType": "rectangle",
+                "tags": [],
+                "points": {"exterior": [[120.2, 40], [20, 95]], "interior": []}
+            },
+            {
+                "id": 2, "classId": 8, "classTitle": "Kelp", "geometryType": "polygon",
+                "points": {"exterior": [[1, 1], [5, 1], [5, 5]], "interior": []}
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn ann1() {
+        let (annotation, ignored) = parse_ann("ds1/img", "a.jpg", ANN1).unwrap();
+        assert_eq!(ignored, 1);
+        assert_eq!(annotation.filename, "a.jpg");
+        let objects = annotation.objects.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].name, "Aegina");
+        assert_eq!(
+            objects[0].bndbox,
+            annotation::Bndbox {
+                xmin: 20.,
+                ymin: 40.,
+                xmax: 120.2,
+                ymax: 95.,
+            }
+        );
+    }
+
+    #[test]
+    fn meta() {
+        let classes =
+            parse_meta(r#"{"classes": [{"title": "Aegina", "shape": "rectangle"}], "tags": []}"#);
+        assert_eq!(classes.unwrap(), vec!["Aegina"]);
+    }
+}
@@ -0,0 +1,276 @@
+//! TensorFlow output: the object crops as `tf.train.Example` records (the
+//! encoded crop as written, its size, source and class) in TFRecord files of
+//! `--tfrecord-shard-size` examples, with a `label_map.pbtxt` for the class ids.
+//! The protobuf and record framing are written directly, with no TensorFlow
+//! dependency.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Writes crops as `tf.train.Example` records in sharded TFRecord files.
+pub struct TfRecordWriter {
+    dir: PathBuf,
+    shard_size: usize,
+    /// Label name to label id (1-based, per the TF label map convention).
+    label_ids: Vec<String>,
+    state: Mutex<ShardState>,
+}
+
+struct ShardState {
+    writer: Option<BufWriter<File>>,
+    shard: usize,
+    in_shard: usize,
+    total: usize,
+}
+
+impl TfRecordWriter {
+    /// Creates the writer and the `label_map.pbtxt` file in the given directory.
+    pub fn new(dir: &Path, shard_size: usize, mut labels: Vec<String>) -> io::Result<Self> {
+        std::fs::create_dir_all(dir)?;
+        labels.sort();
+        labels.dedup();
+        let label_map_path = dir.join("label_map.pbtxt");
+        let mut label_map = BufWriter::new(File::create(label_map_path)?);
+        for (i, label) in labels.iter().enumerate() {
+            let name = label.replace('\\', "\\\\").replace('\'', "\\'");
+            writeln!(
+                label_map,
+                "item {{\n  id: {}\n  name: '{}'\n}}",
+                i + 1,
+                name
+            )?;
+        }
+        label_map.flush()?;
+
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            shard_size: shard_size.max(1),
+            label_ids: labels,
+            state: Mutex::new(ShardState {
+                writer: None,
+                shard: 0,
+                in_shard: 0,
+                total: 0,
+            }),
+        })
+    }
+
+    /// Adds an example with the encoded crop, of the given format (eg., "png",
+    /// "jpeg") and size.
+    pub fn add(
+        &self,
+        encoded: &[u8],
+        format: &str,
+        (width, height): (u32, u32),
+        label: &str,
+        source_id: &str,
+    ) -> io::Result<()> {
+        let label_id = match self.label_ids.binary_search_by(|l| l.as_str().cmp(label)) {
+            Ok(i) => i as i64 + 1,
+            Err(_) => 0,
+        };
+        let example = encode_example(&[
+            ("image/encoded", Feature::Bytes(encoded)),
+            ("image/format", Feature::Bytes(format.as_bytes())),
+            ("image/width", Feature::Int64(width as i64)),
+            ("image/height", Feature::Int64(height as i64)),
+            ("image/source_id", Feature::Bytes(source_id.as_bytes())),
+            ("image/class/label", Feature::Int64(label_id)),
+            ("image/class/text", Feature::Bytes(label.as_bytes())),
+        ]);
+
+        let mut state = self.state.lock().unwrap();
+        if state.writer.is_none() || state.in_shard == self.shard_size {
+            if let Some(mut writer) = state.writer.take() {
+                writer.flush()?;
+                state.shard += 1;
+            }
+            let path = self.dir.join(format!("crops-{:05}.tfrecord", state.shard));
+            state.writer = Some(BufWriter::new(File::create(path)?));
+            state.in_shard = 0;
+        }
+        write_record(state.writer.as_mut().unwrap(), &example)?;
+        state.in_shard += 1;
+        state.total += 1;
+        Ok(())
+    }
+
+    pub fn finish(&self) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(mut writer) = state.writer.take() {
+            writer.flush()?;
+            println!(
+                "Wrote {} TFRecord examples in {} shards under {:?}",
+                state.total,
+                state.shard + 1,
+                self.dir
+            );
+        }
+        Ok(())
+    }
+}
+
+pub enum Feature<'a> {
+    Bytes(&'a [u8]),
+    Int64(i64),
+}
+
+/// Protobuf encoding of a `tf.train.Example` with the given features.
+pub fn encode_example(features: &[(&str, Feature)]) -> Vec<u8> {
+    let mut features_msg = Vec::new();
+    for (key, feature) in features {
+        let feature_msg = match feature {
+            // Feature.bytes_list = 1 { BytesList.value = 1 }
+            Feature::Bytes(bytes) => {
+                let mut list = Vec::new();
+                put_bytes(&mut list, 1, bytes);
+                let mut msg = Vec::new();
+                put_bytes(&mut msg, 1, &list);
+                msg
+            }
+            // Feature.int64_list = 3 { Int64List.value = 1 (packed) }
+            Feature::Int64(value) => {
+                let mut packed = Vec::new();
+                put_varint(&mut packed, *value as u64);
+                let mut list = Vec::new();
+                put_bytes(&mut list, 1, &packed);
+                let mut msg = Vec::new();
+                put_bytes(&mut msg, 3, &list);
+                msg
+            }
+        };
+        // map<string, Feature> entry: key = 1, value = 2
+        let mut entry = Vec::new();
+        put_bytes(&mut entry, 1, key.as_bytes());
+        put_bytes(&mut entry, 2, &feature_msg);
+        // Features.feature = 1
+        put_bytes(&mut features_msg, 1, &entry);
+    }
+    // Example.features = 1
+    let mut example = Vec::new();
+    put_bytes(&mut example, 1, &features_msg);
+    example
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Length-delimited field.
+fn put_bytes(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    put_varint(buf, ((field << 3) | 2) as u64);
+    put_varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// TFRecord framing: length, masked crc of length, data, masked crc of data.
+fn write_record<W: Write>(w: &mut W, data: &[u8]) -> std::io::Result<()> {
+    let len = (data.len() as u64).to_le_bytes();
+    w.write_all(&len)?;
+    w.write_all(&masked_crc32c(&len).to_le_bytes())?;
+    w.write_all(data)?;
+    w.write_all(&masked_crc32c(data).to_le_bytes())
+}
+
+fn masked_crc32c(data: &[u8]) -> u32 {
+    let crc = crc32c(data);
+    crc.rotate_right(15).wrapping_add(0xa282ead8)
+}
+
+/// CRC-32C (Castagnoli).
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f63b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn crc() {
+        assert_eq!(crc32c(b"123456789"), 0xe3069283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    /// The records of the TFRecord file, checking their framing.
+    fn read_records(path: &Path) -> Vec<Vec<u8>> {
+        let contents = std::fs::read(path).unwrap();
+        let mut records = Vec::new();
+        let mut rest = &contents[..];
+        while !rest.is_empty() {
+            let (len, after) = rest.split_at(8);
+            assert_eq!(after[..4], masked_crc32c(len).to_le_bytes());
+            let len = u64::from_le_bytes(len.try_into().unwrap()) as usize;
+            let (data, after) = after[4..].split_at(len);
+            assert_eq!(after[..4], masked_crc32c(data).to_le_bytes());
+            records.push(data.to_vec());
+            rest = &after[4..];
+        }
+        records
+    }
+
+    #[test]
+    fn shards() {
+        let dir = std::env::temp_dir().join(format!("blaise-tfrecord-{}", std::process::id()));
+        let labels = vec!["Pyrosome".to_string(), "Aegina".to_string()];
+        let writer = TfRecordWriter::new(&dir, 2, labels).unwrap();
+        let crops: [&[u8]; 3] = [b"first jpeg", b"second", b"third"];
+        for (i, crop) in crops.iter().enumerate() {
+            let label = ["Pyrosome", "Aegina", "other"][i];
+            writer.add(crop, "jpeg", (3, 4), label, "imgs/a.jpg").unwrap();
+        }
+        writer.finish().unwrap();
+
+        let label_map = std::fs::read_to_string(dir.join("label_map.pbtxt")).unwrap();
+        assert!(label_map.starts_with("item {\n  id: 1\n  name: 'Aegina'\n}\n"));
+        let records: Vec<Vec<u8>> = ["crops-00000.tfrecord", "crops-00001.tfrecord"]
+            .iter()
+            .flat_map(|name| read_records(&dir.join(name)))
+            .collect();
+        assert_eq!(records.len(), 3);
+        let expected = encode_example(&[
+            ("image/encoded", Feature::Bytes(b"first jpeg")),
+            ("image/format", Feature::Bytes(b"jpeg")),
+            ("image/width", Feature::Int64(3)),
+            ("image/height", Feature::Int64(4)),
+            ("image/source_id", Feature::Bytes(b"imgs/a.jpg")),
+            ("image/class/label", Feature::Int64(2)),
+            ("image/class/text", Feature::Bytes(b"Pyrosome")),
+        ]);
+        assert_eq!(records[0], expected);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn example() {
+        let example = encode_example(&[("a", Feature::Int64(150))]);
+        #[rustfmt::skip]
+        let expected = vec![
+            0x0a, 0x0d,             // Example.features
+            0x0a, 0x0b,             // Features.feature entry
+            0x0a, 0x01, b'a',       // key
+            0x12, 0x06,             // value
+            0x1a, 0x04,             // Feature.int64_list
+            0x0a, 0x02, 0x96, 0x01, // packed values
+        ];
+        assert_eq!(example, expected);
+    }
+}
@@ -0,0 +1,241 @@
+//! Timestamps of the images, parsed from their filenames with `--time-pattern`,
+//! eg., `(\d{4})(\d{2})(\d{2})T(\d{2})(\d{2})(\d{2})` for
+//! `D1234_20230501T221503Z.png`, to select the images in a `--time-range` and
+//! count them by day and hour.
+//!
+//! The captures are the year, month, day, and optionally the hour, minute and
+//! second, in this order, or named `year`, `month`, `day`, `hour`, `minute`
+//! and `second`.
+
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+const FIELDS: [&str; 6] = ["year", "month", "day", "hour", "minute", "second"];
+
+/// Date and time, without time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timestamp {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl Timestamp {
+    /// From the year, month, day, etc., with the fields not given
+    /// defaulting to the start of the period, or to its end.
+    fn from_fields(fields: &[u32], end: bool) -> Option<Timestamp> {
+        let field = |i: usize, max: u32| match fields.get(i) {
+            Some(&v) if v <= max => Some(v as u8),
+            Some(_) => None,
+            None => Some(if end { max as u8 } else { 0 }),
+        };
+        let timestamp = Timestamp {
+            year: u16::try_from(*fields.first()?).ok()?,
+            month: field(1, 12)?,
+            day: field(2, 31)?,
+            hour: field(3, 23)?,
+            minute: field(4, 59)?,
+            second: field(5, 59)?,
+        };
+        (timestamp.month >= 1 && timestamp.day >= 1).then_some(timestamp)
+    }
+
+    pub fn date(&self) -> String {
+        format!("{:04}-{:02}-{:02}", self.year, self.month, self.day)
+    }
+}
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}T{:02}:{:02}:{:02}",
+            self.date(),
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+}
+
+/// Parses `YYYY-MM-DD`, optionally followed by `Thh`, `Thh:mm` or `Thh:mm:ss`
+/// (or with a space instead of `T`), as the start or the end of the period.
+fn parse_timestamp(s: &str, end: bool) -> Result<Timestamp, String> {
+    let error = || format!("invalid time {:?}, expected YYYY-MM-DD[Thh[:mm[:ss]]]", s);
+    let (date, time) = match s.split_once(['T', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (s, None),
+    };
+    let date = date.split('-');
+    let time = time.into_iter().flat_map(|t| t.split(':'));
+    let fields: Vec<u32> = date
+        .chain(time)
+        .map(|v| v.parse().map_err(|_| error()))
+        .collect::<Result<_, _>>()?;
+    if fields.len() < 3 || fields.len() > 6 {
+        return Err(error());
+    }
+    Timestamp::from_fields(&fields, end).ok_or_else(error)
+}
+
+/// The regex of `--time-pattern`.
+#[derive(Debug, Clone)]
+pub struct TimePattern {
+    regex: Regex,
+}
+
+impl FromStr for TimePattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let regex = Regex::new(s).map_err(|e| e.to_string())?;
+        let named = regex.capture_names().flatten().collect::<Vec<_>>();
+        if let Some(name) = named.iter().find(|name| !FIELDS.contains(name)) {
+            return Err(format!(
+                "unexpected capture {:?}, expected {}",
+                name,
+                FIELDS.join(", ")
+            ));
+        }
+        let given = match named.is_empty() {
+            true => regex.captures_len() - 1,
+            false => FIELDS.iter().take_while(|f| named.contains(f)).count(),
+        };
+        if given < 3 || (!named.is_empty() && given != named.len()) {
+            return Err("expected captures of the year, month and day (then hour, minute, second)"
+                .to_string());
+        }
+        Ok(TimePattern { regex })
+    }
+}
+
+impl TimePattern {
+    /// The timestamp in the filename, if any.
+    pub fn timestamp(&self, filename: &str) -> Option<Timestamp> {
+        let captures = self.regex.captures(filename)?;
+        let named = self.regex.capture_names().any(|name| name.is_some());
+        let fields: Vec<u32> = match named {
+            true => FIELDS
+                .iter()
+                .map_while(|f| captures.name(f))
+                .map(|m| m.as_str().parse().ok())
+                .collect::<Option<_>>()?,
+            false => captures
+                .iter()
+                .skip(1)
+                .take(FIELDS.len())
+                .map(|m| m?.as_str().parse().ok())
+                .collect::<Option<_>>()?,
+        };
+        Timestamp::from_fields(&fields, false)
+    }
+}
+
+/// The `--time-range`: `start..end`, either of them optional, both included,
+/// eg., `2023-05-01..2023-05-02` for two full days.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeRange {
+    start: Option<Timestamp>,
+    end: Option<Timestamp>,
+}
+
+impl FromStr for TimeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (start, end) = s
+            .split_once("..")
+            .ok_or_else(|| format!("invalid time range {:?}, expected start..end", s))?;
+        let bound = |s: &str, end| match s.trim() {
+            "" => Ok(None),
+            s => parse_timestamp(s, end).map(Some),
+        };
+        let range = TimeRange {
+            start: bound(start, false)?,
+            end: bound(end, true)?,
+        };
+        match (range.start, range.end) {
+            (Some(start), Some(end)) if start > end => {
+                Err(format!("empty time range {:?}, from {} to {}", s, start, end))
+            }
+            _ => Ok(range),
+        }
+    }
+}
+
+impl TimeRange {
+    pub fn contains(&self, timestamp: &Timestamp) -> bool {
+        self.start.is_none_or(|start| start <= *timestamp)
+            && self.end.is_none_or(|end| *timestamp <= end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ts(s: &str) -> Timestamp {
+        parse_timestamp(s, false).unwrap()
+    }
+
+    #[test]
+    fn patterns() {
+        let pattern: TimePattern = r"(\d{4})(\d{2})(\d{2})T(\d{2})(\d{2})(\d{2})"
+            .parse()
+            .unwrap();
+        let timestamp = pattern.timestamp("D1234_20230501T221503Z.png").unwrap();
+        assert_eq!(timestamp.to_string(), "2023-05-01T22:15:03");
+        assert_eq!(timestamp.date(), "2023-05-01");
+        assert_eq!(pattern.timestamp("D1234_0001.png"), None);
+        // invalid month:
+        assert_eq!(pattern.timestamp("x_20231301T221503.png"), None);
+
+        let named: TimePattern = r"(?P<day>\d\d)\.(?P<month>\d\d)\.(?P<year>\d{4})_(?P<hour>\d\d)"
+            .parse()
+            .unwrap();
+        assert_eq!(named.timestamp("img_01.05.2023_07.jpg"), Some(ts("2023-05-01T07")));
+
+        let date_only: TimePattern = r"(\d{4})-(\d\d)-(\d\d)".parse().unwrap();
+        assert_eq!(date_only.timestamp("2023-05-01.png"), Some(ts("2023-05-01")));
+
+        assert!(r"(\d{4})(\d{2})".parse::<TimePattern>().is_err());
+        assert!(r"(?P<year>\d{4})(?P<day>\d{2})(?P<month>\d{2})"
+            .parse::<TimePattern>()
+            .is_ok());
+        assert!(r"(?P<year>\d{4})(?P<month>\d{2})(?P<hour>\d{2})"
+            .parse::<TimePattern>()
+            .is_err());
+        assert!(r"(?P<year>\d{4})(?P<month>\d{2})(?P<day>\d{2})(?P<ms>\d+)"
+            .parse::<TimePattern>()
+            .unwrap_err()
+            .starts_with("unexpected capture \"ms\""));
+        assert!(r"(\d{4}".parse::<TimePattern>().is_err());
+    }
+
+    #[test]
+    fn ranges() {
+        let range: TimeRange = "2023-05-01..2023-05-02".parse().unwrap();
+        assert!(!range.contains(&ts("2023-04-30T23:59:59")));
+        assert!(range.contains(&ts("2023-05-01")));
+        assert!(range.contains(&ts("2023-05-02T23:59:59")));
+        assert!(!range.contains(&ts("2023-05-03")));
+
+        let range: TimeRange = "2023-05-01T22:30..".parse().unwrap();
+        assert!(!range.contains(&ts("2023-05-01T22:29:59")));
+        assert!(range.contains(&ts("2023-05-01T22:30")));
+        assert!(range.contains(&ts("2024-01-01")));
+
+        let range: TimeRange = "..2023-05-01 06".parse().unwrap();
+        assert!(range.contains(&ts("2023-05-01T06:59:59")));
+        assert!(!range.contains(&ts("2023-05-01T07")));
+
+        assert!("2023-05-01".parse::<TimeRange>().is_err());
+        assert!("2023-05..".parse::<TimeRange>().is_err());
+        assert!("2023-05-01T25..".parse::<TimeRange>().is_err());
+        assert!("2023-05-02..2023-05-01".parse::<TimeRange>().is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! Color-cast correction for underwater imagery.
+
+use image::{DynamicImage, Rgb32FImage, RgbImage};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorCorrection {
+    /// Gray-world white balance: scales each channel to the overall mean intensity
+    Graylevel,
+    /// Dark channel prior: estimates and removes the per-channel veiling light
+    Dcp,
+}
+
+/// Side in pixels of the patches for the dark channel.
+const DCP_PATCH: u32 = 15;
+
+/// Fraction of the haze removed by the dark channel prior (keeping some for depth perception).
+const DCP_OMEGA: f32 = 0.95;
+
+/// Lower bound for the estimated transmission.
+const DCP_MIN_TRANSMISSION: f32 = 0.1;
+
+pub fn correct(img: &DynamicImage, method: ColorCorrection) -> DynamicImage {
+    let rgb = img.to_rgb32f();
+    if rgb.width() == 0 || rgb.height() == 0 {
+        return img.clone();
+    }
+    let corrected = match method {
+        ColorCorrection::Graylevel => gray_world(&rgb),
+        ColorCorrection::Dcp => dark_channel_prior(&rgb),
+    };
+    // kept at 16 bits for the deeper images:
+    match crate::image::bits_per_sample(img) {
+        8 => DynamicImage::ImageRgb8(to_rgb8(&corrected)),
+        _ => DynamicImage::ImageRgb16(DynamicImage::ImageRgb32F(corrected).to_rgb16()),
+    }
+}
+
+fn gray_world(img: &Rgb32FImage) -> Rgb32FImage {
+    let mut sums = [0f64; 3];
+    for p in img.pixels() {
+        for c in 0..3 {
+            sums[c] += p[c] as f64;
+        }
+    }
+    let gray = sums.iter().sum::<f64>() / 3.;
+    let gains: Vec<f32> = sums
+        .iter()
+        .map(|s| if *s > 0. { (gray / s) as f32 } else { 1. })
+        .collect();
+    let mut out = img.clone();
+    for p in out.pixels_mut() {
+        for c in 0..3 {
+            p[c] *= gains[c];
+        }
+    }
+    out
+}
+
+fn dark_channel_prior(img: &Rgb32FImage) -> Rgb32FImage {
+    let (width, height) = img.dimensions();
+
+    // veiling light: per channel, the brightest pixel among the 0.1% with the highest dark channel
+    let dark = dark_channel(img, [1.; 3]);
+    let mut indices: Vec<usize> = (0..dark.len()).collect();
+    indices.sort_by(|a, b| dark[*b].total_cmp(&dark[*a]));
+    let top = (dark.len() / 1000).max(1);
+    let mut light = [0f32; 3];
+    for i in &indices[..top] {
+        let p = img.get_pixel(*i as u32 % width, *i as u32 / width);
+        for c in 0..3 {
+            light[c] = light[c].max(p[c]);
+        }
+    }
+    for l in light.iter_mut() {
+        *l = l.max(1e-3);
+    }
+
+    let normalized_dark = dark_channel(img, light);
+    let mut out = img.clone();
+    for (i, p) in out.pixels_mut().enumerate() {
+        let t = (1. - DCP_OMEGA * normalized_dark[i]).max(DCP_MIN_TRANSMISSION);
+        for c in 0..3 {
+            p[c] = (p[c] - light[c]) / t + light[c];
+        }
+    }
+    debug_assert_eq!(out.dimensions(), (width, height));
+    out
+}
+
+/// Minimum over the channels (each divided by the given light) and over a patch around each pixel.
+fn dark_channel(img: &Rgb32FImage, light: [f32; 3]) -> Vec<f32> {
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let min_channel: Vec<f32> = img
+        .pixels()
+        .map(|p| (0..3).map(|c| p[c] / light[c]).fold(f32::MAX, f32::min))
+        .collect();
+
+    // separable minimum filter: rows, then columns
+    let r = (DCP_PATCH / 2) as usize;
+    let mut rows = vec![0f32; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let (lo, hi) = (x.saturating_sub(r), (x + r).min(width - 1));
+            rows[y * width + x] = min_channel[y * width + lo..=y * width + hi]
+                .iter()
+                .copied()
+                .fold(f32::MAX, f32::min);
+        }
+    }
+    let mut dark = vec![0f32; width * height];
+    for y in 0..height {
+        let (lo, hi) = (y.saturating_sub(r), (y + r).min(height - 1));
+        for x in 0..width {
+            dark[y * width + x] = (lo..=hi)
+                .map(|yy| rows[yy * width + x])
+                .fold(f32::MAX, f32::min);
+        }
+    }
+    dark
+}
+
+fn to_rgb8(img: &Rgb32FImage) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let p = img.get_pixel(x, y);
+        image::Rgb([0, 1, 2].map(|c| (p[c].clamp(0., 1.) * 255.).round() as u8))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channel_means(img: &DynamicImage) -> [f64; 3] {
+        let rgb = img.to_rgb8();
+        let mut sums = [0f64; 3];
+        for p in rgb.pixels() {
+            for c in 0..3 {
+                sums[c] += p[c] as f64;
+            }
+        }
+        sums.map(|s| s / (rgb.width() * rgb.height()) as f64)
+    }
+
+    /// Scene with a blue-green cast.
+    fn bluish() -> DynamicImage {
+        let img = RgbImage::from_fn(40, 30, |x, y| {
+            let v = (x * 3 + y * 2) as u8;
+            image::Rgb([v / 4, v / 2 + 60, v / 2 + 100])
+        });
+        DynamicImage::ImageRgb8(img)
+    }
+
+    #[test]
+    fn graylevel() {
+        let corrected = correct(&bluish(), ColorCorrection::Graylevel);
+        let [r, g, b] = channel_means(&corrected);
+        assert!(
+            (r - g).abs() < 2. && (g - b).abs() < 2.,
+            "{} {} {}",
+            r,
+            g,
+            b
+        );
+    }
+
+    #[test]
+    fn dcp() {
+        let img = bluish();
+        let corrected = correct(&img, ColorCorrection::Dcp);
+        assert_eq!(corrected.width(), img.width());
+        let [r0, _, b0] = channel_means(&img);
+        let [r, _, b] = channel_means(&corrected);
+        // the cast is reduced:
+        assert!(b - r < b0 - r0, "{} {}", b - r, b0 - r0);
+    }
+}
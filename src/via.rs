@@ -0,0 +1,276 @@
+use crate::annotation;
+use crate::scan::ScanProgress;
+use crate::source::{AnnotationSource, Annotations};
+use serde_json::Value;
+use std::error::Error;
+use std::fs::read_to_string;
+use std::path::Path;
+
+type Res<T> = Result<T, Box<dyn Error>>;
+
+/// A VIA project or annotations file (.json or .csv), with the image file
+/// names relative to `image_dir`, by default the directory of the file.
+pub struct ViaSource<'a> {
+    pub file: &'a Path,
+    pub image_dir: Option<&'a Path>,
+    pub label_key: Option<&'a str>,
+}
+
+impl AnnotationSource for ViaSource<'_> {
+    fn format(&self) -> &'static str {
+        "Via"
+    }
+
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String> {
+        println!("getting via annotations from {:?}", self.file);
+        let image_dir = self
+            .image_dir
+            .or_else(|| self.file.parent())
+            .unwrap_or(Path::new("."));
+        let folder = image_dir.to_string_lossy();
+        scan.seen();
+        let src = read_to_string(self.file)
+            .map_err(|e| format!("cannot read via file {:?}: {}", self.file, e))?;
+        let parsed = match self.file.extension() {
+            Some(e) if e == "csv" => parse_via_csv(&folder, &src, self.label_key),
+            _ => parse_via_json(&folder, &src, self.label_key),
+        };
+        let (annotations, unlabeled) =
+            parsed.map_err(|e| format!("invalid via file {:?}: {}", self.file, e))?;
+        scan.parsed();
+        if unlabeled > 0 {
+            println!("rect regions without label: {}", unlabeled);
+        }
+        Ok(Box::new(annotations.into_iter()))
+    }
+}
+
+/// Parses a VGG Image Annotator (VIA) project or exported annotations JSON,
+/// taking the `rect` regions, labeled according to [region_label].
+/// Returns the annotations and the number of rect regions without a label.
+pub fn parse_via_json(
+    folder: &str,
+    src: &str,
+    label_key: Option<&str>,
+) -> Res<(Vec<annotation::Annotation>, usize)> {
+    let json: Value = serde_json::from_str(src)?;
+    // project files have the image metadata under a key; exported annotations are just that:
+    let metadata = json.get("_via_img_metadata").unwrap_or(&json);
+    let metadata = metadata.as_object().ok_or("expected a JSON object")?;
+
+    let mut unlabeled = 0;
+    let mut annotations = Vec::new();
+    for image in metadata.values() {
+        let filename = image["filename"].as_str().ok_or("missing filename")?;
+        // regions is an array in VIA 2, and an object keyed by index in VIA 1:
+        let regions: Vec<&Value> = match &image["regions"] {
+            Value::Array(regions) => regions.iter().collect(),
+            Value::Object(regions) => regions.values().collect(),
+            _ => Vec::new(),
+        };
+        let mut objects = Vec::new();
+        for region in regions {
+            let shape = &region["shape_attributes"];
+            if let Some(object) = rect_object(shape, &region["region_attributes"], label_key)? {
+                objects.push(object);
+            } else if shape["name"] == "rect" {
+                unlabeled += 1;
+            }
+        }
+        annotations.push(annotation(folder, filename, objects));
+    }
+    Ok((annotations, unlabeled))
+}
+
+/// Parses a VIA annotations CSV, with a row per region and the shape and
+/// region attributes as JSON columns.
+pub fn parse_via_csv(
+    folder: &str,
+    src: &str,
+    label_key: Option<&str>,
+) -> Res<(Vec<annotation::Annotation>, usize)> {
+    let mut reader = csv::Reader::from_reader(src.as_bytes());
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h.trim_start_matches('#') == name)
+            .ok_or_else(|| format!("missing column {}", name))
+    };
+    let (filename_col, shape_col, attributes_col) = (
+        column("filename")?,
+        column("region_shape_attributes")?,
+        column("region_attributes")?,
+    );
+
+    let mut unlabeled = 0;
+    let mut by_image: Vec<(String, Vec<annotation::Object>)> = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let filename = &record[filename_col];
+        if by_image.last().map(|(f, _)| f.as_str()) != Some(filename) {
+            by_image.push((filename.to_string(), Vec::new()));
+        }
+        let shape: Value = serde_json::from_str(&record[shape_col])?;
+        let attributes: Value = serde_json::from_str(&record[attributes_col])?;
+        if let Some(object) = rect_object(&shape, &attributes, label_key)? {
+            by_image.last_mut().unwrap().1.push(object);
+        } else if shape["name"] == "rect" {
+            unlabeled += 1;
+        }
+    }
+    let annotations = by_image
+        .into_iter()
+        .map(|(filename, objects)| annotation(folder, &filename, objects))
+        .collect();
+    Ok((annotations, unlabeled))
+}
+
+fn annotation(
+    folder: &str,
+    filename: &str,
+    objects: Vec<annotation::Object>,
+) -> annotation::Annotation {
+    annotation::Annotation {
+        folder: folder.to_string(),
+        filename: filename.to_string(),
+        objects: if objects.is_empty() {
+            None
+        } else {
+            Some(objects)
+        },
+        frame: None,
+        image_size: None,
+    }
+}
+
+/// The object for a `rect` region having a label, if any.
+fn rect_object(
+    shape: &Value,
+    attributes: &Value,
+    label_key: Option<&str>,
+) -> Res<Option<annotation::Object>> {
+    if shape["name"] != "rect" {
+        return Ok(None);
+    }
+    let Some(name) = region_label(attributes, label_key) else {
+        return Ok(None);
+    };
+    let coord = |key: &str| -> Res<f64> {
+        shape[key]
+            .as_f64()
+            .ok_or_else(|| format!("rect without {}", key).into())
+    };
+    let (x, y) = (coord("x")?, coord("y")?);
+    let (width, height) = (coord("width")?, coord("height")?);
+    Ok(Some(annotation::Object {
+        name,
+        bndbox: annotation::Bndbox {
+            xmin: x.max(0.),
+            ymin: y.max(0.),
+            xmax: (x + width).max(0.),
+            ymax: (y + height).max(0.),
+        },
+        track_id: None,
+        id: None,
+        mask: None,
+        attributes: Default::default(),
+    }))
+}
+
+/// Label of a region from the attribute with the given key, or from the
+/// only attribute if no key is given. Text attributes are taken as is;
+/// for checkbox/dropdown attributes (objects), the first selected option.
+fn region_label(attributes: &Value, label_key: Option<&str>) -> Option<String> {
+    let attributes = attributes.as_object()?;
+    let value = match label_key {
+        Some(key) => attributes.get(key)?,
+        None if attributes.len() == 1 => attributes.values().next()?,
+        None => return None,
+    };
+    let label = match value {
+        Value::String(s) => s.trim().to_string(),
+        Value::Object(options) => options
+            .iter()
+            .find(|(_, selected)| selected.as_bool() == Some(true))
+            .map(|(option, _)| option.clone())?,
+        _ => return None,
+    };
+    (!label.is_empty()).then_some(label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const VIA2: &str = r#"{
+        "_via_settings": {},
+        "_via_img_metadata": {
+            "a.jpg12345": {
+                "filename": "a.jpg",
+                "size": 12345,
+                "regions": [
+                    {
+                        "shape_attributes": {"name": "rect", "x": 10, "y": 20, "width": 30, "height": 40},
+                        "region_attributes": {"species": "Aegina", "notes": "blurry"}
+                    },
+                    {
+                        "shape_attributes": {"name": "rect", "x": 1, "y": 2, "width": 3, "height": 4},
+                        "region_attributes": {"species": {"Kelp": true, "Rock": false}}
+                    },
+                    {
+                        "shape_attributes": {"name": "rect", "x": 1, "y": 2, "width": 3, "height": 4},
+                        "region_attributes": {"species": ""}
+                    },
+                    {
+                        "shape_attributes": {"name": "circle", "cx": 1, "cy": 2, "r": 3},
+                        "region_attributes": {"species": "Aegina"}
+                    }
+                ],
+                "file_attributes": {}
+            }
+        }
+    }"#;
+
+    const VIA_CSV: &str = "\
+#filename,file_size,file_attributes,region_count,region_id,region_shape_attributes,region_attributes
+a.jpg,12345,\"{}\",2,0,\"{\"\"name\"\":\"\"rect\"\",\"\"x\"\":10,\"\"y\"\":20,\"\"width\"\":30,\"\"height\"\":40}\",\"{\"\"species\"\":\"\"Aegina\"\"}\"
+a.jpg,12345,\"{}\",2,1,\"{\"\"name\"\":\"\"rect\"\",\"\"x\"\":1,\"\"y\"\":2,\"\"width\"\":3,\"\"height\"\":4}\",\"{}\"
+b.jpg,2345,\"{}\",0,0,\"{}\",\"{}\"
+";
+
+    #[test]
+    fn via_json() {
+        let (annotations, unlabeled) = parse_via_json("imgs", VIA2, Some("species")).unwrap();
+        assert_eq!(unlabeled, 1);
+        assert_eq!(annotations.len(), 1);
+        let objects = annotations[0].objects.as_ref().unwrap();
+        let names: Vec<&str> = objects.iter().map(|o| o.name.as_str()).collect();
+        assert_eq!(names, vec!["Aegina", "Kelp"]);
+        assert_eq!(
+            objects[0].bndbox,
+            annotation::Bndbox {
+                xmin: 10.,
+                ymin: 20.,
+                xmax: 40.,
+                ymax: 60.,
+            }
+        );
+
+        // without a key, only regions with a single attribute are labeled:
+        let (annotations, unlabeled) = parse_via_json("imgs", VIA2, None).unwrap();
+        assert_eq!(unlabeled, 2);
+        assert_eq!(annotations[0].objects.as_ref().unwrap()[0].name, "Kelp");
+    }
+
+    #[test]
+    fn via_csv() {
+        let (annotations, unlabeled) = parse_via_csv("imgs", VIA_CSV, None).unwrap();
+        assert_eq!(unlabeled, 1);
+        assert_eq!(annotations.len(), 2);
+        assert_eq!(annotations[0].objects.as_ref().unwrap()[0].name, "Aegina");
+        assert_eq!(annotations[1].filename, "b.jpg");
+        assert_eq!(annotations[1].objects, None);
+    }
+}
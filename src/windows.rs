@@ -0,0 +1,161 @@
+//! Sliding windows over the images (`--windows WxH`), labeled by the objects
+//! they contain, for patch classification datasets from detection annotations.
+
+use crate::annotation::Object;
+use crate::geometry::{PixelRect, Rect};
+use std::collections::BTreeMap;
+
+/// Fraction of the box of an object in a window for the object to count.
+pub const MIN_CONTAINED: f64 = 0.5;
+
+/// Name of the subdirectory (under the output directory) for the multi-label windows.
+pub const WINDOWS_DIR: &str = "_windows";
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowLabel {
+    /// The label of most of the objects in the window (of the largest total
+    /// area on ties), with the windows in the directories of their labels
+    Majority,
+    /// All the labels of the objects in the window, comma separated, with
+    /// the windows in the `_windows` directory
+    Multi,
+}
+
+/// The windows of the given size, every `stride` pixels, covering the image:
+/// the last ones in each direction are aligned to the edges of the image.
+/// None if the image is smaller than a window.
+pub fn windows(image: (u32, u32), size: (u32, u32), stride: (u32, u32)) -> Vec<PixelRect> {
+    let starts = |length: u32, size: u32, stride: u32| -> Vec<u32> {
+        if size == 0 || length < size {
+            return Vec::new();
+        }
+        let last = length - size;
+        let mut starts: Vec<u32> = (0..=last).step_by(stride.max(1) as usize).collect();
+        if starts.last() != Some(&last) {
+            starts.push(last);
+        }
+        starts
+    };
+    let xs = starts(image.0, size.0, stride.0);
+    let ys = starts(image.1, size.1, stride.1);
+    ys.iter()
+        .flat_map(|&y| {
+            xs.iter().map(move |&x| PixelRect {
+                xmin: x,
+                ymin: y,
+                xmax: x + size.0,
+                ymax: y + size.1,
+            })
+        })
+        .collect()
+}
+
+/// The label of the window, by the objects mostly in it; none without objects.
+pub fn window_label(window: &PixelRect, objects: &[&Object], how: WindowLabel) -> Option<String> {
+    let window = Rect::from(*window);
+    // count and total area by label:
+    let mut contained: BTreeMap<&str, (usize, f64)> = BTreeMap::new();
+    for object in objects {
+        if object.bndbox.containment(&window) >= MIN_CONTAINED {
+            let (count, area) = contained.entry(&object.name).or_insert((0, 0.));
+            *count += 1;
+            *area += object.bndbox.area();
+        }
+    }
+    match how {
+        WindowLabel::Majority => contained
+            .into_iter()
+            .max_by(|(a_name, a), (b_name, b)| {
+                (a.0.cmp(&b.0))
+                    .then(a.1.total_cmp(&b.1))
+                    .then(b_name.cmp(a_name))
+            })
+            .map(|(name, _)| name.to_string()),
+        WindowLabel::Multi => {
+            let names: Vec<&str> = contained.into_keys().collect();
+            (!names.is_empty()).then(|| names.join(","))
+        }
+    }
+}
+
+/// Name of the crop of the window, eg., `a_w64_128.png` for `a.png`.
+pub fn window_filename(filename: &str, window: &PixelRect) -> String {
+    let stem = match filename.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => filename,
+    };
+    format!("{}_w{}_{}.png", stem, window.xmin, window.ymin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotation::Bndbox;
+
+    fn object(name: &str, xmin: f64, ymin: f64, size: f64) -> Object {
+        Object {
+            name: name.to_string(),
+            bndbox: Bndbox {
+                xmin,
+                ymin,
+                xmax: xmin + size,
+                ymax: ymin + size,
+            },
+            track_id: None,
+            id: None,
+            mask: None,
+            attributes: Default::default(),
+        }
+    }
+
+    #[test]
+    fn sliding() {
+        let corners = |image, size, stride| -> Vec<(u32, u32)> {
+            windows(image, size, stride)
+                .iter()
+                .map(|w| (w.xmin, w.ymin))
+                .collect()
+        };
+        assert_eq!(corners((200, 100), (100, 100), (100, 100)), [(0, 0), (100, 0)]);
+        assert_eq!(
+            corners((250, 100), (100, 100), (100, 100)),
+            [(0, 0), (100, 0), (150, 0)]
+        );
+        assert_eq!(
+            corners((200, 150), (100, 100), (50, 50)),
+            [(0, 0), (50, 0), (100, 0), (0, 50), (50, 50), (100, 50)]
+        );
+        assert!(corners((99, 200), (100, 100), (100, 100)).is_empty());
+        let window = windows((100, 100), (100, 100), (1, 1))[0];
+        assert_eq!((window.width(), window.height()), (100, 100));
+    }
+
+    #[test]
+    fn labels() {
+        let objects = [
+            object("A", 10., 10., 20.),
+            object("B", 40., 40., 10.),
+            object("B", 60., 60., 10.),
+            object("C", 90., 90., 20.),
+        ];
+        let objects: Vec<&Object> = objects.iter().collect();
+        let window = PixelRect {
+            xmin: 0,
+            ymin: 0,
+            xmax: 100,
+            ymax: 100,
+        };
+        let label = |how| window_label(&window, &objects, how);
+        assert_eq!(label(WindowLabel::Majority).as_deref(), Some("B"));
+        // C is mostly outside:
+        assert_eq!(label(WindowLabel::Multi).as_deref(), Some("A,B"));
+        // ties, by area:
+        assert_eq!(
+            window_label(&window, &objects[..2], WindowLabel::Majority).as_deref(),
+            Some("A")
+        );
+        assert_eq!(window_label(&window, &[], WindowLabel::Multi), None);
+
+        assert_eq!(window_filename("a.png", &window), "a_w0_0.png");
+    }
+}
@@ -1,16 +1,44 @@
 use crate::annotation;
+use crate::geometry::{Point, Rect};
+use crate::source::{Issue, Strictness};
 use imagesize::ImageSize;
+use std::collections::HashMap;
 use std::error::Error;
 use std::str::FromStr;
+#[cfg(feature = "pipeline")]
+use {
+    crate::imgsize::ImageSizeCache,
+    crate::scan::ScanProgress,
+    crate::source::{self, AnnotationSource, Annotations},
+    log::debug,
+    std::fs::read_to_string,
+    std::path::{Path, PathBuf},
+    walkdir::WalkDir,
+};
 
 type Res<T> = Result<T, Box<dyn Error>>;
 
+/// Objects whose class is mapped to `None` by `class_id_to_name` are dropped.
 pub fn parse_yolo(
     folder: &str,
     filename: &str,
     image_size: &ImageSize,
-    class_id_to_name: impl Fn(u32) -> String,
+    class_id_to_name: impl Fn(u32) -> Option<String>,
     src: &str,
+) -> Res<Yolo> {
+    let normal = |issue: Issue| Strictness::Normal.accepts(&issue);
+    parse_yolo_with(folder, filename, image_size, class_id_to_name, src, normal)
+}
+
+/// As `parse_yolo`, with the malformed lines given to `on_issue`, skipped if
+/// it accepts them.
+pub fn parse_yolo_with(
+    folder: &str,
+    filename: &str,
+    image_size: &ImageSize,
+    class_id_to_name: impl Fn(u32) -> Option<String>,
+    src: &str,
+    mut on_issue: impl FnMut(Issue) -> bool,
 ) -> Res<Yolo> {
     fn parse<F: FromStr>(s: Option<&str>) -> Res<F> {
         let s = s.ok_or("expected a string")?;
@@ -18,26 +46,58 @@ pub fn parse_yolo(
             .map_err(|_| format!("cannot parse '{}'", s).into())
     }
 
-    let parse_object = |line: &str| -> Res<Object> {
+    // Either `class x y width height` (detection) or `class x1 y1 x2 y2 ...`
+    // with 3 or more vertices (segmentation), all normalized.
+    let parse_object = |line: &str| -> Res<Option<Object>> {
         let mut parts = line.split_whitespace();
         let class_id: u32 = parse(parts.next())?;
-        Ok(Object {
-            name: class_id_to_name(class_id),
-            x: parse(parts.next())?,
-            y: parse(parts.next())?,
-            width: parse(parts.next())?,
-            height: parse(parts.next())?,
-        })
+        let values = parts
+            .map(|s| parse::<f64>(Some(s)))
+            .collect::<Res<Vec<_>>>()?;
+        let object = match values.len() {
+            4 => Object {
+                name: String::new(),
+                x: values[0],
+                y: values[1],
+                width: values[2],
+                height: values[3],
+                polygon: None,
+            },
+            n if n >= 6 && n % 2 == 0 => {
+                let polygon: Vec<Point> =
+                    values.chunks(2).map(|p| Point::new(p[0], p[1])).collect();
+                let bounds = Rect::bounding(&polygon).unwrap();
+                let center = bounds.center();
+                Object {
+                    name: String::new(),
+                    x: center.x,
+                    y: center.y,
+                    width: bounds.width(),
+                    height: bounds.height(),
+                    polygon: Some(polygon),
+                }
+            }
+            n => return Err(format!("unexpected number of values: {}", n).into()),
+        };
+        Ok(class_id_to_name(class_id).map(|name| Object { name, ..object }))
     };
 
-    let objects: Vec<Object> = src
-        .split('\n')
-        .map(|line| line.trim())
-        .filter(|line| !line.is_empty() && !line.starts_with('#'))
-        .map(parse_object)
-        .collect::<Vec<Res<Object>>>()
-        .into_iter()
-        .collect::<Res<Vec<_>>>()?;
+    let mut objects: Vec<Object> = Vec::new();
+    for (n, line) in src.split('\n').enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match parse_object(line) {
+            Ok(object) => objects.extend(object),
+            Err(e) => {
+                let reason = format!("line {}: {}", n + 1, e);
+                if !on_issue(Issue::Malformed(reason.clone())) {
+                    return Err(reason.into());
+                }
+            }
+        }
+    }
 
     Ok(Yolo {
         folder: folder.to_string(),
@@ -68,37 +128,22 @@ impl From<Yolo> for annotation::Annotation {
                 let mut objects: Vec<annotation::Object> = objects
                     .into_iter()
                     .map(|object| {
-                        let Object {
-                            name,
-                            mut x,
-                            mut y,
-                            mut width,
-                            mut height,
-                        } = object;
-
                         // Per https://bitbucket.org/mbari/m3-download/src/main/scripts/yolo_to_voc.py:
-                        // Shift x, y from center to upper-left
-                        x -= width / 2.;
-                        y -= height / 2.;
-                        // Scale
-                        x *= image_width;
-                        y *= image_height;
-                        width *= image_width;
-                        height *= image_height;
-
-                        let xmin = x.round() as u32;
-                        let ymin = y.round() as u32;
-                        let xmax = xmin + width.round() as u32;
-                        let ymax = ymin + height.round() as u32;
-
+                        // from center to upper-left, and scaled
+                        let bndbox = object.rect().scale(image_width, image_height);
                         annotation::Object {
-                            name,
-                            bndbox: annotation::Bndbox {
-                                xmin,
-                                ymin,
-                                xmax,
-                                ymax,
-                            },
+                            name: object.name,
+                            bndbox,
+                            track_id: None,
+                            id: None,
+                            mask: object.polygon.map(|polygon| {
+                                let vertices: Vec<Point> = polygon
+                                    .into_iter()
+                                    .map(|p| Point::new(p.x * image_width, p.y * image_height))
+                                    .collect();
+                                annotation::Mask::from_polygon(&vertices)
+                            }),
+                            attributes: Default::default(),
                         }
                     })
                     .collect();
@@ -112,7 +157,267 @@ impl From<Yolo> for annotation::Annotation {
             folder,
             filename,
             objects,
+            frame: None,
+            image_size: Some(image_size),
+        }
+    }
+}
+
+/// Maps class ids in the label files to names, with optional selection of ids
+/// and remapping of ids (to other ids or directly to names).
+#[derive(Debug, Default)]
+pub struct ClassMap {
+    names: Vec<String>,
+    select: Option<Vec<u32>>,
+    remap: HashMap<u32, ClassTarget>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ClassTarget {
+    Id(u32),
+    Name(String),
+}
+
+impl ClassMap {
+    pub fn new(
+        names: Vec<String>,
+        select: Option<Vec<u32>>,
+        remap: HashMap<u32, ClassTarget>,
+    ) -> Self {
+        ClassMap {
+            names,
+            select,
+            remap,
+        }
+    }
+
+    /// Name for the given class id as found in a label file, or `None` if
+    /// the id is not selected. Selection applies to the original ids.
+    pub fn name(&self, class_id: u32) -> Option<String> {
+        if let Some(select) = &self.select {
+            if !select.contains(&class_id) {
+                return None;
+            }
+        }
+        let class_id = match self.remap.get(&class_id) {
+            Some(ClassTarget::Name(name)) => return Some(name.clone()),
+            Some(ClassTarget::Id(id)) => *id,
+            None => class_id,
+        };
+        Some(match self.names.get(class_id as usize) {
+            Some(name) => name.clone(),
+            None => format!("class_{}", class_id),
+        })
+    }
+}
+
+/// Parses a class remap file: one `<old_id> <new_id|name>` pair per line
+/// (whitespace or comma separated), with `#` comments.
+pub fn parse_class_remap(src: &str) -> Res<HashMap<u32, ClassTarget>> {
+    let mut remap = HashMap::new();
+    for line in src.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (old, new) = line
+            .split_once(|c: char| c == ',' || c.is_whitespace())
+            .ok_or_else(|| format!("expected '<old_id> <new_id|name>': '{}'", line))?;
+        let old: u32 = old
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid class id '{}'", old))?;
+        let new = new.trim();
+        let target = match new.parse::<u32>() {
+            Ok(id) => ClassTarget::Id(id),
+            Err(_) => ClassTarget::Name(new.to_string()),
+        };
+        if remap.insert(old, target).is_some() {
+            return Err(format!("class id {} remapped more than once", old).into());
+        }
+    }
+    Ok(remap)
+}
+
+/// What to do with images without a YOLO label file.
+#[cfg(feature = "pipeline")]
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MissingLabels {
+    /// Ignore the image
+    Skip,
+    /// Take the image as having no objects (background)
+    Empty,
+    /// Stop with an error
+    Error,
+}
+
+/// The label files for the images under an image directory
+/// (their sizes are read from the images).
+#[cfg(feature = "pipeline")]
+pub struct YoloSource<'a> {
+    pub image_dir: &'a Path,
+    pub label_dir: &'a Path,
+    pub names_file: &'a Path,
+    pub select_classes: Option<Vec<u32>>,
+    pub class_remap: Option<&'a Path>,
+    pub missing_labels: MissingLabels,
+    pub coord_policy: CoordPolicy,
+    /// Whether to keep the polygons (for the instance masks).
+    pub keep_polygons: bool,
+    /// Threads for the image size scan.
+    pub threads: usize,
+}
+
+#[cfg(feature = "pipeline")]
+impl AnnotationSource for YoloSource<'_> {
+    fn format(&self) -> &'static str {
+        "Yolo"
+    }
+
+    fn scan<'a>(&'a self, scan: &'a mut ScanProgress) -> Result<Annotations<'a>, String> {
+        let (image_dir, label_dir) = (self.image_dir, self.label_dir);
+        println!(
+            "processing yolo annotations with:
+          image_dir:  {:?}
+          yolo_dir:   {:?}
+          yolo_names: {:?}",
+            image_dir, label_dir, self.names_file
+        );
+
+        let yolo_names = source::read_names(self.names_file)?;
+        println!("yolo names loaded: {}", yolo_names.len());
+        debug!(
+            "yolo_names({}): first few={:?}",
+            yolo_names.len(),
+            &yolo_names[0..5.min(yolo_names.len())]
+        );
+
+        let remap = match self.class_remap {
+            Some(path) => {
+                let remap = read_to_string(path)
+                    .map_err(|e| e.to_string())
+                    .and_then(|src| parse_class_remap(&src).map_err(|e| e.to_string()))
+                    .map_err(|e| format!("class remap {:?}: {}", path, e))?;
+                println!("class remap entries: {}", remap.len());
+                remap
+            }
+            None => Default::default(),
+        };
+        let class_map = ClassMap::new(yolo_names, self.select_classes.clone(), remap);
+        let class_id_to_name = |class_id: u32| class_map.name(class_id);
+
+        let image_paths: Vec<PathBuf> = WalkDir::new(image_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(source::is_image)
+            .map(|e| e.into_path())
+            .collect();
+        println!("image files: {}", image_paths.len());
+
+        let mut size_cache = ImageSizeCache::load(image_dir);
+        let progress = scan.bar("image sizes");
+        let sizes = size_cache.sizes(image_dir, &image_paths, self.threads, &progress);
+        size_cache.save();
+        let image_filenames: Vec<(String, ImageSize)> = image_paths
+            .iter()
+            .zip(sizes)
+            .filter_map(|(path, size)| match size {
+                Ok((width, height)) => {
+                    let filename = path.file_name().unwrap().to_string_lossy().into_owned();
+                    Some((filename, ImageSize { width, height }))
+                }
+                Err(e) => {
+                    eprintln!("WARN: cannot get size of image {:?}: {}", path, e);
+                    None
+                }
+            })
+            .collect();
+
+        fn replace_to_txt(e: &str) -> String {
+            let base = e
+                .rfind('.')
+                .map(|i| e[..i].to_string())
+                .unwrap_or_else(|| e.to_string());
+            base + ".txt"
+        }
+
+        let folder = image_dir.to_string_lossy();
+        let mut annotations: Vec<annotation::Annotation> = Vec::new();
+        let mut missing = 0u32;
+        let mut out_of_range = 0usize;
+        for (image_filename, image_size) in &image_filenames {
+            let path = label_dir.join(replace_to_txt(image_filename));
+            scan.seen();
+            let src = match read_to_string(&path) {
+                Ok(src) => src,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                    missing += 1;
+                    match self.missing_labels {
+                        MissingLabels::Skip => continue,
+                        MissingLabels::Empty => String::new(),
+                        MissingLabels::Error => {
+                            return Err(format!("missing label file {:?}", path));
+                        }
+                    }
+                }
+                Err(e) => {
+                    eprintln!("ERROR: cannot read label file {:?}: {}", path, e);
+                    scan.invalid(&path, e);
+                    continue;
+                }
+            };
+            let on_issue = |issue| scan.issue(&path, issue);
+            let parsed = parse_yolo_with(
+                &folder,
+                image_filename,
+                image_size,
+                class_id_to_name,
+                &src,
+                on_issue,
+            );
+            match parsed.and_then(|mut yolo| {
+                let adjusted = yolo.apply_coord_policy(self.coord_policy)?;
+                out_of_range += adjusted;
+                let reason = format!("{} objects with out-of-range coordinates", adjusted);
+                if adjusted > 0 && !scan.issue(&path, Issue::Irregular(reason.clone())) {
+                    return Err(reason.into());
+                }
+                Ok(yolo)
+            }) {
+                Ok(yolo) => {
+                    scan.parsed();
+                    let yolo = if self.keep_polygons {
+                        yolo
+                    } else {
+                        yolo.without_polygons()
+                    };
+                    annotations.push(yolo.into());
+                }
+                Err(e) => {
+                    if self.coord_policy == CoordPolicy::Error {
+                        eprintln!("ERROR: label file {:?}: {}", path, e);
+                    }
+                    scan.invalid(&path, e);
+                }
+            }
+        }
+
+        if out_of_range > 0 {
+            let action = match self.coord_policy {
+                CoordPolicy::Clamp => "clamped",
+                _ => "skipped",
+            };
+            println!(
+                "objects with out-of-range coordinates {}: {}",
+                action, out_of_range
+            );
+        }
+        if missing > 0 {
+            println!(
+                "missing label files: {} (--missing-labels {:?})",
+                missing, self.missing_labels
+            );
         }
+        Ok(Box::new(annotations.into_iter()))
     }
 }
 
@@ -124,6 +429,74 @@ pub struct Yolo {
     pub objects: Option<Vec<Object>>,
 }
 
+/// What to do with objects having coordinates outside the image (below 0 or above 1).
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoordPolicy {
+    /// Clamp the coordinates to the image
+    Clamp,
+    /// Drop the object
+    Skip,
+    /// Reject the label file
+    Error,
+}
+
+impl Yolo {
+    /// Applies the policy to the objects with out-of-range coordinates,
+    /// returning how many were clamped or skipped.
+    pub fn apply_coord_policy(&mut self, policy: CoordPolicy) -> Res<usize> {
+        let in_range = |v: f64| (0. ..=1.).contains(&v);
+        let out_of_range = |object: &Object| {
+            let rect = object.rect();
+            rect != rect.clamp(1., 1.)
+                || object
+                    .polygon
+                    .iter()
+                    .flatten()
+                    .any(|p| !in_range(p.x) || !in_range(p.y))
+        };
+        let Some(objects) = &mut self.objects else {
+            return Ok(0);
+        };
+        let before = objects.len();
+        let mut adjusted = 0;
+        match policy {
+            CoordPolicy::Error => {
+                if let Some(object) = objects.iter().find(|o| out_of_range(o)) {
+                    return Err(format!("out-of-range coordinates for {}", object.name).into());
+                }
+            }
+            CoordPolicy::Skip => {
+                objects.retain(|o| !out_of_range(o));
+                adjusted = before - objects.len();
+            }
+            CoordPolicy::Clamp => {
+                for object in objects.iter_mut().filter(|o| out_of_range(o)) {
+                    let rect = object.rect().clamp(1., 1.);
+                    let center = rect.center();
+                    (object.x, object.y) = (center.x, center.y);
+                    (object.width, object.height) = (rect.width(), rect.height());
+                    for p in object.polygon.iter_mut().flatten() {
+                        (p.x, p.y) = (p.x.clamp(0., 1.), p.y.clamp(0., 1.));
+                    }
+                    adjusted += 1;
+                }
+            }
+        }
+        if objects.is_empty() {
+            self.objects = None;
+        }
+        Ok(adjusted)
+    }
+
+    /// Drops the segmentation polygons, so no masks get computed.
+    pub fn without_polygons(mut self) -> Self {
+        for object in self.objects.iter_mut().flatten() {
+            object.polygon = None;
+        }
+        self
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Object {
     pub name: String,
@@ -131,16 +504,26 @@ pub struct Object {
     pub y: f64,
     pub width: f64,
     pub height: f64,
+    /// Normalized vertices, for segmentation labels.
+    pub polygon: Option<Vec<Point>>,
+}
+
+impl Object {
+    /// The normalized box.
+    pub fn rect(&self) -> Rect {
+        Rect::from_center(self.x, self.y, self.width, self.height)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::geometry;
     use approx::assert_relative_eq;
     use pretty_assertions::assert_eq;
 
-    fn class_id_to_name(class_id: u32) -> String {
-        format!("class_{}", class_id)
+    fn class_id_to_name(class_id: u32) -> Option<String> {
+        Some(format!("class_{}", class_id))
     }
 
     fn assert_eq_objects(obj: &Object, exp: &Object) {
@@ -182,6 +565,7 @@ mod tests {
             y: 0.33,
             width: 0.07,
             height: 0.13,
+            polygon: None,
         }];
         let objects = yolo.objects.unwrap();
         let objects = objects.as_slice();
@@ -190,6 +574,20 @@ mod tests {
         assert_eq_objects(&objects[0], &expected_objects[0]);
     }
 
+    #[test]
+    fn malformed_lines() {
+        let src = "3 0.2 0.8 0.02 0.08\n5 0.3 x 0.07\n5 0.3 0.3\n";
+        let parse = |strictness: Strictness| {
+            parse_yolo_with("D", "FN", &IMAGE_SIZE, class_id_to_name, src, |issue| {
+                strictness.accepts(&issue)
+            })
+        };
+        let e = parse(Strictness::Normal).unwrap_err();
+        assert_eq!(e.to_string(), "line 2: cannot parse 'x'");
+        let yolo = parse(Strictness::Lenient).unwrap();
+        assert_eq!(yolo.objects.unwrap().len(), 1);
+    }
+
     #[test]
     fn yolo2() {
         let yolo = parse_yolo("D", "FN", &IMAGE_SIZE, class_id_to_name, YOLO2).unwrap();
@@ -200,6 +598,7 @@ mod tests {
                 y: 0.8189814814814815,
                 width: 0.027083333333333334,
                 height: 0.08981481481481482,
+                polygon: None,
             },
             Object {
                 name: "class_5".to_string(),
@@ -207,6 +606,7 @@ mod tests {
                 y: 0.33611111111111114,
                 width: 0.07916666666666666,
                 height: 0.1388888888888889,
+                polygon: None,
             },
         ];
         let objects = yolo.objects.clone().unwrap();
@@ -215,31 +615,180 @@ mod tests {
         assert_eq_objects(&objects[0], &expected_objects[0]);
         assert_eq_objects(&objects[1], &expected_objects[1]);
 
-        // as annotation:
+        // as annotation, and in pixels with the default quantization:
         let annotation: annotation::Annotation = yolo.into();
         let ann_objects = annotation.objects.unwrap();
+        assert_eq!(ann_objects[0].name, "class_3");
+        assert_relative_eq!(ann_objects[0].bndbox.xmin, 136.333333, epsilon = 1e-6);
+        assert_relative_eq!(ann_objects[0].bndbox.ymax, 414.666667, epsilon = 1e-6);
+        let pixels: Vec<geometry::PixelRect> = ann_objects
+            .iter()
+            .map(|o| o.bndbox.to_pixels(Default::default()))
+            .collect();
         assert_eq!(
-            ann_objects,
+            pixels,
             vec![
-                annotation::Object {
-                    name: "class_3".to_string(),
-                    bndbox: annotation::Bndbox {
-                        xmin: 136,
-                        ymin: 372,
-                        xmax: 153,
-                        ymax: 415,
-                    },
+                geometry::PixelRect {
+                    xmin: 136,
+                    ymin: 372,
+                    xmax: 153,
+                    ymax: 415,
                 },
-                annotation::Object {
-                    name: "class_5".to_string(),
-                    bndbox: annotation::Bndbox {
-                        xmin: 221,
-                        ymin: 128,
-                        xmax: 272,
-                        ymax: 195,
-                    },
+                geometry::PixelRect {
+                    xmin: 221,
+                    ymin: 128,
+                    xmax: 272,
+                    ymax: 195,
                 },
             ]
         );
     }
+
+    #[test]
+    fn class_map() {
+        let names: Vec<String> = ["a", "b", "c"].iter().map(|s| s.to_string()).collect();
+        let remap = parse_class_remap("# old new\n0 2\n1, Aegina\n\n5 1\n").unwrap();
+        assert_eq!(remap.get(&0), Some(&ClassTarget::Id(2)));
+        assert_eq!(
+            remap.get(&1),
+            Some(&ClassTarget::Name("Aegina".to_string()))
+        );
+        assert!(parse_class_remap("0 1\n0 2").is_err());
+        assert!(parse_class_remap("x 1").is_err());
+
+        let map = ClassMap::new(names.clone(), Some(vec![0, 1, 5, 7]), remap);
+        assert_eq!(map.name(0).as_deref(), Some("c"));
+        assert_eq!(map.name(1).as_deref(), Some("Aegina"));
+        assert_eq!(map.name(2), None);
+        assert_eq!(map.name(5).as_deref(), Some("b"));
+        assert_eq!(map.name(7).as_deref(), Some("class_7"));
+
+        let yolo = parse_yolo("D", "FN", &IMAGE_SIZE, |id| map.name(id), YOLO2).unwrap();
+        let objects = yolo.objects.unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].name, "b");
+
+        let map = ClassMap::new(names, None, HashMap::new());
+        assert_eq!(map.name(2).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn polygon() {
+        let src = "1 0.1 0.1 0.2 0.1 0.2 0.3 0.1 0.3\n2 0.5 0.5 0.1 0.1";
+        let yolo = parse_yolo("D", "FN", &IMAGE_SIZE, class_id_to_name, src).unwrap();
+        let objects = yolo.objects.as_ref().unwrap();
+        assert_eq_objects(
+            &objects[0],
+            &Object {
+                name: "class_1".to_string(),
+                x: 0.15,
+                y: 0.2,
+                width: 0.1,
+                height: 0.2,
+                polygon: None,
+            },
+        );
+        assert_eq!(objects[0].polygon.as_ref().unwrap().len(), 4);
+        assert_eq!(objects[1].polygon, None);
+
+        let annotation: annotation::Annotation = yolo.clone().into();
+        let object = &annotation.objects.unwrap()[0];
+        let pixels = object.bndbox.to_pixels(Default::default());
+        assert_eq!(
+            pixels,
+            geometry::PixelRect {
+                xmin: 64,
+                ymin: 48,
+                xmax: 128,
+                ymax: 144,
+            }
+        );
+        assert_eq!(object.mask.as_ref().unwrap().bounds(), pixels);
+
+        let annotation: annotation::Annotation = yolo.without_polygons().into();
+        assert!(annotation.objects.unwrap()[0].mask.is_none());
+
+        assert!(parse_yolo("D", "FN", &IMAGE_SIZE, class_id_to_name, "1 0.1 0.1 0.2").is_err());
+        assert!(parse_yolo(
+            "D",
+            "FN",
+            &IMAGE_SIZE,
+            class_id_to_name,
+            "1 0.1 0.1 0.2 0.1 0.2 0.3"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn coord_policy() {
+        let src = "1 0.98 0.5 0.06 0.2\n2 0.5 0.5 0.1 0.1\n3 -0.01 0.1 0.1 0.1 0.1 0.2";
+        let parse = || parse_yolo("D", "FN", &IMAGE_SIZE, class_id_to_name, src).unwrap();
+
+        let mut yolo = parse();
+        assert_eq!(yolo.apply_coord_policy(CoordPolicy::Clamp).unwrap(), 2);
+        let objects = yolo.objects.as_ref().unwrap();
+        assert_eq!(objects.len(), 3);
+        assert_relative_eq!(objects[0].x + objects[0].width / 2., 1.);
+        assert_relative_eq!(objects[0].x - objects[0].width / 2., 0.95);
+        assert_eq!(objects[2].polygon.as_ref().unwrap()[0], Point::new(0., 0.1));
+        let annotation: annotation::Annotation = yolo.into();
+        assert_eq!(annotation.objects.unwrap()[0].bndbox.xmax, 640.);
+
+        let mut yolo = parse();
+        assert_eq!(yolo.apply_coord_policy(CoordPolicy::Skip).unwrap(), 2);
+        assert_eq!(yolo.objects.as_ref().unwrap().len(), 1);
+        assert_eq!(yolo.objects.as_ref().unwrap()[0].name, "class_2");
+
+        assert!(parse().apply_coord_policy(CoordPolicy::Error).is_err());
+    }
+
+    #[cfg(feature = "pipeline")]
+    #[test]
+    fn missing_label_files() {
+        let dir = std::env::temp_dir().join(format!("blaise-yolo-{}", std::process::id()));
+        let (images, labels) = (dir.join("images"), dir.join("labels"));
+        std::fs::create_dir_all(&images).unwrap();
+        std::fs::create_dir_all(&labels).unwrap();
+        for name in ["a.png", "b.png"] {
+            image::RgbImage::new(64, 48)
+                .save(images.join(name))
+                .unwrap();
+        }
+        // no label file for b.png:
+        std::fs::write(labels.join("a.txt"), "0 0.5 0.5 0.25 0.25\n").unwrap();
+        let names = dir.join("names.txt");
+        std::fs::write(&names, "Aegina\n").unwrap();
+
+        let scan = |missing_labels| {
+            let source = YoloSource {
+                image_dir: &images,
+                label_dir: &labels,
+                names_file: &names,
+                select_classes: None,
+                class_remap: None,
+                missing_labels,
+                coord_policy: CoordPolicy::Clamp,
+                keep_polygons: false,
+                threads: 1,
+            };
+            let mut progress = ScanProgress::new(false);
+            let annotations = source.scan(&mut progress)?;
+            let mut objects: Vec<(String, usize)> = annotations
+                .map(|a| (a.filename, a.objects.map_or(0, |o| o.len())))
+                .collect();
+            objects.sort();
+            Ok::<_, String>(objects)
+        };
+        let a = ("a.png".to_string(), 1);
+        assert_eq!(scan(MissingLabels::Skip), Ok(vec![a.clone()]));
+        let b = ("b.png".to_string(), 0);
+        assert_eq!(scan(MissingLabels::Empty), Ok(vec![a, b]));
+        let e = scan(MissingLabels::Error).unwrap_err();
+        assert!(
+            e.starts_with("missing label file") && e.contains("b.txt"),
+            "{}",
+            e
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }